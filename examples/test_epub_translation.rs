@@ -46,7 +46,7 @@ async fn main() {
 
             // 测试漏译检测功能
 
-            match processor.check_untranslated(Path::new("/home/louis")).await {
+            match processor.check_untranslated(Path::new("/home/louis"), "zh").await {
                 Ok(leaks) => {
                     println!("✅ 漏译检测完成，找到 {} 个可能的漏译", leaks.len());
                     if !leaks.is_empty() {
@@ -0,0 +1,151 @@
+//! Progress file of a directory job, so a re-run skips files already done
+//!
+//! Each completed input file is recorded with a SHA-256 of its content; a
+//! file is only skipped while its content is unchanged, and only by a run
+//! with the same target language and mode.
+
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+use crate::core::errors::{Result, TranslationError};
+
+/// File name of the progress file, kept in the output directory
+pub const CHECKPOINT_FILE: &str = ".translate-progress.json";
+
+/// Input files translated so far, saved after each one completes
+#[derive(Debug)]
+pub struct Checkpoint {
+    /// Where the progress is saved
+    path: PathBuf,
+    /// Target language and mode of this run, part of every key
+    scope: String,
+    /// Content hash by key; held while the progress is saved
+    done: Mutex<BTreeMap<String, String>>,
+}
+
+impl Checkpoint {
+    /// Read the progress saved at `path` for a run translating to
+    /// `target_lang` in `mode` (such as `translate` or `review`).
+    ///
+    /// Empty if there is none yet; a progress file that cannot be parsed is
+    /// ignored with a warning.
+    pub fn load(path: &Path, target_lang: &str, mode: &str) -> Result<Self> {
+        let done = match std::fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                warn!("Ignoring unreadable checkpoint {}: {}", path.display(), e);
+                BTreeMap::new()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => {
+                return Err(TranslationError::FileError {
+                    path: path.display().to_string(),
+                    message: e.to_string(),
+                })
+            }
+        };
+        debug!("Loaded {} completed files from {}", done.len(), path.display());
+        Ok(Self {
+            path: path.to_path_buf(),
+            scope: format!("{}:{}", mode, target_lang),
+            done: Mutex::new(done),
+        })
+    }
+
+    /// Whether `file` was completed with its current content
+    pub async fn is_done(&self, file: &Path) -> bool {
+        let done = self.done.lock().await;
+        match (done.get(&self.key(file)), content_hash(file)) {
+            (Some(saved), Some(hash)) => *saved == hash,
+            _ => false,
+        }
+    }
+
+    /// Record that `file` completed and save the progress.
+    ///
+    /// Saves are serialised and replace the file whole, so a newer save is
+    /// never overwritten by an older one and a crash cannot truncate it.
+    pub async fn mark_done(&self, file: &Path) -> Result<()> {
+        let Some(hash) = content_hash(file) else {
+            return Ok(());
+        };
+        let mut done = self.done.lock().await;
+        done.insert(self.key(file), hash);
+        let json = serde_json::to_string_pretty(&*done)?;
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        tokio::fs::write(&tmp, json).await?;
+        tokio::fs::rename(&tmp, &self.path).await?;
+        Ok(())
+    }
+
+    /// Key of `file` in the progress file
+    fn key(&self, file: &Path) -> String {
+        format!("{}:{}", self.scope, file.to_string_lossy())
+    }
+}
+
+/// SHA-256 of `file`'s content, `None` if it cannot be read
+fn content_hash(file: &Path) -> Option<String> {
+    let content = std::fs::read(file).ok()?;
+    Some(format!("{:x}", Sha256::digest(&content)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_skips_unchanged_and_redoes_changed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a.md"), dir.path().join("b.md"));
+        std::fs::write(&a, "# A").unwrap();
+        std::fs::write(&b, "# B").unwrap();
+        let path = dir.path().join(CHECKPOINT_FILE);
+
+        let checkpoint = Checkpoint::load(&path, "ja", "translate").unwrap();
+        assert!(!checkpoint.is_done(&a).await);
+        checkpoint.mark_done(&a).await.unwrap();
+        checkpoint.mark_done(&b).await.unwrap();
+
+        // A later run sees the saved progress
+        std::fs::write(&b, "# B, edited").unwrap();
+        let checkpoint = Checkpoint::load(&path, "ja", "translate").unwrap();
+        assert!(checkpoint.is_done(&a).await);
+        assert!(!checkpoint.is_done(&b).await);
+    }
+
+    #[tokio::test]
+    async fn test_progress_is_kept_per_language_and_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a.md");
+        std::fs::write(&a, "# A").unwrap();
+        let path = dir.path().join(CHECKPOINT_FILE);
+
+        let review = Checkpoint::load(&path, "ja", "review").unwrap();
+        review.mark_done(&a).await.unwrap();
+        let german = Checkpoint::load(&path, "de", "translate").unwrap();
+        german.mark_done(&a).await.unwrap();
+
+        let japanese = Checkpoint::load(&path, "ja", "translate").unwrap();
+        assert!(!japanese.is_done(&a).await);
+        assert!(Checkpoint::load(&path, "ja", "review").unwrap().is_done(&a).await);
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_progress_starts_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a.md");
+        std::fs::write(&a, "# A").unwrap();
+        let path = dir.path().join(CHECKPOINT_FILE);
+        std::fs::write(&path, "{\"translate:ja:").unwrap();
+
+        let checkpoint = Checkpoint::load(&path, "ja", "translate").unwrap();
+        assert!(!checkpoint.is_done(&a).await);
+        checkpoint.mark_done(&a).await.unwrap();
+        let checkpoint = Checkpoint::load(&path, "ja", "translate").unwrap();
+        assert!(checkpoint.is_done(&a).await);
+    }
+}
@@ -1,16 +1,182 @@
 //! CLI command definitions and handlers
 
-use clap::{Parser, Subcommand};
+use clap::{Args, Subcommand};
 use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::core::config::TranslatorConfig;
+use crate::core::glossary::Glossary;
+use crate::core::models::ScheduleOrder;
+use crate::processors::epub::{DEFAULT_PIPELINE_DEPTH, DEFAULT_UNTRANSLATED_RATIO};
+
+/// Options shared by commands that translate a batch of files
+#[derive(Args, Debug, Clone, Default)]
+pub struct JobArgs {
+    /// Give up on a single file after this many seconds and move on
+    #[arg(long, value_name = "SECS")]
+    pub per_file_timeout: Option<u64>,
+
+    /// Files translated at the same time; each still shares the client's --max-concurrent requests
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    pub file_concurrency: usize,
+
+    /// Dispatch order: source_order, largest_first or smallest_first (output order is kept)
+    #[arg(long, default_value = "source_order")]
+    pub schedule: ScheduleOrder,
+
+    /// Also write the job's diagnostics (failed segments, failed files, ...) to this JSON file
+    #[arg(long, value_name = "FILE")]
+    pub diagnostics: Option<PathBuf>,
+
+    /// Write segments left untranslated (for ePub: the snippets the leak report
+    /// flags) to this editable fix-up JSON file, to fill in and apply with apply-fix
+    #[arg(long, value_name = "FILE")]
+    pub fixup: Option<PathBuf>,
+
+    /// Preview: translate only the first N segments of each file or book, copy the rest
+    #[arg(long, value_name = "N")]
+    pub sample: Option<usize>,
+
+    /// Without --source-lang, detect the source language from the first file and
+    /// ask before translating if detection is unsure
+    #[arg(long)]
+    pub from_lang_auto_confirm: bool,
+
+    /// Never prompt; proceed with the detected source language
+    #[arg(short = 'y', long)]
+    pub yes: bool,
+}
+
+impl JobArgs {
+    /// Per-file timeout as a duration
+    pub fn per_file_timeout(&self) -> Option<Duration> {
+        self.per_file_timeout.map(Duration::from_secs)
+    }
+}
+
+/// Options specific to Markdown translation
+#[derive(Args, Debug, Clone, Default)]
+pub struct MdArgs {
+    /// Write an editable `<output>.review.json` per file instead of the final document
+    #[arg(long)]
+    pub review: bool,
+
+    /// Earlier version of the (single) input file; only segments changed since are translated
+    #[arg(long, value_name = "FILE", requires = "previous_translation")]
+    pub previous_source: Option<PathBuf>,
+
+    /// Translation produced for --previous-source, reused for unchanged segments
+    #[arg(long, value_name = "FILE", requires = "previous_source")]
+    pub previous_translation: Option<PathBuf>,
+
+    /// Send consecutive segments together, up to MAX_INPUT_TOKENS per request
+    #[arg(long)]
+    pub batch: bool,
+
+    /// Reflow translated paragraphs to WIDTH display columns (CJK counts as two);
+    /// code blocks, tables and headings are left alone
+    #[arg(long, value_name = "WIDTH", value_parser = clap::value_parser!(usize))]
+    pub wrap: Option<usize>,
+
+    /// Comment text that keeps the next block untranslated, e.g. "i18n: skip"
+    /// for `<!-- i18n: skip -->` (repeatable; replaces the default)
+    #[arg(long = "skip-directive", value_name = "TEXT")]
+    pub skip_directives: Vec<String>,
+
+    /// Frontmatter key to translate, a dotted path for nested keys such as
+    /// "seo.excerpt" (repeatable; replaces title, description, summary, name and alt)
+    #[arg(long = "frontmatter-field", value_name = "KEY")]
+    pub frontmatter_fields: Vec<String>,
+
+    /// JSON glossary of forced translations ("terms") and "do_not_translate" terms
+    #[arg(long, value_name = "FILE")]
+    pub glossary: Option<PathBuf>,
+
+    /// Write all translated files, in sorted order, into this one document
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["review", "previous_source"])]
+    pub combine: Option<PathBuf>,
+
+    /// Markdown put between files with --combine
+    #[arg(long, value_name = "TEXT", default_value = "---", requires = "combine")]
+    pub combine_separator: String,
+
+    /// Start each file with a heading naming it with --combine
+    #[arg(long, requires = "combine")]
+    pub combine_headings: bool,
+
+    /// Record finished files in `.translate-progress.json` in the output
+    /// directory and skip them on the next run while their content is unchanged
+    #[arg(long, conflicts_with = "combine")]
+    pub resume: bool,
+
+    /// With --resume, translate files again even if they were finished
+    #[arg(long, requires = "resume")]
+    pub force: bool,
+
+    /// Only report the files, segments and estimated tokens; no API calls
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Skip files and directories matching GLOB, relative to the input
+    /// directory; a pattern without `/` matches names at any depth (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<glob::Pattern>,
+
+    /// Print a single JSON object (processed, failed, failed_files, elapsed_ms,
+    /// tokens_used) to stdout instead of the human summary
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// Options specific to ePub translation
+#[derive(Args, Debug, Clone, Default)]
+pub struct EpubArgs {
+    /// Leave spine items marked linear="no" (e.g. pop-up notes) untranslated
+    #[arg(long)]
+    pub skip_non_linear: bool,
+
+    /// Keep the original dc:language instead of declaring the target language
+    #[arg(long)]
+    pub keep_source_language: bool,
+
+    /// Keep the book title, description and author names untranslated
+    #[arg(long)]
+    pub keep_metadata: bool,
+
+    /// Only report the files, segments and estimated tokens; no API calls
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Also translate text labels of inline SVG diagrams (may no longer fit the drawing)
+    #[arg(long)]
+    pub svg_text: bool,
+
+    /// Chapters read ahead while another translates (0 = read the whole book first)
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_PIPELINE_DEPTH)]
+    pub pipeline_depth: usize,
+
+    /// JSON glossary of forced translations ("terms") and "do_not_translate" terms
+    #[arg(long, value_name = "FILE")]
+    pub glossary: Option<PathBuf>,
+
+    /// Print a single JSON object (processed, failed, failed_files, elapsed_ms,
+    /// tokens_used) to stdout instead of the human summary
+    #[arg(long)]
+    pub json: bool,
+}
 
 /// Commands for Doubao Batch Translator
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     /// Translate Markdown files
     Md {
-        /// Input file or directory (required)
-        #[arg(short, long)]
-        file: PathBuf,
+        /// Input file or directory (required unless --stdin)
+        #[arg(short, long, required_unless_present = "stdin")]
+        file: Option<PathBuf>,
+
+        /// Translate standard input to standard output instead of files
+        #[arg(long, conflicts_with_all = ["file", "output", "recursive"])]
+        stdin: bool,
 
         /// Output file or directory
         #[arg(short, long)]
@@ -27,6 +193,14 @@ pub enum Commands {
         /// Recursively translate subdirectories
         #[arg(short, long)]
         recursive: bool,
+
+        /// Markdown options
+        #[command(flatten)]
+        md: MdArgs,
+
+        /// Batch job options
+        #[command(flatten)]
+        job: JobArgs,
     },
 
     /// Translate ePub files
@@ -50,6 +224,103 @@ pub enum Commands {
         /// Auto-approve translations
         #[arg(long)]
         auto_approve: bool,
+
+        /// ePub options
+        #[command(flatten)]
+        epub: EpubArgs,
+
+        /// Batch job options
+        #[command(flatten)]
+        job: JobArgs,
+    },
+
+    /// Translate standalone HTML files
+    Html {
+        /// Input file or directory (required)
+        #[arg(short, long)]
+        file: PathBuf,
+
+        /// Output file or directory
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Source language (auto-detect if not specified)
+        #[arg(long)]
+        source_lang: Option<String>,
+
+        /// Target language (default: zh)
+        #[arg(short, long, default_value = "zh")]
+        target_lang: String,
+
+        /// Recursively translate subdirectories
+        #[arg(short, long)]
+        recursive: bool,
+
+        /// Attributes whose values are translated (comma-separated, empty for none)
+        #[arg(long, value_delimiter = ',', default_value = "alt,title,aria-label,placeholder")]
+        attributes: Vec<String>,
+
+        /// Batch job options
+        #[command(flatten)]
+        job: JobArgs,
+    },
+
+    /// Translate SRT subtitle files
+    Srt {
+        /// Input file or directory (required)
+        #[arg(short, long)]
+        file: PathBuf,
+
+        /// Output file or directory
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Source language (auto-detect if not specified)
+        #[arg(long)]
+        source_lang: Option<String>,
+
+        /// Target language (default: zh)
+        #[arg(short, long, default_value = "zh")]
+        target_lang: String,
+
+        /// Recursively translate subdirectories
+        #[arg(short, long)]
+        recursive: bool,
+
+        /// Batch job options
+        #[command(flatten)]
+        job: JobArgs,
+    },
+
+    /// Translate plain-text files paragraph by paragraph
+    Txt {
+        /// Input file or directory (required unless --stdin)
+        #[arg(short, long, required_unless_present = "stdin")]
+        file: Option<PathBuf>,
+
+        /// Translate standard input to standard output instead of files
+        #[arg(long, conflicts_with_all = ["file", "output", "recursive"])]
+        stdin: bool,
+
+        /// Output file or directory
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Source language (auto-detect if not specified)
+        #[arg(long)]
+        source_lang: Option<String>,
+
+        /// Target language (default: zh)
+        #[arg(short, long, default_value = "zh")]
+        target_lang: String,
+
+        /// Recursively translate subdirectories
+        #[arg(short, long)]
+        recursive: bool,
+
+        /// Batch job options
+        #[command(flatten)]
+        job: JobArgs,
     },
 
     /// Start HTTP API server
@@ -72,31 +343,112 @@ pub enum Commands {
         /// Directory containing translated ePub files
         #[arg(short, long)]
         dir: PathBuf,
+
+        /// Language the books were translated into (default: zh)
+        #[arg(short, long, default_value = "zh")]
+        target_lang: String,
+
+        /// Share of untranslated words above which a chapter is flagged
+        #[arg(long, value_name = "RATIO", default_value_t = DEFAULT_UNTRANSLATED_RATIO)]
+        threshold: f64,
     },
 
-    /// Apply manual fixes from JSON file
+    /// Apply manual fixes from a fix-up JSON file (--fixup job or ePub leak report)
     ApplyFix {
         /// Path to JSON file with manual translations
         #[arg(short, long)]
         json: PathBuf,
     },
+
+    /// Diagnose common setup problems
+    Doctor {
+        /// Skip the test request to the API endpoint
+        #[arg(long)]
+        offline: bool,
+    },
+
+    /// Reassemble a Markdown document from an edited review file
+    ApplyReview {
+        /// Review file written by `md --review`
+        #[arg(short, long)]
+        review: PathBuf,
+
+        /// Output file
+        #[arg(short, long)]
+        output: PathBuf,
+    },
 }
 
 /// Handle Markdown translation command
+#[allow(clippy::too_many_arguments)]
 pub async fn handle_md(
-    file: PathBuf,
+    config: TranslatorConfig,
+    file: Option<PathBuf>,
     output: Option<PathBuf>,
     source_lang: Option<String>,
     target_lang: String,
     recursive: bool,
+    md: MdArgs,
+    job: JobArgs,
 ) -> anyhow::Result<()> {
-    use crate::processors::markdown::MarkdownProcessor;
+    use crate::cli::checkpoint::{Checkpoint, CHECKPOINT_FILE};
+    use crate::cli::confirm::resolve_source_lang;
+    use crate::cli::runner::{
+        json_summary, order_files, record_diagnostics, report_diagnostics, report_plans,
+        run_files, save_fixups, translate_stream,
+    };
+    use crate::core::client::AsyncTranslator;
+    use crate::processors::markdown::{CombineOptions, MarkdownProcessor, SegmentStatus};
     use indicatif::{ProgressBar, ProgressStyle};
     use std::time::Instant;
-    use tracing::info;
+    use tracing::{info, warn};
 
     let start_time = Instant::now();
 
+    // Create processor
+    let mut config = config;
+    if let Some(path) = &md.glossary {
+        config.glossary = Glossary::load(path)?;
+    }
+    // A dry run makes no API calls, so it needs no API key
+    let translator = if md.dry_run {
+        AsyncTranslator::offline(config)?
+    } else {
+        AsyncTranslator::new(config)?
+    };
+    let processor = MarkdownProcessor::new(translator.clone())
+        .with_schedule(job.schedule)
+        .with_batching(md.batch)
+        .with_sample(job.sample)
+        .with_wrap(md.wrap);
+    let processor = if md.skip_directives.is_empty() {
+        processor
+    } else {
+        processor.with_skip_directives(md.skip_directives)
+    };
+    let processor = if md.frontmatter_fields.is_empty() {
+        processor
+    } else {
+        processor.with_frontmatter_fields(md.frontmatter_fields)
+    };
+
+    // --stdin: translate standard input to standard output
+    let Some(file) = file else {
+        let stdin = std::path::Path::new("<stdin>");
+        translate_stream(tokio::io::stdin(), tokio::io::stdout(), |content| async move {
+            let (translated, outcomes) = processor
+                .translate_content_report(stdin, &content, &target_lang, source_lang)
+                .await?;
+            let failed = outcomes.iter().filter(|o| o.status == SegmentStatus::Failed).count();
+            if failed > 0 {
+                eprintln!("{} of {} segments failed, kept untranslated", failed, outcomes.len());
+            }
+            Ok(translated)
+        })
+        .await?;
+        return Ok(());
+    };
+
     // Determine output path
     let output = output.unwrap_or_else(|| {
         if file.is_dir() {
@@ -115,24 +467,90 @@ pub async fn handle_md(
     info!("Output: {}", output.display());
     info!("Target language: {}", target_lang);
     info!("Recursive: {}", recursive);
-
-    // Create processor
-    let processor = MarkdownProcessor::from_env()?;
+    info!("Review: {}", md.review);
 
     // Find files
-    let files = if file.is_dir() {
+    let input_is_dir = file.is_dir();
+    let files = if input_is_dir {
         if recursive {
-            processor.find_files_recursive(&file)?
+            processor.find_files_recursive(&file, &md.exclude)?
         } else {
-            processor.find_files(&file)?
+            processor.find_files(&file, &md.exclude)?
         }
     } else {
-        vec![file]
+        vec![file.clone()]
     };
 
     if files.is_empty() {
         anyhow::bail!("No Markdown files found");
     }
+    if md.dry_run {
+        let mut plans = Vec::with_capacity(files.len());
+        for file in &files {
+            plans.push(processor.plan(file).await?);
+        }
+        report_plans(&plans);
+        return Ok(());
+    }
+    let source_lang = resolve_source_lang(&files, source_lang, &job)?;
+    let review = md.review;
+    let previous = md.previous_source.zip(md.previous_translation);
+    if previous.is_some() && (input_is_dir || review) {
+        anyhow::bail!("--previous-source works on a single file and without --review");
+    }
+    if let Some(combined) = md.combine {
+        let options = CombineOptions {
+            separator: md.combine_separator,
+            headings: md.combine_headings,
+        };
+        processor
+            .translate_combined(&files, &combined, &target_lang, source_lang, &options)
+            .await?;
+        println!("\n✅ Combined {} files into {}", files.len(), combined.display());
+        println!("   Time: {:?}", start_time.elapsed());
+        return Ok(());
+    }
+    let files = order_files(files, job.schedule);
+
+    // --resume: skip files finished in an earlier run and unchanged since
+    let checkpoint = if md.resume {
+        let dir = if input_is_dir {
+            output.as_path()
+        } else {
+            output.parent().unwrap_or(std::path::Path::new("."))
+        };
+        std::fs::create_dir_all(dir)?;
+        let mode = if previous.is_some() {
+            "incremental"
+        } else if review {
+            "review"
+        } else {
+            "translate"
+        };
+        Some(Checkpoint::load(&dir.join(CHECKPOINT_FILE), &target_lang, mode)?)
+    } else {
+        None
+    };
+    let files = match &checkpoint {
+        Some(checkpoint) if !md.force => {
+            let (mut done, mut todo) = (Vec::new(), Vec::new());
+            for file in files {
+                if checkpoint.is_done(&file).await {
+                    done.push(file);
+                } else {
+                    todo.push(file);
+                }
+            }
+            if !done.is_empty() && !md.json {
+                println!(
+                    "   Skipping {} files finished in an earlier run (--force to redo them)",
+                    done.len()
+                );
+            }
+            todo
+        }
+        _ => files,
+    };
 
     // Create progress bar
     let pb = ProgressBar::new(files.len() as u64);
@@ -142,27 +560,55 @@ pub async fn handle_md(
         .progress_chars("=>-"));
 
     // Process files
-    let mut processed = 0;
-    let mut failed = 0;
-
-    for file_path in files {
-        pb.set_message(format!("Processing: {}", file_path.display()));
-
-        match processor
-            .translate_file(&file_path, &output, &target_lang, source_lang.clone())
-            .await
-        {
-            Ok(_) => {
-                processed += 1;
-                pb.inc(1);
-            }
-            Err(e) => {
-                failed += 1;
-                pb.set_message(format!("Failed: {} - {}", file_path.display(), e));
-                eprintln!("Error processing {}: {}", file_path.display(), e);
+    let tokens_before = translator.get_token_usage().await.used_today;
+    let timeout = job.per_file_timeout();
+    let summary = run_files(files, job.file_concurrency, timeout, &pb, |file_path| {
+        let processor = &processor;
+        let output = &output;
+        let target_lang = &target_lang;
+        let source_lang = source_lang.clone();
+        let previous = previous.as_ref();
+        let checkpoint = checkpoint.as_ref();
+        let destination = if input_is_dir {
+            output.join(file_path.strip_prefix(&file).unwrap_or(&file_path))
+        } else {
+            output.clone()
+        };
+        async move {
+            let result = if let Some((previous_source, previous_translation)) = previous {
+                processor
+                    .translate_file_incremental(
+                        &file_path,
+                        output,
+                        previous_source,
+                        previous_translation,
+                        target_lang,
+                        source_lang,
+                    )
+                    .await
+                    .map(|_| ())
+            } else if review {
+                let review_path = review_path_for(&destination);
+                processor
+                    .export_review(&file_path, &review_path, target_lang, source_lang)
+                    .await
+                    .map(|_| ())
+            } else {
+                processor
+                    .translate_file(&file_path, &destination, target_lang, source_lang)
+                    .await
+            };
+            if let (Ok(()), Some(checkpoint)) = (&result, checkpoint) {
+                // The file is written; only resuming would translate it again
+                if let Err(e) = checkpoint.mark_done(&file_path).await {
+                    warn!("Failed to record {} in the checkpoint: {}", file_path.display(), e);
+                }
             }
+            result
         }
-    }
+    })
+    .await;
+    let (processed, failed) = (summary.processed, summary.failed);
 
     pb.finish_with_message("Completed");
 
@@ -172,23 +618,369 @@ pub async fn handle_md(
         processed, failed, duration
     );
 
+    if md.json {
+        record_diagnostics(processor.diagnostics(), &summary, job.diagnostics.as_deref()).await?;
+        if let Some(path) = &job.fixup {
+            processor.fixups().job().save(path).await?;
+        }
+        let tokens_used = translator.get_token_usage().await.used_today;
+        let tokens_used = tokens_used.saturating_sub(tokens_before);
+        println!("{}", json_summary(&summary, duration, tokens_used));
+        return Ok(());
+    }
+
     println!("\n✅ Translation completed!");
     println!("   Processed: {}", processed);
     println!("   Failed: {}", failed);
     println!("   Time: {:?}", duration);
+    if let Some(n) = job.sample {
+        println!(
+            "   ⚠️  Partial sample: only the first {} segments of each file were translated",
+            n
+        );
+    }
+    report_diagnostics(processor.diagnostics(), &summary, job.diagnostics.as_deref()).await?;
+    save_fixups(processor.fixups(), job.fixup.as_deref()).await?;
+
+    Ok(())
+}
+
+/// Handle HTML translation command
+#[allow(clippy::too_many_arguments)]
+pub async fn handle_html(
+    config: TranslatorConfig,
+    file: PathBuf,
+    output: Option<PathBuf>,
+    source_lang: Option<String>,
+    target_lang: String,
+    recursive: bool,
+    attributes: Vec<String>,
+    job: JobArgs,
+) -> anyhow::Result<()> {
+    use crate::cli::confirm::resolve_source_lang;
+    use crate::cli::runner::{order_files, report_diagnostics, run_files, save_fixups};
+    use crate::processors::html::HtmlProcessor;
+    use crate::utils::progress::create_progress_bar;
+    use std::time::Instant;
+    use tracing::info;
+
+    let start_time = Instant::now();
+
+    // Determine output path
+    let output = output.unwrap_or_else(|| {
+        if file.is_dir() {
+            file.join("translated")
+        } else {
+            let mut out = file.clone();
+            let mut filename = file.file_name().unwrap().to_os_string();
+            filename.push("_translated");
+            out.set_file_name(filename);
+            out
+        }
+    });
+
+    info!("Starting HTML translation");
+    info!("Input: {}", file.display());
+    info!("Output: {}", output.display());
+    info!("Target language: {}", target_lang);
+    info!("Attributes: {}", attributes.join(","));
+
+    let processor = HtmlProcessor::from_config(config)?
+        .with_attributes(&attributes)
+        .with_sample(job.sample);
+
+    // Find files, mirroring the input layout under the output directory
+    let input_is_dir = file.is_dir();
+    let files = if input_is_dir {
+        processor.find_files(&file, recursive)?
+    } else {
+        vec![file.clone()]
+    };
+
+    if files.is_empty() {
+        anyhow::bail!("No HTML files found");
+    }
+    let source_lang = resolve_source_lang(&files, source_lang, &job)?;
+    let files = order_files(files, job.schedule);
+
+    let pb = create_progress_bar(files.len() as u64);
+
+    let timeout = job.per_file_timeout();
+    let summary = run_files(files, job.file_concurrency, timeout, &pb, |file_path| {
+        let processor = &processor;
+        let target_lang = &target_lang;
+        let source_lang = source_lang.clone();
+        let destination = if input_is_dir {
+            output.join(file_path.strip_prefix(&file).unwrap_or(&file_path))
+        } else {
+            output.clone()
+        };
+        async move {
+            processor
+                .translate_file(&file_path, &destination, target_lang, source_lang)
+                .await
+        }
+    })
+    .await;
+
+    pb.finish_with_message("Completed");
+
+    let duration = start_time.elapsed();
+    info!(
+        "Completed: {} processed, {} failed in {:?}",
+        summary.processed, summary.failed, duration
+    );
+
+    println!("\n✅ HTML translation completed!");
+    println!("   Processed: {}", summary.processed);
+    println!("   Failed: {}", summary.failed);
+    println!("   Time: {:?}", duration);
+    if let Some(n) = job.sample {
+        println!(
+            "   ⚠️  Partial sample: only the first {} segments of each file were translated",
+            n
+        );
+    }
+    report_diagnostics(processor.diagnostics(), &summary, job.diagnostics.as_deref()).await?;
+    save_fixups(processor.fixups(), job.fixup.as_deref()).await?;
+
+    Ok(())
+}
+
+/// Handle SRT subtitle translation command
+pub async fn handle_srt(
+    config: TranslatorConfig,
+    file: PathBuf,
+    output: Option<PathBuf>,
+    source_lang: Option<String>,
+    target_lang: String,
+    recursive: bool,
+    job: JobArgs,
+) -> anyhow::Result<()> {
+    use crate::cli::confirm::resolve_source_lang;
+    use crate::cli::runner::{order_files, report_diagnostics, run_files, save_fixups};
+    use crate::processors::subtitle::SubtitleProcessor;
+    use crate::utils::progress::create_progress_bar;
+    use std::time::Instant;
+    use tracing::info;
+
+    let start_time = Instant::now();
+
+    // Determine output path
+    let output = output.unwrap_or_else(|| {
+        if file.is_dir() {
+            file.join("translated")
+        } else {
+            let mut out = file.clone();
+            let mut filename = file.file_name().unwrap().to_os_string();
+            filename.push("_translated");
+            out.set_file_name(filename);
+            out
+        }
+    });
+
+    info!("Starting subtitle translation");
+    info!("Input: {}", file.display());
+    info!("Output: {}", output.display());
+    info!("Target language: {}", target_lang);
+    info!("Recursive: {}", recursive);
+
+    let processor = SubtitleProcessor::from_config(config)?.with_sample(job.sample);
+
+    // Find files, mirroring the input layout under the output directory
+    let input_is_dir = file.is_dir();
+    let files = if input_is_dir {
+        processor.find_files(&file, recursive)?
+    } else {
+        vec![file.clone()]
+    };
+
+    if files.is_empty() {
+        anyhow::bail!("No SRT files found");
+    }
+    let source_lang = resolve_source_lang(&files, source_lang, &job)?;
+    let files = order_files(files, job.schedule);
+
+    let pb = create_progress_bar(files.len() as u64);
+
+    let timeout = job.per_file_timeout();
+    let summary = run_files(files, job.file_concurrency, timeout, &pb, |file_path| {
+        let processor = &processor;
+        let target_lang = &target_lang;
+        let source_lang = source_lang.clone();
+        let destination = if input_is_dir {
+            output.join(file_path.strip_prefix(&file).unwrap_or(&file_path))
+        } else {
+            output.clone()
+        };
+        async move {
+            processor
+                .translate_file(&file_path, &destination, target_lang, source_lang)
+                .await
+        }
+    })
+    .await;
+
+    pb.finish_with_message("Completed");
+
+    let duration = start_time.elapsed();
+    info!(
+        "Completed: {} processed, {} failed in {:?}",
+        summary.processed, summary.failed, duration
+    );
+
+    println!("\n✅ Subtitle translation completed!");
+    println!("   Processed: {}", summary.processed);
+    println!("   Failed: {}", summary.failed);
+    println!("   Time: {:?}", duration);
+    if let Some(n) = job.sample {
+        println!(
+            "   ⚠️  Partial sample: only the first {} cues of each file were translated",
+            n
+        );
+    }
+    report_diagnostics(processor.diagnostics(), &summary, job.diagnostics.as_deref()).await?;
+    save_fixups(processor.fixups(), job.fixup.as_deref()).await?;
+
+    Ok(())
+}
+
+/// Handle plain-text translation command
+pub async fn handle_txt(
+    config: TranslatorConfig,
+    file: Option<PathBuf>,
+    output: Option<PathBuf>,
+    source_lang: Option<String>,
+    target_lang: String,
+    recursive: bool,
+    job: JobArgs,
+) -> anyhow::Result<()> {
+    use crate::cli::confirm::resolve_source_lang;
+    use crate::cli::runner::{
+        order_files, report_diagnostics, run_files, save_fixups, translate_stream,
+    };
+    use crate::processors::text::TextProcessor;
+    use crate::utils::progress::create_progress_bar;
+    use std::time::Instant;
+    use tracing::info;
+
+    let start_time = Instant::now();
+    let processor = TextProcessor::from_config(config)?.with_sample(job.sample);
+
+    // --stdin: translate standard input to standard output
+    let Some(file) = file else {
+        let stdin = std::path::Path::new("<stdin>");
+        translate_stream(tokio::io::stdin(), tokio::io::stdout(), |content| async move {
+            processor.translate_content(stdin, &content, &target_lang, source_lang).await
+        })
+        .await?;
+        return Ok(());
+    };
+
+    // Determine output path
+    let output = output.unwrap_or_else(|| {
+        if file.is_dir() {
+            file.join("translated")
+        } else {
+            let mut out = file.clone();
+            let mut filename = file.file_name().unwrap().to_os_string();
+            filename.push("_translated");
+            out.set_file_name(filename);
+            out
+        }
+    });
+
+    info!("Starting text translation");
+    info!("Input: {}", file.display());
+    info!("Output: {}", output.display());
+    info!("Target language: {}", target_lang);
+    info!("Recursive: {}", recursive);
+
+    // Find files, mirroring the input layout under the output directory
+    let input_is_dir = file.is_dir();
+    let files = if input_is_dir {
+        processor.find_files(&file, recursive)?
+    } else {
+        vec![file.clone()]
+    };
+
+    if files.is_empty() {
+        anyhow::bail!("No text files found");
+    }
+    let source_lang = resolve_source_lang(&files, source_lang, &job)?;
+    let files = order_files(files, job.schedule);
+
+    let pb = create_progress_bar(files.len() as u64);
+
+    let timeout = job.per_file_timeout();
+    let summary = run_files(files, job.file_concurrency, timeout, &pb, |file_path| {
+        let processor = &processor;
+        let target_lang = &target_lang;
+        let source_lang = source_lang.clone();
+        let destination = if input_is_dir {
+            output.join(file_path.strip_prefix(&file).unwrap_or(&file_path))
+        } else {
+            output.clone()
+        };
+        async move {
+            processor
+                .translate_file(&file_path, &destination, target_lang, source_lang)
+                .await
+        }
+    })
+    .await;
+
+    pb.finish_with_message("Completed");
+
+    let duration = start_time.elapsed();
+    info!(
+        "Completed: {} processed, {} failed in {:?}",
+        summary.processed, summary.failed, duration
+    );
+
+    println!("\n✅ Text translation completed!");
+    println!("   Processed: {}", summary.processed);
+    println!("   Failed: {}", summary.failed);
+    println!("   Time: {:?}", duration);
+    if let Some(n) = job.sample {
+        println!(
+            "   ⚠️  Partial sample: only the first {} paragraphs of each file were translated",
+            n
+        );
+    }
+    report_diagnostics(processor.diagnostics(), &summary, job.diagnostics.as_deref()).await?;
+    save_fixups(processor.fixups(), job.fixup.as_deref()).await?;
 
     Ok(())
 }
 
+/// Review file path next to `destination`, the file's translated output; for
+/// directory jobs that mirrors the file's path under the input root
+fn review_path_for(destination: &std::path::Path) -> PathBuf {
+    let mut name = destination.file_name().unwrap_or_default().to_os_string();
+    name.push(".review.json");
+    destination.with_file_name(name)
+}
+
 /// Handle ePub translation command
+#[allow(clippy::too_many_arguments)]
 pub async fn handle_epub(
+    config: TranslatorConfig,
     file: PathBuf,
     output: PathBuf,
     source_lang: Option<String>,
     target_lang: String,
     auto_approve: bool,
+    epub: EpubArgs,
+    job: JobArgs,
 ) -> anyhow::Result<()> {
-    use crate::processors::epub::EpubProcessor;
+    use crate::cli::confirm::resolve_source_lang;
+    use crate::cli::runner::{
+        json_summary, order_files, record_diagnostics, report_diagnostics, report_plans,
+        run_files, save_fixups,
+    };
+    use crate::core::client::AsyncTranslator;
+    use crate::processors::epub::{EpubProcessor, LEAK_REPORT_JSON, LEAK_REPORT_SUMMARY};
     use indicatif::{ProgressBar, ProgressStyle};
     use std::time::Instant;
     use tracing::info;
@@ -202,10 +994,27 @@ pub async fn handle_epub(
     info!("Auto-approve: {}", auto_approve);
 
     // Create processor
-    let processor = EpubProcessor::from_env()?;
+    let mut config = config;
+    if let Some(path) = &epub.glossary {
+        config.glossary = Glossary::load(path)?;
+    }
+    // A dry run makes no API calls, so it needs no API key
+    let translator = if epub.dry_run {
+        AsyncTranslator::offline(config)?
+    } else {
+        AsyncTranslator::new(config)?
+    };
+    let processor = EpubProcessor::new(translator.clone())
+        .with_skip_non_linear(epub.skip_non_linear)
+        .with_update_language(!epub.keep_source_language)
+        .with_translate_metadata(!epub.keep_metadata)
+        .with_pipeline_depth(epub.pipeline_depth)
+        .with_svg_text(epub.svg_text)
+        .with_sample(job.sample);
 
     // Find files
-    let files = if file.is_dir() {
+    let input_is_dir = file.is_dir();
+    let files = if input_is_dir {
         processor.find_epub_files(&file)?
     } else {
         vec![file]
@@ -214,6 +1023,20 @@ pub async fn handle_epub(
     if files.is_empty() {
         anyhow::bail!("No ePub files found");
     }
+    if epub.dry_run {
+        let mut plans = Vec::with_capacity(files.len());
+        for file in &files {
+            plans.push(processor.plan(file).await?);
+        }
+        report_plans(&plans);
+        return Ok(());
+    }
+    let source_lang = resolve_source_lang(&files, source_lang, &job)?;
+    // Several books cannot share one output file: write them into a directory
+    if input_is_dir {
+        std::fs::create_dir_all(&output)?;
+    }
+    let files = order_files(files, job.schedule);
 
     // Create progress bar
     let pb = ProgressBar::new(files.len() as u64);
@@ -223,27 +1046,21 @@ pub async fn handle_epub(
         .progress_chars("=>-"));
 
     // Process files
-    let mut processed = 0;
-    let mut failed = 0;
-
-    for file_path in files {
-        pb.set_message(format!("Processing: {}", file_path.display()));
-
-        match processor
-            .translate_epub(&file_path, &output, &target_lang, source_lang.clone(), auto_approve)
-            .await
-        {
-            Ok(_) => {
-                processed += 1;
-                pb.inc(1);
-            }
-            Err(e) => {
-                failed += 1;
-                pb.set_message(format!("Failed: {} - {}", file_path.display(), e));
-                eprintln!("Error processing {}: {}", file_path.display(), e);
-            }
+    let tokens_before = translator.get_token_usage().await.used_today;
+    let timeout = job.per_file_timeout();
+    let summary = run_files(files, job.file_concurrency, timeout, &pb, |file_path| {
+        let processor = &processor;
+        let output = &output;
+        let target_lang = &target_lang;
+        let source_lang = source_lang.clone();
+        async move {
+            processor
+                .translate_epub(&file_path, output, target_lang, source_lang, auto_approve)
+                .await
         }
-    }
+    })
+    .await;
+    let (processed, failed) = (summary.processed, summary.failed);
 
     pb.finish_with_message("Completed");
 
@@ -253,25 +1070,60 @@ pub async fn handle_epub(
         processed, failed, duration
     );
 
+    // --fixup needs the leak report even when translations are auto-approved
+    let leak_report = !auto_approve || job.fixup.is_some();
+    if epub.json {
+        record_diagnostics(processor.diagnostics(), &summary, job.diagnostics.as_deref()).await?;
+        if leak_report {
+            if let Err(e) = processor.generate_leak_report(&output, &target_lang).await {
+                eprintln!("Failed to generate leak report: {}", e);
+            }
+        }
+        if let Some(path) = &job.fixup {
+            processor.fixups().job().save(path).await?;
+        }
+        let tokens_used = translator.get_token_usage().await.used_today;
+        let tokens_used = tokens_used.saturating_sub(tokens_before);
+        println!("{}", json_summary(&summary, duration, tokens_used));
+        return Ok(());
+    }
+
     println!("\n✅ ePub translation completed!");
     println!("   Processed: {}", processed);
     println!("   Failed: {}", failed);
     println!("   Time: {:?}", duration);
+    if let Some(n) = job.sample {
+        println!(
+            "   ⚠️  Partial sample: only the first {} segments of each file were translated",
+            n
+        );
+    }
+    report_diagnostics(processor.diagnostics(), &summary, job.diagnostics.as_deref()).await?;
 
     // Generate leak report if not auto-approve
-    if !auto_approve {
+    if leak_report {
         println!("\n📝 Generating leak report...");
         match processor.generate_leak_report(&output, &target_lang).await {
-            Ok(_) => println!("   Leak report generated successfully"),
+            Ok(0) => println!("   No untranslated content found"),
+            Ok(leaks) => println!(
+                "   ⚠️  {} untranslated snippets, see {} (edit {} and run apply-fix)",
+                leaks, LEAK_REPORT_SUMMARY, LEAK_REPORT_JSON
+            ),
             Err(e) => eprintln!("   Failed to generate leak report: {}", e),
         }
     }
+    save_fixups(processor.fixups(), job.fixup.as_deref()).await?;
 
     Ok(())
 }
 
 /// Handle server command
-pub async fn handle_server(host: String, port: u16, debug: bool) -> anyhow::Result<()> {
+pub async fn handle_server(
+    config: TranslatorConfig,
+    host: String,
+    port: u16,
+    debug: bool,
+) -> anyhow::Result<()> {
     use crate::server::api::run_server;
     use tracing::info;
 
@@ -284,20 +1136,25 @@ pub async fn handle_server(host: String, port: u16, debug: bool) -> anyhow::Resu
     println!("📊 API Documentation: http://{}:{}/swagger", host, port);
     println!("📄 ReDoc Documentation: http://{}:{}/redoc", host, port);
 
-    run_server(host, port).await?;
+    run_server(config, host, port, debug).await?;
 
     Ok(())
 }
 
 /// Handle check untranslated command
-pub async fn handle_check_untranslated(dir: PathBuf) -> anyhow::Result<()> {
+pub async fn handle_check_untranslated(
+    config: TranslatorConfig,
+    dir: PathBuf,
+    target_lang: String,
+    threshold: f64,
+) -> anyhow::Result<()> {
     use crate::processors::epub::EpubProcessor;
     use tracing::info;
 
     info!("Checking for untranslated content in: {}", dir.display());
 
-    let processor = EpubProcessor::from_env()?;
-    let leaks = processor.check_untranslated(&dir).await?;
+    let processor = EpubProcessor::from_config(config)?.with_untranslated_threshold(threshold);
+    let leaks = processor.check_untranslated(&dir, &target_lang).await?;
 
     if leaks.is_empty() {
         println!("✅ No untranslated content found!");
@@ -309,6 +1166,7 @@ pub async fn handle_check_untranslated(dir: PathBuf) -> anyhow::Result<()> {
     for (i, leak) in leaks.iter().enumerate() {
         println!("\n{}. Book: {}", i + 1, leak.book_name);
         println!("   File: {}", leak.file_path);
+        println!("   Chapter: {}", leak.chapter);
         println!("   Original: {}", leak.original);
     }
 
@@ -324,15 +1182,163 @@ pub async fn handle_check_untranslated(dir: PathBuf) -> anyhow::Result<()> {
 
 /// Handle apply fix command
 pub async fn handle_apply_fix(json: PathBuf) -> anyhow::Result<()> {
-    use crate::processors::epub::EpubProcessor;
+    use crate::processors::fixup::FixupJob;
     use tracing::info;
 
     info!("Applying manual fixes from: {}", json.display());
 
-    let processor = EpubProcessor::from_env()?;
-    let count = processor.apply_fixes(&json).await?;
+    let summary = FixupJob::load(&json).await?.apply().await?;
 
-    println!("✅ Applied {} translations from {}", count, json.display());
+    println!("✅ Applied {} translations from {}", summary.applied, json.display());
+    if summary.skipped > 0 {
+        println!("   Skipped {} entries (no translation, or text not found)", summary.skipped);
+    }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Handle apply review command
+pub async fn handle_apply_review(review: PathBuf, output: PathBuf) -> anyhow::Result<()> {
+    use crate::processors::markdown::MarkdownProcessor;
+    use tracing::info;
+
+    info!("Applying review from: {}", review.display());
+
+    MarkdownProcessor::apply_review(&review, &output).await?;
+
+    println!("✅ Wrote reviewed translation to {}", output.display());
+
+    Ok(())
+}
+
+/// Handle doctor command
+pub async fn handle_doctor(offline: bool) -> anyhow::Result<()> {
+    use crate::cli::doctor::{diagnose, print_checks, CheckStatus};
+
+    let work_dir = std::env::current_dir()?;
+    let checks = diagnose(|name| std::env::var(name).ok(), &work_dir, !offline).await;
+
+    println!("🩺 Doubao Translator doctor\n");
+    print_checks(&checks);
+
+    let failed = checks
+        .iter()
+        .filter(|c| c.status == CheckStatus::Fail)
+        .count();
+    if failed > 0 {
+        anyhow::bail!("{} check(s) failed", failed);
+    }
+
+    println!("\n✅ All checks passed");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_md_folder_job_resumes_into_mirrored_paths() {
+        use crate::testing::{echo_translation, test_config, MockReply, MockServer};
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("docs");
+        let output = temp_dir.path().join("out");
+        std::fs::create_dir_all(input.join("guide")).unwrap();
+        std::fs::write(input.join("intro.md"), "First file\n").unwrap();
+        std::fs::write(input.join("guide/setup.md"), "Second file\n").unwrap();
+
+        let md = MdArgs {
+            resume: true,
+            ..Default::default()
+        };
+        let job = JobArgs {
+            file_concurrency: 1,
+            per_file_timeout: Some(1),
+            ..Default::default()
+        };
+        let run = |url: String| {
+            handle_md(
+                test_config(&url),
+                Some(input.clone()),
+                Some(output.clone()),
+                Some("en".to_string()),
+                "zh".to_string(),
+                true,
+                md.clone(),
+                job.clone(),
+            )
+        };
+
+        // The second file hangs past its timeout, interrupting the job there
+        let server = MockServer::start(|req| {
+            let texts: Vec<String> =
+                req.input_texts().iter().map(|t| echo_translation("zh", t)).collect();
+            let reply = MockReply::translations(&texts);
+            if req.input_texts().iter().any(|t| t.contains("Second")) {
+                reply.with_delay(Duration::from_secs(5))
+            } else {
+                reply
+            }
+        })
+        .await;
+        run(server.url.clone()).await.unwrap();
+        assert!(output.join("intro.md").exists());
+        assert!(!output.join("guide/setup.md").exists());
+
+        let server = MockServer::start(|req| {
+            let texts: Vec<String> =
+                req.input_texts().iter().map(|t| echo_translation("zh", t)).collect();
+            MockReply::translations(&texts)
+        })
+        .await;
+        run(server.url.clone()).await.unwrap();
+
+        assert_eq!(server.sent_texts(), vec!["Second file".to_string()]);
+        let intro = std::fs::read_to_string(output.join("intro.md")).unwrap();
+        let setup = std::fs::read_to_string(output.join("guide/setup.md")).unwrap();
+        assert!(intro.contains("zh:First file"));
+        assert!(setup.contains("zh:Second file"));
+    }
+
+    #[tokio::test]
+    async fn test_md_folder_review_files_mirror_input_paths() {
+        use crate::testing::{test_config, MockServer};
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("docs");
+        let output = temp_dir.path().join("out");
+        for dir in ["en", "fr"] {
+            std::fs::create_dir_all(input.join(dir)).unwrap();
+            std::fs::write(input.join(dir).join("index.md"), format!("Page {}\n", dir)).unwrap();
+        }
+
+        let server = MockServer::echo().await;
+        let md = MdArgs {
+            review: true,
+            ..Default::default()
+        };
+        let job = JobArgs {
+            file_concurrency: 1,
+            ..Default::default()
+        };
+        handle_md(
+            test_config(&server.url),
+            Some(input),
+            Some(output.clone()),
+            Some("en".to_string()),
+            "zh".to_string(),
+            true,
+            md,
+            job,
+        )
+        .await
+        .unwrap();
+
+        // Files sharing a name in different folders get a review file each
+        for dir in ["en", "fr"] {
+            let review = std::fs::read_to_string(output.join(dir).join("index.md.review.json"));
+            assert!(review.unwrap().contains(&format!("Page {}", dir)));
+        }
+    }
+}
@@ -0,0 +1,225 @@
+//! Confirming an auto-detected source language before a job spends tokens
+
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use crate::cli::commands::JobArgs;
+use crate::utils::lang::{guess_language, LanguageGuess};
+
+/// Leading segments of the first file the source language is guessed from
+pub const SAMPLE_SEGMENTS: usize = 3;
+
+/// Below this confidence the detected language is asked about
+pub const LOW_CONFIDENCE: f64 = 0.5;
+
+/// Longest sample shown in the prompt, in characters
+const PREVIEW_CHARS: usize = 60;
+
+/// What to do after detecting the source language
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SourceDecision {
+    /// Translate from this language (`None`: let the API detect it)
+    Proceed(Option<String>),
+    /// Stop before translating anything
+    Abort,
+}
+
+/// Guess the source language of `samples` and, when the guess is unsure
+/// and `interactive`, ask on `output` whether to proceed, abort or use
+/// another language.
+///
+/// Without a prompt the job proceeds with the inferred language: the one
+/// most samples are in. With no language to infer it proceeds on `auto`.
+pub fn confirm_source_lang(
+    samples: &[String],
+    interactive: bool,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> std::io::Result<SourceDecision> {
+    let guesses: Vec<(&String, LanguageGuess)> = samples
+        .iter()
+        .filter_map(|sample| guess_language(sample).map(|guess| (sample, guess)))
+        .collect();
+    let Some(inferred) = most_common_language(&guesses) else {
+        return Ok(SourceDecision::Proceed(None));
+    };
+    let confidence = guesses
+        .iter()
+        .map(|(_, guess)| if guess.language == inferred { guess.confidence } else { 0.0 })
+        .sum::<f64>()
+        / guesses.len() as f64;
+    if !interactive || confidence >= LOW_CONFIDENCE {
+        return Ok(SourceDecision::Proceed(Some(inferred)));
+    }
+
+    writeln!(output, "Source language detection is unsure:")?;
+    for (sample, guess) in &guesses {
+        let mut preview: String = sample.chars().take(PREVIEW_CHARS).collect();
+        if preview.len() < sample.len() {
+            preview.push('…');
+        }
+        let percent = guess.confidence * 100.0;
+        writeln!(output, "  [{} {:>3.0}%] {}", guess.language, percent, preview)?;
+    }
+    write!(
+        output,
+        "Translate from '{}'? [Y]es, [n]o to abort, or type a language code: ",
+        inferred
+    )?;
+    output.flush()?;
+
+    let mut answer = String::new();
+    if input.read_line(&mut answer)? == 0 {
+        return Ok(SourceDecision::Abort);
+    }
+    Ok(match answer.trim().to_lowercase().as_str() {
+        "" | "y" | "yes" => SourceDecision::Proceed(Some(inferred)),
+        "n" | "no" | "a" | "abort" => SourceDecision::Abort,
+        code => SourceDecision::Proceed(Some(code.to_string())),
+    })
+}
+
+/// Language most `guesses` agree on; the earliest wins a tie
+fn most_common_language(guesses: &[(&String, LanguageGuess)]) -> Option<String> {
+    let count = |language: &str| {
+        guesses.iter().filter(|(_, guess)| guess.language == language).count()
+    };
+    guesses
+        .iter()
+        .map(|(_, guess)| &guess.language)
+        .rev()
+        .max_by_key(|language| count(language))
+        .cloned()
+}
+
+/// Source language for a job over `files`.
+///
+/// Unless `--from-lang-auto-confirm` is given or a source language was set,
+/// this is `source_lang` unchanged. Otherwise the language of the first file
+/// is guessed and, on a terminal without `--yes`, confirmed with the user.
+pub fn resolve_source_lang(
+    files: &[PathBuf],
+    source_lang: Option<String>,
+    job: &JobArgs,
+) -> anyhow::Result<Option<String>> {
+    let auto = source_lang.as_deref().is_none_or(|lang| lang == "auto");
+    if !job.from_lang_auto_confirm || !auto {
+        return Ok(source_lang);
+    }
+    let Some(first) = files.first() else {
+        return Ok(source_lang);
+    };
+
+    let samples = sample_segments(first, SAMPLE_SEGMENTS)?;
+    let terminal = std::io::stdin().is_terminal() && std::io::stderr().is_terminal();
+    let interactive = !job.yes && terminal;
+    let decision = confirm_source_lang(
+        &samples,
+        interactive,
+        &mut std::io::stdin().lock(),
+        &mut std::io::stderr(),
+    )?;
+    match decision {
+        SourceDecision::Proceed(lang) => Ok(lang),
+        SourceDecision::Abort => anyhow::bail!("Aborted: source language not confirmed"),
+    }
+}
+
+/// The first `count` paragraphs with letters in `path`
+fn sample_segments(path: &Path, count: usize) -> anyhow::Result<Vec<String>> {
+    let is_epub = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("epub"));
+    if is_epub {
+        return Ok(crate::processors::epub::sample_paragraphs(path, count)?);
+    }
+    let content = String::from_utf8_lossy(&std::fs::read(path)?).into_owned();
+    Ok(content
+        .split("\n\n")
+        .map(str::trim)
+        .filter(|paragraph| paragraph.chars().any(char::is_alphabetic))
+        .take(count)
+        .map(String::from)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Short samples the detector is unsure about
+    fn unsure_samples() -> Vec<String> {
+        vec!["Hola amigo".to_string(), "Ciao amigo".to_string()]
+    }
+
+    #[test]
+    fn test_prompt_is_skipped_without_a_terminal() {
+        let samples = unsure_samples();
+        let guess = guess_language(&samples[0]).unwrap();
+        assert!(guess.confidence < LOW_CONFIDENCE, "{:?}", guess);
+
+        let mut output = Vec::new();
+        let decision =
+            confirm_source_lang(&samples, false, &mut std::io::empty(), &mut output).unwrap();
+        assert_eq!(decision, SourceDecision::Proceed(Some(guess.language)));
+        assert!(output.is_empty());
+
+        let decision =
+            confirm_source_lang(&[], false, &mut std::io::empty(), &mut output).unwrap();
+        assert_eq!(decision, SourceDecision::Proceed(None));
+    }
+
+    #[test]
+    fn test_prompt_answers_proceed_abort_or_override() {
+        let samples = unsure_samples();
+        let inferred = guess_language(&samples[0]).unwrap().language;
+        let answer = |reply: &str| {
+            let mut output = Vec::new();
+            let decision =
+                confirm_source_lang(&samples, true, &mut reply.as_bytes(), &mut output).unwrap();
+            (decision, String::from_utf8(output).unwrap())
+        };
+
+        let (decision, prompt) = answer("\n");
+        assert_eq!(decision, SourceDecision::Proceed(Some(inferred.clone())));
+        assert!(prompt.contains("Hola amigo"));
+        assert!(prompt.contains(&format!("Translate from '{}'?", inferred)));
+        assert_eq!(answer("n\n").0, SourceDecision::Abort);
+        assert_eq!(answer("").0, SourceDecision::Abort);
+        assert_eq!(answer("PT\n").0, SourceDecision::Proceed(Some("pt".to_string())));
+
+        // A confident guess proceeds without asking
+        let confident = vec![
+            "This chapter explains how to install the tool and configure it for your projects."
+                .to_string(),
+        ];
+        let mut output = Vec::new();
+        let decision =
+            confirm_source_lang(&confident, true, &mut std::io::empty(), &mut output).unwrap();
+        assert_eq!(decision, SourceDecision::Proceed(Some("en".to_string())));
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_resolve_uses_detected_language_only_when_asked() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file = temp_dir.path().join("doc.md");
+        std::fs::write(
+            &file,
+            "# 42\n\nThis chapter explains how to install the tool and configure it.\n",
+        )
+        .unwrap();
+        let files = vec![file];
+
+        let job = JobArgs::default();
+        assert_eq!(resolve_source_lang(&files, None, &job).unwrap(), None);
+
+        let job = JobArgs { from_lang_auto_confirm: true, yes: true, ..Default::default() };
+        let detected = resolve_source_lang(&files, None, &job).unwrap();
+        assert_eq!(detected.as_deref(), Some("en"));
+        let auto = resolve_source_lang(&files, Some("auto".to_string()), &job).unwrap();
+        assert_eq!(auto.as_deref(), Some("en"));
+        let given = resolve_source_lang(&files, Some("fr".to_string()), &job).unwrap();
+        assert_eq!(given.as_deref(), Some("fr"));
+    }
+}
@@ -0,0 +1,409 @@
+//! `doctor` command: diagnose common setup problems
+
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+/// Default API endpoint, mirrors [`crate::core::config::TranslatorConfig`]
+const DEFAULT_ENDPOINT: &str = "https://ark.cn-beijing.volces.com/api/v3/responses";
+
+/// Numeric settings read from the environment and the type they must parse as
+const NUMERIC_VARS: &[(&str, NumberKind)] = &[
+    ("MAX_CONCURRENT", NumberKind::Positive),
+    ("MAX_RPS", NumberKind::PositiveFloat),
+    ("MAX_RETRIES", NumberKind::Unsigned),
+    ("RETRY_DELAY_MS", NumberKind::Unsigned),
+    ("MAX_INPUT_TOKENS", NumberKind::Positive),
+    ("REQUEST_TIMEOUT_MS", NumberKind::Positive),
+    ("FAST_LANE_MAX_TOKENS", NumberKind::Unsigned),
+    ("DAILY_TOKEN_LIMIT", NumberKind::Unsigned),
+    ("TRANSLATOR_SEED", NumberKind::Unsigned),
+    ("MAX_COST", NumberKind::PositiveFloat),
+];
+
+/// Proxy variables honoured by the HTTP client
+const PROXY_VARS: &[&str] = &[
+    "HTTPS_PROXY",
+    "https_proxy",
+    "HTTP_PROXY",
+    "http_proxy",
+    "ALL_PROXY",
+    "all_proxy",
+];
+
+/// Largest clock difference to the API server that is not reported
+const MAX_CLOCK_SKEW_SECS: i64 = 300;
+
+/// Expected shape of a numeric setting
+#[derive(Debug, Clone, Copy)]
+enum NumberKind {
+    /// Integer `>= 0`
+    Unsigned,
+    /// Integer `> 0`
+    Positive,
+    /// Float `> 0`
+    PositiveFloat,
+}
+
+/// Outcome of a single check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    /// Everything is fine
+    Pass,
+    /// Works, but probably not as intended
+    Warn,
+    /// Translation will fail until this is fixed
+    Fail,
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckStatus::Pass => write!(f, "✅"),
+            CheckStatus::Warn => write!(f, "⚠️ "),
+            CheckStatus::Fail => write!(f, "❌"),
+        }
+    }
+}
+
+/// A line of the doctor checklist
+#[derive(Debug, Clone)]
+pub struct Check {
+    /// What was checked
+    pub name: &'static str,
+    /// Result of the check
+    pub status: CheckStatus,
+    /// What was found
+    pub detail: String,
+    /// How to fix it, for warnings and failures
+    pub hint: Option<String>,
+}
+
+impl Check {
+    /// Passing check
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    /// Check with a warning
+    fn warn(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Warn,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    /// Failing check
+    fn fail(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Fail,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+/// Run every check.
+///
+/// `env` looks up environment variables and `work_dir` is where output and
+/// reports are written. When `probe` is set and the basic configuration is
+/// usable, a single tiny request is sent to the endpoint to check
+/// reachability, key acceptance and clock skew.
+pub async fn diagnose<F>(env: F, work_dir: &Path, probe: bool) -> Vec<Check>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut checks = Vec::new();
+
+    // API key
+    let api_key = env("ARK_API_KEY").filter(|k| !k.trim().is_empty());
+    checks.push(match &api_key {
+        Some(key) => Check::pass("API key", format!("ARK_API_KEY is set ({} chars)", key.len())),
+        None => Check::fail(
+            "API key",
+            "ARK_API_KEY is not set",
+            "export ARK_API_KEY=<key>, add it to .env, or pass --api-key",
+        ),
+    });
+
+    // Numeric settings
+    let invalid: Vec<String> = NUMERIC_VARS
+        .iter()
+        .filter_map(|(name, kind)| {
+            let value = env(name)?;
+            (!is_valid_number(&value, *kind)).then(|| format!("{}={:?}", name, value))
+        })
+        .collect();
+    checks.push(if invalid.is_empty() {
+        Check::pass("Configuration", "numeric settings are valid")
+    } else {
+        Check::fail(
+            "Configuration",
+            format!("invalid values: {}", invalid.join(", ")),
+            "fix or unset these variables; counts and limits must be positive numbers",
+        )
+    });
+
+    // Endpoint
+    let endpoint = env("API_ENDPOINT").unwrap_or_else(|| DEFAULT_ENDPOINT.to_string());
+    let endpoint_url = reqwest::Url::parse(&endpoint)
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https"));
+    checks.push(match &endpoint_url {
+        Some(_) => Check::pass("Endpoint", endpoint.clone()),
+        None => Check::fail(
+            "Endpoint",
+            format!("API_ENDPOINT is not an http(s) URL: {:?}", endpoint),
+            format!("unset API_ENDPOINT to use {}", DEFAULT_ENDPOINT),
+        ),
+    });
+
+    // Proxy
+    let proxies: Vec<(&str, String)> = PROXY_VARS
+        .iter()
+        .filter_map(|name| env(name).map(|value| (*name, value)))
+        .filter(|(_, value)| !value.is_empty())
+        .collect();
+    let bad_proxy = proxies
+        .iter()
+        .find(|(_, value)| reqwest::Proxy::all(value.as_str()).is_err());
+    checks.push(match (proxies.first(), bad_proxy) {
+        (None, _) => Check::pass("Proxy", "no proxy configured"),
+        (_, Some((name, value))) => Check::fail(
+            "Proxy",
+            format!("{} is not a valid proxy URL: {:?}", name, value),
+            "use a URL like http://host:port, or unset the variable",
+        ),
+        (Some((name, value)), None) => {
+            let no_proxy = env("NO_PROXY").or_else(|| env("no_proxy"));
+            Check::pass(
+                "Proxy",
+                format!("{}={} (NO_PROXY={})", name, value, no_proxy.unwrap_or_default()),
+            )
+        }
+    });
+
+    // Writable output location
+    checks.push(check_writable(work_dir));
+
+    // Clock, locally
+    let now = chrono::Utc::now();
+    let plausible = chrono::DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+        .map(|t| now > t)
+        .unwrap_or(true);
+    let mut clock = if plausible {
+        Check::pass("System clock", format!("{} UTC", now.format("%Y-%m-%d %H:%M:%S")))
+    } else {
+        Check::fail(
+            "System clock",
+            format!("clock reads {}", now.to_rfc3339()),
+            "sync the system clock; the daily token quota resets by date",
+        )
+    };
+
+    // Network: one tiny request
+    match (probe, &api_key, &endpoint_url) {
+        (true, Some(key), Some(_)) => {
+            let (reachable, key_check, server_date) = probe_endpoint(&endpoint, key).await;
+            checks.push(reachable);
+            checks.push(key_check);
+            if let (CheckStatus::Pass, Some(server_date)) = (clock.status, server_date) {
+                let skew = (now - server_date).num_seconds();
+                if skew.abs() > MAX_CLOCK_SKEW_SECS {
+                    clock = Check::warn(
+                        "System clock",
+                        format!("clock differs from the API server by {}s", skew),
+                        "sync the system clock (e.g. enable NTP); daily quota resets use local time",
+                    );
+                }
+            }
+        }
+        _ => {
+            let reason = if probe {
+                "skipped until the checks above pass"
+            } else {
+                "skipped (offline)"
+            };
+            checks.push(Check::warn("Endpoint reachable", reason, "fix the failures above"));
+            checks.push(Check::warn("API key accepted", reason, "fix the failures above"));
+        }
+    }
+    checks.push(clock);
+
+    checks
+}
+
+/// Print the checklist
+pub fn print_checks(checks: &[Check]) {
+    for check in checks {
+        println!("{} {}: {}", check.status, check.name, check.detail);
+        if let Some(hint) = &check.hint {
+            println!("     → {}", hint);
+        }
+    }
+}
+
+/// Whether `value` parses as a number of the given kind
+fn is_valid_number(value: &str, kind: NumberKind) -> bool {
+    match kind {
+        NumberKind::Unsigned => value.parse::<u64>().is_ok(),
+        NumberKind::Positive => value.parse::<u64>().map(|n| n > 0).unwrap_or(false),
+        NumberKind::PositiveFloat => value.parse::<f64>().map(|n| n > 0.0).unwrap_or(false),
+    }
+}
+
+/// Check that files can be created in `dir`
+fn check_writable(dir: &Path) -> Check {
+    let probe = dir.join(format!(".doubao-doctor-{}", std::process::id()));
+    match std::fs::write(&probe, b"ok") {
+        Ok(()) => {
+            let _ = std::fs::remove_file(&probe);
+            Check::pass("Output directory", format!("{} is writable", dir.display()))
+        }
+        Err(e) => Check::fail(
+            "Output directory",
+            format!("cannot write to {}: {}", dir.display(), e),
+            "run from a writable directory or fix its permissions",
+        ),
+    }
+}
+
+/// Send a one-word translation request.
+///
+/// Returns the reachability check, the key check and the server's `Date`.
+async fn probe_endpoint(
+    endpoint: &str,
+    api_key: &str,
+) -> (Check, Check, Option<chrono::DateTime<chrono::Utc>>) {
+    let client = match reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            return (
+                Check::fail("Endpoint reachable", e.to_string(), "check the proxy settings"),
+                Check::warn("API key accepted", "not checked", "fix the failure above"),
+                None,
+            )
+        }
+    };
+
+    let body = serde_json::json!({
+        "model": "doubao-seed-translation-250915",
+        "input": [{
+            "role": "user",
+            "content": [{
+                "type": "input_text",
+                "text": "ok",
+                "translation_options": { "target_language": "zh" }
+            }]
+        }]
+    });
+    let response = client
+        .post(endpoint)
+        .header("Authorization", format!("Bearer {}", api_key))
+        .json(&body)
+        .send()
+        .await;
+
+    let response = match response {
+        Ok(response) => response,
+        Err(e) => {
+            return (
+                Check::fail(
+                    "Endpoint reachable",
+                    format!("request failed: {}", e),
+                    "check network access, the proxy variables and API_ENDPOINT",
+                ),
+                Check::warn("API key accepted", "not checked", "fix the failure above"),
+                None,
+            )
+        }
+    };
+
+    let status = response.status();
+    let server_date = response
+        .headers()
+        .get(reqwest::header::DATE)
+        .and_then(|d| d.to_str().ok())
+        .and_then(|d| chrono::DateTime::parse_from_rfc2822(d).ok())
+        .map(|d| d.with_timezone(&chrono::Utc));
+
+    let reachable = Check::pass("Endpoint reachable", format!("HTTP {}", status.as_u16()));
+    let key_check = match status.as_u16() {
+        200..=299 => Check::pass("API key accepted", "test translation succeeded"),
+        401 | 403 => Check::fail(
+            "API key accepted",
+            format!("HTTP {}: the key was rejected", status.as_u16()),
+            "check ARK_API_KEY and that the model is enabled for your account",
+        ),
+        429 => Check::warn(
+            "API key accepted",
+            "HTTP 429: rate limited or out of quota",
+            "wait and retry, or check the quota in the console",
+        ),
+        code => Check::warn(
+            "API key accepted",
+            format!("HTTP {}: unexpected response", code),
+            "check API_ENDPOINT points at the responses API",
+        ),
+    };
+
+    (reachable, key_check, server_date)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockReply, MockServer};
+    use std::collections::HashMap;
+
+    /// Look up variables in a fixed map instead of the process environment
+    fn env_from(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    /// The check called `name`
+    fn find<'a>(checks: &'a [Check], name: &str) -> &'a Check {
+        checks.iter().find(|c| c.name == name).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_missing_api_key_fails_without_network() {
+        let dir = tempfile::tempdir().unwrap();
+        let checks = diagnose(env_from(&[("MAX_RPS", "0")]), dir.path(), true).await;
+
+        let key = find(&checks, "API key");
+        assert_eq!(key.status, CheckStatus::Fail);
+        assert!(key.hint.as_deref().unwrap().contains("ARK_API_KEY"));
+        assert_eq!(find(&checks, "Configuration").status, CheckStatus::Fail);
+        assert_eq!(find(&checks, "Endpoint reachable").status, CheckStatus::Warn);
+        assert_eq!(find(&checks, "Output directory").status, CheckStatus::Pass);
+    }
+
+    #[tokio::test]
+    async fn test_probe_reports_rejected_key() {
+        let server = MockServer::start(|_| MockReply::error(401, "invalid api key")).await;
+        let dir = tempfile::tempdir().unwrap();
+        let env = env_from(&[("ARK_API_KEY", "bad"), ("API_ENDPOINT", server.url.as_str())]);
+
+        let checks = diagnose(env, dir.path(), true).await;
+
+        assert_eq!(find(&checks, "Endpoint reachable").status, CheckStatus::Pass);
+        assert_eq!(find(&checks, "API key accepted").status, CheckStatus::Fail);
+        assert_eq!(server.request_count(), 1);
+    }
+}
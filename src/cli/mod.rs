@@ -1,3 +1,7 @@
 //! Command-line interface module
 
-pub mod commands;
\ No newline at end of file
+pub mod checkpoint;
+pub mod commands;
+pub mod confirm;
+pub mod doctor;
+pub mod runner;
\ No newline at end of file
@@ -0,0 +1,402 @@
+//! Directory job runner shared by the file-based commands
+
+use futures::stream::{self, StreamExt};
+use indicatif::ProgressBar;
+use serde_json::{json, Value};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::core::diagnostics::{Diagnostic, Diagnostics, Severity};
+use crate::core::errors::Result;
+use crate::core::models::ScheduleOrder;
+use crate::processors::fixup::Fixups;
+use crate::processors::plan::FilePlan;
+
+/// Outcome of a directory job
+#[derive(Debug, Default)]
+pub struct JobSummary {
+    /// Number of files translated successfully
+    pub processed: usize,
+    /// Number of files that failed or timed out
+    pub failed: usize,
+    /// Failed files with the reason they failed
+    pub failed_files: Vec<(PathBuf, String)>,
+}
+
+/// Reorder `files` by size for dispatch according to `schedule`
+pub fn order_files(files: Vec<PathBuf>, schedule: ScheduleOrder) -> Vec<PathBuf> {
+    let sizes: Vec<usize> = files
+        .iter()
+        .map(|f| std::fs::metadata(f).map(|m| m.len() as usize).unwrap_or(0))
+        .collect();
+    let order = schedule.dispatch_order(&sizes);
+    let mut files: Vec<Option<PathBuf>> = files.into_iter().map(Some).collect();
+    order.into_iter().filter_map(|i| files[i].take()).collect()
+}
+
+/// Run `job` for every file, recording failures instead of aborting the run.
+///
+/// Up to `file_concurrency` files are in flight at once. This only bounds
+/// files: requests are still limited by the client's own `max_concurrent`,
+/// which all files share.
+///
+/// When `per_file_timeout` is set, a file that takes longer is abandoned and
+/// recorded as failed. Dropping its future cancels any requests still in flight
+/// that no other file is waiting on.
+pub async fn run_files<F, Fut>(
+    files: Vec<PathBuf>,
+    file_concurrency: usize,
+    per_file_timeout: Option<Duration>,
+    pb: &ProgressBar,
+    mut job: F,
+) -> JobSummary
+where
+    F: FnMut(PathBuf) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let mut summary = JobSummary::default();
+
+    let mut outcomes = stream::iter(files)
+        .map(|file_path| {
+            let fut = job(file_path.clone());
+            async move {
+                let outcome = match per_file_timeout {
+                    Some(limit) => match tokio::time::timeout(limit, fut).await {
+                        Ok(result) => result.map_err(|e| e.to_string()),
+                        Err(_) => Err(format!("timed out after {}s", limit.as_secs_f64())),
+                    },
+                    None => fut.await.map_err(|e| e.to_string()),
+                };
+                (file_path, outcome)
+            }
+        })
+        .buffer_unordered(file_concurrency.max(1));
+
+    while let Some((file_path, outcome)) = outcomes.next().await {
+        match outcome {
+            Ok(()) => {
+                summary.processed += 1;
+                pb.set_message(format!("Processed: {}", file_path.display()));
+                pb.inc(1);
+            }
+            Err(e) => {
+                summary.failed += 1;
+                pb.set_message(format!("Failed: {} - {}", file_path.display(), e));
+                eprintln!("Error processing {}: {}", file_path.display(), e);
+                summary.failed_files.push((file_path, e));
+            }
+        }
+    }
+
+    summary
+}
+
+/// Diagnostics listed individually at job end; the rest only go to the file
+const MAX_PRINTED_DIAGNOSTICS: usize = 20;
+
+/// Record failed files as errors in a job's diagnostics and, if `save_to`
+/// is set, write them all as JSON, without printing anything
+pub async fn record_diagnostics(
+    diagnostics: &Diagnostics,
+    summary: &JobSummary,
+    save_to: Option<&Path>,
+) -> Result<()> {
+    for (file, reason) in &summary.failed_files {
+        diagnostics.push(Diagnostic::new(Severity::Error, reason.clone()).with_file(file));
+    }
+    if let Some(path) = save_to {
+        diagnostics.save(path).await?;
+    }
+    Ok(())
+}
+
+/// Finish a job's diagnostics: record failed files as errors, print a
+/// count-by-severity summary and, if `save_to` is set, write them all as JSON.
+pub async fn report_diagnostics(
+    diagnostics: &Diagnostics,
+    summary: &JobSummary,
+    save_to: Option<&Path>,
+) -> Result<()> {
+    record_diagnostics(diagnostics, summary, save_to).await?;
+
+    let counts = diagnostics.count_by_severity();
+    if !counts.is_empty() {
+        let parts: Vec<String> = counts
+            .iter()
+            .rev()
+            .map(|(severity, count)| format!("{} {}", count, severity))
+            .collect();
+        println!("   Diagnostics: {}", parts.join(", "));
+
+        let all = diagnostics.all();
+        for diagnostic in all.iter().take(MAX_PRINTED_DIAGNOSTICS) {
+            println!("     - {}", diagnostic);
+        }
+        if all.len() > MAX_PRINTED_DIAGNOSTICS {
+            println!("     ... and {} more", all.len() - MAX_PRINTED_DIAGNOSTICS);
+        }
+    }
+
+    if let Some(path) = save_to {
+        println!("   Diagnostics written to {}", path.display());
+    }
+    Ok(())
+}
+
+/// Write the segments `fixups` collected to `save_to` as an editable fix-up job
+pub async fn save_fixups(fixups: &Fixups, save_to: Option<&Path>) -> Result<()> {
+    let Some(path) = save_to else {
+        return Ok(());
+    };
+    let job = fixups.job();
+    job.save(path).await?;
+    println!(
+        "   {} untranslated segments written to {} (fill in and run apply-fix)",
+        job.entries.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// Machine-readable summary of a finished job, printed by `--json`
+pub fn json_summary(summary: &JobSummary, elapsed: Duration, tokens_used: usize) -> Value {
+    let failed_files: Vec<Value> = summary
+        .failed_files
+        .iter()
+        .map(|(file, error)| json!({ "file": file.display().to_string(), "error": error }))
+        .collect();
+    json!({
+        "processed": summary.processed,
+        "failed": summary.failed,
+        "failed_files": failed_files,
+        "elapsed_ms": elapsed.as_millis() as u64,
+        "tokens_used": tokens_used,
+    })
+}
+
+/// Read all of `input`, translate it with `translate` and write the
+/// translation to `output`, for `--stdin` pipelines
+pub async fn translate_stream<R, W, F, Fut>(mut input: R, mut output: W, translate: F) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+    F: FnOnce(String) -> Fut,
+    Fut: Future<Output = Result<String>>,
+{
+    let mut content = String::new();
+    input.read_to_string(&mut content).await?;
+    let translated = translate(content).await?;
+    output.write_all(translated.as_bytes()).await?;
+    output.flush().await?;
+    Ok(())
+}
+
+/// Print a dry run's plan: segments and estimated tokens per file and in total
+pub fn report_plans(plans: &[FilePlan]) {
+    println!("\n🔍 Dry run, nothing was translated");
+    for plan in plans {
+        println!(
+            "   {}: {} segments, ~{} tokens",
+            plan.file.display(),
+            plan.segments,
+            plan.tokens
+        );
+    }
+    let segments: usize = plans.iter().map(|plan| plan.segments).sum();
+    let tokens: usize = plans.iter().map(|plan| plan.tokens).sum();
+    println!("   Total: {} files, {} segments, ~{} tokens", plans.len(), segments, tokens);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    /// Sets a flag when dropped, proving the hung future was cancelled
+    struct DropFlag(Arc<AtomicBool>);
+
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_per_file_timeout_skips_hung_file() {
+        let files = vec![
+            PathBuf::from("a.md"),
+            PathBuf::from("hang.md"),
+            PathBuf::from("c.md"),
+        ];
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        let summary = run_files(
+            files,
+            1,
+            Some(Duration::from_millis(100)),
+            &ProgressBar::hidden(),
+            |path| {
+                let cancelled = cancelled.clone();
+                async move {
+                    if path.ends_with("hang.md") {
+                        let _guard = DropFlag(cancelled);
+                        std::future::pending::<()>().await;
+                    }
+                    Ok(())
+                }
+            },
+        )
+        .await;
+
+        assert_eq!(summary.processed, 2);
+        assert_eq!(summary.failed, 1);
+        assert_eq!(summary.failed_files[0].0, PathBuf::from("hang.md"));
+        assert!(summary.failed_files[0].1.contains("timed out"));
+        assert!(cancelled.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_file_concurrency_bounds_files_not_requests() {
+        use std::sync::atomic::AtomicUsize;
+
+        /// Tracks how many of something are running and the peak
+        #[derive(Default)]
+        struct Gauge {
+            /// Currently running
+            now: AtomicUsize,
+            /// Highest value of `now` seen
+            peak: AtomicUsize,
+        }
+
+        impl Gauge {
+            /// Count `fut` as running until it completes
+            async fn track<T>(&self, fut: impl Future<Output = T>) -> T {
+                let now = self.now.fetch_add(1, Ordering::SeqCst) + 1;
+                self.peak.fetch_max(now, Ordering::SeqCst);
+                let output = fut.await;
+                self.now.fetch_sub(1, Ordering::SeqCst);
+                output
+            }
+        }
+
+        let files: Vec<PathBuf> = (0..6).map(|i| PathBuf::from(format!("{}.md", i))).collect();
+        let (file_gauge, request_gauge) = (Gauge::default(), Gauge::default());
+        // The client's request cap, shared by all files
+        let client = Arc::new(tokio::sync::Semaphore::new(20));
+
+        let summary = run_files(files, 2, None, &ProgressBar::hidden(), |_| {
+            let (file_gauge, request_gauge, client) = (&file_gauge, &request_gauge, &client);
+            let requests = (0..5).map(move |_| async move {
+                let _permit = client.acquire().await.unwrap();
+                request_gauge.track(tokio::time::sleep(Duration::from_millis(30))).await;
+            });
+            file_gauge.track(async move {
+                futures::future::join_all(requests).await;
+                Ok(())
+            })
+        })
+        .await;
+
+        assert_eq!(summary.processed, 6);
+        assert_eq!(file_gauge.peak.load(Ordering::SeqCst), 2);
+        // Both files in flight send all their requests at once
+        assert_eq!(request_gauge.peak.load(Ordering::SeqCst), 10);
+    }
+
+    #[tokio::test]
+    async fn test_report_diagnostics_records_failed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("diagnostics.json");
+        let diagnostics = Diagnostics::new();
+        diagnostics.push(Diagnostic::new(Severity::Warning, "segment left untranslated"));
+        let summary = JobSummary {
+            processed: 1,
+            failed: 1,
+            failed_files: vec![(PathBuf::from("b.md"), "timed out after 1s".to_string())],
+        };
+
+        report_diagnostics(&diagnostics, &summary, Some(&path)).await.unwrap();
+
+        let saved: Vec<Diagnostic> =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved.len(), 2);
+        assert_eq!(saved[1].severity, Severity::Error);
+        assert_eq!(saved[1].file, Some(PathBuf::from("b.md")));
+    }
+
+    #[tokio::test]
+    async fn test_json_summary_parses_after_run() {
+        use crate::core::errors::TranslationError;
+
+        let files = vec![PathBuf::from("a.md"), PathBuf::from("bad.md")];
+        let summary = run_files(files, 1, None, &ProgressBar::hidden(), |path| async move {
+            if path.ends_with("bad.md") {
+                return Err(TranslationError::InternalError("broken table".into()));
+            }
+            Ok(())
+        })
+        .await;
+
+        let printed = json_summary(&summary, Duration::from_millis(1500), 42).to_string();
+        let parsed: Value = serde_json::from_str(&printed).unwrap();
+        assert_eq!(parsed["processed"], 1);
+        assert_eq!(parsed["failed"], 1);
+        assert_eq!(parsed["failed_files"][0]["file"], "bad.md");
+        assert!(parsed["failed_files"][0]["error"].as_str().unwrap().contains("broken table"));
+        assert_eq!(parsed["elapsed_ms"], 1500);
+        assert_eq!(parsed["tokens_used"], 42);
+    }
+
+    #[tokio::test]
+    async fn test_translate_stream_pipes_input_to_output() {
+        use crate::processors::markdown::MarkdownProcessor;
+        use crate::testing::{test_translator, MockServer};
+
+        let server = MockServer::echo().await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url));
+        let input = "# Title\n\nSome `code` here.\n".as_bytes();
+        let mut output = Vec::new();
+
+        translate_stream(input, &mut output, |content| async move {
+            processor.translate_content(Path::new("<stdin>"), &content, "ja", None).await
+        })
+        .await
+        .unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "# ja:Title\n\nja:Some `code` here.\n");
+    }
+
+    #[test]
+    fn test_order_files_by_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let files: Vec<PathBuf> = [("a.md", 10), ("b.md", 300), ("c.md", 50)]
+            .iter()
+            .map(|(name, size)| {
+                let path = dir.path().join(name);
+                std::fs::write(&path, "x".repeat(*size)).unwrap();
+                path
+            })
+            .collect();
+
+        let names = |files: Vec<PathBuf>| -> Vec<String> {
+            files
+                .iter()
+                .map(|f| f.file_name().unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+        assert_eq!(
+            names(order_files(files.clone(), ScheduleOrder::LargestFirst)),
+            vec!["b.md", "c.md", "a.md"]
+        );
+        assert_eq!(
+            names(order_files(files.clone(), ScheduleOrder::SmallestFirst)),
+            vec!["a.md", "c.md", "b.md"]
+        );
+        assert_eq!(
+            names(order_files(files, ScheduleOrder::SourceOrder)),
+            vec!["a.md", "b.md", "c.md"]
+        );
+    }
+}
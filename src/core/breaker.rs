@@ -0,0 +1,235 @@
+//! Per-model circuit breaker, so a failing model is not retried for every request
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+use crate::core::clock::{Clock, SystemClock};
+
+/// Whether a model is tried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Requests go through
+    Closed,
+    /// Too many consecutive failures: the model is skipped until the cooldown ends
+    Open,
+    /// Cooldown over: one probe request decides whether the circuit closes again
+    HalfOpen,
+}
+
+/// Failure record of one model
+#[derive(Debug, Default)]
+struct Circuit {
+    /// Failures since the last success
+    consecutive_failures: u32,
+    /// When the circuit last opened; `None` while closed
+    opened_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// A half-open probe is in flight
+    probing: bool,
+}
+
+/// Opens a model's circuit after `threshold` consecutive failures and keeps
+/// it open for `cooldown`; then a single probe request is let through, which
+/// closes the circuit on success and reopens it on failure.
+///
+/// Clones share the same circuits. A threshold of 0 never opens a circuit.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    /// Consecutive failures that open a circuit
+    threshold: u32,
+    /// How long an open circuit skips its model
+    cooldown: chrono::Duration,
+    /// Circuits by model id
+    circuits: Arc<Mutex<HashMap<String, Circuit>>>,
+    /// Time source for cooldowns
+    clock: Arc<dyn Clock>,
+}
+
+impl CircuitBreaker {
+    /// Create a breaker opening after `threshold` failures for `cooldown_ms`
+    pub fn new(threshold: u32, cooldown_ms: u64) -> Self {
+        Self {
+            threshold,
+            cooldown: chrono::Duration::milliseconds(cooldown_ms as i64),
+            circuits: Arc::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Use `clock` instead of the system clock to time cooldowns
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Current state of `model`'s circuit
+    pub fn state(&self, model: &str) -> CircuitState {
+        let circuits = self.circuits.lock().unwrap();
+        match circuits.get(model).and_then(|c| c.opened_at) {
+            None => CircuitState::Closed,
+            Some(opened_at) if self.clock.now() - opened_at < self.cooldown => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Whether a request may be sent to `model` now. In the half-open state
+    /// only the first caller gets through, as the probe.
+    ///
+    /// Hold the returned permit until the outcome is recorded: a probe whose
+    /// permit is dropped first (e.g. because its request was cancelled) lets
+    /// the next caller probe instead.
+    pub fn allow(&self, model: &str) -> Option<Permit<'_>> {
+        let state = self.state(model);
+        let mut circuits = self.circuits.lock().unwrap();
+        let permit = |probe| Permit {
+            breaker: self,
+            model: model.to_string(),
+            probe,
+        };
+        match state {
+            CircuitState::Closed => Some(permit(None)),
+            CircuitState::Open => None,
+            CircuitState::HalfOpen => {
+                let circuit = circuits.entry(model.to_string()).or_default();
+                if circuit.probing {
+                    return None;
+                }
+                info!("Probing model {} after its cooldown", model);
+                circuit.probing = true;
+                Some(permit(circuit.opened_at))
+            }
+        }
+    }
+
+    /// `model` answered: close its circuit
+    pub fn record_success(&self, model: &str) {
+        let mut circuits = self.circuits.lock().unwrap();
+        if let Some(circuit) = circuits.remove(model) {
+            if circuit.opened_at.is_some() {
+                info!("Model {} recovered, circuit closed", model);
+            }
+        }
+    }
+
+    /// `model` failed: open its circuit once failures reach the threshold,
+    /// or again right away if this was the half-open probe
+    pub fn record_failure(&self, model: &str) {
+        if self.threshold == 0 {
+            return;
+        }
+        let now = self.clock.now();
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits.entry(model.to_string()).or_default();
+        circuit.consecutive_failures += 1;
+        if circuit.probing || circuit.consecutive_failures == self.threshold {
+            warn!(
+                "Model {} failed {} times in a row, skipping it for {}s",
+                model,
+                circuit.consecutive_failures,
+                self.cooldown.num_seconds()
+            );
+            circuit.opened_at = Some(now);
+            circuit.probing = false;
+        }
+    }
+}
+
+/// Leave to send a request to a model, see [`CircuitBreaker::allow`]
+#[derive(Debug)]
+pub struct Permit<'a> {
+    /// Breaker that granted the permit
+    breaker: &'a CircuitBreaker,
+    /// Model the request goes to
+    model: String,
+    /// For the half-open probe, when the circuit it probes opened
+    probe: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let Some(opened_at) = self.probe else {
+            return;
+        };
+        // A recorded outcome already ended the probe, and a circuit that
+        // reopened since belongs to a later probe
+        let mut circuits = self.breaker.circuits.lock().unwrap();
+        if let Some(circuit) = circuits.get_mut(&self.model) {
+            if circuit.probing && circuit.opened_at == Some(opened_at) {
+                circuit.probing = false;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::clock::MockClock;
+
+    #[test]
+    fn test_circuit_opens_half_opens_and_closes() {
+        let clock = MockClock::new(chrono::Utc::now());
+        let breaker = CircuitBreaker::new(2, 1000).with_clock(Arc::new(clock.clone()));
+
+        breaker.record_failure("m");
+        assert!(breaker.allow("m").is_some());
+        breaker.record_failure("m");
+        assert_eq!(breaker.state("m"), CircuitState::Open);
+        assert!(breaker.allow("m").is_none());
+
+        // One probe after the cooldown; a failed probe reopens at once
+        clock.advance(chrono::Duration::milliseconds(1000));
+        assert_eq!(breaker.state("m"), CircuitState::HalfOpen);
+        let probe = breaker.allow("m");
+        assert!(probe.is_some());
+        assert!(breaker.allow("m").is_none());
+        breaker.record_failure("m");
+        drop(probe);
+        assert_eq!(breaker.state("m"), CircuitState::Open);
+
+        clock.advance(chrono::Duration::milliseconds(1000));
+        let probe = breaker.allow("m");
+        assert!(probe.is_some());
+        breaker.record_success("m");
+        drop(probe);
+        assert_eq!(breaker.state("m"), CircuitState::Closed);
+        assert!(breaker.allow("m").is_some());
+
+        // Disabled with a threshold of 0
+        let never = CircuitBreaker::new(0, 1000);
+        for _ in 0..10 {
+            never.record_failure("m");
+        }
+        assert!(never.allow("m").is_some());
+    }
+
+    #[test]
+    fn test_cancelled_probe_lets_the_next_caller_probe() {
+        let clock = MockClock::new(chrono::Utc::now());
+        let breaker = CircuitBreaker::new(1, 1000).with_clock(Arc::new(clock.clone()));
+        breaker.record_failure("m");
+        clock.advance(chrono::Duration::milliseconds(1000));
+
+        // The probe's request is dropped before it records an outcome
+        let probe = breaker.allow("m");
+        assert!(probe.is_some());
+        assert!(breaker.allow("m").is_none());
+        drop(probe);
+
+        let probe = breaker.allow("m");
+        assert!(probe.is_some());
+        assert!(breaker.allow("m").is_none());
+        breaker.record_failure("m");
+        drop(probe);
+
+        // A stale permit does not end a later probe
+        clock.advance(chrono::Duration::milliseconds(1000));
+        let stale = breaker.allow("m");
+        breaker.record_failure("m");
+        clock.advance(chrono::Duration::milliseconds(1000));
+        let probe = breaker.allow("m");
+        assert!(probe.is_some());
+        drop(stale);
+        assert!(breaker.allow("m").is_none());
+    }
+}
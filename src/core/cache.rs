@@ -0,0 +1,183 @@
+//! Persistent translation cache, one JSON line per translated text
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tracing::{debug, warn};
+
+use crate::core::errors::{Result, TranslationError};
+
+/// A cached translation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedTranslation {
+    /// Translated text
+    pub translation: String,
+    /// Source language reported by the model
+    pub detected_source_lang: Option<String>,
+    /// Model that produced the translation
+    pub model: String,
+}
+
+/// Hit and miss counts of a cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Lookups answered from the cache
+    pub hits: u64,
+    /// Lookups that had to go to the API
+    pub misses: u64,
+}
+
+/// A line of the cache file
+#[derive(Serialize, Deserialize)]
+struct CacheLine {
+    /// See [`TranslationCache::key`]
+    key: String,
+    /// The cached value
+    #[serde(flatten)]
+    value: CachedTranslation,
+}
+
+/// Content-addressed cache of translations, persisted as JSON lines.
+///
+/// Entries are appended as soon as they are stored, so a crashed job keeps
+/// everything it translated; a torn last line is skipped on the next load.
+#[derive(Debug)]
+pub struct TranslationCache {
+    /// Cache file
+    path: PathBuf,
+    /// Entries by key
+    entries: Mutex<HashMap<String, CachedTranslation>>,
+    /// Cache file opened for appending
+    file: Mutex<std::fs::File>,
+    /// Lookups answered from the cache
+    hits: AtomicU64,
+    /// Lookups that found nothing
+    misses: AtomicU64,
+}
+
+impl TranslationCache {
+    /// Open the cache at `path`, creating the file (and its directory) if needed
+    pub fn open(path: &Path) -> Result<Self> {
+        let file_error = |e: std::io::Error| TranslationError::FileError {
+            path: path.display().to_string(),
+            message: e.to_string(),
+        };
+
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent).map_err(file_error)?;
+        }
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .map_err(file_error)?;
+
+        let mut entries = HashMap::new();
+        for line in BufReader::new(&file).lines() {
+            let line = line.map_err(file_error)?;
+            match serde_json::from_str::<CacheLine>(&line) {
+                Ok(entry) => {
+                    entries.insert(entry.key, entry.value);
+                }
+                Err(e) if !line.trim().is_empty() => {
+                    warn!("Skipping unreadable line in cache {}: {}", path.display(), e)
+                }
+                Err(_) => {}
+            }
+        }
+        debug!("Loaded {} cached translations from {}", entries.len(), path.display());
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            entries: Mutex::new(entries),
+            file: Mutex::new(file),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        })
+    }
+
+    /// Cache key of `text` translated by `model` under `context` and the
+    /// glossary with [`Glossary::fingerprint`](crate::core::glossary::Glossary::fingerprint)
+    /// `glossary`: the language pair, the model and a SHA-256 of the rest
+    pub fn key(
+        source_lang: Option<&str>,
+        target_lang: &str,
+        model: &str,
+        context: Option<&str>,
+        glossary: Option<&str>,
+        text: &str,
+    ) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(text.as_bytes());
+        // Texts without context keep the keys they always had
+        if let Some(context) = context {
+            hasher.update(b"\0context\0");
+            hasher.update(context.as_bytes());
+        }
+        if let Some(glossary) = glossary {
+            hasher.update(b"\0glossary\0");
+            hasher.update(glossary.as_bytes());
+        }
+        let digest = hasher.finalize();
+        format!(
+            "{}:{}:{}:{:x}",
+            source_lang.unwrap_or("auto"),
+            target_lang,
+            model,
+            digest
+        )
+    }
+
+    /// The entry of the first of `keys` found (e.g. one key per candidate
+    /// model, in routing order); counts as one hit or miss
+    pub fn get(&self, keys: &[String]) -> Option<CachedTranslation> {
+        let entries = self.entries.lock().unwrap();
+        let found = keys.iter().find_map(|key| entries.get(key)).cloned();
+        let counter = if found.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        found
+    }
+
+    /// Store `value` under `key` and append it to the cache file
+    pub fn insert(&self, key: String, value: CachedTranslation) -> Result<()> {
+        let mut line = serde_json::to_string(&CacheLine {
+            key: key.clone(),
+            value: value.clone(),
+        })?;
+        line.push('\n');
+
+        self.file
+            .lock()
+            .unwrap()
+            .write_all(line.as_bytes())
+            .map_err(|e| TranslationError::FileError {
+                path: self.path.display().to_string(),
+                message: e.to_string(),
+            })?;
+        self.entries.lock().unwrap().insert(key, value);
+        Ok(())
+    }
+
+    /// Number of cached translations
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Whether nothing is cached yet
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Hits and misses since the cache was opened
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
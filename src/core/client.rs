@@ -1,15 +1,121 @@
 //! Async translation client with retry and fallback logic
 
-use std::sync::Arc;
+use futures::future::{BoxFuture, FutureExt, Shared};
+use rand::rngs::StdRng;
+use rand::Rng;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 use tokio::sync::{Mutex, Semaphore};
-use tokio::time::sleep;
+use tokio::time::{sleep, Instant};
 use tracing::{debug, info, warn};
 
+use crate::core::breaker::CircuitBreaker;
+use crate::core::cache::{CacheStats, CachedTranslation, TranslationCache};
+use crate::core::clock::Clock;
 use crate::core::errors::{Result, TranslationError};
-use crate::core::models::{LaneType, Model, TranslationRequest, TranslationResult};
+use crate::core::glossary::Glossary;
+use crate::core::metrics;
+use crate::core::models::{
+    LanePolicy, LaneType, Model, Priority, ResponseFormat, TranslationRequest, TranslationResult,
+};
 use crate::core::config::TranslatorConfig;
+use crate::core::cost::CostTracker;
 use crate::core::token_tracker::TokenTracker;
+use crate::utils::rng::seeded_rng;
+use crate::utils::splitter::split_text_by;
+use crate::utils::tokens::{count_tokens, estimate_tokens, warm_tokenizer};
+
+/// Characters of a text sent to the API to detect its language
+const DETECT_SAMPLE_CHARS: usize = 200;
+
+/// Outcome of an in-flight request, shared by every caller waiting on it
+type SharedResult = std::result::Result<TranslationResult, Arc<TranslationError>>;
+
+/// What makes two requests the same translation
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct InflightKey {
+    /// Text to translate
+    text: String,
+    /// Source language, if given
+    source_lang: Option<String>,
+    /// Target language
+    target_lang: String,
+    /// Context sent along with the text
+    context: Option<String>,
+    /// Model the request is pinned to
+    model_id: Option<String>,
+    /// Lanes the request may use, if it overrides the configured policy
+    lane_policy: Option<LanePolicy>,
+    /// Lane the request is routed to
+    lane: LaneType,
+    /// Routing hint of the request
+    priority: Priority,
+}
+
+/// An API call shared by every caller waiting on it
+struct InflightCall {
+    /// Tells this call apart from a later one for the same key
+    id: u64,
+    /// Outcome of the call's task
+    result: Shared<BoxFuture<'static, SharedResult>>,
+    /// Cancels the call's task
+    abort: tokio::task::AbortHandle,
+    /// Callers still waiting on the outcome
+    waiters: usize,
+}
+
+/// Requests being translated right now, so identical ones share one API call
+#[derive(Default)]
+struct InflightRequests {
+    /// Calls in flight by what they translate
+    calls: std::sync::Mutex<HashMap<InflightKey, InflightCall>>,
+    /// Id of the next call
+    next_id: std::sync::atomic::AtomicU64,
+}
+
+impl InflightRequests {
+    /// Forget call `id` under `key`, unless a later call has taken its place
+    fn remove(&self, key: &InflightKey, id: u64) {
+        let mut calls = self.calls.lock().unwrap();
+        if calls.get(key).is_some_and(|call| call.id == id) {
+            calls.remove(key);
+        }
+    }
+}
+
+/// A caller's place in an in-flight call.
+///
+/// Dropping the last one cancels the call, so a caller that gives up
+/// (such as a file hitting its timeout) stops paying for the request.
+struct InflightWaiter<'a> {
+    /// Calls the waiter's call is listed in
+    inflight: &'a InflightRequests,
+    /// What the call translates
+    key: InflightKey,
+    /// Id of the call
+    id: u64,
+}
+
+impl Drop for InflightWaiter<'_> {
+    fn drop(&mut self) {
+        let mut calls = self.inflight.calls.lock().unwrap();
+        let Some(call) = calls.get_mut(&self.key).filter(|call| call.id == self.id) else {
+            return;
+        };
+        call.waiters -= 1;
+        if call.waiters == 0 {
+            call.abort.abort();
+            calls.remove(&self.key);
+        }
+    }
+}
+
+impl std::fmt::Debug for InflightRequests {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("InflightRequests")
+    }
+}
 
 /// Async translation client with smart routing and retry logic
 #[derive(Debug, Clone)]
@@ -18,23 +124,54 @@ pub struct AsyncTranslator {
     config: Arc<TranslatorConfig>,
     semaphore: Arc<Semaphore>,
     token_tracker: Arc<TokenTracker>,
+    /// Estimated spend against `max_cost`
+    cost_tracker: Arc<CostTracker>,
+    /// Source of retry jitter, the config's "jitter" stream
+    jitter_rng: Arc<std::sync::Mutex<StdRng>>,
     current_model: Arc<Mutex<String>>,
+    /// Models switched off at runtime, on top of `enabled` in the config
+    disabled_models: Arc<RwLock<HashSet<String>>>,
+    /// Translations from earlier calls and runs, when `cache_path` is set
+    cache: Option<Arc<TranslationCache>>,
+    /// Requests in flight, joined by identical requests instead of resending
+    inflight: Arc<InflightRequests>,
+    /// Fingerprint of the configured glossary, part of every cache key
+    glossary_fingerprint: Option<Arc<str>>,
+    /// Skips models that keep failing
+    breaker: CircuitBreaker,
 }
 
 impl AsyncTranslator {
     /// Create a new async translator
     pub fn new(config: TranslatorConfig) -> Result<Self> {
         config.validate()?;
+        Self::build(config)
+    }
+
+    /// Create a translator that need not have an API key, for dry runs that
+    /// only plan work; its requests fail without one
+    pub fn offline(config: TranslatorConfig) -> Result<Self> {
+        config.validate_offline()?;
+        Self::build(config)
+    }
 
-        let timeout = Duration::from_millis(config.timeout_ms);
-        let client = reqwest::Client::builder()
-            .timeout(timeout)
-            .pool_idle_timeout(Some(Duration::from_secs(30)))
-            .pool_max_idle_per_host(10)
-            .build()?;
+    /// Create a translator from an already validated `config`
+    fn build(config: TranslatorConfig) -> Result<Self> {
+        let client = http_client(&config)?;
+
+        if !warm_tokenizer() {
+            debug!("No tokenizer, estimating tokens from text length");
+        }
 
         let semaphore = Arc::new(Semaphore::new(config.max_concurrent));
+        let breaker = CircuitBreaker::new(config.breaker_threshold, config.breaker_cooldown_ms);
         let token_tracker = Arc::new(TokenTracker::from_env());
+        let cost_tracker = Arc::new(CostTracker::new(config.max_cost));
+        let jitter_rng = Arc::new(std::sync::Mutex::new(seeded_rng(config.seed, "jitter")));
+        let cache = match &config.cache_path {
+            Some(path) => Some(Arc::new(TranslationCache::open(path)?)),
+            None => None,
+        };
         let current_model = Arc::new(Mutex::new(
             config.models
                 .first()
@@ -42,15 +179,30 @@ impl AsyncTranslator {
                 .unwrap_or_default(),
         ));
 
+        let glossary_fingerprint = config.glossary.fingerprint().map(Arc::from);
+
         Ok(Self {
             client,
             config: Arc::new(config),
             semaphore,
             token_tracker,
+            cost_tracker,
+            jitter_rng,
             current_model,
+            disabled_models: Arc::new(RwLock::new(HashSet::new())),
+            cache,
+            inflight: Arc::default(),
+            glossary_fingerprint,
+            breaker,
         })
     }
 
+    /// Time circuit breaker cooldowns with `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.breaker = self.breaker.with_clock(clock);
+        self
+    }
+
     /// Create from environment
     pub fn from_env() -> Result<Self> {
         let config = TranslatorConfig::load()?;
@@ -58,80 +210,316 @@ impl AsyncTranslator {
     }
 
     /// Translate a single request
+    ///
+    /// Text longer than `max_input_tokens` is split at sentence ends and line
+    /// breaks; the chunks are translated separately and joined back with the
+    /// original separators.
+    ///
+    /// A request pinned with [`with_model`](TranslationRequest::with_model) goes
+    /// to that model only; it must be configured and enabled.
     pub async fn translate(&self, request: &TranslationRequest) -> Result<TranslationResult> {
-        // Check token quota
-        let estimated_tokens = request.text.len() / 4; // Rough estimate
-        if !self.token_tracker.can_use(estimated_tokens).await {
-            return Err(TranslationError::QuotaExceededError);
+        // A bad pin fails before any chunking or request sharing
+        self.pinned_model(request)?;
+        let max_tokens = self.config.max_input_tokens;
+        if request.estimated_tokens() <= max_tokens {
+            return self.translate_one(request).await;
         }
 
-        // Acquire semaphore for concurrency control
-        let _permit = self.semaphore.acquire().await.unwrap();
+        let split = split_text_by(&request.text, max_tokens, count_tokens);
+        if split.chunks.len() == 1 {
+            return self.translate_one(request).await;
+        }
+        debug!("Splitting {} bytes into {} chunks", request.text.len(), split.chunks.len());
 
-        // Try slow lane first (free tier)
-        let result = self.translate_with_lane(request, LaneType::Slow).await;
+        let chunks: Vec<TranslationRequest> = split
+            .chunks
+            .iter()
+            .map(|chunk| TranslationRequest {
+                text: chunk.clone(),
+                ..request.clone()
+            })
+            .collect();
+        let results = futures::future::join_all(chunks.iter().map(|c| self.translate_one(c)))
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
 
-        match result {
-            Ok(trans_result) => {
-                // Track token usage
-                if let Err(e) = self
-                    .token_tracker
-                    .use_tokens(trans_result.tokens_used)
-                    .await
-                {
-                    warn!("Failed to track token usage: {}", e);
-                }
+        let translations: Vec<&str> = results.iter().map(|r| r.translation.as_str()).collect();
+        let first = &results[0];
+        Ok(TranslationResult {
+            translation: split.join(&translations),
+            detected_source_lang: first.detected_source_lang.clone(),
+            tokens_used: results.iter().map(|r| r.tokens_used).sum(),
+            model_used: first.model_used.clone(),
+            request_id: first.request_id.clone(),
+        })
+    }
 
-                // Update current model
-                {
-                    let mut current = self.current_model.lock().await;
-                    *current = trans_result.model_used.clone();
+    /// Translate a request as one API call, or join an identical one in flight.
+    ///
+    /// Only the caller that sent the request reports its tokens; the others
+    /// get the same translation with `tokens_used` 0.
+    async fn translate_one(&self, request: &TranslationRequest) -> Result<TranslationResult> {
+        // Requests for another endpoint may get other answers, never share them
+        if request.endpoint_override.is_some() {
+            return self.translate_cached(request).await;
+        }
+
+        let key = InflightKey {
+            text: request.text.clone(),
+            source_lang: request.source_lang.clone(),
+            target_lang: request.target_lang.clone(),
+            context: request.context.clone(),
+            model_id: request.model_id.clone(),
+            lane_policy: request.lane_policy,
+            lane: self.config.route(request),
+            priority: request.priority,
+        };
+        let (shared, waiter, leader) = {
+            let mut calls = self.inflight.calls.lock().unwrap();
+            match calls.get_mut(&key) {
+                Some(call) => {
+                    call.waiters += 1;
+                    let waiter = InflightWaiter {
+                        inflight: &self.inflight,
+                        key: key.clone(),
+                        id: call.id,
+                    };
+                    (call.result.clone(), waiter, false)
+                }
+                None => {
+                    let id = self
+                        .inflight
+                        .next_id
+                        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    let this = self.clone();
+                    let request = request.clone();
+                    let key_done = key.clone();
+                    // A task of its own, so it keeps going (and releases its
+                    // permit) while anyone waits on it, and is aborted by the
+                    // last waiter to be dropped
+                    let call = tokio::spawn(async move {
+                        let result = this.translate_cached(&request).await.map_err(Arc::new);
+                        this.inflight.remove(&key_done, id);
+                        result
+                    });
+                    let abort = call.abort_handle();
+                    let result = async move {
+                        call.await.unwrap_or_else(|e| {
+                            Err(Arc::new(TranslationError::InternalError(e.to_string())))
+                        })
+                    }
+                    .boxed()
+                    .shared();
+                    calls.insert(
+                        key.clone(),
+                        InflightCall {
+                            id,
+                            result: result.clone(),
+                            abort,
+                            waiters: 1,
+                        },
+                    );
+                    let waiter = InflightWaiter {
+                        inflight: &self.inflight,
+                        key,
+                        id,
+                    };
+                    (result, waiter, true)
                 }
+            }
+        };
 
-                Ok(trans_result)
+        let outcome = shared.await;
+        drop(waiter);
+        match outcome {
+            Ok(result) if leader => Ok(result),
+            Ok(result) => {
+                debug!("Joined an identical request for {} bytes", request.text.len());
+                Ok(TranslationResult {
+                    tokens_used: 0,
+                    ..result
+                })
             }
-            Err(e) => {
-                warn!("Slow lane failed: {}, trying fast lane", e);
-
-                // Fallback to fast lane
-                let fast_result = self.translate_with_lane(request, LaneType::Fast).await;
-
-                match fast_result {
-                    Ok(trans_result) => {
-                        // Track token usage
-                        if let Err(e) = self
-                            .token_tracker
-                            .use_tokens(trans_result.tokens_used)
-                            .await
-                        {
-                            warn!("Failed to track token usage: {}", e);
-                        }
+            Err(e) => Err(e.duplicate()),
+        }
+    }
 
-                        // Update current model
-                        {
-                            let mut current = self.current_model.lock().await;
-                            *current = trans_result.model_used.clone();
-                        }
+    /// Translate a request as one API call, or answer it from the cache
+    async fn translate_cached(&self, request: &TranslationRequest) -> Result<TranslationResult> {
+        // Requests for another endpoint may get other answers, never cache them
+        let cache = self.cache.as_ref().filter(|_| request.endpoint_override.is_none());
+        if let Some(cache) = cache {
+            if let Some(cached) = cache.get(&self.cache_keys(request)) {
+                debug!("Cache hit for {} bytes", request.text.len());
+                return Ok(TranslationResult {
+                    translation: cached.translation,
+                    detected_source_lang: cached.detected_source_lang,
+                    tokens_used: 0,
+                    model_used: cached.model,
+                    request_id: None,
+                });
+            }
+        }
 
-                        Ok(trans_result)
-                    }
-                    Err(fast_err) => {
-                        // Both lanes failed
-                        warn!("Fast lane also failed: {}", fast_err);
-                        Err(e)
+        let result = self.translate_uncached(request).await?;
+
+        if let Some(cache) = cache {
+            let key = TranslationCache::key(
+                request.source_lang.as_deref(),
+                &request.target_lang,
+                &result.model_used,
+                request.context.as_deref(),
+                self.glossary_fingerprint.as_deref(),
+                &request.text,
+            );
+            let value = CachedTranslation {
+                translation: result.translation.clone(),
+                detected_source_lang: result.detected_source_lang.clone(),
+                model: result.model_used.clone(),
+            };
+            if let Err(e) = cache.insert(key, value) {
+                warn!("Failed to write translation cache: {}", e);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Cache keys of `request` for every model that could serve it, in
+    /// the order its lanes are tried
+    fn cache_keys(&self, request: &TranslationRequest) -> Vec<String> {
+        let models: Vec<&str> = match &request.model_id {
+            Some(id) => vec![id.as_str()],
+            None => self
+                .config
+                .lane_order(request)
+                .into_iter()
+                .flat_map(|lane| self.config.models_for(request, lane))
+                .map(|model| model.id.as_str())
+                .collect(),
+        };
+        models
+            .into_iter()
+            .map(|model| {
+                TranslationCache::key(
+                    request.source_lang.as_deref(),
+                    &request.target_lang,
+                    model,
+                    request.context.as_deref(),
+                    self.glossary_fingerprint.as_deref(),
+                    &request.text,
+                )
+            })
+            .collect()
+    }
+
+    /// The model `request` is pinned to, if any; an error if that model is
+    /// unknown or not enabled
+    fn pinned_model(&self, request: &TranslationRequest) -> Result<Option<&Model>> {
+        let Some(id) = &request.model_id else {
+            return Ok(None);
+        };
+        match self.config.find_model(id) {
+            Some(model) if self.is_model_enabled(id) => Ok(Some(model)),
+            Some(_) => Err(TranslationError::ConfigError {
+                message: format!("Requested model {} is disabled", id),
+            }),
+            None => Err(TranslationError::ConfigError {
+                message: format!("Requested model {} is not configured", id),
+            }),
+        }
+    }
+
+    /// Translate a request as one API call
+    async fn translate_uncached(&self, request: &TranslationRequest) -> Result<TranslationResult> {
+        let pinned = self.pinned_model(request)?;
+        // Lanes in the order of the lane policy (routed lane, then the other, by default)
+        let lanes = self.config.lane_order(request);
+
+        // The retry budget also covers waiting for a permit
+        let deadline = self
+            .config
+            .max_request_duration_ms
+            .map(|ms| Instant::now() + Duration::from_millis(ms));
+
+        // Acquire semaphore for concurrency control
+        let _permit = self.semaphore.acquire().await.unwrap();
+
+        // Reserve token quota, counted for the first model to be tried. Only
+        // now, so requests queued for a permit hold no quota; dropping the
+        // reservation (a failure or a cancelled call) releases it.
+        let first_model = match pinned {
+            Some(model) => Some(model.id.clone()),
+            None => lanes.iter().find_map(|&lane| {
+                self.config.models_for(request, lane).first().map(|m| m.id.clone())
+            }),
+        };
+        let estimated_tokens = estimate_tokens(&request.text, first_model.as_deref().unwrap_or(""));
+        let Some(reservation) = self.token_tracker.reserve_guarded(estimated_tokens).await else {
+            return Err(TranslationError::QuotaExceededError);
+        };
+
+        let result = match pinned {
+            Some(model) => self.translate_with_model(request, model, deadline).await,
+            None => self.translate_with_lanes(request, &lanes, deadline).await,
+        }?;
+
+        // Track token usage
+        reservation.settle(&result.model_used, result.tokens_used).await;
+
+        // Update current model
+        {
+            let mut current = self.current_model.lock().await;
+            *current = result.model_used.clone();
+        }
+
+        Ok(result)
+    }
+
+    /// Try `lanes` in order until one translates the request; the first
+    /// lane's error is reported if every lane fails, `TimeoutError` once
+    /// `deadline` has passed
+    async fn translate_with_lanes(
+        &self,
+        request: &TranslationRequest,
+        lanes: &[LaneType],
+        deadline: Option<Instant>,
+    ) -> Result<TranslationResult> {
+        let mut result: Result<TranslationResult> = Err(TranslationError::ConfigError {
+            message: "No lanes to try".to_string(),
+        });
+        for (i, &lane) in lanes.iter().enumerate() {
+            match self.translate_with_lane(request, lane, deadline).await {
+                Ok(trans_result) => return Ok(trans_result),
+                // Another lane would only spend more
+                Err(e @ TranslationError::CostLimitExceeded { .. }) => return Err(e),
+                Err(e @ TranslationError::TimeoutError) if is_past(deadline) => return Err(e),
+                Err(e) if i == 0 => {
+                    if let Some(next) = lanes.get(1) {
+                        warn!("{} lane failed: {}, trying {} lane", lane, e, next);
                     }
+                    result = Err(e);
                 }
+                Err(e) => warn!("{} lane also failed: {}", lane, e),
             }
         }
+        result
     }
 
-    /// Translate with specific lane
+    /// Translate with specific lane; `TimeoutError` if its models timed out
+    /// or `deadline` passed
     async fn translate_with_lane(
         &self,
         request: &TranslationRequest,
         lane: LaneType,
+        deadline: Option<Instant>,
     ) -> Result<TranslationResult> {
-        let models = self.config.get_models_by_lane(lane);
+        let models: Vec<&Model> = self
+            .config
+            .models_for(request, lane)
+            .into_iter()
+            .filter(|m| self.is_model_enabled(&m.id))
+            .collect();
 
         if models.is_empty() {
             return Err(TranslationError::ConfigError {
@@ -139,27 +527,48 @@ impl AsyncTranslator {
             });
         }
 
-        // Try each model in the lane
+        // Try each model in the lane, skipping those whose circuit is open
+        let mut timed_out = false;
         for model in models {
-            match self.translate_with_model(request, model).await {
-                Ok(result) => return Ok(result),
+            if is_past(deadline) {
+                return Err(TranslationError::TimeoutError);
+            }
+            let Some(_permit) = self.breaker.allow(&model.id) else {
+                debug!("Skipping model {}, its circuit is open", model.id);
+                continue;
+            };
+            match self.translate_with_model(request, model, deadline).await {
+                Ok(result) => {
+                    self.breaker.record_success(&model.id);
+                    return Ok(result);
+                }
+                Err(e @ TranslationError::CostLimitExceeded { .. }) => return Err(e),
                 Err(e) => {
                     warn!("Model {} failed: {}", model.id, e);
+                    timed_out |= matches!(e, TranslationError::TimeoutError);
+                    self.breaker.record_failure(&model.id);
                     continue;
                 }
             }
         }
 
+        if timed_out || is_past(deadline) {
+            return Err(TranslationError::TimeoutError);
+        }
         Err(TranslationError::ConfigError {
             message: format!("All models in lane {} failed", lane),
         })
     }
 
-    /// Translate with specific model
+    /// Translate with specific model, retrying until `deadline`.
+    ///
+    /// A backoff that would end past the deadline is not slept: no attempt
+    /// could follow it, so `TimeoutError` is returned right away.
     async fn translate_with_model(
         &self,
         request: &TranslationRequest,
         model: &Model,
+        deadline: Option<Instant>,
     ) -> Result<TranslationResult> {
         let mut last_error = None;
 
@@ -167,10 +576,42 @@ impl AsyncTranslator {
         for attempt in 0..=self.config.max_retries {
             if attempt > 0 {
                 debug!("Retry attempt {} for model {}", attempt, model.id);
-                sleep(Duration::from_millis(self.config.retry_delay_ms * 2_u64.pow(attempt - 1))).await;
+                metrics::record_retry(model);
+                // The server's Retry-After wins over our own backoff
+                let delay = match &last_error {
+                    Some(TranslationError::RateLimitError { retry_after: Some(secs) }) => {
+                        Duration::from_secs(*secs)
+                    }
+                    _ => self.backoff_delay(attempt),
+                };
+                if deadline.is_some_and(|deadline| Instant::now() + delay >= deadline) {
+                    debug!("Retry budget spent for model {}", model.id);
+                    return Err(TranslationError::TimeoutError);
+                }
+                sleep(delay).await;
+            }
+
+            // Input plus a similarly sized output
+            let input_tokens = estimate_tokens(&request.text, &model.id);
+            let estimate = self.config.cost(&model.id, input_tokens * 2);
+            let reservation = self.cost_tracker.reserve_guarded(estimate)?;
+
+            let started = std::time::Instant::now();
+            let send = self.send_request(request, model);
+            let sent = match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, send)
+                    .await
+                    .unwrap_or(Err(TranslationError::TimeoutError)),
+                None => send.await,
+            };
+            let outcome = sent.as_ref().map(|result| result.tokens_used);
+            metrics::record_request(model, started.elapsed(), outcome);
+            match &sent {
+                Ok(result) => reservation.settle(self.config.cost(&model.id, result.tokens_used)),
+                Err(_) => drop(reservation),
             }
 
-            match self.send_request(request, model).await {
+            match sent {
                 Ok(result) => {
                     if attempt > 0 {
                         info!("Successfully translated after {} retries", attempt);
@@ -183,7 +624,13 @@ impl AsyncTranslator {
                     // Don't retry on certain errors
                     match &last_error {
                         Some(TranslationError::QuotaExceededError) => break,
+                        Some(TranslationError::ConfigError { .. }) => break,
                         Some(TranslationError::InvalidResponseError { .. }) => continue,
+                        Some(TranslationError::TimeoutError) => {
+                            let timeout_ms = self.config.timeout_ms;
+                            warn!("Model {} timed out after {}ms", model.id, timeout_ms);
+                            continue;
+                        }
                         _ => {}
                     }
                 }
@@ -193,119 +640,151 @@ impl AsyncTranslator {
         Err(last_error.unwrap())
     }
 
+    /// Delay before retry `attempt` (1-based): `retry_delay_ms * 2^(attempt-1)`,
+    /// or a random duration up to that when jitter is enabled.
+    fn backoff_delay(&self, attempt: u32) -> Duration {
+        let backoff = self.config.retry_delay_ms.saturating_mul(2_u64.saturating_pow(attempt - 1));
+        if !self.config.retry_jitter {
+            return Duration::from_millis(backoff);
+        }
+        let jittered = self.jitter_rng.lock().unwrap().gen_range(0..=backoff);
+        Duration::from_millis(jittered)
+    }
+
     /// Send actual HTTP request
     async fn send_request(
         &self,
         request: &TranslationRequest,
         model: &Model,
     ) -> Result<TranslationResult> {
-        let mut body = serde_json::json!({
+        let mut results = self.send_items(&[request], model).await?;
+        Ok(results.remove(0))
+    }
+
+    /// Send several requests as the input items of one API call.
+    ///
+    /// Output `i` of the response is mapped back to request `i`; a response with
+    /// a different number of outputs is rejected.
+    async fn send_items(
+        &self,
+        requests: &[&TranslationRequest],
+        model: &Model,
+    ) -> Result<Vec<TranslationResult>> {
+        let glossary = &self.config.glossary;
+        // Do-not-translate terms travel as placeholders and cannot be altered
+        let masked: Vec<(String, Vec<&str>)> =
+            requests.iter().map(|request| glossary.mask(&request.text)).collect();
+        let input: Vec<serde_json::Value> = requests
+            .iter()
+            .zip(&masked)
+            .map(|(request, (text, _))| {
+                let mut options = serde_json::json!({
+                    "target_language": request.target_lang
+                });
+                // Add source language if specified
+                if let Some(source_lang) = &request.source_lang {
+                    options["source_language"] = serde_json::json!(source_lang);
+                }
+                // What the text is about, to disambiguate it
+                if let Some(context) = &request.context {
+                    options["context"] = serde_json::json!(context);
+                }
+                let terms = glossary.terms_in(&request.text);
+                if !terms.is_empty() {
+                    options["glossary"] = terms
+                        .iter()
+                        .map(|(source, target)| {
+                            serde_json::json!({"source": source, "target": target})
+                        })
+                        .collect();
+                }
+                serde_json::json!({
+                    "role": "user",
+                    "content": [{
+                        "type": "input_text",
+                        "text": text,
+                        "translation_options": options
+                    }]
+                })
+            })
+            .collect();
+        let body = serde_json::json!({
             "model": model.id,
-            "input": [{
-                "role": "user",
-                "content": [{
-                    "type": "input_text",
-                    "text": request.text,
-                    "translation_options": {
-                        "target_language": request.target_lang
-                    }
-                }]
-            }]
+            "input": input
         });
 
-        // Add source language if specified
-        if let Some(source_lang) = &request.source_lang {
-            if let Some(content) = body["input"][0]["content"][0].as_object_mut() {
-                if let Some(opts) = content.get_mut("translation_options") {
-                    if let Some(obj) = opts.as_object_mut() {
-                        obj.insert("source_language".to_string(), serde_json::json!(source_lang));
-                    }
-                }
+        // All items of one call share an endpoint; the first request decides
+        let endpoint = match requests.first().and_then(|r| r.endpoint_override.as_deref()) {
+            Some(endpoint) => {
+                validate_endpoint(endpoint)?;
+                endpoint
             }
-        }
+            None => &self.config.api_endpoint,
+        };
 
-        let response = self
+        let mut http_request = self
             .client
-            .post(&self.config.api_endpoint)
+            .post(endpoint)
             .header("Authorization", format!("Bearer {}", self.config.api_key))
-            .header("Content-Type", "application/json")
+            .header("Content-Type", "application/json");
+        for (name, value) in &self.config.extra_headers {
+            http_request = http_request.header(name, value);
+        }
+        let response = http_request
             .json(&body)
             .send()
             .await
-            .map_err(|e| TranslationError::NetworkError {
-                message: e.to_string(),
-            })?;
+            .map_err(|e| send_error(e, |message| TranslationError::NetworkError { message }))?;
 
         let status = response.status();
 
         if status.is_success() {
-            let json: serde_json::Value = response
-                .json()
-                .await
-                .map_err(|e| TranslationError::InvalidResponseError {
-                    message: e.to_string(),
-                })?;
-
-            // Parse response - support both OpenAI-style and Doubao-style formats
-            let translation = if let Some(choices) = json["output"]["choices"].as_array() {
-                // OpenAI-style format: {"output": {"choices": [{"message": {"content": "..."}}]}}
-                choices
-                    .get(0)
-                    .and_then(|c| c["message"]["content"].as_str())
-            } else if let Some(output) = json["output"].as_array() {
-                // Doubao-style format: {"output": [{"content": [{"text": "..."}]}]}
-                output
-                    .get(0)
-                    .and_then(|o| o["content"].as_array())
-                    .and_then(|content| content.get(0))
-                    .and_then(|c| c["text"].as_str())
-            } else {
-                None
-            }
-            .ok_or_else(|| TranslationError::InvalidResponseError {
-                message: "No translation in response".to_string(),
-            })?
-            .to_string();
-
-            let tokens_used = json["usage"]["total_tokens"]
-                .as_u64()
-                .unwrap_or(0) as usize;
-
-            // Parse detected source language - support both formats
-            let detected_source_lang = if let Some(choices) = json["output"]["choices"].as_array() {
-                // OpenAI-style format
-                choices
-                    .get(0)
-                    .and_then(|c| c["message"]["detected_source_language"].as_str())
-            } else if let Some(output) = json["output"].as_array() {
-                // Doubao-style format - check if detected source language is present
-                output
-                    .get(0)
-                    .and_then(|o| o["detected_source_language"].as_str())
-            } else {
-                None
-            }
-            .map(|s| s.to_string());
+            let json: serde_json::Value = response.json().await.map_err(|e| {
+                send_error(e, |message| TranslationError::InvalidResponseError { message })
+            })?;
+
+            let format = self.config.response_format;
+            let outputs = parse_outputs(&json, requests.len(), format)?
+                .into_iter()
+                .zip(requests.iter().zip(&masked))
+                .map(|((translation, detected), (request, (_, terms)))| {
+                    let translation = Glossary::unmask(&translation, terms).ok_or_else(|| {
+                        TranslationError::InvalidResponseError {
+                            message: "Do-not-translate placeholder missing from translation"
+                                .to_string(),
+                        }
+                    })?;
+                    Ok((glossary.enforce(&request.text, &translation), detected))
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            // Usage is reported for the whole call; spread it over the items
+            let total_tokens = format.total_tokens(&json);
+            let share = total_tokens / outputs.len();
+            let remainder = total_tokens % outputs.len();
 
             let request_id = json["id"].as_str().map(|s| s.to_string());
 
-            Ok(TranslationResult {
-                translation,
-                detected_source_lang,
-                tokens_used,
-                model_used: model.id.clone(),
-                request_id,
-            })
+            Ok(outputs
+                .into_iter()
+                .enumerate()
+                .map(|(i, (translation, detected_source_lang))| TranslationResult {
+                    translation,
+                    detected_source_lang,
+                    tokens_used: if i == 0 { share + remainder } else { share },
+                    model_used: model.id.clone(),
+                    request_id: request_id.clone(),
+                })
+                .collect())
         } else {
-            // Clone status before consuming response
+            // Read headers before the body consumes the response
             let status_code = status.as_u16();
+            let retry_after = retry_after_secs(response.headers(), chrono::Utc::now());
             let error_text = response.text().await.unwrap_or_default();
 
             // Handle rate limiting
             if status_code == 429 {
-                // Need to re-parse headers since response was consumed
-                // For simplicity, just return generic rate limit error
-                return Err(TranslationError::RateLimitError { retry_after: None });
+                return Err(TranslationError::RateLimitError { retry_after });
             }
 
             // Handle quota exceeded
@@ -320,19 +799,31 @@ impl AsyncTranslator {
         }
     }
 
-    /// Batch translate multiple requests
+    /// Batch translate multiple requests concurrently.
+    ///
+    /// Up to `max_concurrent` requests are in flight at once (gated inside
+    /// [`translate`](Self::translate)); results are returned in input order.
     pub async fn translate_batch(
         &self,
         requests: Vec<TranslationRequest>,
     ) -> Vec<Result<TranslationResult>> {
-        let mut results = Vec::new();
-
-        for request in requests {
-            let result = self.translate(&request).await;
-            results.push(result);
-        }
+        futures::future::join_all(requests.iter().map(|request| self.translate(request))).await
+    }
 
-        results
+    /// Language the API detects for `text`, from translating its first
+    /// [`DETECT_SAMPLE_CHARS`] characters.
+    ///
+    /// The sample goes to English, or to Chinese when it already looks
+    /// English, so it is never a same-language request. `None` if the API
+    /// did not report a language.
+    pub async fn detect_language(&self, text: &str) -> Result<Option<String>> {
+        let sample: String = text.trim().chars().take(DETECT_SAMPLE_CHARS).collect();
+        let target_lang = match crate::utils::lang::detect_language(&sample).as_deref() {
+            Some("en") => "zh",
+            _ => "en",
+        };
+        let request = TranslationRequest::new(sample, target_lang.to_string());
+        Ok(self.translate(&request).await?.detected_source_lang)
     }
 
     /// Get current token usage
@@ -340,6 +831,31 @@ impl AsyncTranslator {
         self.token_tracker.get_stats().await
     }
 
+    /// Today's tokens by model id
+    pub async fn get_usage_by_model(&self) -> std::collections::BTreeMap<String, usize> {
+        self.token_tracker.get_stats_by_model().await
+    }
+
+    /// Continue counting from token usage saved by an earlier process
+    pub async fn restore_token_usage(&self, usage: &crate::core::models::TokenUsage) {
+        self.token_tracker.restore(usage).await;
+    }
+
+    /// Continue counting against `max_cost` from spend saved by an earlier process
+    pub fn restore_cost_spent(&self, spent: f64) {
+        self.cost_tracker.restore(spent);
+    }
+
+    /// Estimated spend of this translator so far, from `prices` in the config
+    pub fn get_cost_spent(&self) -> f64 {
+        self.cost_tracker.spent()
+    }
+
+    /// Cache hits and misses so far (zero when no cache is configured)
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.as_ref().map(|cache| cache.stats()).unwrap_or_default()
+    }
+
     /// Get current model
     pub async fn get_current_model(&self) -> String {
         self.current_model.lock().await.clone()
@@ -347,7 +863,43 @@ impl AsyncTranslator {
 
     /// Get available models
     pub fn get_available_models(&self) -> Vec<&Model> {
-        self.config.get_enabled_models()
+        self.config
+            .get_enabled_models()
+            .into_iter()
+            .filter(|m| self.is_model_enabled(&m.id))
+            .collect()
+    }
+
+    /// Switch a configured model on or off for routing, shared by all clones
+    /// of this translator.
+    ///
+    /// Enabling cannot bring back a model disabled in the config.
+    pub fn set_model_enabled(&self, id: &str, enabled: bool) -> Result<()> {
+        if self.config.find_model(id).is_none() {
+            return Err(TranslationError::ConfigError {
+                message: format!("Unknown model: {}", id),
+            });
+        }
+        let mut disabled = self.disabled_models.write().unwrap_or_else(|e| e.into_inner());
+        if enabled {
+            disabled.remove(id);
+        } else {
+            disabled.insert(id.to_string());
+        }
+        info!("Model {} {}", id, if enabled { "enabled" } else { "disabled" });
+        Ok(())
+    }
+
+    /// Whether `id` is enabled in the config and not switched off at runtime
+    pub fn is_model_enabled(&self, id: &str) -> bool {
+        let configured = self.config.find_model(id).is_some_and(|m| m.enabled);
+        let disabled = self.disabled_models.read().unwrap_or_else(|e| e.into_inner());
+        configured && !disabled.contains(id)
+    }
+
+    /// Configuration this translator was built with
+    pub fn config(&self) -> &TranslatorConfig {
+        &self.config
     }
 
     /// Get model by ID
@@ -356,6 +908,160 @@ impl AsyncTranslator {
     }
 }
 
+/// `TimeoutError` if `e` is reqwest's timeout, else `other` of its message
+fn send_error(
+    e: reqwest::Error,
+    other: impl FnOnce(String) -> TranslationError,
+) -> TranslationError {
+    if e.is_timeout() {
+        TranslationError::TimeoutError
+    } else {
+        other(e.to_string())
+    }
+}
+
+/// Whether `deadline` is set and has passed
+fn is_past(deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|deadline| Instant::now() >= deadline)
+}
+
+/// HTTP client for `config`: its timeout, proxy and extra root certificate
+fn http_client(config: &TranslatorConfig) -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder()
+        .timeout(Duration::from_millis(config.timeout_ms))
+        .pool_idle_timeout(Some(Duration::from_secs(30)))
+        .pool_max_idle_per_host(10);
+
+    if let Some(url) = &config.proxy_url {
+        let proxy = reqwest::Proxy::all(url).map_err(|e| TranslationError::ConfigError {
+            message: format!("Invalid proxy URL '{}': {}", url, e),
+        })?;
+        // An explicit proxy turns off reqwest's own environment lookup, NO_PROXY included
+        builder = builder.proxy(proxy.no_proxy(reqwest::NoProxy::from_env()));
+        debug!("Sending API requests through proxy {}", url);
+    }
+
+    if let Some(path) = &config.extra_ca_cert {
+        let file_error = |message: String| TranslationError::FileError {
+            path: path.display().to_string(),
+            message,
+        };
+        let pem = std::fs::read(path).map_err(|e| file_error(e.to_string()))?;
+        let cert = reqwest::Certificate::from_pem(&pem).map_err(|e| file_error(e.to_string()))?;
+        builder = builder.add_root_certificate(cert);
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Check that `endpoint` is an absolute http(s) URL
+fn validate_endpoint(endpoint: &str) -> Result<()> {
+    match reqwest::Url::parse(endpoint) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") && url.has_host() => Ok(()),
+        Ok(url) => Err(TranslationError::ConfigError {
+            message: format!("Endpoint override must be http(s): {}", url),
+        }),
+        Err(e) => Err(TranslationError::ConfigError {
+            message: format!("Invalid endpoint override '{}': {}", endpoint, e),
+        }),
+    }
+}
+
+/// Seconds to wait before retrying, from `Retry-After` or `X-RateLimit-Reset`.
+///
+/// `Retry-After` may be delta-seconds or an HTTP date. `X-RateLimit-Reset` is
+/// read as delta-seconds, or as a Unix timestamp when it is that large.
+fn retry_after_secs(
+    headers: &reqwest::header::HeaderMap,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Option<u64> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).map(str::trim);
+    let seconds = |value: &str| value.parse::<f64>().ok().filter(|s| *s >= 0.0);
+
+    if let Some(value) = header("retry-after") {
+        if let Some(secs) = seconds(value) {
+            return Some(secs.ceil() as u64);
+        }
+        if let Ok(date) = chrono::DateTime::parse_from_rfc2822(value) {
+            return Some((date.timestamp() - now.timestamp()).max(0) as u64);
+        }
+    }
+
+    let reset = seconds(header("x-ratelimit-reset")?)?.ceil() as i64;
+    // Anything past 2001-09-09 is an epoch timestamp rather than a delay
+    if reset > 1_000_000_000 {
+        Some((reset - now.timestamp()).max(0) as u64)
+    } else {
+        Some(reset as u64)
+    }
+}
+
+/// Extract `(translation, detected_source_language)` for each of `expected` input items.
+///
+/// A [`ResponseFormat::Doubao`] response may be OpenAI-style
+/// (`output.choices[i].message`) or Doubao-style (`output[i].content[0]`);
+/// an [`ResponseFormat::OpenAIChat`] one has top-level `choices[i].message`.
+/// Choices carrying an `index` are matched by it, everything else by position.
+fn parse_outputs(
+    json: &serde_json::Value,
+    expected: usize,
+    format: ResponseFormat,
+) -> Result<Vec<(String, Option<String>)>> {
+    let missing = |i: usize| TranslationError::InvalidResponseError {
+        message: format!("No translation in response for input item {}", i),
+    };
+
+    let choices = match format {
+        ResponseFormat::Doubao => &json["output"]["choices"],
+        ResponseFormat::OpenAIChat => &json["choices"],
+    };
+    let outputs: Vec<(String, Option<String>)> =
+        if let Some(choices) = choices.as_array() {
+            // OpenAI-style format: {"output": {"choices": [{"message": {"content": "..."}}]}}
+            let mut choices: Vec<&serde_json::Value> = choices.iter().collect();
+            choices.sort_by_key(|c| c["index"].as_u64().unwrap_or(u64::MAX));
+            choices
+                .iter()
+                .enumerate()
+                .map(|(i, c)| {
+                    let text = c["message"]["content"].as_str().ok_or_else(|| missing(i))?;
+                    let detected = c["message"]["detected_source_language"].as_str();
+                    Ok((text.to_string(), detected.map(|s| s.to_string())))
+                })
+                .collect::<Result<_>>()?
+        } else if let (ResponseFormat::Doubao, Some(output)) = (format, json["output"].as_array()) {
+            // Doubao-style format: {"output": [{"content": [{"text": "..."}]}]}
+            output
+                .iter()
+                .enumerate()
+                .map(|(i, o)| {
+                    let text = o["content"][0]["text"].as_str().ok_or_else(|| missing(i))?;
+                    let detected = o["detected_source_language"].as_str();
+                    Ok((text.to_string(), detected.map(|s| s.to_string())))
+                })
+                .collect::<Result<_>>()?
+        } else {
+            Vec::new()
+        };
+
+    if outputs.is_empty() {
+        return Err(TranslationError::InvalidResponseError {
+            message: "No translation in response".to_string(),
+        });
+    }
+    if outputs.len() != expected {
+        return Err(TranslationError::InvalidResponseError {
+            message: format!(
+                "Expected {} translations in response, got {}",
+                expected,
+                outputs.len()
+            ),
+        });
+    }
+
+    Ok(outputs)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -367,6 +1073,821 @@ mod tests {
         assert!(translator.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_offline_translator_needs_no_api_key() {
+        use crate::testing::test_config;
+
+        let config = TranslatorConfig {
+            api_key: String::new(),
+            ..test_config("http://127.0.0.1:9")
+        };
+        assert!(AsyncTranslator::new(config.clone()).is_err());
+        assert!(AsyncTranslator::offline(config).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_auto_lane_routing() {
+        use crate::core::models::Priority;
+        use crate::testing::{test_config, test_model, MockServer};
+
+        let server = MockServer::echo().await;
+        let mut config = test_config(&server.url);
+        config.models.push(test_model("mock-fast", LaneType::Fast));
+        let translator = AsyncTranslator::new(config).unwrap();
+
+        let batch = TranslationRequest::new("paragraph. ".repeat(200), "zh".to_string());
+        let result = translator.translate(&batch).await.unwrap();
+        assert_eq!(result.model_used, "mock-slow");
+
+        let interactive = TranslationRequest::new("Hello".to_string(), "zh".to_string())
+            .with_priority(Priority::High);
+        let result = translator.translate(&interactive).await.unwrap();
+        assert_eq!(result.model_used, "mock-fast");
+
+        let models: Vec<String> = server.requests().iter().map(|r| r.model()).collect();
+        assert_eq!(models, vec!["mock-slow", "mock-fast"]);
+    }
+
+    #[tokio::test]
+    async fn test_identical_concurrent_requests_share_one_call() {
+        use crate::testing::{test_config, MockServer};
+
+        let server = MockServer::echo().await;
+        let translator = AsyncTranslator::new(test_config(&server.url)).unwrap();
+
+        let request = TranslationRequest::new("Copyright 2024".to_string(), "zh".to_string());
+        let results =
+            futures::future::join_all((0..10).map(|_| translator.translate(&request))).await;
+
+        assert_eq!(server.request_count(), 1);
+        for result in &results {
+            assert_eq!(result.as_ref().unwrap().translation, "zh:Copyright 2024");
+        }
+        let tokens: usize = results.iter().map(|r| r.as_ref().unwrap().tokens_used).sum();
+        assert_eq!(tokens, 10);
+
+        // Once finished, the same text is translated again
+        translator.translate(&request).await.unwrap();
+        assert_eq!(server.request_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_request_does_not_block_later_ones() {
+        use crate::testing::{test_config, MockReply, MockServer};
+
+        let server = MockServer::start(|req| {
+            MockReply::translations(&req.input_texts()).with_delay(Duration::from_millis(200))
+        })
+        .await;
+        let config = TranslatorConfig {
+            max_concurrent: 1,
+            ..test_config(&server.url)
+        };
+        let translator = AsyncTranslator::new(config).unwrap();
+
+        // The only caller gives up while its call holds the only permit
+        let first = TranslationRequest::new("First".to_string(), "zh".to_string());
+        let cancelled =
+            tokio::time::timeout(Duration::from_millis(50), translator.translate(&first)).await;
+        assert!(cancelled.is_err());
+
+        let second = TranslationRequest::new("Second".to_string(), "zh".to_string());
+        let result = tokio::time::timeout(Duration::from_secs(5), translator.translate(&second))
+            .await
+            .expect("request blocked behind a cancelled one")
+            .unwrap();
+        assert_eq!(result.translation, "Second");
+        // The identical request joins or repeats the first call instead of hanging
+        tokio::time::timeout(Duration::from_secs(5), translator.translate(&first))
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dropping_last_waiter_cancels_shared_call() {
+        use crate::testing::{test_config, MockReply, MockServer};
+
+        let server = MockServer::start(|req| {
+            MockReply::translations(&req.input_texts()).with_delay(Duration::from_millis(300))
+        })
+        .await;
+        let config = TranslatorConfig {
+            max_concurrent: 1,
+            ..test_config(&server.url)
+        };
+        let translator = AsyncTranslator::new(config).unwrap();
+        let request = TranslationRequest::new("Shared".to_string(), "zh".to_string());
+
+        // A joined caller keeps the call going after the first one gives up
+        let joined = tokio::spawn({
+            let translator = translator.clone();
+            let request = request.clone();
+            async move { translator.translate(&request).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let cancelled =
+            tokio::time::timeout(Duration::from_millis(50), translator.translate(&request)).await;
+        assert!(cancelled.is_err());
+        assert_eq!(joined.await.unwrap().unwrap().translation, "Shared");
+
+        // With no one left waiting, the call is aborted and its permit freed
+        let cancelled =
+            tokio::time::timeout(Duration::from_millis(50), translator.translate(&request)).await;
+        assert!(cancelled.is_err());
+        tokio::task::yield_now().await;
+        assert_eq!(translator.semaphore.available_permits(), 1);
+        assert!(translator.inflight.calls.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_requests_on_other_lanes_do_not_share_a_call() {
+        use crate::testing::{test_config, test_model, MockReply, MockServer};
+
+        // The slow lane is down, the fast lane answers after a while
+        let server = MockServer::start(|req| {
+            if req.model() == "mock-slow" {
+                return MockReply::error(503, "slow lane down");
+            }
+            MockReply::translations(&req.input_texts()).with_delay(Duration::from_millis(100))
+        })
+        .await;
+        let mut config = test_config(&server.url);
+        config.models.push(test_model("mock-fast", LaneType::Fast));
+        let translator = AsyncTranslator::new(config).unwrap();
+
+        let request = TranslationRequest::new("Same text".to_string(), "zh".to_string());
+        let fast = request.clone().with_lane_policy(LanePolicy::FastOnly);
+        let slow = request.with_lane_policy(LanePolicy::SlowOnly);
+        let (fast, slow) = tokio::join!(translator.translate(&fast), async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            translator.translate(&slow).await
+        });
+
+        assert_eq!(fast.unwrap().model_used, "mock-fast");
+        // The slow lane's own error, not the fast lane's answer
+        assert!(slow.is_err());
+        assert_eq!(server.request_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_context_is_sent_only_when_set() {
+        use crate::testing::{test_translator, MockServer};
+
+        let server = MockServer::echo().await;
+        let translator = test_translator(&server.url);
+
+        let request = TranslationRequest::new("Fold in the whites".to_string(), "zh".to_string());
+        translator.translate(&request).await.unwrap();
+        let request = request.with_context("a cooking recipe");
+        translator.translate(&request).await.unwrap();
+
+        let options: Vec<serde_json::Value> = server
+            .requests()
+            .iter()
+            .map(|r| r.body["input"][0]["content"][0]["translation_options"].clone())
+            .collect();
+        assert!(options[0].get("context").is_none());
+        assert_eq!(options[1]["context"], "a cooking recipe");
+    }
+
+    #[tokio::test]
+    async fn test_glossary_terms_are_enforced() {
+        use crate::testing::{test_config, MockReply, MockServer};
+
+        // A model that mangles product names and leaves jargon in English
+        let server = MockServer::start(|req| {
+            let texts: Vec<String> = req
+                .input_texts()
+                .iter()
+                .map(|t| format!("翻译:{}", t.replace("Kubernetes", "库伯")))
+                .collect();
+            MockReply::translations(&texts)
+        })
+        .await;
+        let mut config = test_config(&server.url);
+        config.glossary = Glossary {
+            terms: [("pull request".to_string(), "合并请求".to_string())].into(),
+            do_not_translate: ["Kubernetes".to_string()].into(),
+        };
+        let translator = AsyncTranslator::new(config).unwrap();
+
+        let request = TranslationRequest::new(
+            "Open a pull request against Kubernetes".to_string(),
+            "zh".to_string(),
+        );
+        let result = translator.translate(&request).await.unwrap();
+
+        assert_eq!(result.translation, "翻译:Open a 合并请求 against Kubernetes");
+        let sent = &server.requests()[0];
+        assert_eq!(sent.input_texts(), vec!["Open a pull request against ⟬0⟭"]);
+        assert_eq!(
+            sent.body["input"][0]["content"][0]["translation_options"]["glossary"],
+            serde_json::json!([{"source": "pull request", "target": "合并请求"}])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_language_preference_tries_preferred_model_first() {
+        use crate::testing::{test_config, test_model, MockServer};
+
+        let server = MockServer::echo().await;
+        let mut config = test_config(&server.url);
+        // Last in the slow lane, so only the preference puts it first
+        config.models.push(test_model("mock-ja", LaneType::Slow));
+        config.language_models.insert("ja".to_string(), "mock-ja".to_string());
+        let translator = AsyncTranslator::new(config).unwrap();
+
+        for target in ["ja", "zh", "ja-JP"] {
+            let request = TranslationRequest::new(format!("Hello {}", target), target.to_string());
+            translator.translate(&request).await.unwrap();
+        }
+
+        let models: Vec<String> = server.requests().iter().map(|r| r.model()).collect();
+        assert_eq!(models, vec!["mock-ja", "mock-slow", "mock-ja"]);
+    }
+
+    #[tokio::test]
+    async fn test_lane_policy_sets_lane_order_and_fallback() {
+        use crate::core::models::LanePolicy;
+        use crate::testing::{echo_translation, test_config, test_model, MockReply, MockServer};
+
+        // The slow lane is down, the fast lane answers
+        let server = MockServer::start(|req| {
+            if req.model() == "mock-slow" {
+                return MockReply::error(503, "slow lane down");
+            }
+            let texts = req.input_texts();
+            MockReply::translations(&[echo_translation(&req.target_lang(), &texts[0])])
+        })
+        .await;
+        let translator = |policy: Option<LanePolicy>| {
+            let mut config = test_config(&server.url);
+            config.models.push(test_model("mock-fast", LaneType::Fast));
+            config.lane_policy = policy;
+            AsyncTranslator::new(config).unwrap()
+        };
+        let calls = |from: usize| -> Vec<String> {
+            server.requests()[from..].iter().map(|r| r.model()).collect()
+        };
+
+        let cases = [
+            (LanePolicy::SlowThenFast, vec!["mock-slow", "mock-fast"], true),
+            (LanePolicy::FastThenSlow, vec!["mock-fast"], true),
+            (LanePolicy::SlowOnly, vec!["mock-slow"], false),
+            (LanePolicy::FastOnly, vec!["mock-fast"], true),
+        ];
+        for (n, (policy, expected, succeeds)) in cases.into_iter().enumerate() {
+            let from = server.request_count();
+            let request = TranslationRequest::new(format!("Text {}", n), "zh".to_string());
+            let result = translator(Some(policy)).translate(&request).await;
+            assert_eq!(calls(from), expected, "{:?}", policy);
+            match result {
+                Ok(result) => {
+                    assert!(succeeds, "{:?}", policy);
+                    assert_eq!(result.model_used, "mock-fast");
+                }
+                // The slow lane's own error, not a fallback's
+                Err(TranslationError::ConfigError { message }) => {
+                    assert!(!succeeds, "{:?}", policy);
+                    assert_eq!(message, "All models in lane slow failed");
+                }
+                Err(e) => panic!("{:?}: {}", policy, e),
+            }
+        }
+
+        // A request's policy wins over the configured one
+        let from = server.request_count();
+        let request = TranslationRequest::new("Text 4".to_string(), "zh".to_string())
+            .with_lane_policy(LanePolicy::SlowOnly);
+        assert!(translator(Some(LanePolicy::FastOnly)).translate(&request).await.is_err());
+        assert_eq!(calls(from), vec!["mock-slow"]);
+    }
+
+    #[tokio::test]
+    async fn test_requests_go_through_configured_proxy() {
+        use crate::testing::{test_config, MockServer};
+
+        // The mock answers whatever it is sent, so it stands in for the proxy
+        // of an API host that does not resolve
+        let proxy = MockServer::echo().await;
+        let mut config = test_config("http://api.invalid/api/v3/responses");
+        config.proxy_url = Some(proxy.url.clone());
+        let translator = AsyncTranslator::new(config).unwrap();
+
+        let request = TranslationRequest::new("Hello".to_string(), "zh".to_string());
+        let result = translator.translate(&request).await.unwrap();
+        assert_eq!(result.translation, "zh:Hello");
+        assert_eq!(proxy.request_count(), 1);
+
+        let mut config = test_config(&proxy.url);
+        config.proxy_url = Some("not a url".to_string());
+        assert!(matches!(
+            AsyncTranslator::new(config),
+            Err(TranslationError::ConfigError { .. })
+        ));
+        let mut config = test_config(&proxy.url);
+        config.extra_ca_cert = Some("/no/such/ca.pem".into());
+        assert!(matches!(AsyncTranslator::new(config), Err(TranslationError::FileError { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_extra_headers_are_sent() {
+        use crate::testing::{test_config, MockServer};
+
+        let server = MockServer::echo().await;
+        let mut config = test_config(&server.url);
+        config.extra_headers.insert("X-Tenant-Id".to_string(), "acme".to_string());
+        let translator = AsyncTranslator::new(config).unwrap();
+
+        let request = TranslationRequest::new("Hello".to_string(), "zh".to_string());
+        translator.translate(&request).await.unwrap();
+        let sent = &server.requests()[0];
+        assert_eq!(sent.headers["x-tenant-id"], "acme");
+        assert_eq!(sent.headers["authorization"], "Bearer test_key");
+    }
+
+    #[tokio::test]
+    async fn test_retries_stop_at_request_deadline() {
+        use crate::testing::{test_config, MockReply, MockServer};
+
+        let server = MockServer::start(|_| MockReply::error(429, "slow down")).await;
+        let mut config = test_config(&server.url);
+        config.max_retries = 20;
+        config.retry_delay_ms = 50;
+        config.retry_jitter = false;
+        config.max_request_duration_ms = Some(300);
+        let translator = AsyncTranslator::new(config).unwrap();
+
+        let started = std::time::Instant::now();
+        let request = TranslationRequest::new("Hello".to_string(), "zh".to_string());
+        let result = translator.translate(&request).await;
+        assert!(matches!(result, Err(TranslationError::TimeoutError)), "{:?}", result);
+        // Backoffs of 50 and 100ms fit; 200ms more would end past the deadline
+        assert!(started.elapsed() < Duration::from_millis(300));
+        assert_eq!(server.request_count(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_slow_server_times_out() {
+        use crate::testing::{test_config, MockReply, MockServer};
+
+        let server = MockServer::start(|req| {
+            MockReply::translations(&req.input_texts()).with_delay(Duration::from_millis(500))
+        })
+        .await;
+        let mut config = test_config(&server.url);
+        config.timeout_ms = 100;
+        config.max_retries = 1;
+        let translator = AsyncTranslator::new(config).unwrap();
+
+        let request = TranslationRequest::new("Hello".to_string(), "zh".to_string());
+        let result = translator.translate(&request).await;
+        assert!(matches!(result, Err(TranslationError::TimeoutError)), "{:?}", result);
+        // Timeouts are retried
+        assert_eq!(server.request_count(), 2);
+
+        // An unreachable host stays a network error (pinned, to see the model's own error)
+        let translator = AsyncTranslator::new(test_config("http://127.0.0.1:1/api")).unwrap();
+        let result = translator.translate(&request.with_model("mock-slow")).await;
+        assert!(matches!(result, Err(TranslationError::NetworkError { .. })), "{:?}", result);
+    }
+
+    #[tokio::test]
+    async fn test_pinned_model_bypasses_lanes() {
+        use crate::testing::{test_config, test_model, MockServer};
+
+        let server = MockServer::echo().await;
+        let mut config = test_config(&server.url);
+        config.models.push(test_model("mock-vision", LaneType::Fast));
+        let translator = AsyncTranslator::new(config).unwrap();
+
+        let request = TranslationRequest::new("A cat on a mat".to_string(), "zh".to_string())
+            .with_model("mock-vision");
+        let result = translator.translate(&request).await.unwrap();
+        assert_eq!(result.model_used, "mock-vision");
+        // Unpinned, the same text takes the routed slow lane
+        let request = TranslationRequest::new("A cat on a mat".to_string(), "zh".to_string());
+        assert_eq!(translator.translate(&request).await.unwrap().model_used, "mock-slow");
+
+        translator.set_model_enabled("mock-vision", false).unwrap();
+        for id in ["mock-vision", "no-such-model"] {
+            let request =
+                TranslationRequest::new("Hi".to_string(), "zh".to_string()).with_model(id);
+            match translator.translate(&request).await {
+                Err(TranslationError::ConfigError { message }) => assert!(message.contains(id)),
+                other => panic!("{:?}", other.map(|r| r.model_used)),
+            }
+        }
+        assert_eq!(server.request_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_failing_model_is_skipped_until_cooldown() {
+        use crate::core::clock::MockClock;
+        use crate::testing::{echo_translation, test_config, test_model, MockReply, MockServer};
+
+        let server = MockServer::start(|req| {
+            if req.model() == "mock-slow" {
+                return MockReply::error(503, "unavailable");
+            }
+            let texts = req.input_texts();
+            MockReply::translations(&[echo_translation(&req.target_lang(), &texts[0])])
+        })
+        .await;
+        let mut config = test_config(&server.url);
+        config.models.push(test_model("mock-backup", LaneType::Slow));
+        config.breaker_threshold = 2;
+        config.breaker_cooldown_ms = 60_000;
+        let clock = MockClock::new(chrono::Utc::now());
+        let translator = AsyncTranslator::new(config)
+            .unwrap()
+            .with_clock(Arc::new(clock.clone()));
+        let translate = |n: usize| {
+            let translator = translator.clone();
+            async move {
+                let request = TranslationRequest::new(format!("Text {}", n), "zh".to_string());
+                translator.translate(&request).await.unwrap()
+            }
+        };
+        let tried = || server.requests().iter().filter(|r| r.model() == "mock-slow").count();
+
+        // Two failures open the circuit; the backup answers meanwhile
+        for n in 0..4 {
+            assert_eq!(translate(n).await.model_used, "mock-backup");
+        }
+        assert_eq!(tried(), 2);
+
+        // After the cooldown one probe goes out, fails, and reopens the circuit
+        clock.advance(chrono::Duration::seconds(60));
+        translate(4).await;
+        translate(5).await;
+        assert_eq!(tried(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_multiple_choices_map_to_inputs() {
+        use crate::testing::{test_config, MockReply, MockServer};
+
+        let server = MockServer::start(|req| {
+            // Answer out of order to check choices are matched by index
+            let texts = req.input_texts();
+            let choices: Vec<serde_json::Value> = texts
+                .iter()
+                .enumerate()
+                .rev()
+                .map(|(i, t)| {
+                    serde_json::json!({
+                        "index": i,
+                        "message": { "content": format!("zh:{}", t) }
+                    })
+                })
+                .collect();
+            MockReply::json(serde_json::json!({
+                "output": { "choices": choices },
+                "usage": { "total_tokens": 30 }
+            }))
+        })
+        .await;
+        let config = test_config(&server.url);
+        let model = config.models[0].clone();
+        let translator = AsyncTranslator::new(config).unwrap();
+
+        let requests: Vec<TranslationRequest> = ["one", "two", "three"]
+            .iter()
+            .map(|t| TranslationRequest::new(t.to_string(), "zh".to_string()))
+            .collect();
+        let refs: Vec<&TranslationRequest> = requests.iter().collect();
+        let results = translator.send_items(&refs, &model).await.unwrap();
+
+        let translations: Vec<&str> = results.iter().map(|r| r.translation.as_str()).collect();
+        assert_eq!(translations, vec!["zh:one", "zh:two", "zh:three"]);
+        assert_eq!(results.iter().map(|r| r.tokens_used).sum::<usize>(), 30);
+        assert_eq!(server.request_count(), 1);
+    }
+
+    #[test]
+    fn test_parse_outputs_count_mismatch() {
+        let json = serde_json::json!({
+            "output": [
+                { "content": [{ "type": "output_text", "text": "a" }] },
+                { "content": [{ "type": "output_text", "text": "b" }] }
+            ]
+        });
+        assert_eq!(parse_outputs(&json, 2, ResponseFormat::Doubao).unwrap().len(), 2);
+
+        let err = parse_outputs(&json, 3, ResponseFormat::Doubao).unwrap_err();
+        assert!(err.to_string().contains("Expected 3 translations"));
+    }
+
+    #[tokio::test]
+    async fn test_response_formats() {
+        use crate::testing::{test_config, MockReply, MockServer};
+
+        let doubao = serde_json::json!({
+            "id": "resp-1",
+            "output": [{
+                "content": [{ "type": "output_text", "text": "你好" }],
+                "detected_source_language": "en"
+            }],
+            "usage": { "input_tokens": 5, "output_tokens": 3 }
+        });
+        let openai = serde_json::json!({
+            "id": "chatcmpl-1",
+            "choices": [{ "index": 0, "message": { "role": "assistant", "content": "你好" } }],
+            "usage": { "prompt_tokens": 6, "completion_tokens": 2, "total_tokens": 8 }
+        });
+        let request = TranslationRequest::new("Hello".to_string(), "zh".to_string());
+
+        for (format, body, request_id, detected) in [
+            (ResponseFormat::Doubao, doubao.clone(), "resp-1", Some("en")),
+            (ResponseFormat::OpenAIChat, openai.clone(), "chatcmpl-1", None),
+        ] {
+            let server = MockServer::start(move |_| MockReply::json(body.clone())).await;
+            let mut config = test_config(&server.url);
+            config.response_format = format;
+            let translator = AsyncTranslator::new(config).unwrap();
+            let result = translator.translate(&request).await.unwrap();
+            assert_eq!(result.translation, "你好");
+            assert_eq!(result.tokens_used, 8);
+            assert_eq!(result.request_id.as_deref(), Some(request_id));
+            assert_eq!(result.detected_source_lang.as_deref(), detected);
+        }
+
+        // Each format only reads its own shape
+        assert!(parse_outputs(&openai, 1, ResponseFormat::Doubao).is_err());
+        assert!(parse_outputs(&doubao, 1, ResponseFormat::OpenAIChat).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_max_cost_stops_before_exceeding() {
+        use crate::testing::{test_config, MockServer};
+
+        let server = MockServer::echo().await;
+        let mut config = test_config(&server.url);
+        // The mock bills 10 tokens per request: 0.01 each
+        config.prices.insert("mock-slow".to_string(), 1.0);
+        config.max_cost = Some(0.035);
+        let translator = AsyncTranslator::new(config).unwrap();
+
+        let mut stopped = None;
+        for i in 0..10 {
+            // ~11 estimated tokens, reserved as 22 (0.022) against a bill of 0.01
+            let text = format!("segment {} {}", i, "lorem ".repeat(6));
+            let request = TranslationRequest::new(text, "zh".to_string());
+            if let Err(e) = translator.translate(&request).await {
+                stopped = Some((i, e));
+                break;
+            }
+        }
+
+        let (at, error) = stopped.expect("the cap should stop the run");
+        assert!(matches!(error, TranslationError::CostLimitExceeded { .. }));
+        assert_eq!(at, 2);
+        assert_eq!(server.request_count(), 2);
+        assert!(translator.get_cost_spent() <= 0.035);
+    }
+
+    #[tokio::test]
+    async fn test_translate_batch_runs_concurrently_in_order() {
+        use crate::testing::{echo_translation, test_translator, MockReply, MockServer};
+
+        let delay = Duration::from_millis(100);
+        let server = MockServer::start(move |req| {
+            let target = req.target_lang();
+            let texts: Vec<String> = req
+                .input_texts()
+                .iter()
+                .map(|t| echo_translation(&target, t))
+                .collect();
+            MockReply::translations(&texts).with_delay(delay)
+        })
+        .await;
+        let translator = test_translator(&server.url);
+
+        let requests: Vec<TranslationRequest> = (0..50)
+            .map(|i| TranslationRequest::new(format!("text {}", i), "zh".to_string()))
+            .collect();
+        let started = std::time::Instant::now();
+        let results = translator.translate_batch(requests).await;
+        let elapsed = started.elapsed();
+
+        // Serially this would take 50 x 100ms
+        assert!(elapsed < delay * 50 / 4, "took {:?}", elapsed);
+        for (i, result) in results.iter().enumerate() {
+            assert_eq!(result.as_ref().unwrap().translation, format!("zh:text {}", i));
+        }
+        assert_eq!(server.request_count(), 50);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_waits_for_retry_after() {
+        use crate::testing::{test_config, MockReply, MockServer};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = AtomicUsize::new(0);
+        let server = MockServer::start(move |req| {
+            if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                MockReply::error(429, "slow down").with_header("Retry-After", "2")
+            } else {
+                MockReply::translations(&req.input_texts())
+            }
+        })
+        .await;
+        let mut config = test_config(&server.url);
+        config.max_retries = 1;
+        let translator = AsyncTranslator::new(config).unwrap();
+
+        let started = std::time::Instant::now();
+        let request = TranslationRequest::new("Hello".to_string(), "zh".to_string());
+        translator.translate(&request).await.unwrap();
+        let elapsed = started.elapsed();
+
+        assert_eq!(server.request_count(), 2);
+        assert!(elapsed >= Duration::from_millis(1900), "retried after {:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(4), "retried after {:?}", elapsed);
+    }
+
+    #[test]
+    fn test_retry_after_header_formats() {
+        use reqwest::header::{HeaderMap, HeaderValue};
+
+        let now = chrono::DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let headers = |name: &'static str, value: &'static str| {
+            let mut map = HeaderMap::new();
+            map.insert(name, HeaderValue::from_static(value));
+            map
+        };
+
+        assert_eq!(retry_after_secs(&headers("retry-after", "2"), now), Some(2));
+        assert_eq!(
+            retry_after_secs(&headers("retry-after", "Wed, 01 May 2024 12:00:30 GMT"), now),
+            Some(30)
+        );
+        assert_eq!(retry_after_secs(&headers("x-ratelimit-reset", "1.5"), now), Some(2));
+        let reset_at = now.timestamp() + 10;
+        let mut map = HeaderMap::new();
+        map.insert("x-ratelimit-reset", HeaderValue::from(reset_at));
+        assert_eq!(retry_after_secs(&map, now), Some(10));
+        assert_eq!(retry_after_secs(&HeaderMap::new(), now), None);
+    }
+
+    #[test]
+    fn test_backoff_jitter_stays_within_bounds() {
+        let config = TranslatorConfig {
+            retry_delay_ms: 100,
+            seed: Some(42),
+            ..crate::testing::test_config("http://127.0.0.1:9")
+        };
+        let translator = AsyncTranslator::new(config.clone()).unwrap();
+        let replay = AsyncTranslator::new(config.clone()).unwrap();
+
+        let mut jittered = Vec::new();
+        for attempt in 1..=6 {
+            let max = Duration::from_millis(100 * 2_u64.pow(attempt - 1));
+            let delay = translator.backoff_delay(attempt);
+            assert!(delay <= max, "attempt {}: {:?} > {:?}", attempt, delay, max);
+            assert_eq!(replay.backoff_delay(attempt), delay, "same seed, same jitter");
+            jittered.push(delay);
+        }
+        // Not simply the full backoff every time
+        assert!(jittered.iter().enumerate().any(|(i, d)| *d < Duration::from_millis(100 << i)));
+
+        let fixed = TranslatorConfig {
+            retry_jitter: false,
+            ..config
+        };
+        let fixed = AsyncTranslator::new(fixed).unwrap();
+        assert_eq!(fixed.backoff_delay(3), Duration::from_millis(400));
+    }
+
+    #[tokio::test]
+    async fn test_endpoint_override_per_request() {
+        use crate::testing::{test_translator, MockServer};
+
+        let default = MockServer::echo().await;
+        let tenant = MockServer::echo().await;
+        let translator = test_translator(&default.url);
+
+        let plain = TranslationRequest::new("Hello".to_string(), "zh".to_string());
+        let routed = plain.clone().with_endpoint_override(tenant.url.clone());
+        translator.translate(&plain).await.unwrap();
+        translator.translate(&routed).await.unwrap();
+        translator.translate(&plain).await.unwrap();
+
+        assert_eq!(default.request_count(), 2);
+        assert_eq!(tenant.request_count(), 1);
+
+        let invalid = plain.with_endpoint_override("ftp://example.com/translate");
+        assert!(matches!(
+            translator.translate(&invalid).await,
+            Err(TranslationError::ConfigError { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_cache_serves_repeated_requests() {
+        use crate::testing::{test_config, MockServer};
+
+        let server = MockServer::echo().await;
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = test_config(&server.url);
+        config.cache_path = Some(dir.path().join("cache.jsonl"));
+
+        let translator = AsyncTranslator::new(config.clone()).unwrap();
+        let request = TranslationRequest::new("Hello".to_string(), "zh".to_string());
+        let first = translator.translate(&request).await.unwrap();
+        let second = translator.translate(&request).await.unwrap();
+        assert_eq!(second.translation, first.translation);
+        assert_eq!(second.model_used, "mock-slow");
+        assert_eq!(server.request_count(), 1);
+        assert_eq!(translator.cache_stats(), CacheStats { hits: 1, misses: 1 });
+
+        // Another target language is another entry
+        let french = TranslationRequest::new("Hello".to_string(), "fr".to_string());
+        translator.translate(&french).await.unwrap();
+        assert_eq!(server.request_count(), 2);
+
+        // A new run reads the cache file
+        let rerun = AsyncTranslator::new(config).unwrap();
+        assert_eq!(rerun.translate(&request).await.unwrap().translation, "zh:Hello");
+        assert_eq!(server.request_count(), 2);
+        assert_eq!(rerun.cache_stats().hits, 1);
+    }
+
+    #[tokio::test]
+    async fn test_cache_is_not_reused_after_glossary_edit() {
+        use crate::testing::{test_config, MockServer};
+
+        let server = MockServer::echo().await;
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = test_config(&server.url);
+        config.cache_path = Some(dir.path().join("cache.jsonl"));
+        config.glossary.terms = [("pull request".to_string(), "拉取请求".to_string())].into();
+        let request = TranslationRequest::new("Open a pull request".to_string(), "zh".to_string());
+
+        AsyncTranslator::new(config.clone()).unwrap().translate(&request).await.unwrap();
+        AsyncTranslator::new(config.clone()).unwrap().translate(&request).await.unwrap();
+        assert_eq!(server.request_count(), 1);
+
+        // The edited glossary no longer matches the cached translation
+        config.glossary.terms = [("pull request".to_string(), "合并请求".to_string())].into();
+        let result = AsyncTranslator::new(config).unwrap().translate(&request).await.unwrap();
+        assert_eq!(result.translation, "zh:Open a 合并请求");
+        assert_eq!(server.request_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_cache_keeps_contexts_apart() {
+        use crate::testing::{test_config, MockReply, MockServer};
+
+        let server = MockServer::start(|req| {
+            let context = &req.body["input"][0]["content"][0]["translation_options"]["context"];
+            MockReply::translations(&[format!("{}:{}", context, req.input_texts()[0])])
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = test_config(&server.url);
+        config.cache_path = Some(dir.path().join("cache.jsonl"));
+        let translator = AsyncTranslator::new(config).unwrap();
+
+        let request = TranslationRequest::new("Fold".to_string(), "zh".to_string());
+        let cooking = request.clone().with_context("a cooking recipe");
+        let poker = request.with_context("a poker guide");
+        let first = translator.translate(&cooking).await.unwrap();
+        let second = translator.translate(&poker).await.unwrap();
+        assert_eq!(first.translation, "\"a cooking recipe\":Fold");
+        assert_eq!(second.translation, "\"a poker guide\":Fold");
+        assert_eq!(server.request_count(), 2);
+
+        // The same context is a hit
+        let again = translator.translate(&cooking).await.unwrap();
+        assert_eq!(again.translation, first.translation);
+        assert_eq!(server.request_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_long_text_split_keeps_newlines() {
+        use crate::testing::{test_config, MockServer};
+
+        let server = MockServer::echo().await;
+        let mut config = test_config(&server.url);
+        config.max_input_tokens = 4;
+        let translator = AsyncTranslator::new(config).unwrap();
+
+        let text = "First line here.\nSecond line here.\n\nThird one. Fourth one.";
+        let request = TranslationRequest::new(text.to_string(), "zh".to_string());
+        let result = translator.translate(&request).await.unwrap();
+
+        assert_eq!(
+            result.translation,
+            "zh:First line here.\nzh:Second line here.\n\nzh:Third one. zh:Fourth one."
+        );
+        assert_eq!(server.request_count(), 4);
+    }
+
     #[tokio::test]
     async fn test_translator_from_env() {
         // This test requires ARK_API_KEY env var
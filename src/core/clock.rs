@@ -0,0 +1,55 @@
+//! Time source abstraction, so date-dependent logic can be tested
+
+use chrono::{DateTime, Utc};
+use std::sync::{Arc, Mutex};
+
+/// Source of the current time
+pub trait Clock: Send + Sync + std::fmt::Debug {
+    /// Current UTC time
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that only moves when told to.
+///
+/// Clones share the same time, so a test can keep one handle and advance the
+/// clock held by a [`TokenTracker`](crate::core::token_tracker::TokenTracker).
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    /// Current time of the clock
+    now: Arc<Mutex<DateTime<Utc>>>,
+}
+
+impl MockClock {
+    /// Create a clock stopped at `now`
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    /// Jump to `now`
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    /// Move forward by `duration`
+    pub fn advance(&self, duration: chrono::Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+}
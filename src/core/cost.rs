@@ -0,0 +1,157 @@
+//! Spend tracking against an absolute cost cap
+
+use std::sync::Mutex;
+
+use crate::core::errors::{Result, TranslationError};
+
+/// Tracks estimated spend and refuses requests that would exceed the cap.
+///
+/// A request first reserves its estimated cost; once it completes the
+/// reservation is replaced by the actual cost. Reservations make the check
+/// hold for concurrent requests too.
+#[derive(Debug, Default)]
+pub struct CostTracker {
+    /// Maximum spend for the run, `None` for no cap
+    limit: Option<f64>,
+    /// Spend so far and outstanding reservations
+    state: Mutex<CostState>,
+}
+
+/// Mutable part of [`CostTracker`]
+#[derive(Debug, Default)]
+struct CostState {
+    /// Cost of completed requests
+    spent: f64,
+    /// Estimated cost of requests in flight
+    reserved: f64,
+}
+
+impl CostTracker {
+    /// Create a tracker with an optional cap
+    pub fn new(limit: Option<f64>) -> Self {
+        Self {
+            limit,
+            state: Mutex::new(CostState::default()),
+        }
+    }
+
+    /// Reserve `estimate` for a request about to be sent.
+    ///
+    /// Fails with [`TranslationError::CostLimitExceeded`] if the spend so far
+    /// plus all reservations would pass the cap.
+    pub fn reserve(&self, estimate: f64) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(limit) = self.limit {
+            if state.spent + state.reserved + estimate > limit {
+                return Err(TranslationError::CostLimitExceeded {
+                    spent: state.spent,
+                    limit,
+                });
+            }
+        }
+        state.reserved += estimate;
+        Ok(())
+    }
+
+    /// Replace the reservation for a finished request with its actual cost
+    pub fn settle(&self, estimate: f64, actual: f64) {
+        let mut state = self.state.lock().unwrap();
+        state.reserved = (state.reserved - estimate).max(0.0);
+        state.spent += actual;
+    }
+
+    /// Drop the reservation of a request that failed
+    pub fn release(&self, estimate: f64) {
+        self.settle(estimate, 0.0);
+    }
+
+    /// [`reserve`](Self::reserve), returning a guard that releases the
+    /// reservation when dropped unless it was settled, so a request future
+    /// cancelled mid-flight does not hold on to its estimate
+    pub fn reserve_guarded(&self, estimate: f64) -> Result<Reservation<'_>> {
+        self.reserve(estimate)?;
+        Ok(Reservation {
+            tracker: self,
+            estimate,
+            settled: false,
+        })
+    }
+
+    /// Continue from `spent` saved by an earlier process
+    pub fn restore(&self, spent: f64) {
+        self.state.lock().unwrap().spent = spent;
+    }
+
+    /// Cost of completed requests
+    pub fn spent(&self) -> f64 {
+        self.state.lock().unwrap().spent
+    }
+}
+
+/// Reservation held by a request in flight, see [`CostTracker::reserve_guarded`]
+#[derive(Debug)]
+pub struct Reservation<'a> {
+    /// Tracker the estimate is reserved in
+    tracker: &'a CostTracker,
+    /// Reserved estimate
+    estimate: f64,
+    /// Whether the actual cost has replaced the estimate
+    settled: bool,
+}
+
+impl Reservation<'_> {
+    /// Replace the reservation with the request's actual cost
+    pub fn settle(mut self, actual: f64) {
+        self.tracker.settle(self.estimate, actual);
+        self.settled = true;
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if !self.settled {
+            self.tracker.release(self.estimate);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserve_refuses_past_cap() {
+        let tracker = CostTracker::new(Some(1.0));
+        tracker.reserve(0.6).unwrap();
+        assert!(tracker.reserve(0.6).is_err());
+
+        tracker.settle(0.6, 0.3);
+        tracker.reserve(0.6).unwrap();
+        tracker.release(0.6);
+        assert!((tracker.spent() - 0.3).abs() < 1e-9);
+
+        let uncapped = CostTracker::new(None);
+        assert!(uncapped.reserve(1e9).is_ok());
+    }
+
+    #[test]
+    fn test_dropped_reservation_is_released() {
+        let tracker = CostTracker::new(Some(1.0));
+        let reservation = tracker.reserve_guarded(0.6).unwrap();
+        assert!(tracker.reserve_guarded(0.6).is_err());
+        drop(reservation);
+
+        tracker.reserve_guarded(0.6).unwrap().settle(0.3);
+        assert!((tracker.spent() - 0.3).abs() < 1e-9);
+        assert!(tracker.reserve_guarded(0.7).is_ok());
+    }
+
+    #[test]
+    fn test_restored_spend_counts_against_cap() {
+        let tracker = CostTracker::new(Some(1.0));
+        tracker.restore(0.8);
+        assert!((tracker.spent() - 0.8).abs() < 1e-9);
+        assert!(tracker.reserve(0.3).is_err());
+        assert!(tracker.reserve(0.1).is_ok());
+    }
+}
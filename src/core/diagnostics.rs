@@ -0,0 +1,130 @@
+//! Structured per-job diagnostics (failed segments, skipped files, ...)
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::core::errors::Result;
+
+/// How serious a diagnostic is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Worth knowing, nothing was lost
+    Info,
+    /// Part of the output is degraded (e.g. a segment left untranslated)
+    Warning,
+    /// A file could not be produced
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A single problem found while running a job
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    /// How serious it is
+    pub severity: Severity,
+    /// File being processed, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+    /// Where in the file (segment index, chapter id, ...)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    /// What happened
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// Create a diagnostic with no file or location
+    pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            file: None,
+            location: None,
+            message: message.into(),
+        }
+    }
+
+    /// Attach the file being processed
+    pub fn with_file(mut self, file: &Path) -> Self {
+        self.file = Some(file.to_path_buf());
+        self
+    }
+
+    /// Attach a location inside the file
+    pub fn with_location(mut self, location: impl Into<String>) -> Self {
+        self.location = Some(location.into());
+        self
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.severity)?;
+        if let Some(file) = &self.file {
+            write!(f, " {}", file.display())?;
+        }
+        if let Some(location) = &self.location {
+            write!(f, " [{}]", location)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Shared collector for the diagnostics of one job.
+///
+/// Clones share the same list, so a processor and the CLI can hold the same
+/// collector and read it at job end.
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    /// Diagnostics in the order they were reported
+    items: Arc<Mutex<Vec<Diagnostic>>>,
+}
+
+impl Diagnostics {
+    /// Create an empty collector
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a diagnostic
+    pub fn push(&self, diagnostic: Diagnostic) {
+        self.items.lock().unwrap().push(diagnostic);
+    }
+
+    /// Copy of everything recorded so far
+    pub fn all(&self) -> Vec<Diagnostic> {
+        self.items.lock().unwrap().clone()
+    }
+
+    /// Remove and return everything recorded so far
+    pub fn take(&self) -> Vec<Diagnostic> {
+        std::mem::take(&mut *self.items.lock().unwrap())
+    }
+
+    /// Number of diagnostics per severity
+    pub fn count_by_severity(&self) -> BTreeMap<Severity, usize> {
+        let mut counts = BTreeMap::new();
+        for diagnostic in self.items.lock().unwrap().iter() {
+            *counts.entry(diagnostic.severity).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Write all diagnostics as pretty-printed JSON
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(&self.all())?;
+        tokio::fs::write(path, json).await?;
+        Ok(())
+    }
+}
@@ -22,6 +22,15 @@ pub enum TranslationError {
     #[error("Token quota exceeded for today")]
     QuotaExceededError,
 
+    /// The next request would push the run's estimated spend past `--max-cost`
+    #[error("Cost limit reached: spent {spent:.4} of {limit:.4}")]
+    CostLimitExceeded {
+        /// Spend of completed requests
+        spent: f64,
+        /// Configured cap
+        limit: f64,
+    },
+
     /// Network error
     #[error("Network error: {message}")]
     NetworkError {
@@ -92,6 +101,79 @@ pub enum TranslationError {
     ZipError(String),
 }
 
+impl TranslationError {
+    /// Short name of the kind of error, e.g. `rate_limit`, as used in metrics
+    pub fn category(&self) -> &'static str {
+        use TranslationError::*;
+        match self {
+            ApiError { .. } => "api",
+            RateLimitError { .. } => "rate_limit",
+            QuotaExceededError => "quota",
+            CostLimitExceeded { .. } => "cost_limit",
+            NetworkError { .. } | HttpError(_) => "network",
+            InvalidResponseError { .. } | JsonError(_) => "invalid_response",
+            TimeoutError => "timeout",
+            ConfigError { .. } => "config",
+            FileError { .. } | IoError(_) => "file",
+            InvalidFormat { .. } | MissingField { .. } | YamlError(_) => "format",
+            EpubError(_) | ZipError(_) => "container",
+            InternalError(_) => "internal",
+        }
+    }
+
+    /// A copy of this error for another caller waiting on the same request.
+    ///
+    /// Errors wrapping a source error keep only its message.
+    pub fn duplicate(&self) -> Self {
+        use TranslationError::*;
+        match self {
+            ApiError { status, message } => ApiError {
+                status: *status,
+                message: message.clone(),
+            },
+            RateLimitError { retry_after } => RateLimitError {
+                retry_after: *retry_after,
+            },
+            QuotaExceededError => QuotaExceededError,
+            CostLimitExceeded { spent, limit } => CostLimitExceeded {
+                spent: *spent,
+                limit: *limit,
+            },
+            NetworkError { message } => NetworkError {
+                message: message.clone(),
+            },
+            InvalidResponseError { message } => InvalidResponseError {
+                message: message.clone(),
+            },
+            TimeoutError => TimeoutError,
+            FileError { path, message } => FileError {
+                path: path.clone(),
+                message: message.clone(),
+            },
+            ConfigError { message } => ConfigError {
+                message: message.clone(),
+            },
+            InvalidFormat { format } => InvalidFormat {
+                format: format.clone(),
+            },
+            MissingField { field } => MissingField {
+                field: field.clone(),
+            },
+            InternalError(message) => InternalError(message.clone()),
+            EpubError(message) => EpubError(message.clone()),
+            ZipError(message) => ZipError(message.clone()),
+            IoError(e) => IoError(std::io::Error::new(e.kind(), e.to_string())),
+            HttpError(e) => NetworkError {
+                message: e.to_string(),
+            },
+            JsonError(e) => InvalidResponseError {
+                message: e.to_string(),
+            },
+            YamlError(e) => InternalError(e.to_string()),
+        }
+    }
+}
+
 impl From<anyhow::Error> for TranslationError {
     fn from(err: anyhow::Error) -> Self {
         TranslationError::InternalError(err.to_string())
@@ -0,0 +1,188 @@
+//! Fixed renderings of terms and terms that must stay untranslated
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use crate::core::errors::{Result, TranslationError};
+
+/// Opening bracket of a do-not-translate placeholder
+const TERM_OPEN: char = '⟬';
+/// Closing bracket of a do-not-translate placeholder
+const TERM_CLOSE: char = '⟭';
+
+/// Terms with a forced translation, and terms never to translate.
+///
+/// Read from JSON:
+/// `{"terms": {"pull request": "合并请求"}, "do_not_translate": ["Kubernetes"]}`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Glossary {
+    /// Source term → the translation it must get
+    #[serde(default)]
+    pub terms: BTreeMap<String, String>,
+    /// Terms copied verbatim (product names, commands, ...)
+    #[serde(default)]
+    pub do_not_translate: BTreeSet<String>,
+}
+
+impl Glossary {
+    /// Load a glossary from a JSON file
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path).map_err(|e| TranslationError::FileError {
+            path: path.display().to_string(),
+            message: e.to_string(),
+        })?;
+        serde_json::from_str(&content).map_err(|e| TranslationError::ConfigError {
+            message: format!("Invalid glossary {}: {}", path.display(), e),
+        })
+    }
+
+    /// Whether the glossary has no entries
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty() && self.do_not_translate.is_empty()
+    }
+
+    /// SHA-256 of the glossary's entries, so translations made under it can
+    /// be told from those made under another; `None` when it is empty
+    pub fn fingerprint(&self) -> Option<String> {
+        if self.is_empty() {
+            return None;
+        }
+        let json = serde_json::to_string(self).unwrap_or_default();
+        Some(format!("{:x}", Sha256::digest(json.as_bytes())))
+    }
+
+    /// Forced translations of the terms that occur in `text`, to send along
+    /// with it
+    pub fn terms_in<'g>(&'g self, text: &str) -> Vec<(&'g str, &'g str)> {
+        self.terms
+            .iter()
+            .filter(|(source, _)| find_term(text, source, 0).is_some())
+            .map(|(source, target)| (source.as_str(), target.as_str()))
+            .collect()
+    }
+
+    /// Replace every do-not-translate term in `text` with a numbered
+    /// placeholder; returns the masked text and the terms by number.
+    /// Longer terms win over terms they contain. Text that already holds
+    /// placeholder brackets is left as is.
+    pub fn mask<'g>(&'g self, text: &str) -> (String, Vec<&'g str>) {
+        if text.contains([TERM_OPEN, TERM_CLOSE]) {
+            return (text.to_string(), Vec::new());
+        }
+        let mut dnt: Vec<&str> = self.do_not_translate.iter().map(String::as_str).collect();
+        dnt.sort_by_key(|term| std::cmp::Reverse(term.len()));
+
+        let mut masked = String::with_capacity(text.len());
+        let mut masked_terms = Vec::new();
+        let mut pos = 0;
+        while pos < text.len() {
+            let next = dnt
+                .iter()
+                .filter(|term| !term.is_empty())
+                .filter_map(|term| find_term(text, term, pos).map(|at| (at, *term)))
+                .min_by_key(|&(at, term)| (at, std::cmp::Reverse(term.len())));
+            let Some((at, term)) = next else { break };
+            masked.push_str(&text[pos..at]);
+            masked.push_str(&placeholder(masked_terms.len()));
+            masked_terms.push(term);
+            pos = at + term.len();
+        }
+        masked.push_str(&text[pos..]);
+        (masked, masked_terms)
+    }
+
+    /// Put the `terms` masked by [`Glossary::mask`] back into `translation`;
+    /// `None` if a placeholder got lost or duplicated
+    pub fn unmask(translation: &str, terms: &[&str]) -> Option<String> {
+        let mut result = translation.to_string();
+        for (i, term) in terms.iter().enumerate() {
+            let placeholder = placeholder(i);
+            if result.matches(&placeholder).count() != 1 {
+                return None;
+            }
+            result = result.replacen(&placeholder, term, 1);
+        }
+        Some(result)
+    }
+
+    /// Enforce the forced translations on the `translation` of `source`:
+    /// terms of `source` that came back untranslated are replaced
+    pub fn enforce(&self, source: &str, translation: &str) -> String {
+        let mut result = translation.to_string();
+        for (term, target) in self.terms_in(source) {
+            let mut pos = 0;
+            while let Some(at) = find_term(&result, term, pos) {
+                result.replace_range(at..at + term.len(), target);
+                pos = at + target.len();
+            }
+        }
+        result
+    }
+}
+
+/// Placeholder standing for the `index`th masked term
+fn placeholder(index: usize) -> String {
+    format!("{}{}{}", TERM_OPEN, index, TERM_CLOSE)
+}
+
+/// Byte offset of the first occurrence of `term` in `text` at or after
+/// `from`. Terms starting or ending with a letter or digit only match whole
+/// words, so "Go" is not found in "Google".
+fn find_term(text: &str, term: &str, from: usize) -> Option<usize> {
+    if term.is_empty() {
+        return None;
+    }
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let word_start = term.chars().next().is_some_and(is_word);
+    let word_end = term.chars().last().is_some_and(is_word);
+
+    let mut pos = from;
+    while let Some(found) = text.get(pos..)?.find(term) {
+        let at = pos + found;
+        let end = at + term.len();
+        let before_ok = !word_start || !text[..at].chars().last().is_some_and(is_word);
+        let after_ok = !word_end || !text[end..].chars().next().is_some_and(is_word);
+        if before_ok && after_ok {
+            return Some(at);
+        }
+        pos = at + text[at..].chars().next().map_or(1, char::len_utf8);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glossary() -> Glossary {
+        Glossary {
+            terms: [("pull request".to_string(), "合并请求".to_string())].into(),
+            do_not_translate: ["Go".to_string(), "Go modules".to_string()].into(),
+        }
+    }
+
+    #[test]
+    fn test_mask_and_unmask_terms() {
+        let glossary = glossary();
+        let (masked, terms) = glossary.mask("Go modules beat Google's Go");
+        assert_eq!(masked, "⟬0⟭ beat Google's ⟬1⟭");
+        assert_eq!(terms, vec!["Go modules", "Go"]);
+
+        assert_eq!(
+            Glossary::unmask("⟬1⟭ 比 ⟬0⟭ 好", &terms).as_deref(),
+            Some("Go 比 Go modules 好")
+        );
+        assert_eq!(Glossary::unmask("⟬0⟭ 好", &terms), None);
+    }
+
+    #[test]
+    fn test_enforce_replaces_untranslated_terms() {
+        let glossary = glossary();
+        let source = "Open a pull request";
+        assert_eq!(glossary.terms_in(source), vec![("pull request", "合并请求")]);
+        assert_eq!(glossary.enforce(source, "打开一个 pull request"), "打开一个 合并请求");
+        assert_eq!(glossary.enforce("Open it", "打开 pull requests"), "打开 pull requests");
+    }
+}
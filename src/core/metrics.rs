@@ -0,0 +1,96 @@
+//! Prometheus metrics of the API calls the client makes
+//!
+//! Metrics are recorded through the `metrics` facade and are no-ops until
+//! [`install_prometheus`] sets up the exporter (the server does, for
+//! `GET /metrics`).
+
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::core::errors::{Result, TranslationError};
+use crate::core::models::{LaneType, Model};
+
+/// API calls, by `lane`, `model`, `outcome` (`success`/`failure`) and error
+/// `category` (empty on success)
+pub const REQUESTS_TOTAL: &str = "translator_requests_total";
+
+/// Retried API calls, by `lane` and `model`
+pub const RETRIES_TOTAL: &str = "translator_retries_total";
+
+/// Tokens used by successful API calls, by `lane` and `model`
+pub const TOKENS_TOTAL: &str = "translator_tokens_total";
+
+/// Duration of API calls in seconds, by `lane` and `outcome`
+pub const REQUEST_DURATION_SECONDS: &str = "translator_request_duration_seconds";
+
+/// Histogram buckets of [`REQUEST_DURATION_SECONDS`]
+const DURATION_BUCKETS: &[f64] = &[0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// The process-wide exporter, installed once
+static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// Install the Prometheus exporter as the process's metrics recorder (once)
+/// and return a handle rendering the metrics in the text exposition format
+pub fn install_prometheus() -> Result<PrometheusHandle> {
+    if let Some(handle) = HANDLE.get() {
+        return Ok(handle.clone());
+    }
+    let config_error = |e: &dyn std::fmt::Display| TranslationError::ConfigError {
+        message: format!("Cannot install metrics recorder: {}", e),
+    };
+    let recorder = PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Full(REQUEST_DURATION_SECONDS.to_string()),
+            DURATION_BUCKETS,
+        )
+        .map_err(|e| config_error(&e))?
+        .build_recorder();
+    let handle = recorder.handle();
+    // A concurrent caller may have won the race; keep the recorder it installed
+    if HANDLE.set(handle).is_ok() {
+        metrics::set_global_recorder(recorder).map_err(|e| config_error(&e))?;
+    }
+    Ok(HANDLE.get().expect("handle was just set").clone())
+}
+
+/// Record one API call to `model` that took `elapsed`; `tokens` on success
+pub fn record_request(
+    model: &Model,
+    elapsed: Duration,
+    outcome: std::result::Result<usize, &TranslationError>,
+) {
+    let lane = lane_label(model.lane);
+    let (label, category) = match outcome {
+        Ok(tokens) => {
+            metrics::counter!(TOKENS_TOTAL, "lane" => lane, "model" => model.id.clone())
+                .increment(tokens as u64);
+            ("success", "")
+        }
+        Err(e) => ("failure", e.category()),
+    };
+    metrics::counter!(
+        REQUESTS_TOTAL,
+        "lane" => lane,
+        "model" => model.id.clone(),
+        "outcome" => label,
+        "category" => category
+    )
+    .increment(1);
+    metrics::histogram!(REQUEST_DURATION_SECONDS, "lane" => lane, "outcome" => label)
+        .record(elapsed.as_secs_f64());
+}
+
+/// Record a retry of a call to `model`
+pub fn record_retry(model: &Model) {
+    let lane = lane_label(model.lane);
+    metrics::counter!(RETRIES_TOTAL, "lane" => lane, "model" => model.id.clone()).increment(1);
+}
+
+/// Label value of `lane`
+fn lane_label(lane: LaneType) -> &'static str {
+    match lane {
+        LaneType::Slow => "slow",
+        LaneType::Fast => "fast",
+    }
+}
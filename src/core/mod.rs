@@ -1,7 +1,14 @@
 //! Core translation engine module
 
+pub mod breaker;
+pub mod cache;
 pub mod client;
+pub mod clock;
 pub mod config;
+pub mod cost;
+pub mod diagnostics;
 pub mod errors;
+pub mod glossary;
+pub mod metrics;
 pub mod models;
 pub mod token_tracker;
\ No newline at end of file
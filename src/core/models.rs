@@ -1,10 +1,11 @@
 //! Core data models for translation
 
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt;
 
 /// Lane type for model selection
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum LaneType {
     /// Slow lane: doubao-seed-translation-250915 (RPM=5000, 80 concurrent)
     Slow,
@@ -12,6 +13,16 @@ pub enum LaneType {
     Fast,
 }
 
+impl LaneType {
+    /// The lane to fall back to when this one fails
+    pub fn other(self) -> LaneType {
+        match self {
+            LaneType::Slow => LaneType::Fast,
+            LaneType::Fast => LaneType::Slow,
+        }
+    }
+}
+
 impl fmt::Display for LaneType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -21,6 +32,16 @@ impl fmt::Display for LaneType {
     }
 }
 
+/// Lane a request asks for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum LaneChoice {
+    /// Pick a lane per request from its estimated size and priority
+    #[default]
+    Auto,
+    /// Start on this lane
+    Lane(LaneType),
+}
+
 /// Translation model information
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Model {
@@ -38,6 +59,17 @@ impl Model {
     }
 }
 
+/// How urgently a request needs an answer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    /// Batch work that can wait on the free slow lane
+    #[default]
+    Normal,
+    /// Interactive request; small ones are routed to the fast lane
+    High,
+}
+
 /// Translation request
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TranslationRequest {
@@ -45,6 +77,23 @@ pub struct TranslationRequest {
     pub source_lang: Option<String>,
     pub target_lang: String,
     pub context: Option<String>,
+    /// Lane to use; `Auto` lets the client decide
+    #[serde(default)]
+    pub lane: LaneChoice,
+    /// Routing hint for `LaneChoice::Auto`
+    #[serde(default)]
+    pub priority: Priority,
+    /// Send this request to another endpoint instead of the configured one.
+    ///
+    /// Never read from serialized requests: the API key goes to this URL.
+    #[serde(skip)]
+    pub endpoint_override: Option<String>,
+    /// Lanes to try, in order, instead of the configured policy
+    #[serde(default)]
+    pub lane_policy: Option<LanePolicy>,
+    /// Send to this model only, bypassing lane selection
+    #[serde(default)]
+    pub model_id: Option<String>,
 }
 
 impl TranslationRequest {
@@ -54,6 +103,11 @@ impl TranslationRequest {
             source_lang: None,
             target_lang,
             context: None,
+            lane: LaneChoice::Auto,
+            priority: Priority::Normal,
+            endpoint_override: None,
+            lane_policy: None,
+            model_id: None,
         }
     }
 
@@ -66,6 +120,177 @@ impl TranslationRequest {
         self.context = Some(context.into());
         self
     }
+
+    /// Force a specific lane instead of automatic routing
+    pub fn with_lane(mut self, lane: LaneType) -> Self {
+        self.lane = LaneChoice::Lane(lane);
+        self
+    }
+
+    /// Set the routing priority
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Send this request to `endpoint` instead of the configured API endpoint
+    pub fn with_endpoint_override(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint_override = Some(endpoint.into());
+        self
+    }
+
+    /// Try the lanes of `policy`, overriding the configured lane policy
+    pub fn with_lane_policy(mut self, policy: LanePolicy) -> Self {
+        self.lane_policy = Some(policy);
+        self
+    }
+
+    /// Translate with the enabled model `model_id` only, without lane selection
+    pub fn with_model(mut self, model_id: impl Into<String>) -> Self {
+        self.model_id = Some(model_id.into());
+        self
+    }
+
+    /// Token estimate used for quota checks and routing, see [`count_tokens`]
+    ///
+    /// [`count_tokens`]: crate::utils::tokens::count_tokens
+    pub fn estimated_tokens(&self) -> usize {
+        crate::utils::tokens::count_tokens(&self.text)
+    }
+}
+
+/// Which lanes a request may use, in the order they are tried.
+///
+/// The `*Only` policies never fall back: the lane's error is returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LanePolicy {
+    /// Free slow lane first, fast lane if it fails
+    SlowThenFast,
+    /// Fast lane first for latency, slow lane if it fails
+    FastThenSlow,
+    /// Slow lane only
+    SlowOnly,
+    /// Fast lane only
+    FastOnly,
+}
+
+impl LanePolicy {
+    /// Lanes to try, in order
+    pub fn lanes(self) -> &'static [LaneType] {
+        match self {
+            LanePolicy::SlowThenFast => &[LaneType::Slow, LaneType::Fast],
+            LanePolicy::FastThenSlow => &[LaneType::Fast, LaneType::Slow],
+            LanePolicy::SlowOnly => &[LaneType::Slow],
+            LanePolicy::FastOnly => &[LaneType::Fast],
+        }
+    }
+}
+
+impl std::str::FromStr for LanePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.replace('-', "_").as_str() {
+            "slow_then_fast" => Ok(LanePolicy::SlowThenFast),
+            "fast_then_slow" => Ok(LanePolicy::FastThenSlow),
+            "slow_only" => Ok(LanePolicy::SlowOnly),
+            "fast_only" => Ok(LanePolicy::FastOnly),
+            other => Err(format!(
+                "unknown lane policy '{}', expected slow_then_fast, fast_then_slow, \
+                 slow_only or fast_only",
+                other
+            )),
+        }
+    }
+}
+
+/// Shape of the API's responses, which decides where translations and token
+/// usage are read from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ResponseFormat {
+    /// Doubao: `output[i].content[0].text`, or `output.choices[i].message.content`
+    #[default]
+    #[serde(rename = "doubao")]
+    Doubao,
+    /// OpenAI-compatible chat completions: `choices[i].message.content`
+    #[serde(rename = "openai_chat")]
+    OpenAIChat,
+}
+
+impl ResponseFormat {
+    /// Tokens used by the call `json` answers, 0 if not reported
+    pub fn total_tokens(self, json: &serde_json::Value) -> usize {
+        let usage = &json["usage"];
+        let (input, output) = match self {
+            ResponseFormat::Doubao => ("input_tokens", "output_tokens"),
+            ResponseFormat::OpenAIChat => ("prompt_tokens", "completion_tokens"),
+        };
+        let total = usage["total_tokens"].as_u64().unwrap_or_else(|| {
+            usage[input].as_u64().unwrap_or(0) + usage[output].as_u64().unwrap_or(0)
+        });
+        total as usize
+    }
+}
+
+impl std::str::FromStr for ResponseFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.replace('-', "_").to_lowercase().as_str() {
+            "doubao" => Ok(ResponseFormat::Doubao),
+            "openai_chat" | "openai" => Ok(ResponseFormat::OpenAIChat),
+            other => Err(format!(
+                "unknown response format '{}', expected doubao or openai_chat",
+                other
+            )),
+        }
+    }
+}
+
+/// Order in which a batch of segments or files is dispatched.
+///
+/// Results are always assembled in source order; this only changes which work
+/// starts first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleOrder {
+    /// Document / directory order
+    #[default]
+    SourceOrder,
+    /// Biggest first, so small items fill the tail and the ETA settles early
+    LargestFirst,
+    /// Smallest first
+    SmallestFirst,
+}
+
+impl ScheduleOrder {
+    /// Indices of `sizes` in dispatch order (stable for equal sizes)
+    pub fn dispatch_order(self, sizes: &[usize]) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..sizes.len()).collect();
+        match self {
+            ScheduleOrder::SourceOrder => {}
+            ScheduleOrder::LargestFirst => order.sort_by_key(|&i| std::cmp::Reverse(sizes[i])),
+            ScheduleOrder::SmallestFirst => order.sort_by_key(|&i| sizes[i]),
+        }
+        order
+    }
+}
+
+impl std::str::FromStr for ScheduleOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.replace('-', "_").as_str() {
+            "source_order" => Ok(ScheduleOrder::SourceOrder),
+            "largest_first" => Ok(ScheduleOrder::LargestFirst),
+            "smallest_first" => Ok(ScheduleOrder::SmallestFirst),
+            other => Err(format!(
+                "unknown schedule '{}', expected source_order, largest_first or smallest_first",
+                other
+            )),
+        }
+    }
 }
 
 /// Translation result
@@ -79,11 +304,15 @@ pub struct TranslationResult {
 }
 
 /// Token usage tracking
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TokenUsage {
     pub daily_limit: usize,
     pub used_today: usize,
     pub last_reset: chrono::DateTime<chrono::Utc>,
+    /// Today's tokens by model id (usage not attributed to a model is only
+    /// in `used_today`)
+    #[serde(default)]
+    pub by_model: BTreeMap<String, usize>,
 }
 
 impl TokenUsage {
@@ -92,6 +321,7 @@ impl TokenUsage {
             daily_limit,
             used_today: 0,
             last_reset: chrono::Utc::now(),
+            by_model: BTreeMap::new(),
         }
     }
 
@@ -111,10 +341,22 @@ impl TokenUsage {
         Ok(())
     }
 
+    /// Count `tokens` used by `model`, even past the limit
+    pub fn record(&mut self, model: &str, tokens: usize) {
+        self.used_today += tokens;
+        *self.by_model.entry(model.to_string()).or_default() += tokens;
+    }
+
     pub fn reset_if_needed(&mut self) {
-        let now = chrono::Utc::now();
-        if now.date_naive() != self.last_reset.date_naive() {
+        self.reset_if_needed_at(chrono::Utc::now());
+    }
+
+    /// Reset the daily count if `now` falls on a later (UTC) day than the last
+    /// reset; an earlier `now` (a timestamp taken before a reset) changes nothing
+    pub fn reset_if_needed_at(&mut self, now: chrono::DateTime<chrono::Utc>) {
+        if now.date_naive() > self.last_reset.date_naive() {
             self.used_today = 0;
+            self.by_model.clear();
             self.last_reset = now;
         }
     }
@@ -1,25 +1,71 @@
 //! Token usage tracking and quota management
 
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use tracing::{debug, info};
 
+use crate::core::clock::{Clock, SystemClock};
 use crate::core::models::TokenUsage;
 
-/// Token tracker for managing daily quota
+/// Token tracker for managing daily quota.
+///
+/// The daily reset, the quota check and the count update happen under one
+/// lock, so concurrent requests around midnight neither reset twice nor lose
+/// updates. Requests reserve their estimate first (see [`TokenTracker::reserve`])
+/// so that concurrent checks cannot all pass and overshoot together.
 #[derive(Debug, Clone)]
 pub struct TokenTracker {
-    usage: Arc<RwLock<TokenUsage>>,
+    /// Today's usage and reservations, shared by clones
+    quota: Arc<RwLock<Quota>>,
+    /// Time source for daily resets
+    clock: Arc<dyn Clock>,
+}
+
+/// Mutable part of [`TokenTracker`]
+#[derive(Debug)]
+struct Quota {
+    /// Today's limit and usage
+    usage: TokenUsage,
+    /// Estimated tokens of requests in flight; survives the daily reset since
+    /// those requests are counted on the day they complete
+    reserved: usize,
+}
+
+impl Quota {
+    /// Start a new day if `now` is past the last reset's day
+    fn at(&mut self, now: chrono::DateTime<chrono::Utc>) -> &mut Self {
+        self.usage.reset_if_needed_at(now);
+        self
+    }
+
+    /// Tokens left once requests in flight complete as estimated
+    fn available(&self) -> usize {
+        self.usage.remaining().saturating_sub(self.reserved)
+    }
 }
 
 impl TokenTracker {
     /// Create a new token tracker
     pub fn new(daily_limit: usize) -> Self {
         Self {
-            usage: Arc::new(RwLock::new(TokenUsage::new(daily_limit))),
+            quota: Arc::new(RwLock::new(Quota {
+                usage: TokenUsage::new(daily_limit),
+                reserved: 0,
+            })),
+            clock: Arc::new(SystemClock),
         }
     }
 
+    /// Use `clock` instead of the system clock to decide when a new day starts
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        if let Ok(mut quota) = self.quota.try_write() {
+            quota.usage.last_reset = clock.now();
+        }
+        self.clock = clock;
+        self
+    }
+
     /// Create from environment variable
     pub fn from_env() -> Self {
         let daily_limit = std::env::var("DAILY_TOKEN_LIMIT")
@@ -32,62 +78,145 @@ impl TokenTracker {
 
     /// Check if enough tokens are available
     pub async fn can_use(&self, tokens: usize) -> bool {
-        let mut usage = self.usage.write().await;
-        usage.reset_if_needed();
-        usage.can_use(tokens)
+        let mut quota = self.quota.write().await;
+        quota.at(self.clock.now()).available() >= tokens
     }
 
-    /// Use tokens from quota
-    pub async fn use_tokens(&self, tokens: usize) -> anyhow::Result<()> {
-        // First check if we can use the tokens
-        {
-            let mut usage = self.usage.write().await;
-            usage.reset_if_needed();
-            usage.use_tokens(tokens)?;
+    /// Reserve `tokens` for a request about to be sent; `false` (nothing
+    /// reserved) if they would not fit in today's quota
+    pub async fn reserve(&self, tokens: usize) -> bool {
+        let mut quota = self.quota.write().await;
+        let quota = quota.at(self.clock.now());
+        if quota.available() < tokens {
+            return false;
         }
+        quota.reserved += tokens;
+        true
+    }
+
+    /// Replace a reservation with the tokens `model` actually used.
+    ///
+    /// The tokens are counted even past the limit: they have been spent.
+    pub async fn settle(&self, reserved: usize, model: &str, used: usize) {
+        let mut quota = self.quota.write().await;
+        let quota = quota.at(self.clock.now());
+        quota.reserved = quota.reserved.saturating_sub(reserved);
+        quota.usage.record(model, used);
+        debug!("{} used {} tokens, remaining: {}", model, used, quota.usage.remaining());
+    }
 
-        // Log success
-        {
-            let usage = self.usage.read().await;
-            debug!("Used {} tokens, remaining: {}", tokens, usage.remaining());
+    /// Drop the reservation of a request that failed
+    pub async fn release(&self, reserved: usize) {
+        let mut quota = self.quota.write().await;
+        quota.reserved = quota.reserved.saturating_sub(reserved);
+    }
+
+    /// [`reserve`](Self::reserve), returning a guard that releases the
+    /// reservation when dropped unless it was settled, so a request future
+    /// cancelled mid-flight does not hold on to its estimate
+    pub async fn reserve_guarded(&self, tokens: usize) -> Option<TokenReservation> {
+        if !self.reserve(tokens).await {
+            return None;
         }
+        Some(TokenReservation {
+            tracker: self.clone(),
+            tokens,
+            settled: false,
+        })
+    }
 
+    /// Use tokens from quota
+    pub async fn use_tokens(&self, tokens: usize) -> anyhow::Result<()> {
+        let mut quota = self.quota.write().await;
+        let quota = quota.at(self.clock.now());
+        if quota.available() < tokens {
+            return Err(anyhow::anyhow!("Token quota exceeded"));
+        }
+        quota.usage.used_today += tokens;
+        debug!("Used {} tokens, remaining: {}", tokens, quota.usage.remaining());
         Ok(())
     }
 
     /// Get current usage statistics
     pub async fn get_stats(&self) -> TokenUsage {
-        let usage = self.usage.read().await;
-        usage.clone()
+        self.quota.read().await.usage.clone()
+    }
+
+    /// Today's tokens by model id
+    pub async fn get_stats_by_model(&self) -> BTreeMap<String, usize> {
+        self.quota.read().await.usage.by_model.clone()
     }
 
     /// Get remaining tokens
     pub async fn remaining(&self) -> usize {
-        let usage = self.usage.read().await;
-        usage.remaining()
+        self.quota.read().await.usage.remaining()
     }
 
     /// Check if quota is low (less than 10% remaining)
     pub async fn is_low(&self) -> bool {
-        let usage = self.usage.read().await;
-        let remaining = usage.remaining();
-        let daily_limit = usage.daily_limit;
-        drop(usage);
-        remaining < daily_limit / 10
+        let quota = self.quota.read().await;
+        quota.usage.remaining() < quota.usage.daily_limit / 10
+    }
+
+    /// Continue from `saved` usage (e.g. persisted before a restart), keeping
+    /// the configured daily limit; a day that has since ended is reset
+    pub async fn restore(&self, saved: &TokenUsage) {
+        let mut quota = self.quota.write().await;
+        quota.usage.used_today = saved.used_today;
+        quota.usage.last_reset = saved.last_reset;
+        quota.usage.by_model = saved.by_model.clone();
+        quota.at(self.clock.now());
     }
 
     /// Reset quota (for testing or manual reset)
     pub async fn reset(&self) {
-        let mut usage = self.usage.write().await;
-        usage.used_today = 0;
-        usage.last_reset = chrono::Utc::now();
+        let mut quota = self.quota.write().await;
+        quota.usage.used_today = 0;
+        quota.usage.by_model.clear();
+        quota.usage.last_reset = self.clock.now();
         info!("Token quota reset");
     }
 }
 
+/// Tokens reserved by a request in flight, see [`TokenTracker::reserve_guarded`]
+#[derive(Debug)]
+pub struct TokenReservation {
+    /// Tracker the tokens are reserved in
+    tracker: TokenTracker,
+    /// Reserved estimate
+    tokens: usize,
+    /// Whether the used tokens have replaced the estimate
+    settled: bool,
+}
+
+impl TokenReservation {
+    /// Replace the reservation with the tokens `model` actually used
+    pub async fn settle(mut self, model: &str, used: usize) {
+        self.tracker.settle(self.tokens, model, used).await;
+        self.settled = true;
+    }
+}
+
+impl Drop for TokenReservation {
+    fn drop(&mut self) {
+        if self.settled {
+            return;
+        }
+        let tokens = self.tokens;
+        if let Ok(mut quota) = self.tracker.quota.try_write() {
+            quota.reserved = quota.reserved.saturating_sub(tokens);
+        } else if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            // Contended: release once the lock is free
+            let tracker = self.tracker.clone();
+            runtime.spawn(async move { tracker.release(tokens).await });
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::core::clock::MockClock;
 
     #[tokio::test]
     async fn test_token_tracker() {
@@ -120,4 +249,101 @@ mod tests {
         tracker.reset().await;
         assert!(!tracker.is_low().await);
     }
+
+    #[tokio::test]
+    async fn test_quota_resets_after_midnight() {
+        let start = chrono::DateTime::parse_from_rfc3339("2024-03-01T23:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let clock = MockClock::new(start);
+        let tracker = TokenTracker::new(1000).with_clock(Arc::new(clock.clone()));
+
+        tracker.use_tokens(800).await.unwrap();
+        assert!(!tracker.can_use(300).await);
+
+        // Still the same day: no reset
+        clock.advance(chrono::Duration::minutes(59));
+        assert!(!tracker.can_use(300).await);
+        assert_eq!(tracker.remaining().await, 200);
+
+        // Past midnight: the next check starts a fresh day
+        clock.advance(chrono::Duration::minutes(2));
+        assert!(tracker.can_use(300).await);
+        let stats = tracker.get_stats().await;
+        assert_eq!(stats.used_today, 0);
+        assert_eq!(stats.last_reset, clock.now());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_reservations_never_overshoot() {
+        let tracker = TokenTracker::new(500);
+        let tasks: Vec<_> = (0..100)
+            .map(|_| {
+                let tracker = tracker.clone();
+                tokio::spawn(async move { tracker.reserve(10).await })
+            })
+            .collect();
+        let mut granted = 0;
+        for task in tasks {
+            granted += usize::from(task.await.unwrap());
+        }
+        assert_eq!(granted, 50);
+        assert!(!tracker.can_use(1).await);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_reservation_is_released() {
+        let tracker = TokenTracker::new(100);
+        let reservation = tracker.reserve_guarded(80).await.unwrap();
+        assert!(tracker.reserve_guarded(80).await.is_none());
+        drop(reservation);
+
+        let reservation = tracker.reserve_guarded(80).await.unwrap();
+        reservation.settle("m", 30).await;
+        assert_eq!(tracker.remaining().await, 70);
+        assert_eq!(tracker.quota.read().await.reserved, 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_midnight_under_concurrency_loses_no_updates() {
+        let start = chrono::DateTime::parse_from_rfc3339("2024-03-01T23:59:59Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let clock = MockClock::new(start);
+        let tracker = TokenTracker::new(100_000).with_clock(Arc::new(clock.clone()));
+
+        // Yesterday's usage, and requests sent before midnight that finish after it
+        tracker.use_tokens(5_000).await.unwrap();
+        for _ in 0..50 {
+            assert!(tracker.reserve(10).await);
+        }
+
+        clock.advance(chrono::Duration::seconds(2));
+        let mut tasks = Vec::new();
+        for i in 0..100 {
+            let tracker = tracker.clone();
+            tasks.push(tokio::spawn(async move {
+                if i < 50 {
+                    // In flight since yesterday
+                    tracker.settle(10, "slow", 7).await;
+                } else {
+                    assert!(tracker.reserve(10).await);
+                    tokio::task::yield_now().await;
+                    tracker.settle(10, "fast", 7).await;
+                }
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        // One reset, then every completion counted on the new day
+        let stats = tracker.get_stats().await;
+        assert_eq!(stats.used_today, 100 * 7);
+        assert_eq!(stats.by_model, [("fast".into(), 350), ("slow".into(), 350)].into());
+        assert_eq!(stats.last_reset.date_naive(), clock.now().date_naive());
+        assert_eq!(tracker.quota.read().await.reserved, 0);
+        assert!(tracker.can_use(100_000 - 700).await);
+        assert!(!tracker.can_use(100_000 - 699).await);
+    }
 }
\ No newline at end of file
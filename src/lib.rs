@@ -13,17 +13,23 @@ pub mod server;
 pub mod cli;
 pub mod utils;
 
+#[cfg(test)]
+pub(crate) mod testing;
+
 // Re-export key types for convenience
 pub use core::{
     client::AsyncTranslator,
     config::TranslatorConfig,
-    models::{Model, LaneType, TranslationRequest, TranslationResult, TokenUsage},
+    models::{Model, LaneChoice, LaneType, Priority, ScheduleOrder, TranslationRequest, TranslationResult, TokenUsage},
     errors::TranslationError,
 };
 
 pub use processors::{
     markdown::MarkdownProcessor,
     epub::EpubProcessor,
+    html::HtmlProcessor,
+    subtitle::SubtitleProcessor,
+    text::TextProcessor,
 };
 
 /// Library version
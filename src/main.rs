@@ -13,7 +13,11 @@ mod processors;
 mod server;
 mod utils;
 
+#[cfg(test)]
+mod testing;
+
 use cli::commands::Commands;
+use crate::core::config::TranslatorConfig;
 
 /// Doubao Batch Translator - High-performance Rust translation tool
 #[derive(Parser, Debug)]
@@ -35,10 +39,77 @@ struct Args {
     #[arg(long)]
     max_rps: Option<f64>,
 
+    /// Abort once the estimated spend would pass this amount (needs MODEL_PRICES)
+    #[arg(long)]
+    max_cost: Option<f64>,
+
+    /// Seed for reproducible segmentation, ordering, jitter and delimiters
+    /// (network timing and model output stay nondeterministic)
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// JSON or TOML file with models and settings; its keys override environment
+    /// variables, and the flags above override the file
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Reuse translations stored in this file and add new ones, so re-running
+    /// a job does not pay again for text already translated
+    #[arg(long, value_name = "FILE")]
+    cache: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
 
+impl Args {
+    /// Translator configuration for the translating commands.
+    ///
+    /// Precedence: command-line flags > `--config` file > environment
+    /// variables > defaults.
+    fn translator_config(&self) -> anyhow::Result<TranslatorConfig> {
+        self.layered_config(TranslatorConfig::load)
+    }
+
+    /// [`translator_config`](Self::translator_config) without requiring an
+    /// API key, for dry runs
+    fn dry_run_config(&self) -> anyhow::Result<TranslatorConfig> {
+        self.layered_config(TranslatorConfig::load_offline)
+    }
+
+    /// Flags and `--config` file layered over `load_env`
+    fn layered_config(
+        &self,
+        load_env: fn() -> anyhow::Result<TranslatorConfig>,
+    ) -> anyhow::Result<TranslatorConfig> {
+        let mut config = match &self.config {
+            Some(path) => TranslatorConfig::load_with_file(path)?,
+            None => load_env()?,
+        };
+
+        if let Some(api_key) = &self.api_key {
+            config.api_key = api_key.clone();
+        }
+        if let Some(max_concurrent) = self.max_concurrent {
+            config.max_concurrent = max_concurrent;
+        }
+        if let Some(max_rps) = self.max_rps {
+            config.max_rps = max_rps;
+        }
+        if let Some(max_cost) = self.max_cost {
+            config.max_cost = Some(max_cost);
+        }
+        if let Some(seed) = self.seed {
+            config.seed = Some(seed);
+        }
+        if let Some(cache) = &self.cache {
+            config.cache_path = Some(cache.clone());
+        }
+
+        Ok(config)
+    }
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     // Load environment variables
@@ -56,13 +127,14 @@ async fn main() -> anyhow::Result<()> {
             tracing_subscriber::EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| format!("{}={}", env!("CARGO_PKG_NAME"), log_level).into()),
         )
-        .with(tracing_subscriber::fmt::layer())
+        // Logs go to stderr, so stdout carries only results (e.g. with --stdin)
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
         .init();
 
-    let args = Args::parse();
+    let mut args = Args::parse();
 
-    // Override config with CLI args if provided
-    if let Some(api_key) = args.api_key {
+    // The doctor command checks the environment, so it sees --api-key there
+    if let Some(api_key) = &args.api_key {
         std::env::set_var("ARK_API_KEY", api_key);
     }
 
@@ -71,15 +143,33 @@ async fn main() -> anyhow::Result<()> {
     }
 
     // Execute command
-    match args.command {
+    match args.command.take() {
         Some(Commands::Md {
             file,
+            stdin: _,
             output,
             source_lang,
             target_lang,
             recursive,
+            md,
+            job,
         }) => {
-            cli::commands::handle_md(file, output, source_lang, target_lang, recursive).await?;
+            let config = if md.dry_run {
+                args.dry_run_config()?
+            } else {
+                args.translator_config()?
+            };
+            cli::commands::handle_md(
+                config,
+                file,
+                output,
+                source_lang,
+                target_lang,
+                recursive,
+                md,
+                job,
+            )
+            .await?;
         }
         Some(Commands::Epub {
             file,
@@ -87,26 +177,168 @@ async fn main() -> anyhow::Result<()> {
             source_lang,
             target_lang,
             auto_approve,
+            epub,
+            job,
+        }) => {
+            let config = if epub.dry_run {
+                args.dry_run_config()?
+            } else {
+                args.translator_config()?
+            };
+            cli::commands::handle_epub(
+                config,
+                file,
+                output,
+                source_lang,
+                target_lang,
+                auto_approve,
+                epub,
+                job,
+            )
+            .await?;
+        }
+        Some(Commands::Html {
+            file,
+            output,
+            source_lang,
+            target_lang,
+            recursive,
+            attributes,
+            job,
         }) => {
-            cli::commands::handle_epub(file, output, source_lang, target_lang, auto_approve).await?;
+            cli::commands::handle_html(
+                args.translator_config()?,
+                file,
+                output,
+                source_lang,
+                target_lang,
+                recursive,
+                attributes,
+                job,
+            )
+            .await?;
+        }
+        Some(Commands::Srt {
+            file,
+            output,
+            source_lang,
+            target_lang,
+            recursive,
+            job,
+        }) => {
+            cli::commands::handle_srt(
+                args.translator_config()?,
+                file,
+                output,
+                source_lang,
+                target_lang,
+                recursive,
+                job,
+            )
+            .await?;
+        }
+        Some(Commands::Txt {
+            file,
+            stdin: _,
+            output,
+            source_lang,
+            target_lang,
+            recursive,
+            job,
+        }) => {
+            cli::commands::handle_txt(
+                args.translator_config()?,
+                file,
+                output,
+                source_lang,
+                target_lang,
+                recursive,
+                job,
+            )
+            .await?;
         }
         Some(Commands::Server {
             host,
             port,
             debug,
         }) => {
-            cli::commands::handle_server(host, port, debug).await?;
+            cli::commands::handle_server(args.translator_config()?, host, port, debug).await?;
         }
-        Some(Commands::CheckUntranslated { dir }) => {
-            cli::commands::handle_check_untranslated(dir).await?;
+        Some(Commands::CheckUntranslated {
+            dir,
+            target_lang,
+            threshold,
+        }) => {
+            let config = args.translator_config()?;
+            cli::commands::handle_check_untranslated(config, dir, target_lang, threshold).await?;
         }
         Some(Commands::ApplyFix { json }) => {
             cli::commands::handle_apply_fix(json).await?;
         }
+        Some(Commands::Doctor { offline }) => {
+            cli::commands::handle_doctor(offline).await?;
+        }
+        Some(Commands::ApplyReview { review, output }) => {
+            cli::commands::handle_apply_review(review, output).await?;
+        }
         None => {
             println!("Please specify a command. Use --help for more information.");
         }
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::client::AsyncTranslator;
+
+    #[test]
+    fn test_flags_override_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("translator.json");
+        std::fs::write(
+            &path,
+            r#"{"api_key": "file_key", "max_concurrent": 7, "max_rps": 1.0,
+                "models": [{"id": "custom-model", "lane": "Slow", "rpm": 100,
+                            "max_concurrent": 4, "enabled": true}]}"#,
+        )
+        .unwrap();
+
+        let args = Args::try_parse_from([
+            "doubao-translator".as_ref(),
+            "--config".as_ref(),
+            path.as_os_str(),
+            "--max-concurrent".as_ref(),
+            "50".as_ref(),
+            "--max-rps".as_ref(),
+            "2.5".as_ref(),
+            "doctor".as_ref(),
+            "--offline".as_ref(),
+        ])
+        .unwrap();
+
+        let translator = AsyncTranslator::new(args.translator_config().unwrap()).unwrap();
+        assert_eq!(translator.config().max_concurrent, 50);
+        assert_eq!(translator.config().max_rps, 2.5);
+        assert_eq!(translator.config().api_key, "file_key");
+        assert_eq!(translator.get_available_models()[0].id, "custom-model");
+    }
+
+    #[test]
+    fn test_stdin_replaces_file() {
+        let parse = |argv: &[&str]| Args::try_parse_from(["doubao-translator"].iter().chain(argv));
+        let args = parse(&["md", "--stdin", "-t", "ja"]).unwrap();
+        match args.command {
+            Some(Commands::Md { file, stdin, target_lang, .. }) => {
+                assert!(stdin);
+                assert_eq!(file, None);
+                assert_eq!(target_lang, "ja");
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(parse(&["txt"]).is_err());
+        assert!(parse(&["txt", "--stdin", "-f", "a.txt"]).is_err());
+    }
+}
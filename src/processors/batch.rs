@@ -0,0 +1,201 @@
+//! Sending several independent texts in one request, joined by a delimiter
+
+use std::path::Path;
+use tracing::warn;
+
+use crate::core::client::AsyncTranslator;
+use crate::core::diagnostics::{Diagnostic, Diagnostics, Severity};
+use crate::core::errors::{Result, TranslationError};
+use crate::core::models::TranslationRequest;
+use crate::utils::rng::{delimiter_token, seeded_rng};
+use crate::utils::tokens::count_tokens;
+
+/// Tokens a batched text adds besides itself: the delimiter and its newlines
+pub(crate) const BATCH_DELIMITER_TOKENS: usize = 4;
+
+/// Group consecutive `texts` into runs that fit in `max_tokens` together
+pub(crate) fn group_batches(texts: &[&str], max_tokens: usize) -> Vec<Vec<usize>> {
+    let all: Vec<usize> = (0..texts.len()).collect();
+    group_pending(texts, &all, max_tokens)
+}
+
+/// Group the `pending` indices of `texts` into runs of consecutive indices
+/// that fit in `max_tokens` together
+pub(crate) fn group_pending(
+    texts: &[&str],
+    pending: &[usize],
+    max_tokens: usize,
+) -> Vec<Vec<usize>> {
+    let mut batches: Vec<Vec<usize>> = Vec::new();
+    let mut batch_len = 0;
+    for &i in pending {
+        let len = count_tokens(texts[i]) + BATCH_DELIMITER_TOKENS;
+        let consecutive = batches
+            .last()
+            .and_then(|batch| batch.last())
+            .is_some_and(|&last| last + 1 == i);
+        match batches.last_mut() {
+            Some(batch) if consecutive && batch_len + len <= max_tokens => {
+                batch.push(i);
+                batch_len += len;
+            }
+            _ => {
+                batches.push(vec![i]);
+                batch_len = len;
+            }
+        }
+    }
+    batches
+}
+
+/// Translates texts of one file in delimiter-joined batches, falling back to
+/// one request per text
+pub(crate) struct Batcher<'a> {
+    /// Client used for all requests
+    pub translator: &'a AsyncTranslator,
+    /// Where failures are reported
+    pub diagnostics: &'a Diagnostics,
+    /// File the texts come from
+    pub input: &'a Path,
+    /// What a text is called in messages ("cue", "paragraph", ...)
+    pub unit: &'a str,
+}
+
+impl Batcher<'_> {
+    /// Translate `texts`, batched up to `max_input_tokens` per request.
+    ///
+    /// `None` marks a text that could not be translated (reported as a
+    /// warning). Only reaching the cost cap aborts.
+    pub async fn translate_all(
+        &self,
+        texts: &[&str],
+        target_lang: &str,
+        source_lang: Option<&str>,
+    ) -> Result<Vec<Option<String>>> {
+        let mut translations: Vec<Option<String>> = vec![None; texts.len()];
+        let max_tokens = self.translator.config().max_input_tokens;
+        for batch in group_batches(texts, max_tokens) {
+            let translated = if batch.len() > 1 {
+                self.translate_batch(texts, &batch, None, target_lang, source_lang)
+                    .await?
+            } else {
+                None
+            };
+            match translated {
+                Some(parts) => {
+                    for (&i, part) in batch.iter().zip(parts) {
+                        translations[i] = Some(part);
+                    }
+                }
+                None => {
+                    for &i in &batch {
+                        translations[i] = self
+                            .translate_one(i, texts[i], target_lang, source_lang)
+                            .await?;
+                    }
+                }
+            }
+        }
+        Ok(translations)
+    }
+
+    /// Translate several texts in one request, joined by a delimiter, with
+    /// `context` (e.g. the document and section of the first text).
+    ///
+    /// Returns `None` if the request failed or the response does not split
+    /// back into one part per text; the caller then sends them one by one.
+    pub async fn translate_batch(
+        &self,
+        texts: &[&str],
+        batch: &[usize],
+        context: Option<String>,
+        target_lang: &str,
+        source_lang: Option<&str>,
+    ) -> Result<Option<Vec<String>>> {
+        let delimiter =
+            delimiter_token(&mut seeded_rng(self.translator.config().seed, "delimiter"));
+        if batch.iter().any(|&i| texts[i].contains(&delimiter)) {
+            return Ok(None);
+        }
+
+        let joined: Vec<&str> = batch.iter().map(|&i| texts[i]).collect();
+        let mut request = TranslationRequest::new(
+            joined.join(&format!("\n{}\n", delimiter)),
+            target_lang.to_string(),
+        )
+        .with_source_lang(source_lang.unwrap_or("auto"));
+        request.context = context;
+
+        let location = format!(
+            "{}s {}-{}",
+            self.unit,
+            batch[0] + 1,
+            batch[batch.len() - 1] + 1
+        );
+        match self.translator.translate(&request).await {
+            Ok(result) => {
+                let parts: Vec<String> = result
+                    .translation
+                    .split(delimiter.as_str())
+                    .map(|part| part.trim().to_string())
+                    .collect();
+                if parts.len() == batch.len() {
+                    return Ok(Some(parts));
+                }
+                warn!(
+                    "Batch {} came back in {} parts instead of {}, translating individually",
+                    location,
+                    parts.len(),
+                    batch.len()
+                );
+                self.diagnostics.push(
+                    Diagnostic::new(
+                        Severity::Info,
+                        format!(
+                            "batch delimiter not preserved, {}s translated individually",
+                            self.unit
+                        ),
+                    )
+                    .with_file(self.input)
+                    .with_location(location),
+                );
+                Ok(None)
+            }
+            Err(e @ TranslationError::CostLimitExceeded { .. }) => Err(e),
+            Err(e) => {
+                warn!("Batch {} failed: {}, translating individually", location, e);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Translate text `i` on its own; `None` (original kept) if it fails
+    async fn translate_one(
+        &self,
+        i: usize,
+        text: &str,
+        target_lang: &str,
+        source_lang: Option<&str>,
+    ) -> Result<Option<String>> {
+        let request = TranslationRequest::new(text.to_string(), target_lang.to_string())
+            .with_source_lang(source_lang.unwrap_or("auto"));
+
+        match self.translator.translate(&request).await {
+            Ok(result) => Ok(Some(result.translation.trim().to_string())),
+            Err(e @ TranslationError::CostLimitExceeded { .. }) => Err(e),
+            Err(e) => {
+                let location = format!("{} {}", self.unit, i + 1);
+                warn!("Translation failed for {}: {}", location, e);
+                self.diagnostics.push(
+                    Diagnostic::new(
+                        Severity::Warning,
+                        format!("{} left untranslated: {}", self.unit, e),
+                    )
+                    .with_file(self.input)
+                    .with_location(location),
+                );
+                Ok(None)
+            }
+        }
+    }
+}
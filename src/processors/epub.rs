@@ -1,23 +1,175 @@
 //! ePub file processor with translation and leak detection
 
+use futures::stream::{self, StreamExt};
+use std::collections::HashMap;
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
 use tracing::{debug, info, warn};
 
 use crate::core::client::AsyncTranslator;
+use crate::core::config::TranslatorConfig;
+use crate::core::diagnostics::{Diagnostic, Diagnostics, Severity};
 use crate::core::errors::{Result, TranslationError};
-use crate::core::models::TranslationRequest;
+use crate::processors::html::{translatable_texts, HtmlProcessor};
+use crate::processors::plan::FilePlan;
+use crate::processors::progress::{Progress, ProgressFn};
+use crate::processors::fixup::{FixupEntry, FixupJob, Fixups};
+
+/// Name of the zip entry declaring the container type
+const MIMETYPE_ENTRY: &str = "mimetype";
+
+/// Required content of the `mimetype` entry
+const EPUB_MIMETYPE: &[u8] = b"application/epub+zip";
+
+/// Chapters read ahead of translation by default
+pub const DEFAULT_PIPELINE_DEPTH: usize = 2;
+
+/// Translated note bodies by note id and source body, shared by concurrently
+/// translated chapters; each note is translated by whichever chapter reaches it
+/// first. Keying on the body too keeps ids that chapters reuse for different
+/// notes (e.g. `fn1` in every chapter) from sharing a translation.
+type NoteCache = Mutex<HashMap<(String, String), Arc<OnceCell<String>>>>;
 
 /// ePub processor for translation and leak detection
 #[derive(Debug, Clone)]
 pub struct EpubProcessor {
-    translator: AsyncTranslator,
+    /// Chapter translator; attributes are left untouched in ePubs
+    html: HtmlProcessor,
+    /// Leave spine items marked `linear="no"` untranslated
+    skip_non_linear: bool,
+    /// Rewrite the OPF `dc:language` to the target language
+    update_language: bool,
+    /// Translate the OPF title, description and creator names
+    translate_metadata: bool,
+    /// Problems found while translating (missing chapters, ...)
+    diagnostics: Diagnostics,
+    /// Snippets the leak report flags, for a fix-up job
+    fixups: Fixups,
+    /// Chapters read ahead while an earlier one translates; 0 reads the whole
+    /// book before translating
+    pipeline_depth: usize,
+    /// Chapters translated at the same time
+    chapter_concurrency: usize,
+    /// Receives chapter and file progress
+    progress: ProgressFn,
+    /// Share of untranslated words above which leak detection flags a
+    /// chapter or snippet
+    untranslated_threshold: f64,
+    /// Simulated time to read each chapter
+    #[cfg(test)]
+    read_delay: std::time::Duration,
 }
 
 impl EpubProcessor {
     /// Create a new ePub processor
     pub fn new(translator: AsyncTranslator) -> Self {
-        Self { translator }
+        let chapter_concurrency = translator.config().max_concurrent.max(1);
+        Self {
+            html: HtmlProcessor::new(translator).with_attributes(Vec::<String>::new()),
+            skip_non_linear: false,
+            update_language: true,
+            translate_metadata: true,
+            diagnostics: Diagnostics::new(),
+            fixups: Fixups::new(),
+            pipeline_depth: DEFAULT_PIPELINE_DEPTH,
+            chapter_concurrency,
+            progress: ProgressFn::default(),
+            untranslated_threshold: DEFAULT_UNTRANSLATED_RATIO,
+            #[cfg(test)]
+            read_delay: std::time::Duration::ZERO,
+        }
+    }
+
+    /// Skip spine items marked `linear="no"` (e.g. pop-up notes).
+    ///
+    /// Skipped items are copied into the output unchanged.
+    pub fn with_skip_non_linear(mut self, skip: bool) -> Self {
+        self.skip_non_linear = skip;
+        self
+    }
+
+    /// Rewrite the OPF `dc:language` to the target language on repack (default).
+    ///
+    /// When enabled the original language is kept as a secondary `dc:language`.
+    /// Disable to leave the package metadata untouched.
+    pub fn with_update_language(mut self, update: bool) -> Self {
+        self.update_language = update;
+        self
+    }
+
+    /// Translate `dc:title`, `dc:description` and `dc:creator` in the OPF (default).
+    ///
+    /// Only the element text changes; sort keys such as `opf:file-as` are kept.
+    /// Metadata does not count towards [`with_sample`](Self::with_sample).
+    pub fn with_translate_metadata(mut self, translate: bool) -> Self {
+        self.translate_metadata = translate;
+        self
+    }
+
+    /// Read up to `depth` chapters ahead while the current one is translating.
+    ///
+    /// Reading and decompressing chapters then overlaps with the network
+    /// round-trips. `0` reads every chapter first and translates afterwards.
+    pub fn with_pipeline_depth(mut self, depth: usize) -> Self {
+        self.pipeline_depth = depth;
+        self
+    }
+
+    /// Translate up to `concurrency` chapters at the same time (default:
+    /// the translator's `max_concurrent`).
+    ///
+    /// Requests are still bounded by the translator; chapters keep their
+    /// spine order in the output. While sampling, chapters are translated one
+    /// at a time so the sample covers the start of the book.
+    pub fn with_chapter_concurrency(mut self, concurrency: usize) -> Self {
+        self.chapter_concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Translate the `<text>`/`<tspan>` labels of inline SVG diagrams (off by
+    /// default), see [`HtmlProcessor::with_svg_text`]
+    pub fn with_svg_text(mut self, translate: bool) -> Self {
+        self.html = self.html.with_svg_text(translate);
+        self
+    }
+
+    /// Translate only the first `limit` texts of each book, as a cheap preview;
+    /// the rest of the book is copied unchanged.
+    pub fn with_sample(mut self, limit: Option<usize>) -> Self {
+        self.html = self.html.with_sample(limit);
+        self
+    }
+
+    /// Flag chapters and snippets whose share of untranslated words exceeds
+    /// `threshold` when checking for leaks (default
+    /// [`DEFAULT_UNTRANSLATED_RATIO`])
+    pub fn with_untranslated_threshold(mut self, threshold: f64) -> Self {
+        self.untranslated_threshold = threshold;
+        self
+    }
+
+    /// Report problems into `diagnostics` instead of a private collector
+    pub fn with_diagnostics(mut self, diagnostics: Diagnostics) -> Self {
+        self.diagnostics = diagnostics;
+        self
+    }
+
+    /// Problems found so far
+    pub fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    /// Collect the snippets leak reports flag into `fixups` (e.g. one shared by a whole job)
+    pub fn with_fixups(mut self, fixups: Fixups) -> Self {
+        self.fixups = fixups;
+        self
+    }
+
+    /// Snippets flagged by leak reports so far
+    pub fn fixups(&self) -> &Fixups {
+        &self.fixups
     }
 
     /// Create from environment configuration
@@ -26,6 +178,12 @@ impl EpubProcessor {
         Ok(Self::new(translator))
     }
 
+    /// Create from an already loaded configuration (e.g. from `--config`)
+    pub fn from_config(config: TranslatorConfig) -> Result<Self> {
+        let translator = AsyncTranslator::new(config)?;
+        Ok(Self::new(translator))
+    }
+
     /// Find ePub files in directory
     pub fn find_epub_files(&self, dir: &Path) -> Result<Vec<PathBuf>> {
         if !dir.is_dir() {
@@ -51,14 +209,99 @@ impl EpubProcessor {
 
     /// Translate ePub file
     pub async fn translate_epub(
+        &self,
+        input: &Path,
+        output: &Path,
+        target_lang: &str,
+        source_lang: Option<String>,
+        auto_approve: bool,
+    ) -> Result<()> {
+        self.translate_epub_with_progress(
+            input,
+            output,
+            target_lang,
+            source_lang,
+            auto_approve,
+            |_| {},
+        )
+        .await
+    }
+
+    /// [`translate_epub`](Self::translate_epub), reporting to `progress` as
+    /// chapters finish (in spine order) and once the book is written
+    pub async fn translate_epub_with_progress(
         &self,
         input: &Path,
         output: &Path,
         target_lang: &str,
         source_lang: Option<String>,
         _auto_approve: bool,
+        progress: impl Fn(Progress) + Send + Sync + 'static,
     ) -> Result<()> {
         debug!("Translating ePub: {}", input.display());
+        let output = &resolve_output_path(input, output, target_lang)?;
+
+        // Every book starts with a fresh sample budget
+        let book = Self {
+            html: self.html.restart_sample(),
+            progress: ProgressFn::new(progress),
+            ..self.clone()
+        };
+        book.translate_book(input, output, target_lang, source_lang)
+            .await?;
+
+        let skipped = book.html.sample_skipped();
+        if skipped > 0 {
+            info!("Partial sample: {} texts of {} left untranslated", skipped, input.display());
+            self.diagnostics.push(
+                Diagnostic::new(
+                    Severity::Info,
+                    format!("partial sample: {} texts left untranslated", skipped),
+                )
+                .with_file(input),
+            );
+        }
+        Ok(())
+    }
+
+    /// What translating `input` would send, without calling the API: the
+    /// texts of its chapters, and of its metadata when that is translated
+    pub async fn plan(&self, input: &Path) -> Result<FilePlan> {
+        let mut book = epub::doc::EpubDoc::new(input)?;
+        let mut texts = Vec::new();
+        if self.translate_metadata {
+            let root_file = book.root_file.clone();
+            if let Some(opf) = book.get_resource_str_by_path(&root_file) {
+                for caps in metadata_regex().captures_iter(&opf) {
+                    texts.extend(translatable_texts(&caps["text"]));
+                }
+            }
+        }
+
+        let reader = ChapterReader {
+            spine: book.spine.clone(),
+            book,
+            skip_non_linear: self.skip_non_linear,
+            #[cfg(test)]
+            delay: std::time::Duration::ZERO,
+        };
+        reader.read(|chapter| {
+            if let (Some(_), Some(content)) = (chapter.entry, chapter.content) {
+                texts.extend(translatable_texts(&content));
+            }
+            true
+        });
+        Ok(FilePlan::new(input, &texts, &self.html.current_model().await))
+    }
+
+    /// Translate the chapters of `input` and repack them into `output`
+    async fn translate_book(
+        &self,
+        input: &Path,
+        output: &Path,
+        target_lang: &str,
+        source_lang: Option<String>,
+    ) -> Result<()> {
 
         // 打开并解析 ePub 文件
         let mut book = epub::doc::EpubDoc::new(input)?;
@@ -71,183 +314,297 @@ impl EpubProcessor {
         let spine = book.spine.clone();
         info!("Found {} chapters", spine.len());
 
-        // 翻译每个章节
-        let mut translated_chapters = Vec::with_capacity(spine.len());
-        for (i, item) in spine.iter().enumerate() {
-            debug!("Translating chapter {}: {}", i + 1, item.idref);
-
-            // 获取章节内容
-            if let Some((content, _mime)) = book.get_resource(&item.idref) {
-                let content_str = String::from_utf8_lossy(&content).to_string();
-
-                // 翻译章节内容
-                let translated_content = self.translate_html_content(
-                    &content_str,
-                    target_lang,
-                    source_lang.as_deref(),
-                ).await?;
+        let mut replacements = HashMap::with_capacity(spine.len() + 1);
+
+        // 翻译 OPF 元数据并更新语言声明
+        if self.update_language || self.translate_metadata {
+            let root_file = book.root_file.clone();
+            let opf_path = zip_entry_name(&root_file);
+            match book.get_resource_str_by_path(&root_file) {
+                Some(mut opf) => {
+                    if self.translate_metadata {
+                        opf = self
+                            .translate_opf_metadata(&opf, target_lang, source_lang.as_deref())
+                            .await?;
+                    }
+                    if self.update_language {
+                        opf = update_opf_language(&opf, target_lang);
+                    }
+                    replacements.insert(opf_path, opf);
+                }
+                None => {
+                    warn!("Failed to read package document: {}", opf_path);
+                    self.diagnostics.push(
+                        Diagnostic::new(Severity::Warning, "metadata not updated")
+                            .with_file(input)
+                            .with_location(opf_path),
+                    );
+                }
+            }
+        }
 
-                translated_chapters.push((item.idref.clone(), translated_content));
-            } else {
-                warn!("Failed to get content for chapter: {}", item.idref);
+        let total = spine
+            .iter()
+            .filter(|item| item.linear || !self.skip_non_linear)
+            .count();
+        let reader = ChapterReader {
+            book,
+            spine,
+            skip_non_linear: self.skip_non_linear,
+            #[cfg(test)]
+            delay: self.read_delay,
+        };
+        let notes = NoteCache::default();
+        let source = source_lang.as_deref();
+
+        // 读取章节：整本读完，或由读取线程提前最多 `pipeline_depth` 章
+        let (chapters, parser) = if self.pipeline_depth == 0 {
+            let mut chapters = Vec::new();
+            reader.read(|chapter| {
+                chapters.push(chapter);
+                true
+            });
+            (stream::iter(chapters).boxed(), None)
+        } else {
+            // If translation fails the receiver is dropped and the reader stops
+            let (tx, mut rx) = tokio::sync::mpsc::channel(self.pipeline_depth);
+            let parser = tokio::task::spawn_blocking(move || {
+                reader.read(|chapter| tx.blocking_send(chapter).is_ok())
+            });
+            let chapters = stream::poll_fn(move |cx| rx.poll_recv(cx)).boxed();
+            (chapters, Some(parser))
+        };
+
+        // 并发翻译章节，结果按书脊顺序返回
+        let concurrency = if self.html.sample_limit().is_some() {
+            1
+        } else {
+            self.chapter_concurrency
+        };
+        let mut translated = chapters
+            .map(|chapter| self.translate_chapter(input, chapter, target_lang, source, &notes))
+            .buffered(concurrency);
+        let mut current = 0;
+        while let Some(chapter) = translated.next().await {
+            if let Some((entry, content)) = chapter? {
+                replacements.insert(entry, content);
             }
+            current += 1;
+            self.progress.emit(Progress::Chapter { current, total });
+        }
+        drop(translated);
+        if let Some(parser) = parser {
+            parser
+                .await
+                .map_err(|e| TranslationError::InternalError(e.to_string()))?;
         }
 
         // 重新打包 ePub
-        self.repack_epub(input, output, &translated_chapters, &book.resources).await?;
+        self.repack_epub(input, output, &replacements).await?;
+        let bytes = tokio::fs::metadata(output).await?.len() as usize;
+        self.progress.emit(Progress::File {
+            output: output.to_path_buf(),
+            bytes,
+        });
 
         info!("ePub translation complete: {} -> {}", input.display(), output.display());
         Ok(())
     }
 
-    /// 翻译 HTML 内容
-    async fn translate_html_content(
+    /// Translate the `dc:title`, `dc:description` and `dc:creator` texts of
+    /// an OPF package document, keeping their attributes
+    async fn translate_opf_metadata(
         &self,
-        html: &str,
+        opf: &str,
         target_lang: &str,
         source_lang: Option<&str>,
     ) -> Result<String> {
-        // 简单的 HTML 标签保留逻辑（实际项目中应该使用更复杂的解析）
-        // 这里使用简单的方法：保留标签，翻译文本内容
-
-        let mut translated = String::new();
-        let mut in_tag = false;
-        let mut buffer = String::new();
-
-        for c in html.chars() {
-            match c {
-                '<' => {
-                    in_tag = true;
-                    if !buffer.is_empty() {
-                        // 翻译缓冲的文本
-                        let translated_text = self.translate_text(&buffer, target_lang, source_lang).await?;
-                        translated.push_str(&translated_text);
-                        buffer.clear();
-                    }
-                    translated.push(c);
-                }
-                '>' => {
-                    in_tag = false;
-                    translated.push(c);
-                }
-                _ => {
-                    if in_tag {
-                        translated.push(c);
-                    } else {
-                        buffer.push(c);
-                    }
-                }
-            }
+        // The sample budget is for chapter text
+        let html = self.html.clone().with_sample(None);
+
+        let mut result = String::with_capacity(opf.len());
+        let mut last = 0;
+        for caps in metadata_regex().captures_iter(opf) {
+            let text = caps.name("text").unwrap();
+            let translated = html.translate_html(text.as_str(), target_lang, source_lang).await?;
+            result.push_str(&opf[last..text.start()]);
+            result.push_str(&translated);
+            last = text.end();
         }
+        result.push_str(&opf[last..]);
+        Ok(result)
+    }
 
-        // 翻译最后剩余的文本
-        if !buffer.is_empty() {
-            let translated_text = self.translate_text(&buffer, target_lang, source_lang).await?;
-            translated.push_str(&translated_text);
+    /// Translate one chapter read by [`ChapterReader`].
+    ///
+    /// Returns the zip entry to replace and its new content, or `None` (with a
+    /// diagnostic) if the chapter could not be read or is not in the manifest.
+    async fn translate_chapter(
+        &self,
+        input: &Path,
+        chapter: ChapterSource,
+        target_lang: &str,
+        source_lang: Option<&str>,
+        notes: &NoteCache,
+    ) -> Result<Option<(String, String)>> {
+        let Some(content) = chapter.content else {
+            warn!("Failed to get content for chapter: {}", chapter.idref);
+            self.diagnostics.push(
+                Diagnostic::new(Severity::Warning, "chapter content could not be read")
+                    .with_file(input)
+                    .with_location(chapter.idref),
+            );
+            return Ok(None);
+        };
+        let Some(entry) = chapter.entry else {
+            warn!("Chapter {} is missing from the manifest", chapter.idref);
+            self.diagnostics.push(
+                Diagnostic::new(Severity::Warning, "chapter missing from the manifest")
+                    .with_file(input)
+                    .with_location(chapter.idref),
+            );
+            return Ok(None);
+        };
+
+        debug!("Translating chapter {}: {}", chapter.index + 1, chapter.idref);
+        let (translated, fell_back) = self
+            .translate_html_content(&content, target_lang, source_lang, notes)
+            .await?;
+        if fell_back {
+            warn!(
+                "Chapter {} has malformed XHTML, translated it with the fallback scanner",
+                chapter.idref
+            );
+            self.diagnostics.push(
+                Diagnostic::new(
+                    Severity::Warning,
+                    "malformed XHTML, translated with the fallback scanner",
+                )
+                .with_file(input)
+                .with_location(chapter.idref),
+            );
         }
-
-        Ok(translated)
+        Ok(Some((entry, translated)))
     }
 
-    /// 翻译纯文本内容
-    async fn translate_text(
+    /// 翻译 HTML 内容
+    ///
+    /// Footnote and endnote `<aside>` bodies are translated once per note id
+    /// and body and reused from `notes`, so a note that appears both as a
+    /// pop-up and in a notes section gets the same translation everywhere.
+    ///
+    /// Also returns whether malformed markup had to be translated with the
+    /// fallback scanner (see [`HtmlProcessor::translate_html`]).
+    async fn translate_html_content(
         &self,
-        text: &str,
+        html: &str,
         target_lang: &str,
         source_lang: Option<&str>,
-    ) -> Result<String> {
-        let trimmed = text.trim();
-        if trimmed.is_empty() {
-            return Ok(text.to_string());
+        notes: &NoteCache,
+    ) -> Result<(String, bool)> {
+        let mut translated = String::with_capacity(html.len());
+        let mut last = 0;
+        let mut fell_back = false;
+
+        for caps in note_regex().captures_iter(html) {
+            let Some(id) = note_id(&caps["attrs"]) else {
+                continue;
+            };
+            let inner = caps.name("inner").unwrap();
+
+            let before = &html[last..inner.start()];
+            let (text, scanned) = self
+                .html
+                .translate_html_checked(before, target_lang, source_lang)
+                .await?;
+            translated.push_str(&text);
+            fell_back |= scanned;
+
+            let key = (id, inner.as_str().to_string());
+            let cell = notes.lock().unwrap().entry(key.clone()).or_default().clone();
+            if cell.initialized() {
+                debug!("Reusing translation of note {}", key.0);
+            }
+            let translate = || self.html.translate_html(inner.as_str(), target_lang, source_lang);
+            let body = cell.get_or_try_init(translate).await?;
+            translated.push_str(body);
+            last = inner.end();
         }
 
-        let request = TranslationRequest::new(trimmed.to_string(), target_lang.to_string())
-            .with_source_lang(source_lang.unwrap_or("auto"));
-
-        let result = self.translator.translate(&request).await?;
-        Ok(text.replace(trimmed, &result.translation))
+        let rest = &html[last..];
+        let (text, scanned) = self
+            .html
+            .translate_html_checked(rest, target_lang, source_lang)
+            .await?;
+        translated.push_str(&text);
+        Ok((translated, fell_back || scanned))
     }
 
     /// 重新打包 ePub 文件
+    ///
+    /// `replacements` maps zip entry names to their new content; see [`repack`].
     async fn repack_epub(
         &self,
         input: &Path,
         output: &Path,
-        translated_chapters: &[(String, String)],
-        resources: &std::collections::HashMap<String, epub::doc::ResourceItem>,
+        replacements: &HashMap<String, String>,
     ) -> Result<()> {
-        // 读取原始 ePub 文件
-        let file = tokio::fs::read(input).await?;
-        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(file))?;
-
-        // 创建新的 ePub 文件（使用标准库的 File）
-        let file = std::fs::File::create(output)?;
-        let mut writer = zip::ZipWriter::new(file);
-
-        // 复制所有文件，替换翻译后的章节
-        for i in 0..zip.len() {
-            let mut file = zip.by_index(i)?;
-            let file_name = file.name().to_string();
-
-            // 获取资源路径
-            let mut resource_path = None;
-            for (id, content) in translated_chapters.iter() {
-                // 查找资源对应的路径
-                let mut found = false;
-                if let Some(resource) = resources.get(id) {
-                    let resource_str = resource.path.to_str().unwrap_or("");
-                    if file_name.contains(resource_str) {
-                        resource_path = Some((id, content));
-                        found = true;
-                    }
-                }
-                if found {
-                    break;
-                }
-            }
-
-            if let Some((_, content)) = resource_path {
-                debug!("Replacing: {}", file_name);
-                let options = zip::write::FileOptions::default()
-                    .compression_method(file.compression());
-
-                writer.start_file(file_name, options)?;
-                writer.write_all(content.as_bytes())?;
-            } else {
-                // 复制原始文件
-                let options = zip::write::FileOptions::default()
-                    .compression_method(file.compression());
-
-                writer.start_file(file_name, options)?;
-                let mut buffer = Vec::new();
-                std::io::copy(&mut file, &mut buffer)?;
-                writer.write_all(&buffer)?;
-            }
-        }
-
-        writer.finish()?;
-        Ok(())
+        repack(input, output, replacements)
     }
 
     /// Generate leak report
+    ///
+    /// Scans the ePubs in `dir` (or the single ePub it names) with
+    /// [`check_untranslated`](Self::check_untranslated) and writes the leaks
+    /// next to them: [`LEAK_REPORT_JSON`], a fix-up job to edit for
+    /// `apply-fix`, and a readable [`LEAK_REPORT_SUMMARY`]. The leaks are also
+    /// added to [`fixups`](Self::fixups). Returns the number of leaks found.
     pub async fn generate_leak_report(
         &self,
         dir: &Path,
-        _target_lang: &str,
-    ) -> Result<()> {
+        target_lang: &str,
+    ) -> Result<usize> {
         info!("Generating leak report for: {}", dir.display());
-        // TODO: Implement leak detection
-        Ok(())
+        let leaks = self.check_untranslated(dir, target_lang).await?;
+
+        let report_dir = if dir.is_dir() {
+            dir
+        } else {
+            dir.parent().unwrap_or_else(|| Path::new("."))
+        };
+        let job = leak_fixup_job(&leaks)?;
+        job.save(&report_dir.join(LEAK_REPORT_JSON)).await?;
+        for entry in job.entries {
+            self.fixups.push(entry);
+        }
+        tokio::fs::write(report_dir.join(LEAK_REPORT_SUMMARY), leak_summary(&leaks)).await?;
+
+        info!("Leak report: {} untranslated snippets", leaks.len());
+        Ok(leaks.len())
     }
 
     /// Check for untranslated content
-    pub async fn check_untranslated(&self, dir: &Path) -> Result<Vec<LeakInfo>> {
+    ///
+    /// `dir` is a directory of ePubs or a single ePub. Every chapter flagged
+    /// by the untranslated-content heuristic yields one [`LeakInfo`] per text
+    /// snippet that still looks untranslated. Code (`<pre>`, `<code>`, ...)
+    /// and URLs are ignored; for a Chinese, Japanese or Korean `target_lang`
+    /// Latin words are weighed against the CJK text around them.
+    pub async fn check_untranslated(
+        &self,
+        dir: &Path,
+        target_lang: &str,
+    ) -> Result<Vec<LeakInfo>> {
         info!("Checking untranslated in: {}", dir.display());
 
         let mut leaks = Vec::new();
 
         // 检查目录中的所有 ePub 文件
-        let epub_files = self.find_epub_files(dir)?;
+        let epub_files = if dir.is_file() {
+            vec![dir.to_path_buf()]
+        } else {
+            self.find_epub_files(dir)?
+        };
         for file_path in epub_files {
             if let Ok(mut book) = epub::doc::EpubDoc::new(&file_path) {
                 let book_name = book.get_title().unwrap_or_else(|| {
@@ -258,18 +615,23 @@ impl EpubProcessor {
                 let spine = book.spine.clone();
 
                 // 检查每个章节
-                for (_i, item) in spine.iter().enumerate() {
+                for item in spine.iter() {
                     if let Some((content, _mime)) = book.get_resource(&item.idref) {
                         let content_str = String::from_utf8_lossy(&content).to_string();
 
                         // 简单的漏译检测：检查是否包含大量英文内容（可以根据需要调整）
-                        if self.has_untranslated_content(&content_str) {
-                            leaks.push(LeakInfo {
-                                book_name: book_name.clone(),
-                                file_path: file_path.display().to_string(),
-                                original: content_str.clone(),
-                                translation: None,
-                            });
+                        if self.has_untranslated_content(&content_str, target_lang) {
+                            for snippet in text_nodes(&content_str) {
+                                let (share, _) = untranslated_share(&snippet, target_lang);
+                                if share > self.untranslated_threshold {
+                                    leaks.push(LeakInfo {
+                                        book_name: book_name.clone(),
+                                        file_path: file_path.display().to_string(),
+                                        chapter: item.idref.clone(),
+                                        original: snippet,
+                                    });
+                                }
+                            }
                         }
                     }
                 }
@@ -281,29 +643,11 @@ impl EpubProcessor {
 
     /// 提取纯文本内容（移除 HTML/XML 标签）
     fn extract_text_content(&self, html_content: &str) -> String {
-        // 简单的文本提取，移除标签
-        let mut result = String::new();
-        let mut in_tag = false;
-        let mut chars = html_content.chars().peekable();
-
-        while let Some(ch) = chars.next() {
-            match ch {
-                '<' => in_tag = true,
-                '>' => in_tag = false,
-                _ => {
-                    if !in_tag && !ch.is_whitespace() {
-                        result.push(ch);
-                    }
-                }
-            }
-        }
-
-        // 如果简单方法不够好，可以考虑使用 html5ever 或其他 HTML 解析库
-        result
+        text_nodes(html_content).join(" ")
     }
 
     /// 检查内容是否包含未翻译的内容
-    fn has_untranslated_content(&self, content: &str) -> bool {
+    fn has_untranslated_content(&self, content: &str, target_lang: &str) -> bool {
         // 更准确的漏译检测逻辑：
         // 1. 忽略 XML 标签和属性
         // 2. 只检查文本内容
@@ -317,66 +661,499 @@ impl EpubProcessor {
             return false;
         }
 
-        // 计算英文单词的比例
-        let words: Vec<&str> = text_content.split_whitespace().collect();
-        let english_word_count = words.iter()
-            .filter(|&&word| {
-                // 只计算纯英文字母组成的单词
-                !word.is_empty() &&
-                word.chars().all(|c| c.is_ascii_alphabetic()) &&
-                word.len() > 1 // 忽略单个字母
-            })
-            .count();
+        // 如果英文单词比例超过阈值（默认 70%）且总单词数大于 5，则认为可能是未翻译的内容
+        let (share, word_count) = untranslated_share(&text_content, target_lang);
+        share > self.untranslated_threshold && word_count > 5
+    }
 
-        let total_word_count = words.len();
+    /// Save leak report to JSON, as a fix-up job to edit for `apply-fix`
+    pub async fn save_leak_report(&self, leaks: &[LeakInfo], path: &Path) -> Result<()> {
+        leak_fixup_job(leaks)?.save(path).await
+    }
+}
 
-        if total_word_count == 0 {
-            return false;
-        }
+/// `leaks` as a [`FixupJob`] on the chapters' zip entries
+fn leak_fixup_job(leaks: &[LeakInfo]) -> Result<FixupJob> {
+    let mut job = FixupJob::default();
+    let mut books: HashMap<&str, epub::doc::EpubDoc<std::io::BufReader<std::fs::File>>> =
+        HashMap::new();
+    for (index, leak) in leaks.iter().enumerate() {
+        let book = match books.entry(&leak.file_path) {
+            std::collections::hash_map::Entry::Occupied(book) => book.into_mut(),
+            std::collections::hash_map::Entry::Vacant(slot) => {
+                slot.insert(epub::doc::EpubDoc::new(&leak.file_path)?)
+            }
+        };
+        let Some(resource) = book.resources.get(&leak.chapter) else {
+            warn!("Chapter {} not found in {}", leak.chapter, leak.file_path);
+            continue;
+        };
+        job.entries.push(FixupEntry {
+            source: PathBuf::from(&leak.file_path),
+            file: PathBuf::from(&leak.file_path),
+            part: Some(zip_entry_name(&resource.path)),
+            index,
+            span: None,
+            original: leak.original.clone(),
+            reason: format!("untranslated in {} ({})", leak.book_name, leak.chapter),
+            translation: None,
+        });
+    }
+    Ok(job)
+}
 
-        // 如果英文单词比例超过 70% 且总单词数大于 5，则认为可能是未翻译的内容
-        let english_ratio = english_word_count as f64 / total_word_count as f64;
-        english_ratio > 0.7 && total_word_count > 5
+/// The first `count` text nodes with letters in the book's spine, as a
+/// sample of its language
+pub fn sample_paragraphs(path: &Path, count: usize) -> Result<Vec<String>> {
+    let mut book = epub::doc::EpubDoc::new(path).map_err(|e| TranslationError::FileError {
+        path: path.display().to_string(),
+        message: e.to_string(),
+    })?;
+    let mut paragraphs = Vec::new();
+    for item in book.spine.clone() {
+        let Some((content, _mime)) = book.get_resource(&item.idref) else {
+            continue;
+        };
+        let content = String::from_utf8_lossy(&content);
+        let nodes = text_nodes(&content).into_iter();
+        paragraphs.extend(nodes.filter(|node| node.chars().any(char::is_alphabetic)));
+        if paragraphs.len() >= count {
+            break;
+        }
     }
+    paragraphs.truncate(count);
+    Ok(paragraphs)
+}
 
-    /// Save leak report to JSON
-    pub async fn save_leak_report(&self, leaks: &[LeakInfo], path: &Path) -> Result<()> {
-        let json = serde_json::to_string_pretty(leaks)?;
-        tokio::fs::write(path, json).await?;
-        Ok(())
+/// Copy the ePub `input` to `output`, with `replacements` (zip entry name
+/// to new content) in place of their entries. The `mimetype` entry is always
+/// written first and stored uncompressed, as the OCF container format requires.
+///
+/// Entries are streamed from file to file one at a time, and unchanged
+/// ones are copied still compressed, so memory use does not grow with
+/// the size of the book's images and fonts. The book is written next to
+/// `output` and renamed over it at the end, so `output` may be `input`.
+pub(crate) fn repack(
+    input: &Path,
+    output: &Path,
+    replacements: &HashMap<String, String>,
+) -> Result<()> {
+    // 逐条读取原始 ePub 文件
+    let file = std::io::BufReader::new(std::fs::File::open(input)?);
+    let mut zip = zip::ZipArchive::new(file)?;
+
+    // 创建新的 ePub 文件（先写入临时文件）
+    let mut tmp = output.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let file = std::io::BufWriter::new(std::fs::File::create(&tmp)?);
+    let mut writer = zip::ZipWriter::new(file);
+
+    // mimetype 必须是第一个条目且不压缩
+    let stored = zip::write::FileOptions::default()
+        .compression_method(zip::CompressionMethod::Stored);
+    writer.start_file(MIMETYPE_ENTRY, stored)?;
+    writer.write_all(EPUB_MIMETYPE)?;
+
+    // 复制所有文件，替换翻译后的内容
+    for i in 0..zip.len() {
+        let file = zip.by_index_raw(i)?;
+        let file_name = file.name().to_string();
+        if file_name == MIMETYPE_ENTRY {
+            continue;
+        }
+
+        if let Some(content) = replacements.get(&file_name) {
+            debug!("Replacing: {}", file_name);
+            let options = zip::write::FileOptions::default()
+                .compression_method(file.compression());
+            writer.start_file(file_name, options)?;
+            writer.write_all(content.as_bytes())?;
+        } else {
+            // 原样复制压缩数据，不解压
+            writer.raw_copy_file(file)?;
+        }
     }
 
-    /// Apply fixes from JSON file
-    pub async fn apply_fixes(&self, json_path: &Path) -> Result<usize> {
-        info!("Applying fixes from: {}", json_path.display());
+    writer.finish()?.flush()?;
+    drop(zip);
+    std::fs::rename(&tmp, output)?;
+    Ok(())
+}
 
-        // 读取修复文件
-        let content = tokio::fs::read_to_string(json_path).await?;
-        let leaks: Vec<LeakInfo> = serde_json::from_str(&content)?;
+/// A spine item as read from the book, ready to translate
+#[derive(Debug)]
+struct ChapterSource {
+    /// Position in the spine
+    index: usize,
+    /// Manifest id
+    idref: String,
+    /// Zip entry holding the chapter, `None` if it is not in the manifest
+    entry: Option<String>,
+    /// Chapter markup, `None` if it could not be read
+    content: Option<String>,
+}
 
-        let mut fixed_count = 0;
+/// Reading side of the chapter pipeline: owns the book and reads its spine
+struct ChapterReader {
+    /// Book being read
+    book: epub::doc::EpubDoc<std::io::BufReader<std::fs::File>>,
+    /// Spine items to read, in order
+    spine: Vec<epub::doc::SpineItem>,
+    /// Leave out spine items marked `linear="no"`
+    skip_non_linear: bool,
+    /// Simulated time to read each chapter
+    #[cfg(test)]
+    delay: std::time::Duration,
+}
 
-        // 遍历所有漏译信息
-        for leak in leaks.iter() {
-            if let Some(_translation) = &leak.translation {
-                // 这里应该实现将翻译内容应用到 ePub 文件的逻辑
-                // 目前只是一个占位符
-                debug!("Applying fix to book: {}, file: {}", leak.book_name, leak.file_path);
-                fixed_count += 1;
+impl ChapterReader {
+    /// Read chapters in spine order, handing each to `send`; stops early when
+    /// `send` returns false. Blocking: reads from the zip archive.
+    fn read(mut self, mut send: impl FnMut(ChapterSource) -> bool) {
+        for (index, item) in self.spine.iter().enumerate() {
+            if self.skip_non_linear && !item.linear {
+                debug!("Skipping non-linear chapter {}: {}", index + 1, item.idref);
+                continue;
+            }
+            #[cfg(test)]
+            std::thread::sleep(self.delay);
+
+            let chapter = ChapterSource {
+                index,
+                idref: item.idref.clone(),
+                entry: self
+                    .book
+                    .resources
+                    .get(&item.idref)
+                    .map(|resource| zip_entry_name(&resource.path)),
+                content: self
+                    .book
+                    .get_resource(&item.idref)
+                    .map(|(content, _mime)| String::from_utf8_lossy(&content).into_owned()),
+            };
+            if !send(chapter) {
+                break;
             }
         }
+    }
+}
+
+/// Zip entry name for a path inside the ePub container
+fn zip_entry_name(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+/// `<aside>` elements; notes among them are picked out by [`note_id`]
+fn note_regex() -> &'static regex::Regex {
+    static RE: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    RE.get_or_init(|| {
+        regex::Regex::new(r"(?s)<aside\b(?P<attrs>[^>]*)>(?P<inner>.*?)</aside>").unwrap()
+    })
+}
 
-        Ok(fixed_count)
+/// Id of a footnote/endnote `<aside>`, from its attribute string.
+///
+/// Notes are recognised by `epub:type` or `role` (e.g. `doc-footnote`).
+fn note_id(attrs: &str) -> Option<String> {
+    static NOTE_TYPE: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    static ID: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+
+    let note_type = NOTE_TYPE.get_or_init(|| {
+        regex::Regex::new(
+            r#"(?:epub:type|role)\s*=\s*["'][^"']*\b(?:doc-)?(?:footnote|endnote|rearnote)\b"#,
+        )
+        .unwrap()
+    });
+    if !note_type.is_match(attrs) {
+        return None;
     }
+    let id = ID.get_or_init(|| regex::Regex::new(r#"\sid\s*=\s*["']([^"']+)["']"#).unwrap());
+    id.captures(attrs).map(|caps| caps[1].to_string())
 }
 
+/// Translatable OPF metadata elements; `text` is their escaped content
+fn metadata_regex() -> &'static regex::Regex {
+    static RE: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    RE.get_or_init(|| {
+        regex::Regex::new(concat!(
+            r"<dc:(?:title|description|creator)(?:\s[^>]*)?>",
+            r"(?P<text>[^<]*)</dc:(?:title|description|creator)>"
+        ))
+        .unwrap()
+    })
+}
+
+/// `<dc:language>` elements; `lang` is their content
+fn language_regex() -> &'static regex::Regex {
+    static RE: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    RE.get_or_init(|| {
+        regex::Regex::new(r"<dc:language(?:\s[^>]*)?>(?P<lang>[^<]*)</dc:language>").unwrap()
+    })
+}
+
+/// Closing tag of the OPF `<metadata>`, with or without a namespace prefix
+fn metadata_end_regex() -> &'static regex::Regex {
+    static RE: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    RE.get_or_init(|| regex::Regex::new(r"</(?:[A-Za-z_][-\w.]*:)?metadata\s*>").unwrap())
+}
+
+/// Set the primary `dc:language` of an OPF package document to `target_lang`.
+///
+/// The original language is kept as a secondary `dc:language`, which OPF
+/// allows; a package that already lists `target_lang` further down has the
+/// two swapped. If the package declares no language one is added.
+fn update_opf_language(opf: &str, target_lang: &str) -> String {
+    let languages: Vec<regex::Match> = language_regex()
+        .captures_iter(opf)
+        .filter_map(|caps| caps.name("lang"))
+        .collect();
+
+    let Some(first) = languages.first() else {
+        let Some(end) = metadata_end_regex().find(opf) else {
+            return opf.to_string();
+        };
+        return format!(
+            "{}  <dc:language>{}</dc:language>\n  {}",
+            &opf[..end.start()],
+            target_lang,
+            &opf[end.start()..]
+        );
+    };
+    let source = first.as_str().trim();
+    if source == target_lang {
+        return opf.to_string();
+    }
+
+    let declared = languages[1..].iter().find(|lang| lang.as_str().trim() == target_lang);
+    match declared {
+        // 目标语言已在后面声明：交换两者，避免重复
+        Some(declared) => format!(
+            "{}{}{}{}{}",
+            &opf[..first.start()],
+            target_lang,
+            &opf[first.end()..declared.start()],
+            first.as_str(),
+            &opf[declared.end()..]
+        ),
+        None => {
+            let close = opf[first.end()..].find('>');
+            let element_end = close.map_or(opf.len(), |i| first.end() + i + 1);
+            let secondary = if source.is_empty() {
+                String::new()
+            } else {
+                format!("\n    <dc:language>{}</dc:language>", source)
+            };
+            format!(
+                "{}{}{}{}{}",
+                &opf[..first.start()],
+                target_lang,
+                &opf[first.end()..element_end],
+                secondary,
+                &opf[element_end..]
+            )
+        }
+    }
+}
+
+/// Default share of English words above which text counts as untranslated
+pub const DEFAULT_UNTRANSLATED_RATIO: f64 = 0.7;
+
+/// File name of the editable leak report written by `generate_leak_report`
+pub const LEAK_REPORT_JSON: &str = "leak_report.json";
+
+/// File name of the human-readable leak summary written by `generate_leak_report`
+pub const LEAK_REPORT_SUMMARY: &str = "leak_report.txt";
+
 /// Leak information for manual translation
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone)]
 pub struct LeakInfo {
     pub book_name: String,
     pub file_path: String,
+    /// Manifest id of the chapter the snippet was found in
+    pub chapter: String,
     pub original: String,
-    pub translation: Option<String>,
+}
+
+/// Share of English words in `text`, and the number of words it was taken
+/// over.
+///
+/// English words are made only of ASCII letters once surrounding punctuation
+/// is trimmed (single letters ignored); URLs, e-mail addresses, file names
+/// and entities are not words. For a CJK `target_lang` the share is taken
+/// against the CJK text instead of all words, counting two CJK characters as
+/// one word, so identifiers and names in a translated chapter stay a
+/// minority.
+fn untranslated_share(text: &str, target_lang: &str) -> (f64, usize) {
+    let words: Vec<&str> = text
+        .split_whitespace()
+        .filter(|token| !is_url_like(token))
+        .map(|token| token.trim_matches(|c: char| !c.is_alphanumeric()))
+        .filter(|word| !word.is_empty())
+        .collect();
+    // 只计算纯英文字母组成的单词
+    let english = words
+        .iter()
+        .filter(|word| word.len() > 1 && word.chars().all(|c| c.is_ascii_alphabetic()))
+        .count();
+
+    let total = if is_cjk_lang(target_lang) {
+        let cjk = text.chars().filter(|&c| is_cjk(c)).count();
+        english + cjk / 2
+    } else {
+        words.len()
+    };
+    if total == 0 {
+        return (0.0, 0);
+    }
+    (english as f64 / total as f64, total)
+}
+
+/// Whether `token` is a URL, e-mail address, domain or file name, or an
+/// entity such as `&amp;`, rather than a word
+fn is_url_like(token: &str) -> bool {
+    let token = token.trim_matches(|c: char| !c.is_alphanumeric() && c != '&' && c != ';');
+    if token.contains("://") || token.starts_with("www.") || token.contains('/') {
+        return true;
+    }
+    if token.starts_with('&') && token.ends_with(';') {
+        return true;
+    }
+    // example.com, user@example.org, main.rs
+    let inner = token.trim_end_matches(|c: char| !c.is_alphanumeric());
+    inner.contains('@')
+        || inner.split('.').count() > 1 && inner.split('.').all(|part| !part.is_empty())
+}
+
+/// Whether `lang` is Chinese, Japanese or Korean
+fn is_cjk_lang(lang: &str) -> bool {
+    let primary = lang.split(['-', '_']).next().unwrap_or(lang);
+    ["zh", "ja", "ko"].iter().any(|cjk| primary.eq_ignore_ascii_case(cjk))
+}
+
+/// Whether `c` is a CJK ideograph, kana or hangul syllable
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}'
+        | '\u{3400}'..='\u{4dbf}'
+        | '\u{4e00}'..='\u{9fff}'
+        | '\u{ac00}'..='\u{d7af}'
+        | '\u{f900}'..='\u{faff}')
+}
+
+/// Trimmed text nodes of a chapter as they appear in the markup (entities
+/// escaped), leaving out `<script>`, `<style>` and code (`<pre>`, `<code>`,
+/// `<kbd>`, `<samp>`) contents
+fn text_nodes(html: &str) -> Vec<String> {
+    let mut reader = quick_xml::Reader::from_str(html);
+    let config = reader.config_mut();
+    config.check_end_names = false;
+    config.allow_unmatched_ends = true;
+
+    let mut nodes = Vec::new();
+    let mut code_depth = 0usize;
+    let is_code = |name: &[u8]| {
+        [&b"script"[..], b"style", b"pre", b"code", b"kbd", b"samp"]
+            .iter()
+            .any(|code| name.eq_ignore_ascii_case(code))
+    };
+    loop {
+        match reader.read_event() {
+            Ok(quick_xml::events::Event::Start(tag)) if is_code(tag.local_name().as_ref()) => {
+                code_depth += 1;
+            }
+            Ok(quick_xml::events::Event::End(tag)) if is_code(tag.local_name().as_ref()) => {
+                code_depth = code_depth.saturating_sub(1);
+            }
+            Ok(quick_xml::events::Event::Text(text)) if code_depth == 0 => {
+                let text = String::from_utf8_lossy(&text).trim().to_string();
+                if !text.is_empty() {
+                    nodes.push(text);
+                }
+            }
+            Ok(quick_xml::events::Event::Eof) | Err(_) => break,
+            Ok(_) => {}
+        }
+    }
+    nodes
+}
+
+/// Replace the first text node whose trimmed markup is `original` with
+/// `replacement`, keeping the node's surrounding whitespace; `None` if no
+/// text node matches
+pub(crate) fn replace_text_node(html: &str, original: &str, replacement: &str) -> Option<String> {
+    let mut reader = quick_xml::Reader::from_str(html);
+    let config = reader.config_mut();
+    config.check_end_names = false;
+    config.allow_unmatched_ends = true;
+
+    let mut start = 0;
+    loop {
+        match reader.read_event() {
+            Ok(quick_xml::events::Event::Text(_)) => {
+                let end = reader.buffer_position() as usize;
+                let node = &html[start..end];
+                if node.trim() == original {
+                    let leading = node.len() - node.trim_start().len();
+                    let trailing = node.trim_end().len();
+                    return Some(format!(
+                        "{}{}{}{}{}",
+                        &html[..start],
+                        &node[..leading],
+                        replacement,
+                        &node[trailing..],
+                        &html[end..]
+                    ));
+                }
+                start = end;
+            }
+            Ok(quick_xml::events::Event::Eof) | Err(_) => return None,
+            Ok(_) => start = reader.buffer_position() as usize,
+        }
+    }
+}
+
+/// Human-readable leak report: snippets grouped by book and chapter
+fn leak_summary(leaks: &[LeakInfo]) -> String {
+    let mut summary = format!("Untranslated snippets: {}\n", leaks.len());
+    let mut current: Option<(&str, &str)> = None;
+    for leak in leaks {
+        if current.map(|(file, _)| file) != Some(leak.file_path.as_str()) {
+            summary.push_str(&format!("\n{} ({})\n", leak.book_name, leak.file_path));
+        }
+        if current != Some((leak.file_path.as_str(), leak.chapter.as_str())) {
+            summary.push_str(&format!("  [{}]\n", leak.chapter));
+            current = Some((leak.file_path.as_str(), leak.chapter.as_str()));
+        }
+        let snippet: String = leak.original.chars().take(120).collect();
+        summary.push_str(&format!("    - {}\n", snippet));
+    }
+    summary
+}
+
+/// Resolve where the translated ePub is written.
+///
+/// If `output` is an existing directory, or ends with a path separator, the
+/// file is placed inside it as `<input stem>_<target_lang>.epub`; otherwise
+/// `output` is used as the file path.
+pub fn resolve_output_path(input: &Path, output: &Path, target_lang: &str) -> Result<PathBuf> {
+    let wants_dir = output
+        .as_os_str()
+        .to_string_lossy()
+        .ends_with(std::path::is_separator);
+    if !output.is_dir() && !wants_dir {
+        return Ok(output.to_path_buf());
+    }
+
+    std::fs::create_dir_all(output).map_err(|e| TranslationError::FileError {
+        path: output.display().to_string(),
+        message: e.to_string(),
+    })?;
+    let stem = input
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "book".to_string());
+    Ok(output.join(format!("{}_{}.epub", stem, target_lang)))
 }
 
 #[cfg(test)]
@@ -434,15 +1211,15 @@ mod tests {
 
         // 测试英文内容
         let english_content = "This is a test paragraph. It contains only English text.";
-        assert!(processor.has_untranslated_content(english_content));
+        assert!(processor.has_untranslated_content(english_content, "zh"));
 
         // 测试中文内容
         let chinese_content = "这是一个测试段落。它只包含中文文本。";
-        assert!(!processor.has_untranslated_content(chinese_content));
+        assert!(!processor.has_untranslated_content(chinese_content, "zh"));
 
         // 测试混合内容
         let mixed_content = "This is a test 测试段落. It contains both English and 中文.";
-        assert!(processor.has_untranslated_content(mixed_content));
+        assert!(processor.has_untranslated_content(mixed_content, "zh"));
     }
 
     #[tokio::test]
@@ -456,7 +1233,757 @@ mod tests {
         let processor = EpubProcessor::new(translator);
 
         // 测试空目录
-        let leaks = processor.check_untranslated(temp_path).await.unwrap();
+        let leaks = processor.check_untranslated(temp_path, "zh").await.unwrap();
         assert!(leaks.is_empty());
     }
+
+    #[tokio::test]
+    async fn test_leak_report_lists_untranslated_chapter() {
+        use crate::testing::{test_translator, write_epub, TestChapter};
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        write_epub(
+            &temp_dir.path().join("book_zh.epub"),
+            "Book",
+            "zh",
+            &[
+                TestChapter::new("c1", "<p>这是已经翻译好的章节内容。</p>"),
+                TestChapter::new("c2", "<p>The quick brown fox jumps over the lazy dog today</p>"),
+            ],
+        );
+
+        let processor = EpubProcessor::new(test_translator("http://127.0.0.1:9"));
+        let count = processor
+            .generate_leak_report(temp_dir.path(), "zh")
+            .await
+            .unwrap();
+
+        // The untranslated <title> and body of c2
+        assert_eq!(count, 2);
+        let job = FixupJob::load(&temp_dir.path().join(LEAK_REPORT_JSON)).await.unwrap();
+        assert_eq!(job, processor.fixups().job());
+        assert!(job
+            .entries
+            .iter()
+            .all(|entry| entry.reason == "untranslated in Book (c2)"
+                && entry.part.as_deref() == Some("OEBPS/c2.xhtml")));
+        assert_eq!(job.entries[1].original, "The quick brown fox jumps over the lazy dog today");
+
+        let summary = std::fs::read_to_string(temp_dir.path().join(LEAK_REPORT_SUMMARY)).unwrap();
+        assert!(summary.starts_with("Untranslated snippets: 2\n"));
+        assert!(summary.contains("  [c2]\n    - Chapter\n    - The quick brown fox"));
+        assert!(!summary.contains("[c1]"));
+    }
+
+    #[tokio::test]
+    async fn test_code_heavy_chapter_is_not_a_leak() {
+        use crate::testing::{test_translator, write_epub, TestChapter};
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        write_epub(
+            &temp_dir.path().join("book_zh.epub"),
+            "图书",
+            "zh",
+            &[
+                TestChapter::new(
+                    "c1",
+                    "<p>使用 <code>cargo build --release</code> 构建项目，\
+                     然后在 Kubernetes 上部署，详见 https://example.com/docs/guide 和 main.rs。</p>\
+                     <pre>fn main() { let value = compute(); println!(\"{}\", value); }\
+                     for item in items.iter() { process(item).await; }</pre>",
+                ),
+                TestChapter::new(
+                    "c2",
+                    "<p>The build finished without errors, so we can \
+                     deploy the service and watch the logs for a while.</p>",
+                ),
+            ],
+        );
+
+        let processor = EpubProcessor::new(test_translator("http://127.0.0.1:9"));
+        let leaks = processor.check_untranslated(temp_dir.path(), "zh").await.unwrap();
+        // The untranslated <title> and body of c2
+        assert_eq!(leaks.len(), 2);
+        assert!(leaks.iter().all(|leak| leak.chapter == "c2"));
+        assert!(leaks[1].original.starts_with("The build finished"));
+
+        // No share of English words exceeds a threshold of 1
+        let lax = processor.clone().with_untranslated_threshold(1.0);
+        assert!(lax.check_untranslated(temp_dir.path(), "zh").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_apply_fixes_removes_leak() {
+        use crate::processors::fixup::FixSummary;
+        use crate::testing::{read_zip_entry, test_translator, write_epub, TestChapter};
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let book = temp_dir.path().join("book_zh.epub");
+        write_epub(
+            &book,
+            "Book",
+            "zh",
+            &[
+                TestChapter::new("c1", "<p>这是已经翻译好的章节内容。</p>"),
+                TestChapter::new(
+                    "c2",
+                    "<p>\n  Cats &amp; dogs run over the lazy hills today\n</p>",
+                ),
+            ],
+        );
+
+        let processor = EpubProcessor::new(test_translator("http://127.0.0.1:9"));
+        let leaks = processor.check_untranslated(temp_dir.path(), "zh").await.unwrap();
+        assert_eq!(leaks.len(), 2);
+        let json = temp_dir.path().join("fixes.json");
+        processor.save_leak_report(&leaks, &json).await.unwrap();
+        // Leave the <title> untranslated, fix the paragraph
+        let mut job = FixupJob::load(&json).await.unwrap();
+        job.entries[1].translation = Some("猫 & 狗".to_string());
+
+        let summary = job.apply().await.unwrap();
+        assert_eq!(summary, FixSummary { applied: 1, skipped: 1 });
+
+        let chapter = read_zip_entry(&book, "OEBPS/c2.xhtml");
+        assert!(chapter.contains("<p>\n  猫 &amp; 狗\n</p>"));
+        assert!(processor.check_untranslated(temp_dir.path(), "zh").await.unwrap().is_empty());
+        // Still a valid book
+        assert_eq!(read_zip_entry(&book, "mimetype"), "application/epub+zip");
+    }
+
+    #[tokio::test]
+    async fn test_skip_non_linear_chapters() {
+        use crate::testing::{read_zip_entry, test_translator, write_epub, MockServer, TestChapter};
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("book.epub");
+        let output = temp_dir.path().join("book_zh.epub");
+        write_epub(
+            &input,
+            "Book",
+            "en",
+            &[
+                TestChapter::new("chapter", "<p>Reading content</p>"),
+                TestChapter {
+                    id: "notes",
+                    body: "<p>Pop-up note</p>",
+                    linear: false,
+                },
+            ],
+        );
+
+        let server = MockServer::echo().await;
+        let processor =
+            EpubProcessor::new(test_translator(&server.url)).with_skip_non_linear(true);
+        processor
+            .translate_epub(&input, &output, "zh", None, true)
+            .await
+            .unwrap();
+
+        let sent = server.sent_texts();
+        assert!(sent.contains(&"Reading content".to_string()));
+        assert!(!sent.contains(&"Pop-up note".to_string()));
+
+        assert!(read_zip_entry(&output, "OEBPS/chapter.xhtml").contains("zh:Reading content"));
+        assert_eq!(
+            read_zip_entry(&output, "OEBPS/notes.xhtml"),
+            read_zip_entry(&input, "OEBPS/notes.xhtml")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_overlaps_reading_and_translating() {
+        use crate::testing::{
+            echo_translation, read_zip_entry, test_translator, write_epub, MockReply, MockServer,
+            TestChapter,
+        };
+        use std::time::{Duration, Instant};
+
+        let step = Duration::from_millis(100);
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("book.epub");
+        let ids = ["c1", "c2", "c3", "c4"];
+        let bodies: Vec<String> = ids.iter().map(|id| format!("<p>Text of {}</p>", id)).collect();
+        let chapters: Vec<TestChapter> = ids
+            .iter()
+            .zip(&bodies)
+            .map(|(id, body)| TestChapter::new(id, body))
+            .collect();
+        write_epub(&input, "Book", "en", &chapters);
+
+        let server = MockServer::start(move |req| {
+            let target = req.target_lang();
+            let texts: Vec<String> = req
+                .input_texts()
+                .iter()
+                .map(|t| echo_translation(&target, t))
+                .collect();
+            MockReply::translations(&texts).with_delay(step)
+        })
+        .await;
+
+        let mut elapsed = Vec::new();
+        for depth in [0, 2] {
+            let mut processor = EpubProcessor::new(test_translator(&server.url))
+                .with_pipeline_depth(depth)
+                .with_chapter_concurrency(1);
+            processor.read_delay = step;
+            let output = temp_dir.path().join(format!("depth{}.epub", depth));
+            let started = Instant::now();
+            processor
+                .translate_epub(&input, &output, "zh", None, true)
+                .await
+                .unwrap();
+            elapsed.push(started.elapsed());
+        }
+
+        let serial = temp_dir.path().join("depth0.epub");
+        let pipelined = temp_dir.path().join("depth2.epub");
+        for id in ids {
+            let entry = format!("OEBPS/{}.xhtml", id);
+            let chapter = read_zip_entry(&pipelined, &entry);
+            assert!(chapter.contains(&format!("zh:Text of {}", id)));
+            assert_eq!(chapter, read_zip_entry(&serial, &entry));
+        }
+        // Three of the four reads overlap with translating the previous chapter
+        assert!(
+            elapsed[1] + step * 2 <= elapsed[0],
+            "pipelined {:?} vs serial {:?}",
+            elapsed[1],
+            elapsed[0]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_chapters_keep_spine_order() {
+        use crate::testing::{
+            echo_translation, read_zip_entry, test_translator, write_epub, MockReply, MockServer,
+            TestChapter,
+        };
+        use std::time::{Duration, Instant};
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("book.epub");
+        let output = temp_dir.path().join("book_zh.epub");
+        let ids = ["c1", "c2", "c3", "c4", "c5", "c6"];
+        let bodies: Vec<String> = ids.iter().map(|id| format!("<p>Text of {}</p>", id)).collect();
+        let chapters: Vec<TestChapter> = ids
+            .iter()
+            .zip(&bodies)
+            .map(|(id, body)| TestChapter::new(id, body))
+            .collect();
+        write_epub(&input, "Book", "en", &chapters);
+
+        // Earlier chapters answer slower, so they finish last
+        let server = MockServer::start(|req| {
+            let target = req.target_lang();
+            let texts = req.input_texts();
+            let delay = match texts[0].strip_prefix("Text of c") {
+                Some(n) => 350 - 50 * n.parse::<u64>().unwrap(),
+                None => 10,
+            };
+            let texts: Vec<String> = texts.iter().map(|t| echo_translation(&target, t)).collect();
+            MockReply::translations(&texts).with_delay(Duration::from_millis(delay))
+        })
+        .await;
+        let processor =
+            EpubProcessor::new(test_translator(&server.url)).with_chapter_concurrency(6);
+
+        let started = Instant::now();
+        processor
+            .translate_epub(&input, &output, "zh", None, true)
+            .await
+            .unwrap();
+        // One at a time the chapter texts alone would take 1.05s
+        assert!(started.elapsed() < Duration::from_millis(900), "{:?}", started.elapsed());
+
+        for id in ids {
+            let chapter = read_zip_entry(&output, &format!("OEBPS/{}.xhtml", id));
+            assert!(chapter.contains(&format!("<p>zh:Text of {}</p>", id)));
+        }
+        let entry_names = |path: &Path| {
+            let mut zip = zip::ZipArchive::new(std::fs::File::open(path).unwrap()).unwrap();
+            (0..zip.len())
+                .map(|i| zip.by_index(i).unwrap().name().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(entry_names(&output), entry_names(&input));
+    }
+
+    #[tokio::test]
+    async fn test_plan_matches_texts_sent() {
+        use crate::testing::{test_translator, write_epub, MockServer, TestChapter};
+
+        let body = "<p>One &amp; two</p><p> </p><p>— 42 —</p><script>skip()</script><p>Three</p>";
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("book.epub");
+        write_epub(&input, "Book", "en", &[TestChapter::new("chapter", body)]);
+
+        let server = MockServer::echo().await;
+        let processor = EpubProcessor::new(test_translator(&server.url));
+        let plan = processor.plan(&input).await.unwrap();
+        assert_eq!(server.request_count(), 0);
+
+        let output = temp_dir.path().join("book_zh.epub");
+        processor.translate_epub(&input, &output, "zh", None, true).await.unwrap();
+        assert_eq!(plan.segments, server.sent_texts().len());
+        assert_eq!(plan.segments, 4);
+    }
+
+    #[tokio::test]
+    async fn test_progress_reports_each_chapter() {
+        use crate::testing::{test_translator, write_epub, MockServer, TestChapter};
+
+        let chapters = [
+            TestChapter::new("ch1", "<p>Chapter one</p>"),
+            TestChapter::new("ch2", "<p>Chapter two</p>"),
+            TestChapter::new("ch3", "<p>Chapter three</p>"),
+        ];
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("book.epub");
+        let output = temp_dir.path().join("book_zh.epub");
+        write_epub(&input, "Book", "en", &chapters);
+
+        let server = MockServer::echo().await;
+        let processor = EpubProcessor::new(test_translator(&server.url));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        processor
+            .translate_epub_with_progress(&input, &output, "zh", None, true, move |p| {
+                sink.lock().unwrap().push(p)
+            })
+            .await
+            .unwrap();
+
+        let events = events.lock().unwrap();
+        let chapters: Vec<(usize, usize)> = events
+            .iter()
+            .filter_map(|p| match *p {
+                Progress::Chapter { current, total } => Some((current, total)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(chapters, [(1, 3), (2, 3), (3, 3)]);
+        let bytes = std::fs::metadata(&output).unwrap().len() as usize;
+        assert_eq!(
+            events.last(),
+            Some(&Progress::File { output: output.clone(), bytes })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wordless_text_nodes_are_not_sent() {
+        use crate::testing::{read_zip_entry, test_translator, write_epub, MockServer, TestChapter};
+
+        let body = "<div>\n  <p> </p>\n\t<p>\u{a0}&#160;</p>\n  <p>…</p> <p>— 42 —</p>\n  \
+                    <p>1.2.3</p><span>&amp;</span>\n  <p>Real text</p>\n</div>";
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("book.epub");
+        let output = temp_dir.path().join("book_zh.epub");
+        write_epub(&input, "Book", "en", &[TestChapter::new("chapter", body)]);
+
+        let server = MockServer::echo().await;
+        let processor = EpubProcessor::new(test_translator(&server.url));
+        processor
+            .translate_epub(&input, &output, "zh", None, true)
+            .await
+            .unwrap();
+
+        assert_eq!(server.sent_texts(), vec!["Book", "Chapter", "Real text"]);
+        let expected = body.replace("Real text", "zh:Real text");
+        assert!(read_zip_entry(&output, "OEBPS/chapter.xhtml").contains(&expected));
+    }
+
+    #[tokio::test]
+    async fn test_repeated_words_replace_only_the_text_span() {
+        use crate::testing::{read_zip_entry, test_translator, write_epub, MockServer, TestChapter};
+
+        let body = "<p>\n    Echo echo Echo\n  </p><p>Echo</p><p> echo  </p>";
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("book.epub");
+        let output = temp_dir.path().join("book_zh.epub");
+        write_epub(&input, "Book", "en", &[TestChapter::new("chapter", body)]);
+
+        let server = MockServer::echo().await;
+        let processor = EpubProcessor::new(test_translator(&server.url));
+        processor
+            .translate_epub(&input, &output, "zh", None, true)
+            .await
+            .unwrap();
+
+        // Each node is translated once, and its own whitespace is put back around it
+        let expected = "<p>\n    zh:Echo echo Echo\n  </p><p>zh:Echo</p><p> zh:echo  </p>";
+        assert!(read_zip_entry(&output, "OEBPS/chapter.xhtml").contains(expected));
+        let sent = server.sent_texts();
+        assert_eq!(sent[sent.len() - 3..], ["Echo echo Echo", "Echo", "echo"]);
+    }
+
+    #[tokio::test]
+    async fn test_malformed_chapter_falls_back_to_scanner() {
+        use crate::testing::{read_zip_entry, test_translator, write_epub, MockServer, TestChapter};
+
+        // The unbalanced quote makes the tokenizer give up on the rest of the chapter
+        let body = "<p class=\"intro>Broken start</p>\n<p>After the break</p>\n\
+                    <script>if (a < b) { go(1); }</script>\n<p>Last words</p>";
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("book.epub");
+        let output = temp_dir.path().join("book_zh.epub");
+        write_epub(&input, "Book", "en", &[TestChapter::new("chapter", body)]);
+
+        let server = MockServer::echo().await;
+        let processor = EpubProcessor::new(test_translator(&server.url));
+        processor
+            .translate_epub(&input, &output, "zh", None, true)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            server.sent_texts(),
+            vec!["Book", "Chapter", "Broken start", "After the break", "Last words"]
+        );
+        let chapter = read_zip_entry(&output, "OEBPS/chapter.xhtml");
+        assert!(chapter.contains(
+            "<p class=\"intro>zh:Broken start</p>\n<p>zh:After the break</p>\n\
+             <script>if (a < b) { go(1); }</script>\n<p>zh:Last words</p>"
+        ));
+        let diagnostics = processor.diagnostics().all();
+        assert!(diagnostics
+            .iter()
+            .any(|d| d.message.contains("fallback scanner")
+                && d.location.as_deref() == Some("chapter")));
+    }
+
+    #[tokio::test]
+    async fn test_sample_leaves_rest_of_book_unchanged() {
+        use crate::testing::{read_zip_entry, test_translator, write_epub, MockServer, TestChapter};
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("book.epub");
+        let output = temp_dir.path().join("book_zh.epub");
+        write_epub(
+            &input,
+            "Book",
+            "en",
+            &[
+                TestChapter::new("c1", "<p>First text</p><p>Second text</p>"),
+                TestChapter::new("c2", "<p>Later chapter</p>"),
+            ],
+        );
+
+        let server = MockServer::echo().await;
+        let processor = EpubProcessor::new(test_translator(&server.url)).with_sample(Some(2));
+        processor
+            .translate_epub(&input, &output, "zh", None, true)
+            .await
+            .unwrap();
+
+        // Metadata is outside the sample; the <title> in the head is the
+        // first text of each chapter
+        assert_eq!(server.sent_texts(), vec!["Book", "Chapter", "First text"]);
+        let c1 = read_zip_entry(&output, "OEBPS/c1.xhtml");
+        assert!(c1.contains("<p>zh:First text</p><p>Second text</p>"));
+        assert_eq!(
+            read_zip_entry(&output, "OEBPS/c2.xhtml"),
+            read_zip_entry(&input, "OEBPS/c2.xhtml")
+        );
+        assert!(processor.diagnostics().all()[0].message.starts_with("partial sample"));
+    }
+
+    #[tokio::test]
+    async fn test_repack_writes_mimetype_first_and_stored() {
+        use crate::testing::{test_translator, write_epub, MockServer, TestChapter};
+        use std::io::Read;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let fixture = temp_dir.path().join("fixture.epub");
+        write_epub(&fixture, "Book", "en", &[TestChapter::new("c1", "<p>Text</p>")]);
+
+        // Same book with mimetype moved last and deflated, as some tools write it
+        let input = temp_dir.path().join("book.epub");
+        {
+            let mut source = zip::ZipArchive::new(std::fs::File::open(&fixture).unwrap()).unwrap();
+            let mut writer = zip::ZipWriter::new(std::fs::File::create(&input).unwrap());
+            let deflated = zip::write::FileOptions::default()
+                .compression_method(zip::CompressionMethod::Deflated);
+            let mut names: Vec<String> = source.file_names().map(String::from).collect();
+            names.sort_by_key(|name| name == "mimetype");
+            for name in names {
+                let mut content = Vec::new();
+                source.by_name(&name).unwrap().read_to_end(&mut content).unwrap();
+                writer.start_file(name, deflated).unwrap();
+                writer.write_all(&content).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+
+        let server = MockServer::echo().await;
+        let output = temp_dir.path().join("book_zh.epub");
+        EpubProcessor::new(test_translator(&server.url))
+            .translate_epub(&input, &output, "zh", None, true)
+            .await
+            .unwrap();
+
+        let mut zip = zip::ZipArchive::new(std::fs::File::open(&output).unwrap()).unwrap();
+        let mimetype_count = zip.file_names().filter(|name| *name == "mimetype").count();
+        assert_eq!(mimetype_count, 1);
+        let mut first = zip.by_index(0).unwrap();
+        assert_eq!(first.name(), "mimetype");
+        assert_eq!(first.compression(), zip::CompressionMethod::Stored);
+        assert!(first.extra_data().is_empty());
+        let mut content = Vec::new();
+        first.read_to_end(&mut content).unwrap();
+        assert_eq!(content, b"application/epub+zip");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_repack_streams_large_entries() {
+        use crate::testing::{peak_resident_growth, test_translator, write_epub, TestChapter};
+        use std::io::Read;
+
+        const IMAGES: usize = 16;
+        const IMAGE_BYTES: usize = 4 << 20;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("book.epub");
+        let output = temp_dir.path().join("book_zh.epub");
+        write_epub(&input, "Book", "en", &[TestChapter::new("chapter", "<p>Hello</p>")]);
+        {
+            let file = std::fs::OpenOptions::new().read(true).write(true).open(&input).unwrap();
+            let mut writer = zip::ZipWriter::new_append(file).unwrap();
+            let stored = zip::write::FileOptions::default()
+                .compression_method(zip::CompressionMethod::Stored);
+            let image = vec![0xA5u8; IMAGE_BYTES];
+            for i in 0..IMAGES {
+                writer.start_file(format!("OEBPS/images/{}.jpg", i), stored).unwrap();
+                writer.write_all(&image).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+
+        let processor = EpubProcessor::new(test_translator("http://127.0.0.1:9"));
+        let replacements =
+            HashMap::from([("OEBPS/chapter.xhtml".to_string(), "<p>你好</p>".to_string())]);
+        let (result, growth) =
+            peak_resident_growth(processor.repack_epub(&input, &output, &replacements)).await;
+        result.unwrap();
+
+        // The 64 MiB book is never held in memory, nor even a few of its images
+        assert!(growth < 3 * IMAGE_BYTES, "resident memory grew by {} bytes", growth);
+        let mut zip = zip::ZipArchive::new(std::fs::File::open(&output).unwrap()).unwrap();
+        assert_eq!(zip.len(), 4 + IMAGES);
+        let mut chapter = String::new();
+        zip.by_name("OEBPS/chapter.xhtml").unwrap().read_to_string(&mut chapter).unwrap();
+        assert_eq!(chapter, "<p>你好</p>");
+        let last = format!("OEBPS/images/{}.jpg", IMAGES - 1);
+        assert_eq!(zip.by_name(&last).unwrap().size(), IMAGE_BYTES as u64);
+    }
+
+    #[tokio::test]
+    async fn test_repack_updates_language() {
+        use crate::testing::{read_zip_entry, test_translator, write_epub, MockServer, TestChapter};
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("book.epub");
+        write_epub(&input, "Book", "en", &[TestChapter::new("chapter", "<p>Hello</p>")]);
+
+        let server = MockServer::echo().await;
+        let processor = EpubProcessor::new(test_translator(&server.url));
+
+        let output = temp_dir.path().join("updated.epub");
+        processor
+            .with_update_language(true)
+            .translate_epub(&input, &output, "zh", None, true)
+            .await
+            .unwrap();
+        let opf = read_zip_entry(&output, "OEBPS/content.opf");
+        assert!(opf.contains("<dc:language>zh</dc:language>\n    <dc:language>en</dc:language>"));
+
+        let book = epub::doc::EpubDoc::new(&output).unwrap();
+        assert_eq!(book.mdata("language").unwrap().value, "zh");
+    }
+
+    #[tokio::test]
+    async fn test_repack_translates_metadata() {
+        use crate::testing::{
+            read_zip_entry, test_translator, write_epub_with_metadata, MockServer, TestChapter,
+        };
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("book.epub");
+        let output = temp_dir.path().join("book_zh.epub");
+        write_epub_with_metadata(
+            &input,
+            "<dc:title>Tom &amp; Jerry</dc:title>\n    \
+             <dc:creator opf:file-as=\"Doe, Jane\">Jane Doe</dc:creator>\n    \
+             <dc:description>A short story.</dc:description>",
+            "en",
+            &[TestChapter::new("chapter", "<p>Hello</p>")],
+        );
+
+        let server = MockServer::echo().await;
+        let processor = EpubProcessor::new(test_translator(&server.url));
+        processor
+            .translate_epub(&input, &output, "zh", None, true)
+            .await
+            .unwrap();
+
+        let opf = read_zip_entry(&output, "OEBPS/content.opf");
+        assert!(opf.contains("<dc:title>zh:Tom &amp; Jerry</dc:title>"));
+        assert!(opf.contains("<dc:creator opf:file-as=\"Doe, Jane\">zh:Jane Doe</dc:creator>"));
+        assert!(opf.contains("<dc:description>zh:A short story.</dc:description>"));
+        assert!(opf.contains("<dc:language>zh</dc:language>"));
+
+        let book = epub::doc::EpubDoc::new(&output).unwrap();
+        assert_eq!(book.get_title().unwrap(), "zh:Tom & Jerry");
+        assert_eq!(book.mdata("language").unwrap().value, "zh");
+    }
+
+    #[tokio::test]
+    async fn test_output_directory_derives_file_name() {
+        use crate::testing::{read_zip_entry, test_translator, write_epub, MockServer, TestChapter};
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("book.epub");
+        write_epub(&input, "Book", "en", &[TestChapter::new("chapter", "<p>Hello</p>")]);
+        let out_dir = temp_dir.path().join("out");
+        std::fs::create_dir(&out_dir).unwrap();
+
+        let server = MockServer::echo().await;
+        let processor = EpubProcessor::new(test_translator(&server.url));
+        processor
+            .translate_epub(&input, &out_dir, "zh", None, true)
+            .await
+            .unwrap();
+
+        let output = out_dir.join("book_zh.epub");
+        assert!(output.is_file());
+        assert!(read_zip_entry(&output, "OEBPS/chapter.xhtml").contains("zh:Hello"));
+
+        let file_output = temp_dir.path().join("named.epub");
+        assert_eq!(resolve_output_path(&input, &file_output, "zh").unwrap(), file_output);
+    }
+
+    #[tokio::test]
+    async fn test_footnote_translated_once_by_id() {
+        use crate::testing::{
+            read_zip_entry, test_translator, write_epub, MockReply, MockServer, TestChapter,
+        };
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        const NOTE: &str = r#"<aside epub:type="footnote" id="n1"><p>The note</p></aside>"#;
+        let chapter = format!(r##"<p>See<a epub:type="noteref" href="#n1">1</a></p>{}"##, NOTE);
+        let endnotes = format!(r#"<section epub:type="endnotes">{}</section>"#, NOTE);
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("book.epub");
+        let output = temp_dir.path().join("book_zh.epub");
+        write_epub(
+            &input,
+            "Book",
+            "en",
+            &[TestChapter::new("chapter", &chapter), TestChapter::new("notes", &endnotes)],
+        );
+
+        // Number every call so a second translation of the note would differ
+        let calls = AtomicUsize::new(0);
+        let server = MockServer::start(move |req| {
+            let texts: Vec<String> = req
+                .input_texts()
+                .iter()
+                .map(|t| format!("zh{}:{}", calls.fetch_add(1, Ordering::SeqCst), t))
+                .collect();
+            MockReply::translations(&texts)
+        })
+        .await;
+        let processor = EpubProcessor::new(test_translator(&server.url));
+        processor
+            .translate_epub(&input, &output, "zh", None, true)
+            .await
+            .unwrap();
+
+        let sent = server.sent_texts();
+        assert_eq!(sent.iter().filter(|t| *t == "The note").count(), 1);
+
+        let note_translation = regex::Regex::new(r"zh\d+:The note").unwrap();
+        let in_chapter = read_zip_entry(&output, "OEBPS/chapter.xhtml");
+        let in_notes = read_zip_entry(&output, "OEBPS/notes.xhtml");
+        let first = note_translation.find(&in_chapter).unwrap().as_str();
+        assert_eq!(note_translation.find(&in_notes).unwrap().as_str(), first);
+    }
+
+    #[tokio::test]
+    async fn test_same_note_id_in_two_chapters_keeps_each_body() {
+        use crate::testing::{
+            echo_translation, read_zip_entry, test_translator, write_epub, MockReply, MockServer,
+            TestChapter,
+        };
+
+        let note = |body: &str| {
+            format!(
+                r##"<p>See<a epub:type="noteref" href="#fn1">1</a></p><aside epub:type="footnote" id="fn1"><p>{}</p></aside>"##,
+                body
+            )
+        };
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("book.epub");
+        let output = temp_dir.path().join("book_zh.epub");
+        write_epub(
+            &input,
+            "Book",
+            "en",
+            &[
+                TestChapter::new("one", &note("Apples are red")),
+                TestChapter::new("two", &note("Bananas are yellow")),
+            ],
+        );
+
+        let server = MockServer::start(|req| {
+            let texts: Vec<String> =
+                req.input_texts().iter().map(|t| echo_translation("zh", t)).collect();
+            MockReply::translations(&texts)
+        })
+        .await;
+        let processor = EpubProcessor::new(test_translator(&server.url));
+        processor
+            .translate_epub(&input, &output, "zh", None, true)
+            .await
+            .unwrap();
+
+        let one = read_zip_entry(&output, "OEBPS/one.xhtml");
+        let two = read_zip_entry(&output, "OEBPS/two.xhtml");
+        assert!(one.contains("zh:Apples are red") && !one.contains("Bananas"));
+        assert!(two.contains("zh:Bananas are yellow") && !two.contains("Apples"));
+    }
+
+    #[test]
+    fn test_update_opf_language() {
+        let opf = "<metadata><dc:language>en</dc:language></metadata>";
+        assert_eq!(
+            update_opf_language(opf, "zh"),
+            "<metadata><dc:language>zh</dc:language>\n    <dc:language>en</dc:language></metadata>"
+        );
+
+        let no_language = "<metadata>\n  </metadata>";
+        assert!(update_opf_language(no_language, "zh").contains("<dc:language>zh</dc:language>"));
+
+        // Both languages listed already: swapped, neither lost nor repeated
+        let both =
+            "<metadata><dc:language>en</dc:language><dc:language>zh</dc:language></metadata>";
+        assert_eq!(
+            update_opf_language(both, "zh"),
+            "<metadata><dc:language>zh</dc:language><dc:language>en</dc:language></metadata>"
+        );
+        let done =
+            "<metadata><dc:language>zh</dc:language><dc:language>en</dc:language></metadata>";
+        assert_eq!(update_opf_language(done, "zh"), done);
+
+        // A prefixed metadata element still gets a language
+        let prefixed = "<opf:metadata>\n  </opf:metadata>";
+        assert_eq!(
+            update_opf_language(prefixed, "zh"),
+            "<opf:metadata>\n    <dc:language>zh</dc:language>\n  </opf:metadata>"
+        );
+    }
 }
\ No newline at end of file
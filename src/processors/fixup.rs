@@ -0,0 +1,341 @@
+//! Fix-up jobs: segments left untranslated, exported for hand editing and
+//! put back into the translated files
+//!
+//! A processor reports each failed segment to a shared [`Fixups`]
+//! collector; once the translated file is written, the segment is located in
+//! it. The ePub leak check adds the snippets it flags, already located in
+//! their chapter. The resulting [`FixupJob`] is saved as JSON, edited
+//! (filling in each `translation`) and applied with [`FixupJob::apply`].
+
+use quick_xml::escape::partial_escape;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::{debug, warn};
+
+use crate::core::errors::{Result, TranslationError};
+use crate::processors::epub::{replace_text_node, repack};
+
+/// A segment of a translated file that needs a hand-made translation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FixupEntry {
+    /// File the segment was translated from
+    pub source: PathBuf,
+    /// Translated file to patch
+    pub file: PathBuf,
+    /// Zip entry holding the segment, for container formats (ePub chapters);
+    /// its text is XHTML, so translations are escaped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub part: Option<String>,
+    /// Index of the segment (paragraph, ...) in the source
+    pub index: usize,
+    /// Byte range of `original` in `file` (or `part`) when exported
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub span: Option<(usize, usize)>,
+    /// Text as it stands in the translated file
+    pub original: String,
+    /// Why the segment needs fixing
+    pub reason: String,
+    /// Translation to put in place of `original`; entries without one are skipped
+    #[serde(default)]
+    pub translation: Option<String>,
+}
+
+/// Editable list of fix-ups, saved as JSON
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FixupJob {
+    /// Segments in the order they were found
+    pub entries: Vec<FixupEntry>,
+}
+
+/// Outcome of applying fixes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FixSummary {
+    /// Translations written into their file
+    pub applied: usize,
+    /// Entries without a translation, or whose text could not be found
+    pub skipped: usize,
+}
+
+impl FixupJob {
+    /// Whether there is nothing to fix
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Read a job saved by [`FixupJob::save`]
+    pub async fn load(path: &Path) -> Result<Self> {
+        let content = tokio::fs::read_to_string(path).await.map_err(|e| file_error(path, e))?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Write the job as pretty-printed JSON
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        tokio::fs::write(path, json).await.map_err(|e| file_error(path, e))
+    }
+
+    /// Put every entry's translation in place of its original text.
+    ///
+    /// An entry is found at its recorded span if the original is still there,
+    /// otherwise where the original occurs once in the file (in an HTML file
+    /// or ePub chapter: the text node it makes up). Entries that cannot be
+    /// found, or have no translation, are skipped.
+    pub async fn apply(&self) -> Result<FixSummary> {
+        let mut summary = FixSummary::default();
+        // Group entries by file, keeping their order
+        let mut by_file: Vec<(&Path, Vec<&FixupEntry>)> = Vec::new();
+        for entry in &self.entries {
+            if entry.translation.is_none() {
+                summary.skipped += 1;
+                continue;
+            }
+            match by_file.iter_mut().find(|(file, _)| *file == entry.file) {
+                Some((_, entries)) => entries.push(entry),
+                None => by_file.push((&entry.file, vec![entry])),
+            }
+        }
+
+        for (file, entries) in by_file {
+            let (parts, plain): (Vec<&FixupEntry>, Vec<&FixupEntry>) =
+                entries.into_iter().partition(|entry| entry.part.is_some());
+            if !plain.is_empty() {
+                let content =
+                    tokio::fs::read_to_string(file).await.map_err(|e| file_error(file, e))?;
+                let (fixed, applied) = patch(&content, &plain, is_markup(file));
+                summary.applied += applied;
+                summary.skipped += plain.len() - applied;
+                if applied > 0 {
+                    tokio::fs::write(file, fixed).await.map_err(|e| file_error(file, e))?;
+                }
+            }
+            if !parts.is_empty() {
+                let applied = patch_container(file, &parts)?;
+                summary.applied += applied;
+                summary.skipped += parts.len() - applied;
+            }
+        }
+        Ok(summary)
+    }
+}
+
+/// Whether `file` is HTML, whose text nodes are escaped markup
+fn is_markup(file: &Path) -> bool {
+    file.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| matches!(ext.to_ascii_lowercase().as_str(), "html" | "htm" | "xhtml"))
+}
+
+/// Apply `entries` (all with a `part`) to the zip container `file` in place;
+/// returns how many were applied
+fn patch_container(file: &Path, entries: &[&FixupEntry]) -> Result<usize> {
+    let archive = std::fs::File::open(file).map_err(|e| file_error(file, e))?;
+    let mut zip = zip::ZipArchive::new(std::io::BufReader::new(archive))?;
+    let mut by_part: Vec<(&str, Vec<&FixupEntry>)> = Vec::new();
+    for entry in entries {
+        let part = entry.part.as_deref().unwrap_or_default();
+        match by_part.iter_mut().find(|(p, _)| *p == part) {
+            Some((_, entries)) => entries.push(entry),
+            None => by_part.push((part, vec![entry])),
+        }
+    }
+
+    let mut applied = 0;
+    let mut replacements = HashMap::new();
+    for (part, entries) in by_part {
+        let mut content = String::new();
+        match zip.by_name(part) {
+            Ok(mut entry) => std::io::Read::read_to_string(&mut entry, &mut content)?,
+            Err(_) => {
+                warn!("{} is not in {}", part, file.display());
+                continue;
+            }
+        };
+        let (fixed, part_applied) = patch(&content, &entries, true);
+        if part_applied > 0 {
+            replacements.insert(part.to_string(), fixed);
+            applied += part_applied;
+        }
+    }
+    drop(zip);
+    if !replacements.is_empty() {
+        repack(file, file, &replacements)?;
+    }
+    Ok(applied)
+}
+
+/// `content` with the translations of `entries` in place of their
+/// originals, and how many were applied.
+///
+/// In `markup` (HTML, XHTML) the original is the trimmed text of a text node,
+/// replaced by the escaped translation. Elsewhere it is found at its span,
+/// or else where it occurs exactly once.
+fn patch(content: &str, entries: &[&FixupEntry], markup: bool) -> (String, usize) {
+    let mut content = content.to_string();
+    let mut ranges: Vec<(usize, usize, &str)> = Vec::new();
+    let mut applied = 0;
+    for entry in entries {
+        let translation = entry.translation.as_deref().unwrap_or_default();
+        if markup {
+            let escaped = partial_escape(translation);
+            match replace_text_node(&content, &entry.original, &escaped) {
+                Some(fixed) => {
+                    content = fixed;
+                    applied += 1;
+                }
+                None => warn!("Text not found in {}: {}", entry.file.display(), entry.original),
+            }
+            continue;
+        }
+
+        let original = entry.original.as_str();
+        let at_span =
+            entry.span.filter(|&(start, end)| content.get(start..end) == Some(original));
+        let range = at_span.or_else(|| {
+            let mut found = content.match_indices(original);
+            match (found.next(), found.next()) {
+                (Some((start, _)), None) => Some((start, start + original.len())),
+                _ => None,
+            }
+        });
+        let overlaps = |(start, end): (usize, usize)| {
+            ranges.iter().any(|&(s, e, _)| start < e && s < end)
+        };
+        match range.filter(|&range| !overlaps(range)) {
+            Some((start, end)) => ranges.push((start, end, translation)),
+            None => warn!("Text not found in {}: {}", entry.file.display(), original),
+        }
+    }
+
+    // Replace from the end so earlier ranges stay valid
+    ranges.sort_by_key(|&(start, _, _)| std::cmp::Reverse(start));
+    applied += ranges.len();
+    for (start, end, translation) in ranges {
+        debug!("Fixing {}..{}", start, end);
+        content.replace_range(start..end, translation);
+    }
+    (content, applied)
+}
+
+/// A failed segment whose translated file is not written yet
+#[derive(Debug, Clone)]
+struct PendingFixup {
+    /// File the segment was translated from
+    source: PathBuf,
+    /// Index of the segment in the source
+    index: usize,
+    /// Text left in the translated file
+    original: String,
+    /// Why it failed
+    reason: String,
+}
+
+/// Shared collector of the fix-ups of one job.
+///
+/// Clones share the same lists, like [`Diagnostics`](crate::core::diagnostics::Diagnostics).
+#[derive(Debug, Clone, Default)]
+pub struct Fixups {
+    /// Failed segments waiting for their file to be written
+    pending: Arc<Mutex<Vec<PendingFixup>>>,
+    /// Failed segments located in their translated file
+    entries: Arc<Mutex<Vec<FixupEntry>>>,
+}
+
+impl Fixups {
+    /// Create an empty collector
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that segment `index` of `source` was left as `original`
+    pub fn push_failed(&self, source: &Path, index: usize, original: &str, reason: &str) {
+        self.pending.lock().unwrap().push(PendingFixup {
+            source: source.to_path_buf(),
+            index,
+            original: original.to_string(),
+            reason: reason.to_string(),
+        });
+    }
+
+    /// The failed segments of `source` were written to `output` as
+    /// `written`: locate each of them, in segment order
+    pub fn resolve(&self, source: &Path, output: &Path, written: &str) {
+        let mut pending = self.pending.lock().unwrap();
+        let (mut resolved, rest): (Vec<PendingFixup>, Vec<PendingFixup>) =
+            pending.drain(..).partition(|fixup| fixup.source == source);
+        *pending = rest;
+        resolved.sort_by_key(|fixup| fixup.index);
+
+        let mut from = 0;
+        let mut entries = self.entries.lock().unwrap();
+        for fixup in resolved {
+            let span = written.get(from..).and_then(|rest| rest.find(&fixup.original)).map(|at| {
+                let start = from + at;
+                (start, start + fixup.original.len())
+            });
+            if let Some((_, end)) = span {
+                from = end;
+            }
+            entries.push(FixupEntry {
+                source: fixup.source,
+                file: output.to_path_buf(),
+                part: None,
+                index: fixup.index,
+                span,
+                original: fixup.original,
+                reason: fixup.reason,
+                translation: None,
+            });
+        }
+    }
+
+    /// Add a segment already located in its translated file
+    pub fn push(&self, entry: FixupEntry) {
+        self.entries.lock().unwrap().push(entry);
+    }
+
+    /// Failed segments located so far
+    pub fn job(&self) -> FixupJob {
+        FixupJob {
+            entries: self.entries.lock().unwrap().clone(),
+        }
+    }
+}
+
+/// `FileError` for `path`
+fn file_error(path: &Path, e: std::io::Error) -> TranslationError {
+    TranslationError::FileError {
+        path: path.display().to_string(),
+        message: e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(original: &str, span: Option<(usize, usize)>, translation: &str) -> FixupEntry {
+        FixupEntry {
+            source: PathBuf::from("doc.txt"),
+            file: PathBuf::from("doc_zh.txt"),
+            part: None,
+            index: 0,
+            span,
+            original: original.to_string(),
+            reason: "failed".to_string(),
+            translation: Some(translation.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_patch_prefers_span_then_unique_match() {
+        let content = "Hi. 你好。Hi. Bye.";
+        let at_span = entry("Hi.", Some((13, 16)), "嗨。");
+        let unique = entry("Bye.", Some((0, 4)), "再见。");
+        let ambiguous = entry("Hi.", None, "嗨。");
+        let (fixed, applied) = patch(content, &[&at_span, &unique, &ambiguous], false);
+        assert_eq!(fixed, "Hi. 你好。嗨。 再见。");
+        assert_eq!(applied, 2);
+    }
+}
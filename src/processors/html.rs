@@ -0,0 +1,960 @@
+//! HTML processor: translates text content and whitelisted attributes
+
+use quick_xml::escape::{partial_escape, unescape};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use regex::Regex;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use tracing::{debug, info, warn};
+
+use crate::core::client::AsyncTranslator;
+use crate::core::config::TranslatorConfig;
+use crate::core::diagnostics::{Diagnostic, Diagnostics, Severity};
+use crate::core::errors::{Result, TranslationError};
+use crate::core::models::TranslationRequest;
+use crate::processors::fixup::Fixups;
+
+/// Attributes translated by default
+pub const DEFAULT_ATTRIBUTES: &[&str] = &["alt", "title", "aria-label", "placeholder"];
+
+/// Text nodes of a file kept untranslated, with the error each failed with
+type FailedTexts = Arc<Mutex<Vec<(String, String)>>>;
+
+/// HTML processor that keeps markup intact and translates text
+#[derive(Debug, Clone)]
+pub struct HtmlProcessor {
+    /// Client that translates text nodes and attribute values
+    translator: AsyncTranslator,
+    /// Lower-case names of attributes whose values are translated
+    attributes: Vec<String>,
+    /// Translate only the first few texts, see [`HtmlProcessor::with_sample`]
+    sample: Option<Sample>,
+    /// Translate `<text>`/`<tspan>` labels of inline SVG
+    svg_text: bool,
+    /// Text nodes of the current file kept untranslated because their
+    /// request failed, with the error; without it a failure fails the document
+    failed: Option<FailedTexts>,
+    /// Problems found while translating files (failed text nodes, ...)
+    diagnostics: Diagnostics,
+    /// Text nodes left untranslated, for a fix-up job
+    fixups: Fixups,
+}
+
+/// Budget of texts left to translate in sample mode, shared by clones
+#[derive(Debug, Clone)]
+struct Sample {
+    /// Texts translated per document
+    limit: usize,
+    /// Texts still to translate in the current document
+    left: Arc<AtomicUsize>,
+    /// Texts copied unchanged because the budget ran out
+    skipped: Arc<AtomicUsize>,
+}
+
+impl Sample {
+    /// Fresh budget of `limit` texts
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            left: Arc::new(AtomicUsize::new(limit)),
+            skipped: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Take one text from the budget; false (and counted as skipped) once it is spent
+    fn take(&self) -> bool {
+        let taken = self
+            .left
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok();
+        if !taken {
+            self.skipped.fetch_add(1, Ordering::SeqCst);
+        }
+        taken
+    }
+}
+
+impl HtmlProcessor {
+    /// Create a new HTML processor translating [`DEFAULT_ATTRIBUTES`]
+    pub fn new(translator: AsyncTranslator) -> Self {
+        Self {
+            translator,
+            attributes: DEFAULT_ATTRIBUTES.iter().map(|a| a.to_string()).collect(),
+            sample: None,
+            svg_text: false,
+            failed: None,
+            diagnostics: Diagnostics::new(),
+            fixups: Fixups::new(),
+        }
+    }
+
+    /// Create from environment configuration
+    pub fn from_env() -> Result<Self> {
+        let translator = AsyncTranslator::from_env()?;
+        Ok(Self::new(translator))
+    }
+
+    /// Create from an already loaded configuration (e.g. from `--config`)
+    pub fn from_config(config: TranslatorConfig) -> Result<Self> {
+        let translator = AsyncTranslator::new(config)?;
+        Ok(Self::new(translator))
+    }
+
+    /// Translate the values of these attributes (case-insensitive) instead of the defaults.
+    ///
+    /// Pass an empty list to translate text content only.
+    pub fn with_attributes<I, S>(mut self, attributes: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.attributes = attributes
+            .into_iter()
+            .map(|a| a.as_ref().trim().to_ascii_lowercase())
+            .filter(|a| !a.is_empty())
+            .collect();
+        self
+    }
+
+    /// Translate only the first `limit` texts (text runs and attribute values)
+    /// of each document; the rest is copied unchanged.
+    ///
+    /// The budget is shared by clones and by successive [`translate_html`]
+    /// calls until [`restart_sample`] is called; [`translate_file`] restarts it
+    /// for every file.
+    ///
+    /// [`translate_html`]: Self::translate_html
+    /// [`restart_sample`]: Self::restart_sample
+    /// [`translate_file`]: Self::translate_file
+    pub fn with_sample(mut self, limit: Option<usize>) -> Self {
+        self.sample = limit.map(Sample::new);
+        self
+    }
+
+    /// Translate the `<text>` and `<tspan>` labels of inline SVG (off by default).
+    ///
+    /// Only their text content changes; coordinates, transforms and all other
+    /// SVG markup are kept. Translated labels may no longer fit the drawing,
+    /// hence opt-in. Without it, text inside `<svg>` is never translated.
+    pub fn with_svg_text(mut self, translate: bool) -> Self {
+        self.svg_text = translate;
+        self
+    }
+
+    /// Report problems into `diagnostics` (e.g. one shared by a whole job)
+    pub fn with_diagnostics(mut self, diagnostics: Diagnostics) -> Self {
+        self.diagnostics = diagnostics;
+        self
+    }
+
+    /// Problems found so far
+    pub fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    /// Collect untranslated text nodes into `fixups` (e.g. one shared by a whole job)
+    pub fn with_fixups(mut self, fixups: Fixups) -> Self {
+        self.fixups = fixups;
+        self
+    }
+
+    /// Text nodes left untranslated so far
+    pub fn fixups(&self) -> &Fixups {
+        &self.fixups
+    }
+
+    /// Copy of this processor with a fresh sample budget, for a new document
+    pub fn restart_sample(&self) -> Self {
+        let mut restarted = self.clone();
+        restarted.sample = self.sample.as_ref().map(|sample| Sample::new(sample.limit));
+        restarted
+    }
+
+    /// Number of texts translated per document when sampling
+    pub fn sample_limit(&self) -> Option<usize> {
+        self.sample.as_ref().map(|sample| sample.limit)
+    }
+
+    /// Texts copied unchanged so far because the sample budget was spent
+    pub fn sample_skipped(&self) -> usize {
+        self.sample
+            .as_ref()
+            .map_or(0, |sample| sample.skipped.load(Ordering::SeqCst))
+    }
+
+    /// Find HTML files in a directory
+    pub fn find_files(&self, dir: &Path, recursive: bool) -> Result<Vec<PathBuf>> {
+        if !dir.is_dir() {
+            return Err(TranslationError::FileError {
+                path: dir.display().to_string(),
+                message: "Not a directory".to_string(),
+            });
+        }
+
+        let max_depth = if recursive { usize::MAX } else { 1 };
+        let files = walkdir::WalkDir::new(dir)
+            .max_depth(max_depth)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .map(|e| e.into_path())
+            .filter(|path| path.is_file() && self.is_html_file(path))
+            .collect();
+
+        Ok(files)
+    }
+
+    /// Check if file is HTML
+    pub fn is_html_file(&self, path: &Path) -> bool {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .map(|e| matches!(e.to_lowercase().as_str(), "html" | "htm" | "xhtml"))
+            .unwrap_or(false)
+    }
+
+    /// Translate a single HTML file.
+    ///
+    /// A text node that cannot be translated keeps its original text and is
+    /// recorded in [`fixups`](Self::fixups); only reaching the cost cap aborts.
+    pub async fn translate_file(
+        &self,
+        input: &Path,
+        output: &Path,
+        target_lang: &str,
+        source_lang: Option<String>,
+    ) -> Result<()> {
+        debug!("Translating: {}", input.display());
+
+        let html = tokio::fs::read_to_string(input)
+            .await
+            .map_err(|e| TranslationError::FileError {
+                path: input.display().to_string(),
+                message: e.to_string(),
+            })?;
+
+        let mut processor = self.restart_sample();
+        let failed = FailedTexts::default();
+        processor.failed = Some(failed.clone());
+        let translated = processor
+            .translate_html(&html, target_lang, source_lang.as_deref())
+            .await?;
+        if processor.sample_skipped() > 0 {
+            info!(
+                "Partial sample: {} texts of {} left untranslated",
+                processor.sample_skipped(),
+                input.display()
+            );
+        }
+        for (i, (original, error)) in failed.lock().unwrap().drain(..).enumerate() {
+            self.diagnostics.push(
+                Diagnostic::new(Severity::Warning, format!("text left untranslated: {}", error))
+                    .with_file(input)
+                    .with_location(format!("text {}", i + 1)),
+            );
+            self.fixups.push_failed(input, i, &original, "text left untranslated");
+        }
+
+        if let Some(parent) = output.parent() {
+            if !parent.as_os_str().is_empty() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+        }
+        tokio::fs::write(output, &translated)
+            .await
+            .map_err(|e| TranslationError::FileError {
+                path: output.display().to_string(),
+                message: e.to_string(),
+            })?;
+        self.fixups.resolve(input, output, &translated);
+
+        info!("Translated: {} -> {}", input.display(), output.display());
+        Ok(())
+    }
+
+    /// Translate HTML content.
+    ///
+    /// The markup is walked with an XML tokenizer configured to tolerate HTML
+    /// (void elements, unmatched end tags). Only text nodes and whitelisted
+    /// attribute values are translated; tags, comments, CDATA, `<script>` and
+    /// `<style>` contents and everything else are copied byte for byte. Inside
+    /// `<svg>` only text labels are translated, and only with
+    /// [`with_svg_text`](Self::with_svg_text). If the markup cannot be
+    /// tokenized (e.g. an unbalanced attribute quote), the rest of it is
+    /// translated with a plain character scan instead.
+    pub async fn translate_html(
+        &self,
+        html: &str,
+        target_lang: &str,
+        source_lang: Option<&str>,
+    ) -> Result<String> {
+        let (translated, fell_back) = self
+            .translate_html_checked(html, target_lang, source_lang)
+            .await?;
+        if fell_back {
+            warn!("Malformed HTML, translated the rest with the fallback scanner");
+        }
+        Ok(translated)
+    }
+
+    /// [`translate_html`](Self::translate_html), also telling whether the
+    /// fallback scanner had to be used
+    pub(crate) async fn translate_html_checked(
+        &self,
+        html: &str,
+        target_lang: &str,
+        source_lang: Option<&str>,
+    ) -> Result<(String, bool)> {
+        let mut reader = lenient_reader(html);
+        // Where `reader` started within `html`
+        let mut offset = 0;
+        let mut translated = String::with_capacity(html.len());
+        let mut last = 0;
+        // Open <svg> elements, and open <text>/<tspan> elements inside them
+        let mut svg_depth = 0usize;
+        let mut svg_text_depth = 0usize;
+
+        loop {
+            let event = match reader.read_event() {
+                Ok(event) => event,
+                Err(e) => {
+                    debug!("Cannot tokenize HTML after byte {}: {}, scanning the rest", last, e);
+                    let rest = self
+                        .translate_scanned(&html[last..], target_lang, source_lang)
+                        .await?;
+                    translated.push_str(&rest);
+                    return Ok((translated, true));
+                }
+            };
+            let end = offset + reader.buffer_position() as usize;
+            let raw = &html[last..end];
+            match event {
+                Event::Eof => break,
+                Event::Start(tag) if is_raw_text_element(tag.local_name().as_ref()) => {
+                    // The body is code that need not be valid markup (`a < b`):
+                    // copy it up to the end tag and tokenize again from there
+                    let name = String::from_utf8_lossy(tag.local_name().as_ref())
+                        .to_ascii_lowercase();
+                    let body_end = html[end..]
+                        .to_ascii_lowercase()
+                        .find(&format!("</{}", name))
+                        .map_or(html.len(), |i| end + i);
+                    translated.push_str(&html[last..body_end]);
+                    last = body_end;
+                    offset = body_end;
+                    reader = lenient_reader(&html[body_end..]);
+                    continue;
+                }
+                Event::Start(tag) => {
+                    let name = tag.local_name();
+                    if name.as_ref().eq_ignore_ascii_case(b"svg") {
+                        svg_depth += 1;
+                    } else if svg_depth > 0 && is_svg_text_element(name.as_ref()) {
+                        svg_text_depth += 1;
+                    }
+                    let tag = self.translate_tag(raw, target_lang, source_lang).await?;
+                    translated.push_str(&tag);
+                }
+                Event::Empty(_) => {
+                    let tag = self.translate_tag(raw, target_lang, source_lang).await?;
+                    translated.push_str(&tag);
+                }
+                Event::End(tag) => {
+                    let name = tag.local_name();
+                    if svg_depth > 0 && name.as_ref().eq_ignore_ascii_case(b"svg") {
+                        svg_depth -= 1;
+                        if svg_depth == 0 {
+                            svg_text_depth = 0;
+                        }
+                    } else if svg_text_depth > 0 && is_svg_text_element(name.as_ref()) {
+                        svg_text_depth -= 1;
+                    }
+                    translated.push_str(raw);
+                }
+                Event::Text(_) if svg_depth == 0 || (self.svg_text && svg_text_depth > 0) => {
+                    let text = self.translate_text_node(raw, target_lang, source_lang).await?;
+                    translated.push_str(&text);
+                }
+                // SVG text, comments, CDATA, declarations and doctypes
+                _ => translated.push_str(raw),
+            }
+            last = end;
+        }
+
+        translated.push_str(&html[last..]);
+        Ok((translated, false))
+    }
+
+    /// Translate markup the tokenizer rejected, split by [`scan_markup`]
+    async fn translate_scanned(
+        &self,
+        html: &str,
+        target_lang: &str,
+        source_lang: Option<&str>,
+    ) -> Result<String> {
+        let mut translated = String::with_capacity(html.len());
+        for piece in scan_markup(html) {
+            match piece {
+                ScannedPiece::Tag(start, end) => {
+                    let tag = &html[start..end];
+                    translated.push_str(&self.translate_tag(tag, target_lang, source_lang).await?);
+                }
+                ScannedPiece::Text(start, end) => {
+                    let text = self
+                        .translate_text_node(&html[start..end], target_lang, source_lang)
+                        .await?;
+                    translated.push_str(&text);
+                }
+                ScannedPiece::Raw(start, end) => translated.push_str(&html[start..end]),
+            }
+        }
+        Ok(translated)
+    }
+
+    /// Translate a text node given as it appears in the markup (entities escaped).
+    ///
+    /// The text is unescaped for translation and the translation escaped again;
+    /// a node that comes back unchanged keeps its original entities.
+    async fn translate_text_node(
+        &self,
+        raw: &str,
+        target_lang: &str,
+        source_lang: Option<&str>,
+    ) -> Result<String> {
+        if raw.trim().is_empty() {
+            return Ok(raw.to_string());
+        }
+        let text = match unescape(raw) {
+            Ok(text) => text,
+            Err(e) => {
+                debug!("Keeping text node with unknown entity: {}", e);
+                return Ok(raw.to_string());
+            }
+        };
+
+        let translated = match self.translate_text(&text, target_lang, source_lang).await {
+            Ok(translated) => translated,
+            Err(e @ TranslationError::CostLimitExceeded { .. }) => return Err(e),
+            Err(e) => {
+                let Some(failed) = &self.failed else {
+                    return Err(e);
+                };
+                warn!("Translation failed, keeping text node: {}", e);
+                failed.lock().unwrap().push((raw.trim().to_string(), e.to_string()));
+                return Ok(raw.to_string());
+            }
+        };
+        if translated == text {
+            return Ok(raw.to_string());
+        }
+        Ok(partial_escape(&translated).into_owned())
+    }
+
+    /// Translate whitelisted attribute values of a single tag, keeping everything
+    /// else; a value whose request fails keeps its original text, except when
+    /// the cost limit is reached
+    async fn translate_tag(
+        &self,
+        tag: &str,
+        target_lang: &str,
+        source_lang: Option<&str>,
+    ) -> Result<String> {
+        // Closing tags, comments, doctypes and processing instructions carry no attributes
+        if self.attributes.is_empty()
+            || tag.starts_with("</")
+            || tag.starts_with("<!")
+            || tag.starts_with("<?")
+        {
+            return Ok(tag.to_string());
+        }
+
+        let mut result = String::with_capacity(tag.len());
+        let mut last = 0;
+        for caps in attribute_regex().captures_iter(tag) {
+            let name = &caps["name"];
+            if !self.attributes.contains(&name.to_ascii_lowercase()) {
+                continue;
+            }
+            let (value, quote) = match (caps.name("dq"), caps.name("sq")) {
+                (Some(value), _) => (value, '"'),
+                (None, Some(value)) => (value, '\''),
+                (None, None) => continue,
+            };
+
+            let text = match unescape(value.as_str()) {
+                Ok(text) => text,
+                Err(e) => {
+                    debug!("Keeping attribute {} with unknown entity: {}", name, e);
+                    continue;
+                }
+            };
+            let translated = match self.translate_text(&text, target_lang, source_lang).await {
+                Ok(translated) => translated,
+                Err(e @ TranslationError::CostLimitExceeded { .. }) => return Err(e),
+                Err(e) => {
+                    warn!("Translation failed, keeping attribute {}: {}", name, e);
+                    continue;
+                }
+            };
+            // Values that come back unchanged keep their original entities
+            if translated == text {
+                continue;
+            }
+
+            result.push_str(&tag[last..value.start()]);
+            result.push_str(&escape_attribute(&translated, quote));
+            last = value.end();
+        }
+        result.push_str(&tag[last..]);
+
+        Ok(result)
+    }
+
+    /// Model requests currently go to, for token estimates
+    pub(crate) async fn current_model(&self) -> String {
+        self.translator.get_current_model().await
+    }
+
+    /// Translate a text node or attribute value, keeping the whitespace around it
+    async fn translate_text(
+        &self,
+        text: &str,
+        target_lang: &str,
+        source_lang: Option<&str>,
+    ) -> Result<String> {
+        // Whitespace, punctuation and numbers need no translation
+        let trimmed = text.trim();
+        if !has_words(trimmed) || self.sample.as_ref().is_some_and(|sample| !sample.take()) {
+            return Ok(text.to_string());
+        }
+
+        let request = TranslationRequest::new(trimmed.to_string(), target_lang.to_string())
+            .with_source_lang(source_lang.unwrap_or("auto"));
+
+        let result = self.translator.translate(&request).await?;
+
+        // Put back exactly the whitespace that was stripped around the text
+        let leading = &text[..text.len() - text.trim_start().len()];
+        let trailing = &text[text.trim_end().len()..];
+        Ok(format!("{}{}{}", leading, result.translation, trailing))
+    }
+}
+
+/// Texts of `html` that [`HtmlProcessor::translate_html`] would send, in
+/// order: its text nodes, unescaped and trimmed, that contain words
+pub(crate) fn translatable_texts(html: &str) -> Vec<String> {
+    text_node_ranges(html)
+        .into_iter()
+        .filter_map(|(start, end)| unescape(&html[start..end]).ok())
+        .map(|text| text.trim().to_string())
+        .filter(|text| has_words(text))
+        .collect()
+}
+
+/// Byte ranges of the translatable text nodes of `html`, in order.
+///
+/// Uses the same rules as [`HtmlProcessor::translate_html`]: blank nodes,
+/// nodes with unknown entities and text inside `<script>`, `<style>` and
+/// `<svg>` are left out. The ranges cover the text as written (entities escaped).
+pub(crate) fn text_node_ranges(html: &str) -> Vec<(usize, usize)> {
+    let mut reader = lenient_reader(html);
+    let mut offset = 0;
+    let mut last = 0;
+    let mut svg_depth = 0usize;
+    let mut ranges = Vec::new();
+
+    loop {
+        let event = match reader.read_event() {
+            Ok(Event::Eof) | Err(_) => break,
+            Ok(event) => event,
+        };
+        let end = offset + reader.buffer_position() as usize;
+        match event {
+            Event::Start(tag) if is_raw_text_element(tag.local_name().as_ref()) => {
+                let name = String::from_utf8_lossy(tag.local_name().as_ref()).to_ascii_lowercase();
+                let body_end = html[end..]
+                    .to_ascii_lowercase()
+                    .find(&format!("</{}", name))
+                    .map_or(html.len(), |i| end + i);
+                last = body_end;
+                offset = body_end;
+                reader = lenient_reader(&html[body_end..]);
+                continue;
+            }
+            Event::Start(tag) if tag.local_name().as_ref().eq_ignore_ascii_case(b"svg") => {
+                svg_depth += 1;
+            }
+            Event::End(tag) if tag.local_name().as_ref().eq_ignore_ascii_case(b"svg") => {
+                svg_depth = svg_depth.saturating_sub(1);
+            }
+            Event::Text(_) if svg_depth == 0 => {
+                let raw = &html[last..end];
+                if !raw.trim().is_empty() && unescape(raw).is_ok() {
+                    ranges.push((last, end));
+                }
+            }
+            _ => {}
+        }
+        last = end;
+    }
+    ranges
+}
+
+/// A piece of markup found by [`scan_markup`], as a byte range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScannedPiece {
+    /// Start or empty-element tag, whose attributes may be translated
+    Tag(usize, usize),
+    /// Text between tags
+    Text(usize, usize),
+    /// Copied as is: end tags, comments, CDATA, declarations, the bodies of
+    /// `<script>` and `<style>`, and text inside `<svg>`
+    Raw(usize, usize),
+}
+
+/// Split `html` into tags and text with a plain character scan.
+///
+/// Fallback for markup the tokenizer rejects. A `<` that does not start a
+/// tag is text; a tag whose quotes never close ends at the first `>`, and
+/// so does a comment without `-->`.
+fn scan_markup(html: &str) -> Vec<ScannedPiece> {
+    let mut pieces = Vec::new();
+    let mut svg_depth = 0usize;
+    let mut text_start = 0;
+    let mut pos = 0;
+
+    while let Some(found) = html[pos..].find('<') {
+        let start = pos + found;
+        let rest = &html[start..];
+        let next = rest[1..].chars().next();
+        let end = if rest.starts_with("<!--") {
+            rest.find("-->").map(|i| i + 3).or_else(|| rest.find('>').map(|i| i + 1))
+        } else if rest.starts_with("<![CDATA[") {
+            rest.find("]]>").map(|i| i + 3)
+        } else if next.is_some_and(|c| c.is_ascii_alphabetic() || matches!(c, '/' | '!' | '?')) {
+            tag_end(rest)
+        } else {
+            // A stray `<` in text
+            pos = start + 1;
+            continue;
+        };
+        let end = start + end.unwrap_or(rest.len());
+
+        if start > text_start {
+            pieces.push(if svg_depth == 0 {
+                ScannedPiece::Text(text_start, start)
+            } else {
+                ScannedPiece::Raw(text_start, start)
+            });
+        }
+
+        let tag = &html[start..end];
+        let closing = tag.starts_with("</");
+        let name: String = tag
+            .trim_start_matches(['<', '/'])
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        let self_closing = tag.ends_with("/>");
+        pos = end;
+
+        if !next.is_some_and(|c| c.is_ascii_alphabetic()) {
+            pieces.push(ScannedPiece::Raw(start, end));
+            if closing && name == "svg" {
+                svg_depth = svg_depth.saturating_sub(1);
+            }
+        } else if !self_closing && is_raw_text_element(name.as_bytes()) {
+            pieces.push(ScannedPiece::Tag(start, end));
+            let body_end = html[end..]
+                .to_ascii_lowercase()
+                .find(&format!("</{}", name))
+                .map_or(html.len(), |i| end + i);
+            if body_end > end {
+                pieces.push(ScannedPiece::Raw(end, body_end));
+            }
+            pos = body_end;
+        } else {
+            pieces.push(ScannedPiece::Tag(start, end));
+            if name == "svg" && !self_closing {
+                svg_depth += 1;
+            }
+        }
+        text_start = pos;
+    }
+
+    if text_start < html.len() {
+        pieces.push(if svg_depth == 0 {
+            ScannedPiece::Text(text_start, html.len())
+        } else {
+            ScannedPiece::Raw(text_start, html.len())
+        });
+    }
+    pieces
+}
+
+/// Length of the tag at the start of `tag` (up to and including its `>`),
+/// skipping `>` inside quoted attribute values when the quotes are balanced
+fn tag_end(tag: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in tag.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, '>') => return Some(i + 1),
+            _ => {}
+        }
+    }
+    tag.find('>').map(|i| i + 1)
+}
+
+/// Whether `text` contains any letters (including CJK characters)
+fn has_words(text: &str) -> bool {
+    text.chars().any(char::is_alphabetic)
+}
+
+/// Tokenizer over `html` that accepts HTML void elements and unmatched end tags
+fn lenient_reader(html: &str) -> Reader<&[u8]> {
+    let mut reader = Reader::from_str(html);
+    let config = reader.config_mut();
+    config.check_end_names = false;
+    config.allow_unmatched_ends = true;
+    reader
+}
+
+/// Elements whose content is code, not text: `<script>` and `<style>`
+fn is_raw_text_element(local_name: &[u8]) -> bool {
+    local_name.eq_ignore_ascii_case(b"script") || local_name.eq_ignore_ascii_case(b"style")
+}
+
+/// SVG elements holding label text: `<text>` and `<tspan>`
+fn is_svg_text_element(local_name: &[u8]) -> bool {
+    local_name.eq_ignore_ascii_case(b"text") || local_name.eq_ignore_ascii_case(b"tspan")
+}
+
+/// Quoted `name="value"` / `name='value'` pairs inside a tag
+fn attribute_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(
+            r#"\s(?P<name>[A-Za-z_:][-A-Za-z0-9_:.]*)\s*=\s*(?:"(?P<dq>[^"]*)"|'(?P<sq>[^']*)')"#,
+        )
+        .unwrap()
+    })
+}
+
+/// Escape a value for an attribute delimited by `quote`
+fn escape_attribute(value: &str, quote: char) -> String {
+    let escaped = value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    match quote {
+        '"' => escaped.replace('"', "&quot;"),
+        _ => escaped.replace('\'', "&#39;"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{test_translator, MockServer};
+
+    const FORM: &str = r#"<form><input type="text" name="q" placeholder="Search &amp; find" data-hint="Keep me"><button aria-label='Say "hi"' class="btn">Go</button></form>"#;
+
+    #[tokio::test]
+    async fn test_translates_whitelisted_attributes_only() {
+        let server = MockServer::echo().await;
+        let processor = HtmlProcessor::new(test_translator(&server.url));
+
+        let translated = processor.translate_html(FORM, "zh", None).await.unwrap();
+        assert_eq!(
+            translated,
+            r#"<form><input type="text" name="q" placeholder="zh:Search &amp; find" data-hint="Keep me"><button aria-label='zh:Say "hi"' class="btn">zh:Go</button></form>"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_custom_attribute_whitelist() {
+        let server = MockServer::echo().await;
+        let processor =
+            HtmlProcessor::new(test_translator(&server.url)).with_attributes(["Placeholder"]);
+
+        let translated = processor.translate_html(FORM, "zh", None).await.unwrap();
+        assert!(translated.contains(r#"placeholder="zh:Search &amp; find""#));
+        assert!(translated.contains(r#"aria-label='Say "hi"'"#));
+    }
+
+    #[tokio::test]
+    async fn test_sample_budget_spans_calls_until_restarted() {
+        let server = MockServer::echo().await;
+        let processor = HtmlProcessor::new(test_translator(&server.url)).with_sample(Some(2));
+
+        let first = processor
+            .translate_html("<p>One</p><p>Two</p><p>Three</p>", "zh", None)
+            .await
+            .unwrap();
+        assert_eq!(first, "<p>zh:One</p><p>zh:Two</p><p>Three</p>");
+        let second = processor.translate_html("<p>Four</p>", "zh", None).await.unwrap();
+        assert_eq!(second, "<p>Four</p>");
+        assert_eq!(processor.sample_skipped(), 2);
+        assert_eq!(server.sent_texts(), vec!["One", "Two"]);
+
+        let restarted = processor.restart_sample();
+        let third = restarted.translate_html("<p>Five</p>", "zh", None).await.unwrap();
+        assert_eq!(third, "<p>zh:Five</p>");
+        assert_eq!(restarted.sample_skipped(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_markup_kept_byte_for_byte() {
+        let server = MockServer::echo().await;
+        let processor = HtmlProcessor::new(test_translator(&server.url));
+
+        let html = "<?xml version=\"1.0\"?>\n<!DOCTYPE html>\n<html><body>\n\
+                    <!-- a <comment> with Text -->\n\
+                    <p data-rule=\"a < b\" title=\"x &lt; y\">Tom &amp; Jerry &lt;3</p>\n\
+                    <script>if (a < b && c) { go(); }</script>\n\
+                    <style>p > a { color: red; }</style>\n\
+                    <p><![CDATA[raw <data>]]> and <br/>more</p>\n</body></html>";
+        let translated = processor.translate_html(html, "zh", None).await.unwrap();
+        assert_eq!(
+            translated,
+            "<?xml version=\"1.0\"?>\n<!DOCTYPE html>\n<html><body>\n\
+             <!-- a <comment> with Text -->\n\
+             <p data-rule=\"a < b\" title=\"zh:x &lt; y\">zh:Tom &amp; Jerry &lt;3</p>\n\
+             <script>if (a < b && c) { go(); }</script>\n\
+             <style>p > a { color: red; }</style>\n\
+             <p><![CDATA[raw <data>]]> zh:and <br/>zh:more</p>\n</body></html>"
+        );
+        // Whitespace-only runs between tags are not sent
+        assert_eq!(
+            server.sent_texts(),
+            vec!["x < y", "Tom & Jerry <3", "and", "more"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_untranslated_text_keeps_its_entities() {
+        let server = MockServer::echo().await;
+        let processor = HtmlProcessor::new(test_translator(&server.url)).with_sample(Some(0));
+
+        let html = "<p>caf&#233; &mdash; &amp;</p>";
+        let translated = processor.translate_html(html, "zh", None).await.unwrap();
+        assert_eq!(translated, html);
+        assert_eq!(server.request_count(), 0);
+    }
+
+    const DIAGRAM: &str = "<p>Figure</p><svg viewBox=\"0 0 100 50\">\
+        <path d=\"M0 0L10 10\"/><title>Chart</title>\
+        <text x=\"10\" y=\"20\" transform=\"rotate(45 10 20)\">Input \
+        <tspan dx=\"5\" font-weight=\"bold\">queue</tspan></text></svg><p>After</p>";
+
+    #[tokio::test]
+    async fn test_svg_text_is_opt_in() {
+        let server = MockServer::echo().await;
+        let processor = HtmlProcessor::new(test_translator(&server.url));
+
+        let translated = processor.translate_html(DIAGRAM, "zh", None).await.unwrap();
+        assert_eq!(
+            translated,
+            DIAGRAM.replace("<p>Figure", "<p>zh:Figure").replace("<p>After", "<p>zh:After")
+        );
+
+        let processor = processor.with_svg_text(true);
+        let translated = processor.translate_html(DIAGRAM, "zh", None).await.unwrap();
+        assert_eq!(
+            translated,
+            "<p>zh:Figure</p><svg viewBox=\"0 0 100 50\">\
+             <path d=\"M0 0L10 10\"/><title>Chart</title>\
+             <text x=\"10\" y=\"20\" transform=\"rotate(45 10 20)\">zh:Input \
+             <tspan dx=\"5\" font-weight=\"bold\">zh:queue</tspan></text></svg><p>zh:After</p>"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_text_keeps_surrounding_whitespace_once() {
+        let server = MockServer::echo().await;
+        let processor = HtmlProcessor::new(test_translator(&server.url));
+
+        let translated = processor.translate_text("  Hello Hello  ", "zh", None).await.unwrap();
+        assert_eq!(translated, "  zh:Hello Hello  ");
+        let translated = processor.translate_text("\n\tHi\n", "zh", None).await.unwrap();
+        assert_eq!(translated, "\n\tzh:Hi\n");
+        assert_eq!(server.sent_texts(), vec!["Hello Hello", "Hi"]);
+    }
+
+    #[test]
+    fn test_escape_attribute_for_quote_style() {
+        assert_eq!(escape_attribute(r#"a "b" & 'c'"#, '"'), "a &quot;b&quot; &amp; 'c'");
+        assert_eq!(escape_attribute(r#"a "b" & 'c'"#, '\''), r#"a "b" &amp; &#39;c&#39;"#);
+    }
+
+    #[tokio::test]
+    async fn test_attribute_entities_are_decoded_once() {
+        let server = MockServer::echo().await;
+        let processor = HtmlProcessor::new(test_translator(&server.url));
+
+        let html = r#"<img alt="Caf&#233; &eacute;t&eacute; &amp; more" title="&#169; 2024">"#;
+        let translated = processor.translate_html(html, "zh", None).await.unwrap();
+
+        assert_eq!(server.sent_texts(), vec!["Café été & more"]);
+        assert_eq!(translated, r#"<img alt="zh:Café été &amp; more" title="&#169; 2024">"#);
+    }
+
+    #[tokio::test]
+    async fn test_failed_attribute_keeps_its_original_text() {
+        use crate::testing::{echo_translation, MockReply};
+
+        let server = MockServer::start(|req| {
+            let texts = req.input_texts();
+            if texts.iter().any(|text| text.contains("Search")) {
+                return MockReply::error(400, "rejected");
+            }
+            let target = req.target_lang();
+            let texts: Vec<String> =
+                texts.iter().map(|text| echo_translation(&target, text)).collect();
+            MockReply::translations(&texts)
+        })
+        .await;
+        let processor = HtmlProcessor::new(test_translator(&server.url));
+
+        let translated = processor.translate_html(FORM, "zh", None).await.unwrap();
+        assert!(translated.contains(r#"placeholder="Search &amp; find""#));
+        assert!(translated.contains(r#"aria-label='zh:Say "hi"'"#));
+        assert!(translated.contains(">zh:Go<"));
+    }
+
+    #[tokio::test]
+    async fn test_failed_text_node_is_kept_for_a_fixup_job() {
+        use crate::testing::{echo_translation, MockReply};
+
+        let server = MockServer::start(|req| {
+            let texts = req.input_texts();
+            if texts.iter().any(|text| text.contains("Fish")) {
+                return MockReply::error(400, "rejected");
+            }
+            let target = req.target_lang();
+            let texts: Vec<String> =
+                texts.iter().map(|text| echo_translation(&target, text)).collect();
+            MockReply::translations(&texts)
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let (input, output) = (dir.path().join("a.html"), dir.path().join("a_zh.html"));
+        std::fs::write(&input, "<p>Chips</p><p>Fish &amp; chips</p>").unwrap();
+        let processor = HtmlProcessor::new(test_translator(&server.url));
+        processor.translate_file(&input, &output, "zh", None).await.unwrap();
+        assert_eq!(processor.diagnostics().all().len(), 1);
+
+        let mut job = processor.fixups().job();
+        assert_eq!(job.entries.len(), 1);
+        assert_eq!(job.entries[0].original, "Fish &amp; chips");
+        job.entries[0].translation = Some("炸鱼 & 薯条".to_string());
+        job.apply().await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "<p>zh:Chips</p><p>炸鱼 &amp; 薯条</p>"
+        );
+    }
+}
@@ -4,20 +4,206 @@ use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use tracing::{debug, info, warn};
 
+use pulldown_cmark::{Event, LinkType, Options, Parser, Tag, TagEnd};
+use quick_xml::escape::{partial_escape, unescape};
+use unicode_width::UnicodeWidthStr;
+
 use crate::core::client::AsyncTranslator;
+use crate::core::config::TranslatorConfig;
+use crate::core::diagnostics::{Diagnostic, Diagnostics, Severity};
 use crate::core::errors::{Result, TranslationError};
-use crate::core::models::TranslationRequest;
+use crate::core::models::{ScheduleOrder, TranslationRequest};
+use crate::processors::batch::{group_pending, Batcher};
+use crate::processors::fixup::Fixups;
+use crate::processors::html::text_node_ranges;
+use crate::processors::plan::FilePlan;
+use crate::processors::progress::{Progress, ProgressFn};
+use crate::processors::review::{ReviewFile, ReviewSegment};
+use crate::utils::rng::{delimiter_token, seeded_rng};
+use crate::utils::wrap::{is_wide, wrap};
+
+/// What translating a document would send, without calling the API
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentPlan {
+    /// Translatable segments in document order
+    pub segments: Vec<String>,
+    /// Delimiter token for joining segments into one request
+    pub delimiter: String,
+}
+
+/// What happened to one text segment, see [`MarkdownProcessor::translate_content_report`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentOutcome {
+    /// Index of the segment in document order, or of the string among the
+    /// frontmatter strings when `field` is set
+    pub index: usize,
+    /// Frontmatter field (e.g. `seo.excerpt`) the string belongs to; `None`
+    /// for body segments
+    pub field: Option<String>,
+    /// Whether it was translated
+    pub status: SegmentStatus,
+    /// Why it failed, for [`SegmentStatus::Failed`]
+    pub error: Option<String>,
+}
+
+/// Result of translating one text segment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentStatus {
+    /// Translated, or reused from an earlier translation
+    Translated,
+    /// The request failed; the original text was kept
+    Failed,
+    /// Not sent (beyond a `--sample` limit); the original text was kept
+    Skipped,
+}
+
+/// How [`MarkdownProcessor::translate_combined`] joins translated files
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CombineOptions {
+    /// Markdown put between two files, on its own paragraph (default `---`)
+    pub separator: String,
+    /// Start every file with a `# <file name>` heading
+    pub headings: bool,
+}
+
+impl Default for CombineOptions {
+    fn default() -> Self {
+        Self {
+            separator: "---".to_string(),
+            headings: false,
+        }
+    }
+}
+
+/// HTML comments that mark the next block as not to be translated
+pub const DEFAULT_SKIP_DIRECTIVES: &[&str] = &["i18n: skip"];
+
+/// Frontmatter keys translated unless [`MarkdownProcessor::with_frontmatter_fields`]
+/// replaces them
+pub const DEFAULT_FRONTMATTER_FIELDS: &[&str] = &["title", "description", "summary", "name", "alt"];
 
 /// Markdown processor that preserves code blocks and links
 #[derive(Debug, Clone)]
 pub struct MarkdownProcessor {
+    /// Client that translates segments and frontmatter strings
     translator: AsyncTranslator,
+    /// Translate inline code together with its sentence behind placeholders
+    protect_inline_code: bool,
+    /// Order in which segments are sent
+    schedule: ScheduleOrder,
+    /// Send consecutive segments together in one request
+    batching: bool,
+    /// Translate only this many leading segments per file
+    sample: Option<usize>,
+    /// Problems found while translating (failed segments, ...)
+    diagnostics: Diagnostics,
+    /// Reflow translated paragraphs to this many display columns
+    wrap: Option<usize>,
+    /// Comment texts (e.g. `i18n: skip`) that keep the next block untranslated
+    skip_directives: Vec<String>,
+    /// Segments left untranslated, for a fix-up job
+    fixups: Fixups,
+    /// Frontmatter keys (dotted paths for nested ones) whose text is translated
+    frontmatter_fields: Vec<String>,
+    /// Receives segment and file progress
+    progress: ProgressFn,
 }
 
 impl MarkdownProcessor {
     /// Create a new markdown processor
     pub fn new(translator: AsyncTranslator) -> Self {
-        Self { translator }
+        Self {
+            translator,
+            protect_inline_code: true,
+            schedule: ScheduleOrder::SourceOrder,
+            batching: false,
+            sample: None,
+            diagnostics: Diagnostics::new(),
+            wrap: None,
+            skip_directives: default_skip_directives(),
+            fixups: Fixups::new(),
+            frontmatter_fields: DEFAULT_FRONTMATTER_FIELDS.iter().map(|f| f.to_string()).collect(),
+            progress: ProgressFn::default(),
+        }
+    }
+
+    /// Keep sentences containing inline code in one segment, masking the code
+    /// behind placeholders (enabled by default). When disabled, text around
+    /// inline code is translated as separate fragments.
+    pub fn with_protect_inline_code(mut self, protect: bool) -> Self {
+        self.protect_inline_code = protect;
+        self
+    }
+
+    /// Dispatch segments in this order; the output keeps source order
+    pub fn with_schedule(mut self, schedule: ScheduleOrder) -> Self {
+        self.schedule = schedule;
+        self
+    }
+
+    /// Join consecutive segments, up to `max_input_tokens`, into one request
+    /// separated by a delimiter token (off by default).
+    ///
+    /// If the response does not split back into the same number of segments,
+    /// that batch is translated segment by segment instead.
+    pub fn with_batching(mut self, batching: bool) -> Self {
+        self.batching = batching;
+        self
+    }
+
+    /// Translate only the first `limit` text segments of each file, as a cheap
+    /// preview; later segments and the frontmatter are copied unchanged.
+    pub fn with_sample(mut self, limit: Option<usize>) -> Self {
+        self.sample = limit;
+        self
+    }
+
+    /// Report problems into `diagnostics` instead of a private collector
+    pub fn with_diagnostics(mut self, diagnostics: Diagnostics) -> Self {
+        self.diagnostics = diagnostics;
+        self
+    }
+
+    /// Reflow translated paragraphs to `width` display columns (CJK characters
+    /// count as two); code blocks, tables, headings and HTML are left alone
+    pub fn with_wrap(mut self, width: Option<usize>) -> Self {
+        self.wrap = width;
+        self
+    }
+
+    /// Comment directives that copy the block right after them verbatim.
+    ///
+    /// A directive matches an HTML comment block whose text, trimmed, equals it:
+    /// `i18n: skip` matches `<!-- i18n: skip -->`. Defaults to
+    /// [`DEFAULT_SKIP_DIRECTIVES`]; all other comments are preserved as they are.
+    pub fn with_skip_directives(mut self, directives: Vec<String>) -> Self {
+        self.skip_directives = directives;
+        self
+    }
+
+    /// Frontmatter keys to translate, replacing [`DEFAULT_FRONTMATTER_FIELDS`].
+    ///
+    /// A dotted path such as `seo.excerpt` names a key nested in mappings;
+    /// every string under a named key is translated, lists included.
+    pub fn with_frontmatter_fields(mut self, fields: Vec<String>) -> Self {
+        self.frontmatter_fields = fields;
+        self
+    }
+
+    /// Problems found so far
+    pub fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    /// Collect untranslated segments into `fixups` (e.g. one shared by a whole job)
+    pub fn with_fixups(mut self, fixups: Fixups) -> Self {
+        self.fixups = fixups;
+        self
+    }
+
+    /// Segments left untranslated so far
+    pub fn fixups(&self) -> &Fixups {
+        &self.fixups
     }
 
     /// Create from environment configuration
@@ -26,8 +212,15 @@ impl MarkdownProcessor {
         Ok(Self::new(translator))
     }
 
-    /// Find Markdown files in directory
-    pub fn find_files(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+    /// Create from an already loaded configuration (e.g. from `--config`)
+    pub fn from_config(config: TranslatorConfig) -> Result<Self> {
+        let translator = AsyncTranslator::new(config)?;
+        Ok(Self::new(translator))
+    }
+
+    /// Find Markdown files in directory, leaving out those matching `exclude`
+    /// (see [`is_excluded`])
+    pub fn find_files(&self, dir: &Path, exclude: &[glob::Pattern]) -> Result<Vec<PathBuf>> {
         if !dir.is_dir() {
             return Err(TranslationError::FileError {
                 path: dir.display().to_string(),
@@ -39,7 +232,8 @@ impl MarkdownProcessor {
         for entry in std::fs::read_dir(dir)? {
             let entry = entry?;
             let path = entry.path();
-            if path.is_file() && self.is_markdown_file(&path) {
+            let relative = path.strip_prefix(dir).unwrap_or(&path);
+            if path.is_file() && self.is_markdown_file(&path) && !is_excluded(relative, exclude) {
                 files.push(path);
             }
         }
@@ -47,8 +241,13 @@ impl MarkdownProcessor {
         Ok(files)
     }
 
-    /// Find Markdown files recursively
-    pub fn find_files_recursive(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+    /// Find Markdown files recursively, leaving out those matching `exclude`
+    /// and everything under directories matching it (see [`is_excluded`])
+    pub fn find_files_recursive(
+        &self,
+        dir: &Path,
+        exclude: &[glob::Pattern],
+    ) -> Result<Vec<PathBuf>> {
         if !dir.is_dir() {
             return Err(TranslationError::FileError {
                 path: dir.display().to_string(),
@@ -57,8 +256,13 @@ impl MarkdownProcessor {
         }
 
         let mut files = Vec::new();
+        // Excluded directories are not descended into
+        let included = |e: &walkdir::DirEntry| {
+            e.depth() == 0 || !is_excluded(e.path().strip_prefix(dir).unwrap_or(e.path()), exclude)
+        };
         for entry in walkdir::WalkDir::new(dir)
             .into_iter()
+            .filter_entry(included)
             .filter_map(|e| e.ok())
         {
             let path = entry.path();
@@ -87,182 +291,710 @@ impl MarkdownProcessor {
         output: &Path,
         target_lang: &str,
         source_lang: Option<String>,
+    ) -> Result<()> {
+        self.translate_file_with_progress(input, output, target_lang, source_lang, |_| {})
+            .await
+    }
+
+    /// [`translate_file`](Self::translate_file), reporting to `progress` after
+    /// each translated segment (or batch) and once the file is written
+    pub async fn translate_file_with_progress(
+        &self,
+        input: &Path,
+        output: &Path,
+        target_lang: &str,
+        source_lang: Option<String>,
+        progress: impl Fn(Progress) + Send + Sync + 'static,
     ) -> Result<()> {
         debug!("Translating: {}", input.display());
+        let processor = Self {
+            progress: ProgressFn::new(progress),
+            ..self.clone()
+        };
 
-        // Read file content
-        let content = tokio::fs::read_to_string(input)
-            .await
-            .map_err(|e| TranslationError::FileError {
-                path: input.display().to_string(),
-                message: e.to_string(),
-            })?;
+        let content = read_file(input).await?;
 
         // Parse and translate
-        let translated = self
-            .translate_content(&content, target_lang, source_lang.clone())
+        let translated = processor
+            .translate_content(input, &content, target_lang, source_lang.clone())
             .await?;
 
-        // Ensure output directory exists
-        if let Some(parent) = output.parent() {
-            if !parent.exists() {
-                tokio::fs::create_dir_all(parent)
-                    .await
-                    .map_err(|e| TranslationError::FileError {
-                        path: parent.display().to_string(),
-                        message: e.to_string(),
-                    })?;
+        write_file(output, &translated).await?;
+        self.fixups.resolve(input, output, &translated);
+        processor.progress.emit(Progress::File {
+            output: output.to_path_buf(),
+            bytes: translated.len(),
+        });
+
+        info!("Translated: {} -> {}", input.display(), output.display());
+        Ok(())
+    }
+
+    /// Translate a file that was translated before, re-translating only the
+    /// segments that changed since `previous_source`.
+    ///
+    /// Unchanged segments reuse their translation from `previous_translation`
+    /// (the output produced for `previous_source`), aligned by segment text.
+    /// If the previous translation no longer lines up with its source,
+    /// everything is translated. Returns the number of reused segments.
+    pub async fn translate_file_incremental(
+        &self,
+        input: &Path,
+        output: &Path,
+        previous_source: &Path,
+        previous_translation: &Path,
+        target_lang: &str,
+        source_lang: Option<String>,
+    ) -> Result<usize> {
+        debug!("Translating incrementally: {}", input.display());
+
+        let content = read_file(input).await?;
+        let old_source = read_file(previous_source).await?;
+        let old_translation = read_file(previous_translation).await?;
+
+        let previous = match self.previous_translations(&old_source, &old_translation) {
+            Some(previous) => previous,
+            None => {
+                warn!(
+                    "{} does not line up with {}, translating everything",
+                    previous_translation.display(),
+                    previous_source.display()
+                );
+                self.diagnostics.push(
+                    Diagnostic::new(
+                        Severity::Info,
+                        "previous translation does not match its source, translated everything",
+                    )
+                    .with_file(input),
+                );
+                HashMap::new()
             }
+        };
+
+        let extractor = self.extract(&content);
+        let reused = extractor
+            .text_segments
+            .iter()
+            .filter(|s| previous.contains_key(*s))
+            .count();
+        let (translated, _) = self
+            .translate_extracted_with(
+                input,
+                &extractor,
+                target_lang,
+                source_lang.clone(),
+                &previous,
+            )
+            .await?;
+        // Sampling leaves the frontmatter alone, as in translate_content
+        let frontmatter = match extractor.frontmatter().filter(|_| self.sample.is_none()) {
+            Some((start, end)) => Some(
+                self.translate_yaml_frontmatter(
+                    input,
+                    &content[start..end],
+                    target_lang,
+                    source_lang,
+                )
+                .await?
+                .0,
+            ),
+            None => None,
+        };
+        let translated = self.reflow(extractor.reconstruct(&translated, frontmatter.as_deref()));
+        write_file(output, &translated).await?;
+        self.fixups.resolve(input, output, &translated);
+
+        info!(
+            "Translated: {} -> {} ({} of {} segments reused)",
+            input.display(),
+            output.display(),
+            reused,
+            extractor.text_segments.len()
+        );
+        Ok(reused)
+    }
+
+    /// Map each source segment of an earlier version to its translation.
+    ///
+    /// Both documents are segmented the same way; `None` if the segment
+    /// counts differ, as the translation can then not be aligned.
+    fn previous_translations(
+        &self,
+        old_source: &str,
+        old_translation: &str,
+    ) -> Option<HashMap<String, String>> {
+        let source = self.extract(old_source);
+        let translation = self.extract(old_translation);
+        if source.text_segments.len() != translation.text_segments.len() {
+            return None;
         }
+        Some(
+            source
+                .text_segments
+                .into_iter()
+                .zip(translation.text_segments)
+                .collect(),
+        )
+    }
 
-        // Write translated content
-        tokio::fs::write(output, translated)
-            .await
-            .map_err(|e| TranslationError::FileError {
-                path: output.display().to_string(),
-                message: e.to_string(),
-            })?;
+    /// Translate a Markdown file into an editable review file instead of a final document
+    pub async fn export_review(
+        &self,
+        input: &Path,
+        review_path: &Path,
+        target_lang: &str,
+        source_lang: Option<String>,
+    ) -> Result<ReviewFile> {
+        debug!("Exporting review: {}", input.display());
 
-        info!("Translated: {} -> {}", input.display(), output.display());
+        let content = read_file(input).await?;
+        let extractor = self.extract(&content);
+
+        let (translations, _) = self
+            .translate_extracted(input, &extractor, target_lang, source_lang.clone())
+            .await?;
+        let frontmatter = match extractor.frontmatter() {
+            Some((start, end)) => Some(
+                self.translate_yaml_frontmatter(
+                    input,
+                    &content[start..end],
+                    target_lang,
+                    source_lang,
+                )
+                .await?
+                .0,
+            ),
+            None => None,
+        };
+
+        let review = ReviewFile {
+            source: input.to_path_buf(),
+            target_lang: target_lang.to_string(),
+            frontmatter,
+            skip_directives: self.skip_directives.clone(),
+            protect_inline_code: self.protect_inline_code,
+            segments: extractor
+                .text_segments
+                .iter()
+                .zip(translations)
+                .enumerate()
+                .map(|(index, (source, mt))| ReviewSegment {
+                    index,
+                    source: source.clone(),
+                    mt,
+                    edited: None,
+                })
+                .collect(),
+        };
+        review.save(review_path).await?;
+
+        info!(
+            "Review exported: {} -> {}",
+            input.display(),
+            review_path.display()
+        );
+        Ok(review)
+    }
+
+    /// Reassemble the final document from a (possibly edited) review file.
+    ///
+    /// No translation happens here, so no translator is needed.
+    pub async fn apply_review(review_path: &Path, output: &Path) -> Result<()> {
+        let review = ReviewFile::load(review_path).await?;
+        let content = read_file(&review.source).await?;
+
+        // Extract the way the review was exported, so the segments line up
+        let mut extractor = MarkdownExtractor::new(&content)
+            .with_inline_code_masking(review.protect_inline_code)
+            .with_skip_directives(review.skip_directives.clone());
+        extractor.extract();
+        let texts = review.final_texts(&extractor.text_segments)?;
+
+        // Edited segments must keep every inline code placeholder
+        for (i, codes) in extractor.segment_inline_code().iter().enumerate() {
+            if !codes.is_empty() && unmask_inline_code(&texts[i], codes).is_none() {
+                return Err(TranslationError::InvalidFormat {
+                    format: format!(
+                        "review segment {} must keep each inline code placeholder exactly once",
+                        i
+                    ),
+                });
+            }
+        }
+
+        let translated = extractor.reconstruct(&texts, review.frontmatter.as_deref());
+        write_file(output, &translated).await?;
+
+        info!(
+            "Review applied: {} -> {}",
+            review_path.display(),
+            output.display()
+        );
+        Ok(())
+    }
+
+    /// Translate `inputs` into one document at `output`, in sorted path order.
+    ///
+    /// Only the first file keeps its frontmatter, so the others don't end up
+    /// as stray `---` blocks in the middle of the document. Any failing file
+    /// fails the whole document.
+    pub async fn translate_combined(
+        &self,
+        inputs: &[PathBuf],
+        output: &Path,
+        target_lang: &str,
+        source_lang: Option<String>,
+        options: &CombineOptions,
+    ) -> Result<()> {
+        let mut inputs = inputs.to_vec();
+        inputs.sort();
+
+        let mut parts = Vec::with_capacity(inputs.len());
+        for input in &inputs {
+            let content = read_file(input).await?;
+            let translated = self
+                .translate_content(input, &content, target_lang, source_lang.clone())
+                .await?;
+            let body = match body_start(&translated) {
+                start if !parts.is_empty() => &translated[start..],
+                _ => translated.as_str(),
+            };
+            let mut part = String::new();
+            if options.headings {
+                let name = input.file_stem().unwrap_or_default().to_string_lossy();
+                part.push_str(&format!("# {}\n\n", name));
+            }
+            part.push_str(body.trim_matches('\n'));
+            parts.push(part);
+        }
+
+        let separator = if options.separator.is_empty() {
+            "\n\n".to_string()
+        } else {
+            format!("\n\n{}\n\n", options.separator)
+        };
+        let mut combined = parts.join(&separator);
+        combined.push('\n');
+        write_file(output, &combined).await?;
+        for input in &inputs {
+            self.fixups.resolve(input, output, &combined);
+        }
+
+        info!("Combined {} files -> {}", inputs.len(), output.display());
         Ok(())
     }
 
-    /// Translate Markdown content
-    async fn translate_content(
+    /// Translate Markdown content read from `input`
+    pub async fn translate_content(
         &self,
+        input: &Path,
         content: &str,
         target_lang: &str,
         source_lang: Option<String>,
     ) -> Result<String> {
+        let (translated, _) = self
+            .translate_content_report(input, content, target_lang, source_lang)
+            .await?;
+        Ok(translated)
+    }
+
+    /// [`translate_content`](Self::translate_content), also returning what
+    /// happened to each text segment: a segment whose request failed keeps
+    /// its original text and is reported as [`SegmentStatus::Failed`].
+    /// Frontmatter strings come first, with their `field` set.
+    pub async fn translate_content_report(
+        &self,
+        input: &Path,
+        content: &str,
+        target_lang: &str,
+        source_lang: Option<String>,
+    ) -> Result<(String, Vec<SegmentOutcome>)> {
         // Extract special elements
-        let mut extractor = MarkdownExtractor::new(content);
-        extractor.extract();
+        let extractor = self.extract(content);
+        if extractor.text_segments.is_empty() && extractor.frontmatter().is_none() {
+            debug!("Nothing to translate in {}", input.display());
+            return Ok((content.to_string(), Vec::new()));
+        }
 
         // Translate regular text segments
-        let mut translated_segments = Vec::new();
-        for segment in &extractor.text_segments {
-            let request = TranslationRequest::new(segment.clone(), target_lang.to_string())
-                .with_source_lang(source_lang.clone().unwrap_or_else(|| "auto".to_string()));
+        let (translated_segments, segment_outcomes) = self
+            .translate_extracted(input, &extractor, target_lang, source_lang.clone())
+            .await?;
+
+        // Translate frontmatter separately, it is structured data
+        let (frontmatter, mut outcomes) =
+            match extractor.frontmatter().filter(|_| self.sample.is_none()) {
+                Some((start, end)) => {
+                    let (frontmatter, outcomes) = self
+                        .translate_yaml_frontmatter(
+                            input,
+                            &content[start..end],
+                            target_lang,
+                            source_lang.clone(),
+                        )
+                        .await?;
+                    (Some(frontmatter), outcomes)
+                }
+                None => (None, Vec::new()),
+            };
+        outcomes.extend(segment_outcomes);
+
+        debug_assert_eq!(
+            extractor.reconstruct(&extractor.text_segments, None),
+            content,
+            "identity reconstruction must reproduce the source"
+        );
+
+        let translated = extractor.reconstruct(&translated_segments, frontmatter.as_deref());
+        Ok((self.reflow(translated), outcomes))
+    }
+
+    /// Apply the configured `--wrap` width to a translated document
+    fn reflow(&self, document: String) -> String {
+        match self.wrap {
+            Some(width) => wrap_paragraphs(&document, width),
+            None => document,
+        }
+    }
+
+    /// Segment `content` without translating it.
+    ///
+    /// With a configured seed the plan, including the delimiter token, is identical
+    /// across runs.
+    pub fn plan_content(&self, content: &str) -> SegmentPlan {
+        let extractor = self.extract(content);
+        let mut rng = seeded_rng(self.translator.config().seed, "delimiter");
+        SegmentPlan {
+            segments: extractor.text_segments,
+            delimiter: delimiter_token(&mut rng),
+        }
+    }
+
+    /// What translating `input` would send, without calling the API
+    pub async fn plan(&self, input: &Path) -> Result<FilePlan> {
+        let content = read_file(input).await?;
+        let segments = self.plan_content(&content).segments;
+        Ok(FilePlan::new(input, &segments, &self.translator.get_current_model().await))
+    }
+
+    /// Run the extractor over `content` with this processor's settings
+    fn extract<'a>(&self, content: &'a str) -> MarkdownExtractor<'a> {
+        let mut extractor = MarkdownExtractor::new(content)
+            .with_inline_code_masking(self.protect_inline_code)
+            .with_skip_directives(self.skip_directives.clone());
+        extractor.extract();
+        extractor
+    }
+
+    /// Translate the extracted segments, making sure masked inline code survives.
+    ///
+    /// If the model drops or duplicates a placeholder, the text pieces between
+    /// the code spans are translated one by one instead. Returns the
+    /// translations with the outcome of each segment.
+    async fn translate_extracted(
+        &self,
+        input: &Path,
+        extractor: &MarkdownExtractor<'_>,
+        target_lang: &str,
+        source_lang: Option<String>,
+    ) -> Result<(Vec<String>, Vec<SegmentOutcome>)> {
+        self.translate_extracted_with(input, extractor, target_lang, source_lang, &HashMap::new())
+            .await
+    }
+
+    /// [`translate_extracted`](Self::translate_extracted), reusing the
+    /// translations in `previous` for segments found there
+    async fn translate_extracted_with(
+        &self,
+        input: &Path,
+        extractor: &MarkdownExtractor<'_>,
+        target_lang: &str,
+        source_lang: Option<String>,
+        previous: &HashMap<String, String>,
+    ) -> Result<(Vec<String>, Vec<SegmentOutcome>)> {
+        let segments = &extractor.text_segments;
+        let sampled = self.sample.map_or(segments.len(), |n| n.min(segments.len()));
+        let (mut translations, mut errors) = self
+            .translate_segments(
+                input,
+                &segments[..sampled],
+                &extractor.segment_contexts[..sampled],
+                target_lang,
+                source_lang.clone(),
+                previous,
+            )
+            .await?;
+        translations.extend_from_slice(&segments[sampled..]);
+        errors.resize(segments.len(), None);
+        if sampled < segments.len() {
+            info!(
+                "Partial sample: translated {} of {} segments of {}",
+                sampled,
+                segments.len(),
+                input.display()
+            );
+            self.diagnostics.push(
+                Diagnostic::new(
+                    Severity::Info,
+                    format!(
+                        "partial sample: first {} of {} segments translated",
+                        sampled,
+                        segments.len()
+                    ),
+                )
+                .with_file(input),
+            );
+        }
+
+        for (i, codes) in extractor.segment_inline_code().iter().enumerate() {
+            if codes.is_empty() || unmask_inline_code(&translations[i], codes).is_some() {
+                continue;
+            }
+            warn!(
+                "Inline code placeholders lost in segment '{}', translating around the code",
+                extractor.text_segments[i]
+            );
+            self.diagnostics.push(
+                Diagnostic::new(
+                    Severity::Info,
+                    "inline code placeholders lost, translated around the code",
+                )
+                .with_file(input)
+                .with_location(format!("segment {}", i)),
+            );
+
+            let pieces: Vec<&str> = split_masked(&extractor.text_segments[i], codes.len());
+            let to_translate: Vec<String> = pieces
+                .iter()
+                .filter(|p| !p.trim().is_empty())
+                .map(|p| p.trim().to_string())
+                .collect();
+            let contexts = vec![extractor.segment_contexts[i].clone(); to_translate.len()];
+            // The pieces are not segments of their own: no progress events
+            let quiet = Self {
+                progress: ProgressFn::default(),
+                ..self.clone()
+            };
+            let (translated_pieces, piece_errors) = quiet
+                .translate_segments(
+                    input,
+                    &to_translate,
+                    &contexts,
+                    target_lang,
+                    source_lang.clone(),
+                    &HashMap::new(),
+                )
+                .await?;
+            errors[i] = piece_errors.into_iter().flatten().next();
+            let mut translated_pieces = translated_pieces.into_iter();
 
-            match self.translator.translate(&request).await {
-                Ok(result) => {
-                    translated_segments.push(result.translation);
+            let mut rebuilt = String::new();
+            for (n, piece) in pieces.iter().enumerate() {
+                if n > 0 {
+                    rebuilt.push_str(&inline_code_placeholder(n - 1));
                 }
-                Err(e) => {
-                    warn!("Translation failed for segment '{}': {}", segment, e);
-                    // Keep original text if translation fails
-                    translated_segments.push(segment.clone());
+                if piece.trim().is_empty() {
+                    rebuilt.push_str(piece);
+                } else if let Some(translated) = translated_pieces.next() {
+                    push_with_edges(&mut rebuilt, piece, &translated);
                 }
             }
+            translations[i] = rebuilt;
         }
 
-        // Extract elements before translation to avoid borrow issues
-        let elements = extractor.elements.clone();
-        let link_texts: Vec<String> = elements
-            .iter()
-            .filter_map(|e| {
-                if let MarkdownElement::Link(start, end) = e {
-                    Some(extractor.get_link_text(*start, *end))
-                } else {
-                    None
-                }
+        let outcomes = errors
+            .into_iter()
+            .enumerate()
+            .map(|(index, error)| SegmentOutcome {
+                index,
+                field: None,
+                status: match error {
+                    Some(_) => SegmentStatus::Failed,
+                    None if index < sampled => SegmentStatus::Translated,
+                    None => SegmentStatus::Skipped,
+                },
+                error,
             })
             .collect();
+        Ok((translations, outcomes))
+    }
 
-        // Reconstruct content
-        let mut result = String::new();
-        let mut text_idx = 0;
-        let mut char_idx = 0;
-        let mut link_idx = 0;
+    /// Translate text segments, keeping the original of any segment that fails
+    /// and returning its error next to the translations.
+    ///
+    /// Segments found in `reuse` take that translation and are not sent, but
+    /// count as done in the [`Progress::Segment`] events. With
+    /// batching, consecutive segments share a request (and the context of the
+    /// first). Only reaching the cost cap aborts: the run has to stop, not keep
+    /// going with untranslated text.
+    async fn translate_segments(
+        &self,
+        input: &Path,
+        segments: &[String],
+        contexts: &[Option<String>],
+        target_lang: &str,
+        source_lang: Option<String>,
+        reuse: &HashMap<String, String>,
+    ) -> Result<(Vec<String>, Vec<Option<String>>)> {
+        let mut translated_segments = segments.to_vec();
+        let mut errors = vec![None; segments.len()];
+        let mut pending = Vec::with_capacity(segments.len());
+        for (i, segment) in segments.iter().enumerate() {
+            match reuse.get(segment) {
+                Some(previous) => translated_segments[i] = previous.clone(),
+                None => pending.push(i),
+            }
+        }
 
-        for element in &elements {
-            match element {
-                MarkdownElement::Text(_start, end) => {
-                    if text_idx < translated_segments.len() {
-                        result.push_str(&translated_segments[text_idx]);
-                        text_idx += 1;
-                    }
-                    char_idx = *end;
-                }
-                MarkdownElement::CodeBlock(start, end) | MarkdownElement::InlineCode(start, end) => {
-                    result.push_str(&content[*start..*end]);
-                    char_idx = *end;
-                }
-                MarkdownElement::Link(start, end) => {
-                    // Keep URL, translate text
-                    let link_text = if link_idx < link_texts.len() {
-                        &link_texts[link_idx]
-                    } else {
-                        ""
-                    };
-                    link_idx += 1;
-
-                    if !link_text.is_empty() && text_idx < translated_segments.len() {
-                        // Replace link text with translation
-                        let original_text = &content[*start..*end];
-                        let translated = &translated_segments[text_idx];
-                        let new_link = original_text.replace(link_text, translated);
-                        result.push_str(&new_link);
-                        text_idx += 1;
-                    } else {
-                        result.push_str(&content[*start..*end]);
+        let texts: Vec<&str> = segments.iter().map(String::as_str).collect();
+        let batcher = Batcher {
+            translator: &self.translator,
+            diagnostics: &self.diagnostics,
+            input,
+            unit: "segment",
+        };
+        let batches = if self.batching {
+            group_pending(&texts, &pending, self.translator.config().max_input_tokens)
+        } else {
+            pending.iter().map(|&i| vec![i]).collect()
+        };
+        let sizes: Vec<usize> = batches
+            .iter()
+            .map(|batch| batch.iter().map(|&i| segments[i].len()).sum())
+            .collect();
+        // Reused segments are done from the start
+        let total = segments.len();
+        let mut current = total - pending.len();
+        let mut bytes = segments.iter().map(String::len).sum::<usize>();
+        bytes -= sizes.iter().sum::<usize>();
+
+        for b in self.schedule.dispatch_order(&sizes) {
+            let batch = &batches[b];
+            current += batch.len();
+            bytes += sizes[b];
+            if batch.len() > 1 {
+                let context = contexts.get(batch[0]).cloned().flatten();
+                let translated = batcher
+                    .translate_batch(&texts, batch, context, target_lang, source_lang.as_deref())
+                    .await?;
+                if let Some(translated) = translated {
+                    for (&i, translation) in batch.iter().zip(translated) {
+                        translated_segments[i] = translation;
                     }
-                    char_idx = *end;
+                    self.progress.emit(Progress::Segment { current, total, bytes });
+                    continue;
                 }
-                MarkdownElement::YamlFrontmatter(start, end) => {
-                    let yaml_content = &content[*start..*end];
-                    let translated_yaml = self
-                        .translate_yaml_frontmatter(yaml_content, target_lang, source_lang.clone())
-                        .await?;
-                    result.push_str(&translated_yaml);
-                    char_idx = *end;
+            }
+            for &i in batch {
+                let context = contexts.get(i).cloned().flatten();
+                let source_lang = source_lang.clone();
+                let translated = self
+                    .translate_segment(input, i, &segments[i], context, target_lang, source_lang)
+                    .await?;
+                match translated {
+                    Ok(translation) => translated_segments[i] = translation,
+                    Err(e) => errors[i] = Some(e.to_string()),
                 }
             }
+            self.progress.emit(Progress::Segment { current, total, bytes });
         }
+        Ok((translated_segments, errors))
+    }
 
-        // Append any remaining text
-        if char_idx < content.len() {
-            result.push_str(&content[char_idx..]);
-        }
+    /// Translate segment `i` on its own, with `context` (its document and
+    /// section); the inner error (original kept) if it fails
+    async fn translate_segment(
+        &self,
+        input: &Path,
+        i: usize,
+        segment: &str,
+        context: Option<String>,
+        target_lang: &str,
+        source_lang: Option<String>,
+    ) -> Result<std::result::Result<String, TranslationError>> {
+        let mut request = TranslationRequest::new(segment.to_string(), target_lang.to_string())
+            .with_source_lang(source_lang.unwrap_or_else(|| "auto".to_string()));
+        request.context = context;
 
-        Ok(result)
+        match self.translator.translate(&request).await {
+            Ok(result) => Ok(Ok(result.translation)),
+            Err(e @ TranslationError::CostLimitExceeded { .. }) => Err(e),
+            Err(e) => {
+                // Keep original text if translation fails
+                warn!("Translation failed for segment '{}': {}", segment, e);
+                self.diagnostics.push(
+                    Diagnostic::new(
+                        Severity::Warning,
+                        format!("segment left untranslated: {}", e),
+                    )
+                    .with_file(input)
+                    .with_location(format!("segment {}", i)),
+                );
+                self.fixups.push_failed(input, i, segment, &e.to_string());
+                Ok(Err(e))
+            }
+        }
     }
 
-    /// Translate YAML frontmatter
+    /// Translate a YAML frontmatter block (`---` lines included) of `input`;
+    /// an empty block is returned unchanged. A string whose request fails
+    /// keeps its original text, except when the cost limit is reached.
+    /// Returns the block and what happened to each string.
     async fn translate_yaml_frontmatter(
         &self,
-        yaml_content: &str,
+        input: &Path,
+        block: &str,
         target_lang: &str,
         source_lang: Option<String>,
-    ) -> Result<String> {
-        // Parse YAML
-        let yaml: HashMap<String, serde_yaml::Value> = serde_yaml::from_str(yaml_content)
+    ) -> Result<(String, Vec<SegmentOutcome>)> {
+        let yaml_content = frontmatter_yaml(block);
+        if yaml_content.trim().is_empty() {
+            return Ok((block.to_string(), Vec::new()));
+        }
+
+        // Parse YAML, keeping the key order
+        let mut translated: serde_yaml::Mapping = serde_yaml::from_str(yaml_content)
             .map_err(|e| TranslationError::InvalidFormat {
                 format: format!("YAML: {}", e),
             })?;
 
-        let mut translated = HashMap::new();
-
-        for (key, value) in yaml {
-            // Fields to translate
-            let fields_to_translate = ["title", "description", "summary", "name", "alt"];
+        // Fields to translate, with every string nested in them; other fields
+        // are kept unchanged
+        let mut outcomes = Vec::new();
+        for field in &self.frontmatter_fields {
+            let mut texts = Vec::new();
+            if let Some(value) = yaml_field(&mut translated, field) {
+                yaml_strings(value, &mut texts);
+            }
 
-            if fields_to_translate.contains(&key.as_str()) {
-                if let serde_yaml::Value::String(text) = value {
-                    let request = TranslationRequest::new(text.clone(), target_lang.to_string())
-                        .with_source_lang(source_lang.clone().unwrap_or_else(|| "auto".to_string()));
+            for text in texts {
+                let request = TranslationRequest::new(text.clone(), target_lang.to_string())
+                    .with_source_lang(source_lang.clone().unwrap_or_else(|| "auto".to_string()));
 
-                    if let Ok(result) = self.translator.translate(&request).await {
-                        translated.insert(key, serde_yaml::Value::String(result.translation));
-                    } else {
-                        translated.insert(key, serde_yaml::Value::String(text));
+                let mut outcome = SegmentOutcome {
+                    index: outcomes.len(),
+                    field: Some(field.clone()),
+                    status: SegmentStatus::Translated,
+                    error: None,
+                };
+                match self.translator.translate(&request).await {
+                    Ok(result) => *text = result.translation,
+                    Err(e @ TranslationError::CostLimitExceeded { .. }) => return Err(e),
+                    Err(e) => {
+                        outcome.status = SegmentStatus::Failed;
+                        outcome.error = Some(e.to_string());
+                        warn!("Translation failed for frontmatter field {}: {}", field, e);
+                        self.diagnostics.push(
+                            Diagnostic::new(
+                                Severity::Warning,
+                                format!("frontmatter field left untranslated: {}", e),
+                            )
+                            .with_file(input)
+                            .with_location(format!("frontmatter {}", field)),
+                        );
                     }
-                } else {
-                    translated.insert(key, value);
                 }
-            } else {
-                // Keep other fields unchanged
-                translated.insert(key, value);
+                outcomes.push(outcome);
             }
         }
 
@@ -272,155 +1004,1073 @@ impl MarkdownProcessor {
                 format: format!("YAML serialization: {}", e),
             })?;
 
-        Ok(format!("---\n{}---\n", result))
+        // Keep the source file's BOM and line endings
+        let bom = if block.starts_with(BOM) { "\u{feff}" } else { "" };
+        let frontmatter = format!("{}---\n{}---\n", bom, result);
+        let frontmatter = if block.contains("\r\n") {
+            frontmatter.replace('\n', "\r\n")
+        } else {
+            frontmatter
+        };
+        Ok((frontmatter, outcomes))
     }
 }
 
+/// Read a Markdown file
+async fn read_file(input: &Path) -> Result<String> {
+    tokio::fs::read_to_string(input)
+        .await
+        .map_err(|e| TranslationError::FileError {
+            path: input.display().to_string(),
+            message: e.to_string(),
+        })
+}
+
+/// Write translated content, creating the output directory if needed
+async fn write_file(output: &Path, content: &str) -> Result<()> {
+    // Ensure output directory exists
+    if let Some(parent) = output.parent() {
+        if !parent.as_os_str().is_empty() && !parent.exists() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| TranslationError::FileError {
+                    path: parent.display().to_string(),
+                    message: e.to_string(),
+                })?;
+        }
+    }
+
+    // Write translated content
+    tokio::fs::write(output, content)
+        .await
+        .map_err(|e| TranslationError::FileError {
+            path: output.display().to_string(),
+            message: e.to_string(),
+        })
+}
+
 /// Markdown element types
-#[derive(Debug, Clone, Copy)]
+///
+/// Elements are contiguous byte ranges; together they cover the whole
+/// document so that reconstruction never drops or duplicates source bytes.
+#[derive(Debug, Clone)]
 enum MarkdownElement {
+    /// Translatable inline text (paragraph, heading, table cell, link text, ...)
     Text(usize, usize),
-    CodeBlock(usize, usize),
-    InlineCode(usize, usize),
-    Link(usize, usize),
+    /// Text interleaved with inline code or math, translated as one segment
+    /// with the code spans (byte ranges) replaced by placeholders
+    Masked(usize, usize, Vec<(usize, usize)>),
+    /// Text node of an HTML block (`<details>`/`<summary>`), entities escaped;
+    /// translated unescaped and escaped again
+    HtmlText(usize, usize),
+    /// Title of an inline link or image (`[text](url "Title")`), without its
+    /// quotes; quotes of the same kind in the translation are escaped
+    LinkTitle(usize, usize),
+    /// Alt text of an image (`![alt](path.png)`), between its brackets;
+    /// brackets in the translation are escaped, the path is never touched
+    ImageAlt(usize, usize),
+    /// Collapsed (`[text][]`) or shortcut (`[text]`) reference link, whose
+    /// text is also its label: written back as `[translation][text]` so it
+    /// still resolves to its definition
+    RefLink(usize, usize),
+    /// YAML frontmatter block (`---` lines included), translated on its own
     YamlFrontmatter(usize, usize),
+    /// Copied verbatim: markup, code, HTML, URLs and whitespace
+    Raw(usize, usize),
+}
+
+/// Inline content being collected into one segment
+#[derive(Debug)]
+struct TextRun {
+    /// Byte offset of the first inline content
+    start: usize,
+    /// Byte offset just past the last inline content
+    end: usize,
+    /// Inline code and math spans inside the run
+    codes: Vec<(usize, usize)>,
+    /// Whether the run contains non-whitespace text besides inline code
+    has_text: bool,
+    /// Whether the run is a text node of an HTML block
+    html: bool,
+    /// Whether the run is the title of a link or image
+    title: bool,
+    /// Whether the run is the alt text of an image
+    alt: bool,
+    /// Whether the run is a whole collapsed or shortcut reference link
+    reference: bool,
 }
 
 /// Markdown extractor for parsing content
+///
+/// Built on the `pulldown-cmark` event stream: only inline text is
+/// translated, everything else is copied from the source byte for byte.
 struct MarkdownExtractor<'a> {
+    /// Document being extracted
     content: &'a str,
+    /// The document as a sequence of elements, in source order
     elements: Vec<MarkdownElement>,
+    /// Texts to translate, in document order
     text_segments: Vec<String>,
+    /// For each text segment, the document title and section it is in
+    segment_contexts: Vec<Option<String>>,
+    /// Translate text and inline code as one masked segment
+    mask_inline_code: bool,
+    /// Comment texts that keep the next block untranslated
+    skip_directives: Vec<String>,
 }
 
 impl<'a> MarkdownExtractor<'a> {
+    /// Extractor for `content` with inline code masking and the default skip
+    /// directives; nothing is extracted until [`extract`](Self::extract)
     fn new(content: &'a str) -> Self {
         Self {
             content,
             elements: Vec::new(),
             text_segments: Vec::new(),
+            segment_contexts: Vec::new(),
+            mask_inline_code: true,
+            skip_directives: default_skip_directives(),
         }
     }
 
+    /// Enable or disable inline code masking (enabled by default)
+    fn with_inline_code_masking(mut self, mask: bool) -> Self {
+        self.mask_inline_code = mask;
+        self
+    }
+
+    /// Replace the skip directives (see [`MarkdownProcessor::with_skip_directives`])
+    fn with_skip_directives(mut self, directives: Vec<String>) -> Self {
+        self.skip_directives = directives;
+        self
+    }
+
+    /// Whether the HTML `block` is a comment holding a skip directive
+    fn is_skip_directive(&self, block: &str) -> bool {
+        let comment = block
+            .trim()
+            .strip_prefix("<!--")
+            .and_then(|rest| rest.strip_suffix("-->"));
+        comment.is_some_and(|text| self.skip_directives.iter().any(|d| d == text.trim()))
+    }
+
+    /// Split the document into elements and collect its text segments
     fn extract(&mut self) {
-        let mut pos = 0;
-        let chars: Vec<char> = self.content.chars().collect();
-
-        // Check for YAML frontmatter
-        if self.content.starts_with("---\n") {
-            if let Some(end) = self.content.find("\n---\n") {
-                let yaml_end = end + 5;
-                self.elements
-                    .push(MarkdownElement::YamlFrontmatter(0, yaml_end));
-                pos = yaml_end;
-            }
-        }
-
-        while pos < chars.len() {
-            // Code block
-            if pos + 2 < chars.len() && chars[pos] == '`' && chars[pos + 1] == '`' && chars[pos + 2] == '`' {
-                if let Some(end) = self.content[pos..].find("```") {
-                    let end_pos = pos + end + 3;
-                    self.elements
-                        .push(MarkdownElement::CodeBlock(pos, end_pos));
-                    pos = end_pos;
-                    continue;
-                }
-            }
+        let body_start = body_start(self.content);
 
-            // Inline code
-            if chars[pos] == '`' {
-                if let Some(end) = self.content[pos + 1..].find('`') {
-                    let end_pos = pos + end + 2;
-                    self.elements
-                        .push(MarkdownElement::InlineCode(pos, end_pos));
-                    pos = end_pos;
-                    continue;
-                }
-            }
+        // Check for YAML frontmatter (after the byte order mark, if any)
+        if frontmatter_len(self.content).is_some() {
+            self.elements
+                .push(MarkdownElement::YamlFrontmatter(0, body_start));
+        } else if body_start > 0 {
+            self.elements.push(MarkdownElement::Raw(0, body_start));
+        }
 
-            // Links
-            if chars[pos] == '[' {
-                if let Some(link_end) = self.content[pos..].find(']') {
-                    let bracket_end = pos + link_end + 1;
-                    if bracket_end < chars.len() && chars[bracket_end] == '(' {
-                        if let Some(url_end) = self.content[bracket_end..].find(')') {
-                            let link_end_pos = bracket_end + url_end + 1;
-                            self.elements
-                                .push(MarkdownElement::Link(pos, link_end_pos));
-
-                            // Extract link text for translation
-                            let text_start = pos + 1;
-                            let text_end = bracket_end - 1;
-                            if text_end > text_start {
-                                let text = self.content[text_start..text_end].trim().to_string();
-                                if !text.is_empty() {
-                                    self.text_segments.push(text);
-                                }
-                            }
+        let runs = self.text_runs(body_start);
+        let headings = self.headings(body_start);
+        let title = self.document_title(&headings);
 
-                            pos = link_end_pos;
-                            continue;
-                        }
-                    }
-                }
+        // Everything between runs is copied verbatim
+        let mut pos = body_start;
+        for run in runs {
+            if run.start > pos {
+                self.elements.push(MarkdownElement::Raw(pos, run.start));
             }
+            // Section of the run: the last heading that ends before it
+            let section = headings
+                .iter()
+                .take_while(|(end, _)| *end <= run.start)
+                .last()
+                .map(|(_, text)| text.as_str());
+            let context = match (title.as_deref(), section) {
+                (Some(title), Some(section)) if title != section => {
+                    Some(format!("{} > {}", title, section))
+                }
+                (title, section) => title.or(section).map(str::to_string),
+            };
 
-            // Regular text - find next special element
-            let mut text_end = pos;
-            let mut has_special = false;
+            let first = self.elements.len();
+            self.push_run(run);
+            let segments = self.elements[first..]
+                .iter()
+                .filter(|e| !matches!(e, MarkdownElement::Raw(..)))
+                .count();
+            self.segment_contexts
+                .extend(std::iter::repeat_n(context, segments));
+            pos = self.elements.last().map_or(pos, |e| element_range(e).1);
+        }
+        if pos < self.content.len() {
+            self.elements.push(MarkdownElement::Raw(pos, self.content.len()));
+        }
 
-            for i in pos..chars.len() {
-                if (i + 2 < chars.len()
-                    && chars[i] == '`'
-                    && chars[i + 1] == '`'
-                    && chars[i + 2] == '`')
-                    || chars[i] == '`'
-                    || chars[i] == '['
-                {
-                    text_end = i;
-                    has_special = true;
-                    break;
+        // Collect translatable segments in document order
+        let content = self.content;
+        self.text_segments = self
+            .elements
+            .iter()
+            .filter_map(|element| match element {
+                MarkdownElement::Text(start, end)
+                | MarkdownElement::LinkTitle(start, end)
+                | MarkdownElement::ImageAlt(start, end) => {
+                    Some(content[*start..*end].trim().to_string())
                 }
-            }
-
-            if !has_special {
-                text_end = chars.len();
-            }
+                MarkdownElement::Masked(start, end, codes) => {
+                    Some(mask_code_ranges(content, *start, *end, codes).trim().to_string())
+                }
+                MarkdownElement::RefLink(start, end) => {
+                    let label = self.reference_label(*start, *end);
+                    Some(label.map_or("", |(s, e)| &content[s..e]).trim().to_string())
+                }
+                MarkdownElement::HtmlText(start, end) => {
+                    let text = content[*start..*end].trim();
+                    Some(unescape(text).map_or_else(|_| text.to_string(), |t| t.into_owned()))
+                }
+                _ => None,
+            })
+            .collect();
+    }
 
-            if text_end > pos {
-                let text = self.content[pos..text_end].trim();
-                if !text.is_empty() {
-                    self.elements
-                        .push(MarkdownElement::Text(pos, text_end));
-                    self.text_segments.push(text.to_string());
+    /// Headings of the body starting at `body_start`: where each ends and its text
+    fn headings(&self, body_start: usize) -> Vec<(usize, String)> {
+        let body = &self.content[body_start..];
+        let mut headings = Vec::new();
+        let mut heading: Option<String> = None;
+        for (event, range) in Parser::new_ext(body, markdown_options()).into_offset_iter() {
+            match event {
+                Event::Start(Tag::Heading { .. }) => heading = Some(String::new()),
+                Event::Text(text) | Event::Code(text) | Event::InlineMath(text) => {
+                    if let Some(heading) = heading.as_mut() {
+                        heading.push_str(&text);
+                    }
                 }
-                pos = text_end;
-            } else {
-                pos += 1;
+                Event::End(TagEnd::Heading(_)) => {
+                    if let Some(text) = heading.take().filter(|t| !t.trim().is_empty()) {
+                        headings.push((body_start + range.end, text.trim().to_string()));
+                    }
+                }
+                _ => {}
             }
         }
+        headings
     }
 
-    fn get_link_text(&self, start: usize, end: usize) -> String {
-        let content = &self.content[start..end];
-        if let Some(bracket_end) = content.find(']') {
-            if bracket_end > 1 {
-                return content[1..bracket_end].to_string();
-            }
-        }
-        String::new()
+    /// Byte ranges of the table cells of the body starting at `body_start`
+    fn table_cells(&self, body_start: usize) -> Vec<(usize, usize)> {
+        let body = &self.content[body_start..];
+        Parser::new_ext(body, markdown_options())
+            .into_offset_iter()
+            .filter(|(event, _)| matches!(event, Event::Start(Tag::TableCell)))
+            .map(|(_, range)| (body_start + range.start, body_start + range.end))
+            .collect()
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Title of the document: the frontmatter `title`, else the first heading
+    fn document_title(&self, headings: &[(usize, String)]) -> Option<String> {
+        let frontmatter_title = self.frontmatter().and_then(|(start, end)| {
+            let yaml = frontmatter_yaml(&self.content[start..end]);
+            let yaml: serde_yaml::Value = serde_yaml::from_str(yaml).ok()?;
+            yaml.get("title")?.as_str().map(str::to_string)
+        });
+        frontmatter_title.or_else(|| headings.first().map(|(_, text)| text.clone()))
+    }
 
-    #[test]
-    fn test_markdown_extractor() {
-        let content = r#"---
-title: "Test"
+    /// Inline text runs of the document body starting at `body_start`, in order.
+    ///
+    /// A run ends at every block boundary, link or image boundary, hard break and HTML,
+    /// and at a line break that continues behind container markup (`> `).
+    /// HTML blocks of collapsible sections (`<details>`/`<summary>`) add one
+    /// run per text node; other HTML is never translated. The block after a
+    /// skip directive comment is never translated either.
+    fn text_runs(&self, body_start: usize) -> Vec<TextRun> {
+        let body = &self.content[body_start..];
+        let mut runs = Vec::new();
+        let mut run: Option<TextRun> = None;
+        // Nesting depth of elements whose text is never translated
+        let mut opaque = 0usize;
+        // For each open link, whether it is opaque (autolinks show their URL)
+        let mut links: Vec<bool> = Vec::new();
+        // For each open link or image, the byte range of its title
+        let mut titles: Vec<Option<(usize, usize)>> = Vec::new();
+        // Nesting depth of open images, whose text is alt text
+        let mut images = 0usize;
+        // Nesting depth of all open elements
+        let mut depth = 0usize;
+        // A skip directive was seen and the next block is not open yet
+        let mut skip_next = false;
+        // Depth at which the skipped block was opened
+        let mut skipped: Option<usize> = None;
+
+        let flush = |run: &mut Option<TextRun>, runs: &mut Vec<TextRun>| {
+            if let Some(run) = run.take() {
+                if run.has_text {
+                    runs.push(run);
+                }
+            }
+        };
+
+        // The title comes last in the source, after the text of its link
+        let title_run = |title: Option<(usize, usize)>, opaque: usize, runs: &mut Vec<TextRun>| {
+            if let Some((start, end)) = title.filter(|_| opaque == 0) {
+                runs.push(TextRun {
+                    start,
+                    end,
+                    codes: Vec::new(),
+                    has_text: true,
+                    html: false,
+                    title: true,
+                    alt: false,
+                    reference: false,
+                });
+            }
+        };
+
+        for (event, range) in Parser::new_ext(body, markdown_options()).into_offset_iter() {
+            let (start, end) = (body_start + range.start, body_start + range.end);
+            match &event {
+                Event::Start(Tag::HtmlBlock) => {}
+                Event::Start(_) => {
+                    if std::mem::take(&mut skip_next) {
+                        flush(&mut run, &mut runs);
+                        opaque += 1;
+                        skipped = Some(depth);
+                    }
+                    depth += 1;
+                }
+                Event::End(TagEnd::HtmlBlock) => {}
+                Event::End(_) => {
+                    depth = depth.saturating_sub(1);
+                    if skipped == Some(depth) {
+                        skipped = None;
+                        opaque = opaque.saturating_sub(1);
+                    }
+                }
+                _ => {}
+            }
+            match event {
+                Event::Start(Tag::Image { link_type, ref title, .. }) => {
+                    flush(&mut run, &mut runs);
+                    titles.push(self.title_range(start, end, link_type, title));
+                    images += 1;
+                }
+                Event::End(TagEnd::Image) => {
+                    flush(&mut run, &mut runs);
+                    images = images.saturating_sub(1);
+                    title_run(titles.pop().flatten(), opaque, &mut runs);
+                }
+                Event::Start(Tag::CodeBlock(_) | Tag::MetadataBlock(_)) => {
+                    flush(&mut run, &mut runs);
+                    opaque += 1;
+                }
+                Event::End(TagEnd::CodeBlock | TagEnd::MetadataBlock(_)) => {
+                    opaque = opaque.saturating_sub(1);
+                }
+                Event::Start(Tag::HtmlBlock) => {
+                    flush(&mut run, &mut runs);
+                    opaque += 1;
+                    let block = &self.content[start..end];
+                    if self.is_skip_directive(block) {
+                        skip_next = true;
+                    } else if is_collapsible_html(block) {
+                        runs.extend(text_node_ranges(block).into_iter().map(|(s, e)| TextRun {
+                            start: start + s,
+                            end: start + e,
+                            codes: Vec::new(),
+                            has_text: true,
+                            html: true,
+                            title: false,
+                            alt: false,
+                            reference: false,
+                        }));
+                    }
+                }
+                Event::End(TagEnd::HtmlBlock) => {
+                    opaque = opaque.saturating_sub(1);
+                }
+                Event::Start(Tag::Link { link_type, ref title, .. }) => {
+                    flush(&mut run, &mut runs);
+                    let autolink = matches!(link_type, LinkType::Autolink | LinkType::Email);
+                    // The text of `[text][]` and `[text]` is the label: one segment
+                    let reference = matches!(link_type, LinkType::Collapsed | LinkType::Shortcut);
+                    if reference && opaque == 0 && self.reference_label(start, end).is_some() {
+                        // The range of `[text][]` stops before its `[]`
+                        let collapsed = link_type == LinkType::Collapsed
+                            && self.content[end..].starts_with("[]");
+                        runs.push(TextRun {
+                            start,
+                            end: if collapsed { end + 2 } else { end },
+                            codes: Vec::new(),
+                            has_text: true,
+                            html: false,
+                            title: false,
+                            alt: false,
+                            reference: true,
+                        });
+                    }
+                    links.push(autolink || reference);
+                    titles.push(self.title_range(start, end, link_type, title));
+                    if autolink || reference {
+                        opaque += 1;
+                    }
+                }
+                Event::End(TagEnd::Link) => {
+                    flush(&mut run, &mut runs);
+                    if links.pop() == Some(true) {
+                        opaque = opaque.saturating_sub(1);
+                    }
+                    title_run(titles.pop().flatten(), opaque, &mut runs);
+                }
+                _ if opaque > 0 => {}
+                Event::Text(_) => {
+                    let run = self.continue_run(&mut run, &mut runs, start);
+                    run.alt = images > 0;
+                    run.end = end;
+                    run.has_text |= !self.content[start..end].trim().is_empty();
+                }
+                // Math is masked like inline code: `$...$` and `$$...$$` need a
+                // closing delimiter, so a lone `$` (currency) stays text
+                Event::Code(_) | Event::InlineMath(_) | Event::DisplayMath(_)
+                    if self.mask_inline_code =>
+                {
+                    let run = self.continue_run(&mut run, &mut runs, start);
+                    run.alt = images > 0;
+                    run.end = end;
+                    run.codes.push((start, end));
+                }
+                // Emphasis markup stays inside the segment, around its text
+                Event::Start(
+                    Tag::Emphasis
+                    | Tag::Strong
+                    | Tag::Strikethrough
+                    | Tag::Superscript
+                    | Tag::Subscript,
+                ) => {
+                    self.continue_run(&mut run, &mut runs, start);
+                }
+                Event::End(
+                    TagEnd::Emphasis
+                    | TagEnd::Strong
+                    | TagEnd::Strikethrough
+                    | TagEnd::Superscript
+                    | TagEnd::Subscript,
+                ) => {
+                    if let Some(run) = run.as_mut() {
+                        run.end = run.end.max(end);
+                    }
+                }
+                // Decided when the next inline content arrives
+                Event::SoftBreak => {}
+                _ => flush(&mut run, &mut runs),
+            }
+        }
+        flush(&mut run, &mut runs);
+
+        runs
+    }
+
+    /// Byte range of the title of the inline link or image at `start..end`,
+    /// without its delimiters; `None` without a title or for reference links,
+    /// whose title sits in the definition
+    fn title_range(
+        &self,
+        start: usize,
+        end: usize,
+        link_type: LinkType,
+        title: &str,
+    ) -> Option<(usize, usize)> {
+        if link_type != LinkType::Inline || title.trim().is_empty() {
+            return None;
+        }
+        let source = self.content[start..end].strip_suffix(')')?.trim_end();
+        let close = source.chars().last()?;
+        let open = match close {
+            '"' | '\'' => close,
+            ')' => '(',
+            _ => return None,
+        };
+        let inner = &source[..source.len() - close.len_utf8()];
+        // The nearest unescaped opening delimiter
+        let open_at = inner
+            .char_indices()
+            .rev()
+            .find(|&(i, c)| c == open && !inner[..i].ends_with('\\'))?
+            .0;
+        let (title_start, title_end) = (open_at + open.len_utf8(), inner.len());
+        (title_start < title_end).then_some((start + title_start, start + title_end))
+    }
+
+    /// Byte range of the label of the collapsed or shortcut reference link
+    /// at `start..end`; `None` if it is empty or holds nested brackets
+    fn reference_label(&self, start: usize, end: usize) -> Option<(usize, usize)> {
+        let inner = self.content[start..end].strip_prefix('[')?;
+        let close = inner.find(']')?;
+        let label = &inner[..close];
+        if label.trim().is_empty() || label.contains('[') {
+            return None;
+        }
+        Some((start + 1, start + 1 + close))
+    }
+
+    /// The run that inline content at `start` belongs to, starting a new one
+    /// if there is none or the content continues behind container markup
+    fn continue_run<'r>(
+        &self,
+        run: &'r mut Option<TextRun>,
+        runs: &mut Vec<TextRun>,
+        start: usize,
+    ) -> &'r mut TextRun {
+        if let Some(current) = run.as_ref() {
+            let gap = &self.content[current.end..start];
+            let behind_markup = gap
+                .rfind('\n')
+                .is_some_and(|newline| !gap[newline + 1..].trim().is_empty());
+            if behind_markup {
+                if let Some(done) = run.take().filter(|r| r.has_text) {
+                    runs.push(done);
+                }
+            }
+        }
+        run.get_or_insert(TextRun {
+            start,
+            end: start,
+            codes: Vec::new(),
+            has_text: false,
+            html: false,
+            title: false,
+            alt: false,
+            reference: false,
+        })
+    }
+
+    /// Turn a finished run into elements
+    fn push_run(&mut self, run: TextRun) {
+        let text = &self.content[run.start..run.end];
+        if run.html {
+            self.elements.push(MarkdownElement::HtmlText(run.start, run.end));
+            return;
+        }
+        if run.title {
+            self.elements.push(MarkdownElement::LinkTitle(run.start, run.end));
+            return;
+        }
+        if run.reference {
+            self.elements.push(MarkdownElement::RefLink(run.start, run.end));
+            return;
+        }
+        if run.alt && run.codes.is_empty() {
+            self.elements.push(MarkdownElement::ImageAlt(run.start, run.end));
+            return;
+        }
+        if run.codes.is_empty() {
+            self.elements.push(MarkdownElement::Text(run.start, run.end));
+            return;
+        }
+        if !text.contains(PLACEHOLDER_OPEN) && !text.contains(PLACEHOLDER_CLOSE) {
+            self.elements
+                .push(MarkdownElement::Masked(run.start, run.end, run.codes));
+            return;
+        }
+
+        // Text that already contains placeholder brackets cannot be masked
+        // safely: translate the pieces around the code separately
+        let mut pos = run.start;
+        for (code_start, code_end) in run.codes {
+            if code_start > pos {
+                let piece = &self.content[pos..code_start];
+                self.elements.push(if piece.trim().is_empty() {
+                    MarkdownElement::Raw(pos, code_start)
+                } else {
+                    MarkdownElement::Text(pos, code_start)
+                });
+            }
+            self.elements.push(MarkdownElement::Raw(code_start, code_end));
+            pos = code_end;
+        }
+        if run.end > pos {
+            let piece = &self.content[pos..run.end];
+            self.elements.push(if piece.trim().is_empty() {
+                MarkdownElement::Raw(pos, run.end)
+            } else {
+                MarkdownElement::Text(pos, run.end)
+            });
+        }
+    }
+
+    /// Inline code spans of every segment (empty for segments without masking)
+    fn segment_inline_code(&self) -> Vec<Vec<&'a str>> {
+        let content = self.content;
+        self.elements
+            .iter()
+            .filter_map(|element| match element {
+                MarkdownElement::Text(..)
+                | MarkdownElement::HtmlText(..)
+                | MarkdownElement::LinkTitle(..)
+                | MarkdownElement::ImageAlt(..)
+                | MarkdownElement::RefLink(..) => Some(Vec::new()),
+                MarkdownElement::Masked(_, _, codes) => {
+                    Some(codes.iter().map(|&(s, e)| &content[s..e]).collect())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Byte range of the YAML frontmatter, if any
+    fn frontmatter(&self) -> Option<(usize, usize)> {
+        self.elements.iter().find_map(|e| match e {
+            MarkdownElement::YamlFrontmatter(start, end) => Some((*start, *end)),
+            _ => None,
+        })
+    }
+
+    /// Rebuild the document from `translations` (one per text segment).
+    ///
+    /// Body text and link text share one segment list in document order, and
+    /// each translatable element takes the next translation, so the two can
+    /// never trade places. Surrounding whitespace of each segment is kept from
+    /// the source. When `frontmatter` is `None` the original frontmatter is copied.
+    fn reconstruct(&self, translations: &[String], frontmatter: Option<&str>) -> String {
+        debug_assert_eq!(
+            translations.len(),
+            self.text_segments.len(),
+            "one translation per segment"
+        );
+        let content = self.content;
+        let mut result = String::with_capacity(content.len());
+        let mut segments = translations.iter();
+        // Translations in table cells must not hold a newline or an unescaped
+        // `|`, or they would break the row apart
+        let cells = self.table_cells(body_start(content));
+        let in_cell = |start: usize| cells.iter().any(|&(s, e)| s <= start && start < e);
+
+        for element in &self.elements {
+            match element {
+                MarkdownElement::Text(start, end) => {
+                    let text = &content[*start..*end];
+                    match segments.next() {
+                        Some(translated) if in_cell(*start) => {
+                            push_with_edges(&mut result, text, &escape_table_cell(translated))
+                        }
+                        Some(translated) => push_with_edges(&mut result, text, translated),
+                        None => result.push_str(text),
+                    }
+                }
+                MarkdownElement::Masked(start, end, codes) => {
+                    let text = &content[*start..*end];
+                    let codes: Vec<&str> = codes.iter().map(|&(s, e)| &content[s..e]).collect();
+                    // A translation that lost a placeholder falls back to the source
+                    match segments.next().and_then(|t| unmask_inline_code(t, &codes)) {
+                        Some(translated) if in_cell(*start) => {
+                            push_with_edges(&mut result, text, &escape_table_cell(&translated))
+                        }
+                        Some(translated) => push_with_edges(&mut result, text, &translated),
+                        None => result.push_str(text),
+                    }
+                }
+                MarkdownElement::HtmlText(start, end) => {
+                    let text = &content[*start..*end];
+                    match segments.next() {
+                        Some(translated) => {
+                            push_with_edges(&mut result, text, &partial_escape(translated))
+                        }
+                        None => result.push_str(text),
+                    }
+                }
+                MarkdownElement::LinkTitle(start, end) => {
+                    let text = &content[*start..*end];
+                    // The closing delimiter follows the title
+                    let delimiter = content[*end..].chars().next().unwrap_or('"');
+                    match segments.next() {
+                        Some(translated) => {
+                            let mut escaped = escape_title(translated, delimiter);
+                            if in_cell(*start) {
+                                escaped = escape_table_cell(&escaped);
+                            }
+                            push_with_edges(&mut result, text, &escaped)
+                        }
+                        None => result.push_str(text),
+                    }
+                }
+                MarkdownElement::ImageAlt(start, end) => {
+                    let text = &content[*start..*end];
+                    match segments.next() {
+                        Some(translated) => {
+                            let mut escaped = escape_link_text(translated);
+                            if in_cell(*start) {
+                                escaped = escape_table_cell(&escaped);
+                            }
+                            push_with_edges(&mut result, text, &escaped)
+                        }
+                        None => result.push_str(text),
+                    }
+                }
+                MarkdownElement::RefLink(start, end) => {
+                    let source = &content[*start..*end];
+                    let label = self
+                        .reference_label(*start, *end)
+                        .map_or("", |(s, e)| &content[s..e]);
+                    match segments.next() {
+                        // Keep the original label as the reference, so the
+                        // link still resolves to its definition
+                        Some(translated) if translated != label.trim() => {
+                            let mut text = escape_link_text(translated);
+                            if in_cell(*start) {
+                                text = escape_table_cell(&text);
+                            }
+                            result.push_str(&format!("[{}][{}]", text, label));
+                        }
+                        _ => result.push_str(source),
+                    }
+                }
+                MarkdownElement::YamlFrontmatter(start, end) => {
+                    result.push_str(frontmatter.unwrap_or(&content[*start..*end]));
+                }
+                MarkdownElement::Raw(start, end) => {
+                    result.push_str(&content[*start..*end]);
+                }
+            }
+        }
+
+        result
+    }
+}
+
+/// Reflow every paragraph of `document` to `width` display columns.
+///
+/// Only paragraph text is touched: code blocks, tables, headings, HTML,
+/// display math and frontmatter keep their lines. Quote and list markup before a paragraph is
+/// repeated (quotes) or turned into indentation (lists) on each new line, and
+/// hard breaks (trailing `  ` or `\`) stay line breaks.
+fn wrap_paragraphs(document: &str, width: usize) -> String {
+    let mut paragraphs = Vec::new();
+    let body_start = body_start(document);
+    let body = &document[body_start..];
+    for (event, range) in Parser::new_ext(body, markdown_options()).into_offset_iter() {
+        match event {
+            Event::Start(Tag::Paragraph) => {
+                paragraphs.push((body_start + range.start, body_start + range.end));
+            }
+            // Display math keeps its original line breaks
+            Event::DisplayMath(_) => {
+                let at = body_start + range.start;
+                paragraphs.retain(|&(start, end)| !(start <= at && at < end));
+            }
+            _ => {}
+        }
+    }
+
+    let mut result = String::with_capacity(document.len());
+    let mut last = 0;
+    for (start, end) in paragraphs {
+        let line_start = document[..start].rfind('\n').map_or(0, |i| i + 1);
+        let prefix = &document[line_start..start];
+        let continuation: String =
+            prefix.chars().map(|c| if c == '>' { '>' } else { ' ' }).collect();
+        let quoted = prefix.contains('>');
+
+        // Lines between hard breaks, with the break marker that ended each
+        let mut hard_lines: Vec<(String, &str)> = Vec::new();
+        let mut text = String::new();
+        for (i, line) in document[start..end].trim_end().split('\n').enumerate() {
+            let line = line.trim_end_matches('\r');
+            let line = if i == 0 {
+                line
+            } else if quoted {
+                line.trim_start_matches(['>', ' ', '\t'])
+            } else {
+                line.trim_start()
+            };
+            let marker = if line.ends_with('\\') {
+                "\\"
+            } else if line.ends_with("  ") {
+                "  "
+            } else {
+                ""
+            };
+            let line = line.trim_end_matches('\\').trim_end();
+            let joins_cjk = text.chars().last().is_some_and(is_wide)
+                && line.chars().next().is_some_and(is_wide);
+            if !text.is_empty() && !joins_cjk {
+                text.push(' ');
+            }
+            text.push_str(line);
+            if !marker.is_empty() {
+                hard_lines.push((std::mem::take(&mut text), marker));
+            }
+        }
+        if !text.is_empty() {
+            hard_lines.push((text, ""));
+        }
+
+        let indent = continuation.width();
+        let lines: Vec<String> = hard_lines
+            .iter()
+            .flat_map(|(text, marker)| {
+                let mut lines = wrap(text, width, indent);
+                if let Some(last) = lines.last_mut() {
+                    last.push_str(marker);
+                }
+                lines
+            })
+            .collect();
+
+        result.push_str(&document[last..start]);
+        let separator = format!("\n{}", continuation);
+        result.push_str(&lines.join(&separator));
+        last = start + document[start..end].trim_end().len();
+    }
+    result.push_str(&document[last..]);
+    result
+}
+
+/// Whether `relative` (a path under the search root) matches one of
+/// `exclude`. Patterns match the whole relative path; a pattern without `/`
+/// also matches the file or directory name at any depth, like `.gitignore`.
+pub fn is_excluded(relative: &Path, exclude: &[glob::Pattern]) -> bool {
+    let name = relative.file_name().map(Path::new);
+    exclude.iter().any(|pattern| {
+        pattern.matches_path(relative)
+            || (!pattern.as_str().contains('/') && name.is_some_and(|n| pattern.matches_path(n)))
+    })
+}
+
+/// [`DEFAULT_SKIP_DIRECTIVES`] as owned strings
+fn default_skip_directives() -> Vec<String> {
+    DEFAULT_SKIP_DIRECTIVES.iter().map(|d| d.to_string()).collect()
+}
+
+/// Length of the YAML frontmatter block at the start of `content`, if any
+fn frontmatter_len(content: &str) -> Option<usize> {
+    let bom = if content.starts_with(BOM) { BOM.len_utf8() } else { 0 };
+    let rest = &content[bom..];
+    let opening = ["---\n", "---\r\n"].into_iter().find(|o| rest.starts_with(o))?;
+    // The closing line may also end the file without a newline
+    let mut pos = opening.len();
+    for line in rest[pos..].split_inclusive('\n') {
+        pos += line.len();
+        if line.trim_end_matches(['\r', '\n']) == "---" {
+            return Some(bom + pos);
+        }
+    }
+    None
+}
+
+/// Value at the dotted `path` (e.g. `seo.excerpt`) of a frontmatter mapping
+fn yaml_field<'m>(
+    mapping: &'m mut serde_yaml::Mapping,
+    path: &str,
+) -> Option<&'m mut serde_yaml::Value> {
+    let mut keys = path.split('.');
+    let mut value = mapping.get_mut(keys.next()?)?;
+    for key in keys {
+        value = value.as_mapping_mut()?.get_mut(key)?;
+    }
+    Some(value)
+}
+
+/// Every string in `value`, including those nested in lists and mappings
+fn yaml_strings<'v>(value: &'v mut serde_yaml::Value, out: &mut Vec<&'v mut String>) {
+    match value {
+        serde_yaml::Value::String(text) => out.push(text),
+        serde_yaml::Value::Sequence(items) => {
+            for item in items {
+                yaml_strings(item, out);
+            }
+        }
+        serde_yaml::Value::Mapping(mapping) => {
+            for (_, item) in mapping.iter_mut() {
+                yaml_strings(item, out);
+            }
+        }
+        serde_yaml::Value::Tagged(tagged) => yaml_strings(&mut tagged.value, out),
+        _ => {}
+    }
+}
+
+/// Where the Markdown body starts: after the byte order mark and frontmatter
+fn body_start(content: &str) -> usize {
+    frontmatter_len(content).unwrap_or(if content.starts_with(BOM) { BOM.len_utf8() } else { 0 })
+}
+
+/// The YAML inside a frontmatter block, without its `---` lines
+fn frontmatter_yaml(block: &str) -> &str {
+    let block = block.strip_prefix(BOM).unwrap_or(block);
+    let yaml = block
+        .strip_prefix("---\r\n")
+        .or_else(|| block.strip_prefix("---\n"))
+        .unwrap_or(block);
+    yaml.strip_suffix("---\r\n")
+        .or_else(|| yaml.strip_suffix("---\n"))
+        .or_else(|| yaml.strip_suffix("---"))
+        .unwrap_or(yaml)
+}
+
+/// Markdown extensions recognised by the extractor
+fn markdown_options() -> Options {
+    Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_MATH
+}
+
+/// `title` with every unescaped `delimiter` (and `(` for parenthesized
+/// titles) escaped, so it cannot end the title early
+fn escape_title(title: &str, delimiter: char) -> String {
+    let mut escaped = String::with_capacity(title.len());
+    let mut backslash = false;
+    for c in title.chars() {
+        if !backslash && (c == delimiter || (delimiter == ')' && c == '(')) {
+            escaped.push('\\');
+        }
+        backslash = c == '\\' && !backslash;
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Image alt or link text with every unmatched, unescaped bracket escaped,
+/// so it cannot end the text early and swallow the path into the text
+/// (balanced brackets are valid alt text and kept as they are)
+fn escape_link_text(alt: &str) -> String {
+    // Byte offsets of the brackets that have no partner
+    let mut unmatched = Vec::new();
+    let mut open = Vec::new();
+    let mut backslash = false;
+    for (i, c) in alt.char_indices() {
+        match c {
+            '[' if !backslash => open.push(i),
+            ']' if !backslash && open.pop().is_none() => unmatched.push(i),
+            _ => {}
+        }
+        backslash = c == '\\' && !backslash;
+    }
+    unmatched.extend(open);
+
+    let mut escaped = String::with_capacity(alt.len() + unmatched.len());
+    for (i, c) in alt.char_indices() {
+        if unmatched.contains(&i) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Translated table cell text made safe for its row: line breaks become
+/// spaces and every unescaped `|` is escaped, so the column count is kept
+fn escape_table_cell(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let mut backslash = false;
+    for c in text.chars() {
+        match c {
+            '|' if !backslash => escaped.push_str("\\|"),
+            '\r' => {}
+            '\n' => escaped.push(' '),
+            _ => escaped.push(c),
+        }
+        // Like the table parser, only the character right before counts
+        backslash = c == '\\';
+    }
+    escaped
+}
+
+/// Byte range covered by `element`
+fn element_range(element: &MarkdownElement) -> (usize, usize) {
+    match *element {
+        MarkdownElement::Text(s, e)
+        | MarkdownElement::Masked(s, e, _)
+        | MarkdownElement::HtmlText(s, e)
+        | MarkdownElement::LinkTitle(s, e)
+        | MarkdownElement::ImageAlt(s, e)
+        | MarkdownElement::RefLink(s, e)
+        | MarkdownElement::YamlFrontmatter(s, e)
+        | MarkdownElement::Raw(s, e) => (s, e),
+    }
+}
+
+/// UTF-8 byte order mark some editors put at the start of a file
+const BOM: char = '\u{feff}';
+
+/// Opening bracket of an inline code placeholder
+const PLACEHOLDER_OPEN: char = '⟦';
+/// Closing bracket of an inline code placeholder
+const PLACEHOLDER_CLOSE: char = '⟧';
+
+/// Whether an HTML block belongs to a collapsible `<details>`/`<summary>` section
+fn is_collapsible_html(block: &str) -> bool {
+    let lower = block.to_ascii_lowercase();
+    lower.contains("<details") || lower.contains("<summary")
+}
+
+/// Placeholder standing in for the `index`-th inline code span of a segment
+fn inline_code_placeholder(index: usize) -> String {
+    format!("{}{}{}", PLACEHOLDER_OPEN, index, PLACEHOLDER_CLOSE)
+}
+
+/// `content[start..end]` with the inline code spans `codes` replaced by placeholders
+fn mask_code_ranges(content: &str, start: usize, end: usize, codes: &[(usize, usize)]) -> String {
+    let mut masked = String::with_capacity(end - start);
+    let mut pos = start;
+    for (i, &(code_start, code_end)) in codes.iter().enumerate() {
+        masked.push_str(&content[pos..code_start]);
+        masked.push_str(&inline_code_placeholder(i));
+        pos = code_end;
+    }
+    masked.push_str(&content[pos..end]);
+    masked
+}
+
+/// Put the inline code back into a translated segment.
+///
+/// Returns `None` unless every placeholder appears exactly once.
+fn unmask_inline_code(translated: &str, codes: &[&str]) -> Option<String> {
+    let mut restored = translated.to_string();
+    for (i, code) in codes.iter().enumerate() {
+        let placeholder = inline_code_placeholder(i);
+        if translated.matches(&placeholder).count() != 1 {
+            return None;
+        }
+        restored = restored.replacen(&placeholder, code, 1);
+    }
+    Some(restored)
+}
+
+/// Text pieces around the `count` placeholders of a masked segment
+fn split_masked(masked: &str, count: usize) -> Vec<&str> {
+    let mut pieces = Vec::with_capacity(count + 1);
+    let mut rest = masked;
+    for i in 0..count {
+        let placeholder = inline_code_placeholder(i);
+        match rest.find(&placeholder) {
+            Some(at) => {
+                pieces.push(&rest[..at]);
+                rest = &rest[at + placeholder.len()..];
+            }
+            None => break,
+        }
+    }
+    pieces.push(rest);
+    pieces
+}
+
+/// Push `translated` in place of `original`, keeping the original's
+/// leading and trailing whitespace
+fn push_with_edges(result: &mut String, original: &str, translated: &str) {
+    let core_start = original.len() - original.trim_start().len();
+    let core_end = original.trim_end().len().max(core_start);
+    result.push_str(&original[..core_start]);
+    result.push_str(translated);
+    result.push_str(&original[core_end..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_markdown_extractor() {
+        let content = r#"---
+title: "Test"
 description: "A test document"
 ---
 
@@ -444,6 +2094,1245 @@ Check out [this link](https://example.com) for more info."#;
         assert!(!extractor.text_segments.is_empty());
     }
 
+    /// Reconstruct `content` with every segment left untranslated
+    fn identity(content: &str) -> String {
+        let mut extractor = MarkdownExtractor::new(content);
+        extractor.extract();
+        extractor.reconstruct(&extractor.text_segments, None)
+    }
+
+    #[test]
+    fn test_extractor_covers_every_byte() {
+        let content = "---\ntitle: x\n---\n# Title\n\nA [link](u) and `code`.\n\n```\nfn x() {}\n```\n[ ](empty) lone [ and ` tick\n";
+
+        let mut extractor = MarkdownExtractor::new(content);
+        extractor.extract();
+
+        let mut expected_start = 0;
+        for element in &extractor.elements {
+            let (start, end) = element_range(element);
+            assert_eq!(start, expected_start, "gap or overlap before {:?}", element);
+            assert!(end > start, "empty element {:?}", element);
+            expected_start = end;
+        }
+        assert_eq!(expected_start, content.len());
+        assert_eq!(identity(content), content);
+    }
+
+    #[test]
+    fn test_identity_reconstruction_random_inputs() {
+        const PIECES: &[&str] = &[
+            "a", "word", "two words", " ", "  ", "\n", "\n\n", "`", "```", "[", "]", "(", ")",
+            "[x](y)", "---\n", "\n---\n", "# ", "- ", "*", "- [ ] ", "- [x]", "1. [ ]",
+            "> ", "    ", "~~~", "===", "|", "<b>", "<http://x>", "[^1]", "[r]: /u", "\\",
+        ];
+
+        // xorshift keeps the test deterministic without extra dependencies
+        let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for _ in 0..2000 {
+            let len = (next() % 24) as usize;
+            let content: String = (0..len)
+                .map(|_| PIECES[(next() % PIECES.len() as u64) as usize])
+                .collect();
+            assert_eq!(identity(&content), content, "input: {:?}", content);
+        }
+    }
+
+    /// Constructs the old hand-rolled parser got wrong
+    const REAL_WORLD: &str = "Setext Title\n============\n\n\
+        > Quoted *text*\n> over two lines\n\n\
+        \x20   indented code\n\n\
+        ````md\n```rust\nnested fence\n```\n````\n\n\
+        See [the docs][docs] or <https://example.com>.\n\n\
+        | Col | Other |\n|-----|-------|\n| a b | `c` d |\n\n\
+        <div>raw html</div>\n\n\
+        [docs]: https://example.com/docs \"Docs\"\n";
+
+    #[test]
+    fn test_real_world_markdown_round_trip() {
+        assert_eq!(identity(REAL_WORLD), REAL_WORLD);
+
+        let mut extractor = MarkdownExtractor::new(REAL_WORLD);
+        extractor.extract();
+        assert_eq!(
+            extractor.text_segments,
+            vec![
+                "Setext Title",
+                "Quoted *text*",
+                "over two lines",
+                "See",
+                "the docs",
+                "or",
+                ".",
+                "Col",
+                "Other",
+                "a b",
+                "⟦0⟧ d",
+            ]
+        );
+
+        // Only the segments change; code, HTML and URLs are copied verbatim
+        let upper: Vec<String> = extractor.text_segments.iter().map(|s| s.to_uppercase()).collect();
+        assert_eq!(
+            extractor.reconstruct(&upper, None),
+            REAL_WORLD
+                .replace("Setext Title", "SETEXT TITLE")
+                .replace("Quoted *text*", "QUOTED *TEXT*")
+                .replace("over two lines", "OVER TWO LINES")
+                .replace("See [the docs][docs] or", "SEE [THE DOCS][docs] OR")
+                .replace("| Col | Other |", "| COL | OTHER |")
+                .replace("| a b | `c` d |", "| A B | `c` D |")
+        );
+    }
+
+    #[test]
+    fn test_reconstruct_keeps_segment_whitespace() {
+        let content = "Intro text [ link ](https://example.com/link) tail\n";
+        let mut extractor = MarkdownExtractor::new(content);
+        extractor.extract();
+
+        let translated: Vec<String> = extractor
+            .text_segments
+            .iter()
+            .map(|s| s.to_uppercase())
+            .collect();
+        assert_eq!(
+            extractor.reconstruct(&translated, None),
+            "INTRO TEXT [ LINK ](https://example.com/link) TAIL\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_review_round_trip() {
+        use crate::testing::{test_translator, MockServer};
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("doc.md");
+        let review_path = temp_dir.path().join("doc.review.json");
+        let output = temp_dir.path().join("doc.zh.md");
+        std::fs::write(&input, "Read [the guide](guide.md) first.\n").unwrap();
+
+        let server = MockServer::echo().await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url));
+        let review = processor
+            .export_review(&input, &review_path, "zh", None)
+            .await
+            .unwrap();
+        assert_eq!(review.segments.len(), 3);
+        assert_eq!(review.segments[1].source, "the guide");
+        assert_eq!(review.segments[1].mt, "zh:the guide");
+
+        // Post-edit the link text only
+        let mut edited = ReviewFile::load(&review_path).await.unwrap();
+        edited.segments[1].edited = Some("指南".to_string());
+        edited.save(&review_path).await.unwrap();
+
+        MarkdownProcessor::apply_review(&review_path, &output).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "zh:Read [指南](guide.md) zh:first.\n"
+        );
+
+        // Reviews go stale once the source changes
+        std::fs::write(&input, "Rewritten paragraph.\n").unwrap();
+        assert!(MarkdownProcessor::apply_review(&review_path, &output).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_review_round_trip_keeps_custom_skip_directive() {
+        use crate::testing::{test_translator, MockServer};
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("doc.md");
+        let review_path = temp_dir.path().join("doc.review.json");
+        let output = temp_dir.path().join("doc.zh.md");
+        std::fs::write(&input, "Intro.
+
+<!-- no-translate -->
+Keep this.
+
+Outro.
+").unwrap();
+
+        let server = MockServer::echo().await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url))
+            .with_skip_directives(vec!["no-translate".to_string()]);
+        let review = processor
+            .export_review(&input, &review_path, "zh", None)
+            .await
+            .unwrap();
+        assert_eq!(review.segments.len(), 2);
+
+        MarkdownProcessor::apply_review(&review_path, &output).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "zh:Intro.
+
+<!-- no-translate -->
+Keep this.
+
+zh:Outro.
+"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_inline_code_is_masked_and_restored() {
+        use crate::testing::{test_translator, MockServer};
+
+        let content = "Run `cargo build --release` and edit `config.toml` first.\n";
+        let server = MockServer::echo().await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url));
+
+        let translated = processor
+            .translate_content(Path::new("test.md"), content, "zh", None)
+            .await
+            .unwrap();
+        assert_eq!(
+            translated,
+            "zh:Run `cargo build --release` and edit `config.toml` first.\n"
+        );
+        // The whole sentence went out as one request, with the code masked
+        assert_eq!(server.sent_texts(), vec!["Run ⟦0⟧ and edit ⟦1⟧ first."]);
+    }
+
+    #[tokio::test]
+    async fn test_math_is_never_translated() {
+        use crate::testing::{test_translator, MockServer};
+
+        let content = "Energy is $E = mc^2$ for a mass.\n\n\
+                       $$\n\\int_0^1 x\\,dx = \\frac{1}{2}\n$$\n\n\
+                       It costs $5 and $10 today.\n";
+        let server = MockServer::echo().await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url));
+
+        let translated = processor
+            .translate_content(Path::new("test.md"), content, "zh", None)
+            .await
+            .unwrap();
+        // A lone `$` without a closing one is currency, not math
+        assert_eq!(
+            server.sent_texts(),
+            vec!["Energy is ⟦0⟧ for a mass.", "It costs $5 and $10 today."]
+        );
+        assert_eq!(
+            translated,
+            "zh:Energy is $E = mc^2$ for a mass.\n\n$$\n\\int_0^1 x\\,dx = \\frac{1}{2}\n$$\n\n\
+             zh:It costs $5 and $10 today.\n"
+        );
+        // Reflowing leaves display math on its own lines
+        let display = "$$\na + b\n= c\n$$\n";
+        assert_eq!(wrap_paragraphs(display, 4), display);
+    }
+
+    #[tokio::test]
+    async fn test_inline_code_fallback_when_placeholder_dropped() {
+        use crate::testing::{echo_translation, test_translator, MockReply, MockServer};
+
+        // A model that mangles placeholders
+        let server = MockServer::start(|req| {
+            let target = req.target_lang();
+            let texts: Vec<String> = req
+                .input_texts()
+                .iter()
+                .map(|t| echo_translation(&target, &t.replace("⟦1⟧", "")))
+                .collect();
+            MockReply::translations(&texts)
+        })
+        .await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url));
+
+        let content = "Run `make` and then `make install` now.\n";
+        let translated = processor
+            .translate_content(Path::new("test.md"), content, "zh", None)
+            .await
+            .unwrap();
+        assert_eq!(translated, "zh:Run `make` zh:and then `make install` zh:now.\n");
+    }
+
+    #[tokio::test]
+    async fn test_link_text_stays_with_its_link() {
+        use crate::testing::{test_translator, MockServer};
+
+        let content = "First paragraph.\n\n[Link text](https://example.com)\n\n\
+                       Second paragraph with [inline link](x.md) inside.\n\nLast one.\n";
+        let server = MockServer::echo().await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url));
+
+        let translated = processor
+            .translate_content(Path::new("test.md"), content, "zh", None)
+            .await
+            .unwrap();
+        assert_eq!(
+            translated,
+            "zh:First paragraph.\n\n[zh:Link text](https://example.com)\n\n\
+             zh:Second paragraph with [zh:inline link](x.md) zh:inside.\n\nzh:Last one.\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_link_and_image_titles_are_translated() {
+        use crate::testing::{test_translator, MockServer};
+
+        let content = "See [text](https://example.com/a \"Tooltip\") and \
+                       ![Logo](logo.png 'Company logo').\n\n[ref][r]\n\n[r]: x.md \"Kept\"\n";
+        let server = MockServer::echo().await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url));
+
+        let translated = processor
+            .translate_content(Path::new("test.md"), content, "zh", None)
+            .await
+            .unwrap();
+        assert_eq!(
+            translated,
+            "zh:See [zh:text](https://example.com/a \"zh:Tooltip\") zh:and \
+             ![zh:Logo](logo.png 'zh:Company logo')zh:.\n\n[zh:ref][r]\n\n[r]: x.md \"Kept\"\n"
+        );
+        assert_eq!(escape_title("say \"hi\" \\\"ok\\\"", '"'), "say \\\"hi\\\" \\\"ok\\\"");
+    }
+
+    #[tokio::test]
+    async fn test_image_alt_text_is_translated_not_its_path() {
+        use crate::testing::{test_translator, MockServer};
+
+        let content = "See ![Diagram of *flow*](img/flow.png \"Data flow\") and ![Logo][logo].\n\n\
+                       [logo]: assets/logo.svg\n";
+        let server = MockServer::echo().await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url));
+
+        let translated = processor
+            .translate_content(Path::new("test.md"), content, "zh", None)
+            .await
+            .unwrap();
+        assert_eq!(
+            translated,
+            "zh:See ![zh:Diagram of *flow*](img/flow.png \"zh:Data flow\") zh:and \
+             ![zh:Logo][logo]zh:.\n\n[logo]: assets/logo.svg\n"
+        );
+        assert!(server
+            .sent_texts()
+            .iter()
+            .all(|t| !t.contains("png") && !t.contains("svg") && !t.contains('!')));
+
+        // A stray bracket in the translation cannot end the alt text early
+        assert_eq!(escape_link_text("图] [示"), "图\\] \\[示");
+        assert_eq!(escape_link_text("a [b] c"), "a [b] c");
+        assert_eq!(identity("![a [b] c](x.png)\n"), "![a [b] c](x.png)\n");
+    }
+
+    #[tokio::test]
+    async fn test_reference_links_keep_labels_and_definitions() {
+        use crate::testing::{test_translator, MockServer};
+
+        let definitions = "[guide]: https://example.com/guide \"Guide\"\n\
+                           [faq]: ./faq.md\n[Changelog]: CHANGELOG.md\n";
+        let content = format!(
+            "Read [the guide][guide], the [FAQ][] and [Changelog].\n\n{}",
+            definitions
+        );
+        let server = MockServer::echo().await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url));
+
+        let translated = processor
+            .translate_content(Path::new("test.md"), &content, "zh", None)
+            .await
+            .unwrap();
+        assert_eq!(
+            translated,
+            format!(
+                "zh:Read [zh:the guide][guide]zh:, the [zh:FAQ][FAQ] zh:and \
+                 [zh:Changelog][Changelog]zh:.\n\n{}",
+                definitions
+            )
+        );
+        assert!(translated.ends_with(definitions));
+
+        // Every link still resolves to its definition
+        let urls: Vec<String> = Parser::new_ext(&translated, markdown_options())
+            .filter_map(|event| match event {
+                Event::Start(Tag::Link { dest_url, .. }) => Some(dest_url.to_string()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(urls, ["https://example.com/guide", "./faq.md", "CHANGELOG.md"]);
+    }
+
+    #[tokio::test]
+    async fn test_segments_carry_title_and_section_context() {
+        use crate::testing::{test_translator, MockServer};
+
+        let content = "# Baking Basics\n\nIntro.\n\n## Meringue\n\nFold in the whites.\n";
+        let server = MockServer::echo().await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url));
+        processor
+            .translate_content(Path::new("test.md"), content, "zh", None)
+            .await
+            .unwrap();
+
+        let contexts: Vec<(String, String)> = server
+            .requests()
+            .iter()
+            .map(|r| {
+                let item = &r.body["input"][0]["content"][0];
+                let context = item["translation_options"]["context"].as_str().unwrap_or("");
+                (item["text"].as_str().unwrap().to_string(), context.to_string())
+            })
+            .collect();
+        assert_eq!(
+            contexts,
+            vec![
+                ("Baking Basics".to_string(), "Baking Basics".to_string()),
+                ("Intro.".to_string(), "Baking Basics".to_string()),
+                ("Meringue".to_string(), "Baking Basics".to_string()),
+                ("Fold in the whites.".to_string(), "Baking Basics > Meringue".to_string()),
+            ]
+        );
+
+        // A frontmatter title names the document
+        let mut extractor = MarkdownExtractor::new("---\ntitle: Guide\n---\n## Setup\n\nRun it.\n");
+        extractor.extract();
+        assert_eq!(extractor.segment_contexts.last().unwrap().as_deref(), Some("Guide > Setup"));
+    }
+
+    #[tokio::test]
+    async fn test_frontmatter_only_and_empty_files() {
+        use crate::testing::{test_translator, MockServer};
+
+        let server = MockServer::echo().await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url));
+        let translate = |content: &'static str| {
+            let processor = &processor;
+            async move {
+                processor
+                    .translate_content(Path::new("test.md"), content, "zh", None)
+                    .await
+                    .unwrap()
+            }
+        };
+
+        // Only the frontmatter fields are translated, with one pair of fences
+        for content in ["---\ntitle: Guide\n---\n", "---\ntitle: Guide\n---"] {
+            let translated = translate(content).await;
+            assert_eq!(translated, "---\ntitle: zh:Guide\n---\n");
+        }
+        assert_eq!(translate("---\n---\n").await, "---\n---\n");
+
+        // Key order is kept; lists of translated fields are translated item by
+        // item, other lists are left alone
+        let content = "---\ntitle: Guide\ntags:\n- rust\n- cli\nweight: 3\n\
+                       description:\n- One\n- Two\n---\n";
+        assert_eq!(
+            translate(content).await,
+            "---\ntitle: zh:Guide\ntags:\n- rust\n- cli\nweight: 3\n\
+             description:\n- zh:One\n- zh:Two\n---\n"
+        );
+
+        // Nothing translatable: copied unchanged without a request
+        let requests = server.request_count();
+        for content in ["", "\n\n", "<!-- draft -->\n"] {
+            assert_eq!(translate(content).await, content);
+        }
+        assert_eq!(server.request_count(), requests);
+    }
+
+    #[tokio::test]
+    async fn test_frontmatter_failures_are_reported() {
+        use crate::testing::{test_config, test_translator, MockReply, MockServer};
+
+        let server = MockServer::start(|req| {
+            let text = &req.input_texts()[0];
+            if text == "Broken" {
+                MockReply::error(400, "rejected")
+            } else {
+                MockReply::translations(&[format!("zh:{}", text)])
+            }
+        })
+        .await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url));
+
+        let content = "---\ntitle: Broken\ndescription: Fine\n---\n";
+        let translated = processor
+            .translate_content(Path::new("test.md"), content, "zh", None)
+            .await
+            .unwrap();
+        assert_eq!(translated, "---\ntitle: Broken\ndescription: zh:Fine\n---\n");
+        let diagnostics = processor.diagnostics().all();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[0].location.as_deref(), Some("frontmatter title"));
+
+        // The report lists the frontmatter strings before the body
+        let content = "---\ntitle: Broken\n---\nBody.\n";
+        let (_, outcomes) = processor
+            .translate_content_report(Path::new("test.md"), content, "zh", None)
+            .await
+            .unwrap();
+        assert_eq!(outcomes.len(), 2);
+        assert_eq!(outcomes[0].field.as_deref(), Some("title"));
+        assert_eq!(outcomes[0].status, SegmentStatus::Failed);
+        assert!(outcomes[0].error.is_some());
+        assert_eq!((outcomes[1].index, outcomes[1].field.as_deref()), (0, None));
+        assert_eq!(outcomes[1].status, SegmentStatus::Translated);
+
+        // Reaching the cost limit stops the file instead of keeping the original
+        let mut config = test_config(&server.url);
+        config.prices.insert("mock-slow".to_string(), 1.0);
+        config.max_cost = Some(0.001);
+        let processor = MarkdownProcessor::new(AsyncTranslator::new(config).unwrap());
+        let result = processor
+            .translate_content(Path::new("test.md"), "---\ntitle: Guide\n---\n", "zh", None)
+            .await;
+        assert!(matches!(result, Err(TranslationError::CostLimitExceeded { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_custom_frontmatter_fields() {
+        use crate::testing::{test_translator, MockServer};
+
+        let server = MockServer::echo().await;
+        let fields = ["subtitle", "seo.excerpt", "excerpt"].map(String::from).to_vec();
+        let processor =
+            MarkdownProcessor::new(test_translator(&server.url)).with_frontmatter_fields(fields);
+
+        let content = "---\ntitle: Guide\nsubtitle: Getting started\nseo:\n  excerpt: Short\n  \
+                       slug: guide\n---\n";
+        let translated = processor
+            .translate_content(Path::new("test.md"), content, "zh", None)
+            .await
+            .unwrap();
+        // `title` is only a default; the absent `excerpt` is skipped
+        assert_eq!(
+            translated,
+            "---\ntitle: Guide\nsubtitle: zh:Getting started\nseo:\n  excerpt: zh:Short\n  \
+             slug: guide\n---\n"
+        );
+        assert_eq!(server.sent_texts(), vec!["Getting started", "Short"]);
+    }
+
+    #[tokio::test]
+    async fn test_crlf_and_bom_frontmatter() {
+        use crate::testing::{test_translator, MockServer};
+
+        let server = MockServer::echo().await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url));
+        let translate = |content: &'static str| {
+            let processor = &processor;
+            async move {
+                processor
+                    .translate_content(Path::new("test.md"), content, "zh", None)
+                    .await
+                    .unwrap()
+            }
+        };
+
+        // Windows line endings are kept, the YAML is not translated as body text
+        let translated = translate("---\r\ntitle: Guide\r\n---\r\n# Intro\r\n").await;
+        assert_eq!(translated, "---\r\ntitle: zh:Guide\r\n---\r\n# zh:Intro\r\n");
+
+        let translated = translate("\u{feff}---\ntitle: Guide\n---\nBody.\n").await;
+        assert_eq!(translated, "\u{feff}---\ntitle: zh:Guide\n---\nzh:Body.\n");
+
+        // Without frontmatter the mark stays in front of the body
+        assert_eq!(translate("\u{feff}Body.\n").await, "\u{feff}zh:Body.\n");
+        assert!(server.sent_texts().iter().all(|t| !t.contains('\u{feff}') && !t.contains("---")));
+    }
+
+    #[tokio::test]
+    async fn test_details_summary_text_is_translated() {
+        use crate::testing::{test_translator, MockServer};
+
+        let content = "<details><summary>Show &amp; tell</summary> Hidden body </details>\n\n\
+                       <details>\n<summary>Title</summary>\n\nBody paragraph.\n\n</details>\n\n\
+                       <div>raw html</div>\n";
+        let server = MockServer::echo().await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url));
+
+        let translated = processor
+            .translate_content(Path::new("test.md"), content, "zh", None)
+            .await
+            .unwrap();
+        assert_eq!(
+            translated,
+            "<details><summary>zh:Show &amp; tell</summary> zh:Hidden body </details>\n\n\
+             <details>\n<summary>zh:Title</summary>\n\nzh:Body paragraph.\n\n</details>\n\n\
+             <div>raw html</div>\n"
+        );
+        assert_eq!(
+            server.sent_texts(),
+            vec!["Show & tell", "Hidden body", "Title", "Body paragraph."]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_multibyte_text_before_link_and_code() {
+        use crate::testing::{test_translator, MockServer};
+
+        let content = "# 标题 🚀\n\n你好世界🎉[链接](https://example.com)和😀`代码`结束。\n";
+        assert_eq!(identity(content), content);
+
+        let server = MockServer::echo().await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url));
+        let translated = processor
+            .translate_content(Path::new("test.md"), content, "zh", None)
+            .await
+            .unwrap();
+        assert_eq!(
+            translated,
+            "# zh:标题 🚀\n\nzh:你好世界🎉[zh:链接](https://example.com)zh:和😀`代码`结束。\n"
+        );
+        assert_eq!(
+            server.sent_texts(),
+            vec!["标题 🚀", "你好世界🎉", "链接", "和😀⟦0⟧结束。"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_incremental_sends_only_changed_segments() {
+        use crate::testing::{test_translator, MockServer};
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let old_source = temp_dir.path().join("v1.md");
+        let old_output = temp_dir.path().join("v1.zh.md");
+        let new_source = temp_dir.path().join("v2.md");
+        let new_output = temp_dir.path().join("v2.zh.md");
+        std::fs::write(&old_source, "# Intro\n\nFirst paragraph.\n\nRun `make` now.\n").unwrap();
+        std::fs::write(&new_source, "# Intro\n\nEdited paragraph.\n\nRun `make` now.\n").unwrap();
+
+        let server = MockServer::echo().await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url));
+        processor
+            .translate_file(&old_source, &old_output, "zh", None)
+            .await
+            .unwrap();
+        let first_run = server.request_count();
+
+        let reused = processor
+            .translate_file_incremental(
+                &new_source,
+                &new_output,
+                &old_source,
+                &old_output,
+                "zh",
+                None,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(reused, 2);
+        assert_eq!(server.sent_texts()[first_run..], ["Edited paragraph."]);
+        assert_eq!(
+            std::fs::read_to_string(&new_output).unwrap(),
+            "# zh:Intro\n\nzh:Edited paragraph.\n\nzh:Run `make` now.\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_incremental_sample_leaves_frontmatter_alone() {
+        use crate::testing::{test_translator, MockServer};
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let old_source = temp_dir.path().join("v1.md");
+        let old_output = temp_dir.path().join("v1.zh.md");
+        let new_source = temp_dir.path().join("v2.md");
+        let new_output = temp_dir.path().join("v2.zh.md");
+        std::fs::write(&old_source, "One.\n").unwrap();
+        std::fs::write(&old_output, "zh:One.\n").unwrap();
+        std::fs::write(&new_source, "---\ntitle: Guide\n---\nTwo.\n\nThree.\n").unwrap();
+
+        let server = MockServer::echo().await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url)).with_sample(Some(1));
+        processor
+            .translate_file_incremental(
+                &new_source,
+                &new_output,
+                &old_source,
+                &old_output,
+                "zh",
+                None,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(server.sent_texts(), ["Two."]);
+        assert_eq!(
+            std::fs::read_to_string(&new_output).unwrap(),
+            "---\ntitle: Guide\n---\nzh:Two.\n\nThree.\n"
+        );
+    }
+
+    /// Responder translating each line of a request, delimiter lines excepted;
+    /// with `drop_delimiter` it loses the first delimiter like a careless model
+    fn line_translator(
+        drop_delimiter: bool,
+    ) -> impl Fn(&crate::testing::RecordedRequest) -> crate::testing::MockReply {
+        move |req| {
+            let texts: Vec<String> = req
+                .input_texts()
+                .iter()
+                .map(|text| {
+                    let mut lines: Vec<String> = text
+                        .lines()
+                        .map(|l| {
+                            if l.starts_with('⟪') {
+                                l.to_string()
+                            } else {
+                                format!("zh:{}", l)
+                            }
+                        })
+                        .collect();
+                    if drop_delimiter {
+                        if let Some(at) = lines.iter().position(|l| l.starts_with('⟪')) {
+                            lines.remove(at);
+                        }
+                    }
+                    lines.join("\n")
+                })
+                .collect();
+            crate::testing::MockReply::translations(&texts)
+        }
+    }
+
+    const BATCHED: &str = "One.\n\nTwo.\n\nThree `x`.\n\nFour.\n";
+
+    #[tokio::test]
+    async fn test_batching_sends_consecutive_segments_together() {
+        use crate::testing::{test_translator, MockServer};
+
+        let server = MockServer::start(line_translator(false)).await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url)).with_batching(true);
+
+        let translated = processor
+            .translate_content(Path::new("test.md"), BATCHED, "zh", None)
+            .await
+            .unwrap();
+        assert_eq!(
+            translated,
+            "zh:One.\n\nzh:Two.\n\nzh:Three `x`.\n\nzh:Four.\n"
+        );
+        assert_eq!(server.request_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_batch_split_mismatch_falls_back_to_single_segments() {
+        use crate::testing::{test_translator, MockServer};
+
+        let server = MockServer::start(line_translator(true)).await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url)).with_batching(true);
+
+        let translated = processor
+            .translate_content(Path::new("test.md"), BATCHED, "zh", None)
+            .await
+            .unwrap();
+        assert_eq!(
+            translated,
+            "zh:One.\n\nzh:Two.\n\nzh:Three `x`.\n\nzh:Four.\n"
+        );
+        // One failed batch, then each segment on its own
+        assert_eq!(server.request_count(), 5);
+        assert_eq!(processor.diagnostics().all()[0].severity, Severity::Info);
+    }
+
+    #[tokio::test]
+    async fn test_sample_translates_only_leading_segments() {
+        use crate::testing::{test_translator, MockServer};
+
+        let content = "---\ntitle: Guide\n---\n# Title\n\nOne.\n\nTwo.\n\nThree.\n";
+        let server = MockServer::echo().await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url)).with_sample(Some(2));
+
+        let translated = processor
+            .translate_content(Path::new("test.md"), content, "zh", None)
+            .await
+            .unwrap();
+        assert_eq!(server.sent_texts(), vec!["Title", "One."]);
+        assert_eq!(
+            translated,
+            "---\ntitle: Guide\n---\n# zh:Title\n\nzh:One.\n\nTwo.\n\nThree.\n"
+        );
+        let diagnostics = processor.diagnostics().all();
+        assert!(diagnostics[0].message.contains("partial sample"));
+    }
+
+    #[tokio::test]
+    async fn test_skip_directive_keeps_next_block() {
+        use crate::testing::{test_translator, MockServer};
+
+        let content = "<!-- more -->\nFirst.\n\n<!-- i18n: skip -->\nKeep *this* [as](u) is.\n\n\
+                       <!-- prettier-ignore -->\nLast.\n";
+        let server = MockServer::echo().await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url));
+
+        let translated = processor
+            .translate_content(Path::new("test.md"), content, "zh", None)
+            .await
+            .unwrap();
+        assert_eq!(server.sent_texts(), vec!["First.", "Last."]);
+        assert_eq!(
+            translated,
+            "<!-- more -->\nzh:First.\n\n<!-- i18n: skip -->\nKeep *this* [as](u) is.\n\n\
+             <!-- prettier-ignore -->\nzh:Last.\n"
+        );
+
+        // Directives are configurable
+        let plan = processor
+            .with_skip_directives(vec!["no-translate".to_string()])
+            .plan_content(content);
+        assert_eq!(plan.segments, vec!["First.", "Keep *this*", "as", "is.", "Last."]);
+    }
+
+    #[tokio::test]
+    async fn test_wrap_reflows_paragraphs_only() {
+        use crate::testing::{test_translator, MockReply, MockServer};
+
+        let server = MockServer::start(|req| {
+            let texts: Vec<String> = req
+                .input_texts()
+                .iter()
+                .map(|_| "这是一段翻译之后变得很长的中文文本，需要按照显示宽度重新排版。".repeat(3))
+                .collect();
+            MockReply::translations(&texts)
+        })
+        .await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url)).with_wrap(Some(40));
+
+        let code_line = format!("let s = \"{}\";", "x".repeat(60));
+        let table_row = format!("| {} | b |", "y".repeat(60));
+        let content = format!(
+            "A long paragraph.\n\n> A long quote  \n> with a hard break.\n\n\
+             ```\n{}\n```\n\n| h | k |\n|---|---|\n{}\n",
+            code_line, table_row
+        );
+        let translated = processor
+            .translate_content(Path::new("test.md"), &content, "zh", None)
+            .await
+            .unwrap();
+
+        let mut in_fence = false;
+        for line in translated.lines() {
+            if line.starts_with("```") {
+                in_fence = !in_fence;
+                continue;
+            }
+            if in_fence || line.starts_with('|') {
+                continue;
+            }
+            assert!(line.width() <= 40, "{:?} is wider than 40 columns", line);
+        }
+        // Protected blocks keep their long lines
+        assert!(translated.contains(&format!("\n{}\n", code_line)));
+        let rows: Vec<&str> = translated.lines().filter(|l| l.starts_with('|')).collect();
+        assert_eq!(rows.len(), 3);
+        assert!(rows[2].width() > 40 && rows[2].ends_with(" |"));
+        // Quote lines keep their marker and the hard break survives
+        let quote: Vec<&str> = translated.lines().filter(|l| l.starts_with('>')).collect();
+        assert!(quote.len() > 2);
+        assert!(quote.iter().any(|l| l.ends_with("  ")));
+    }
+
+    #[test]
+    fn test_inline_code_masking_can_be_disabled() {
+        let content = "Run `make` now.";
+        let mut extractor = MarkdownExtractor::new(content).with_inline_code_masking(false);
+        extractor.extract();
+        assert_eq!(extractor.text_segments, vec!["Run", "now."]);
+
+        let mut extractor = MarkdownExtractor::new(content);
+        extractor.extract();
+        assert_eq!(extractor.text_segments, vec!["Run ⟦0⟧ now."]);
+    }
+
+    #[test]
+    fn test_seeded_plan_is_reproducible() {
+        use crate::testing::test_config;
+
+        let content = "# Title\n\nRun `make` now. See [docs](d.md).\n";
+        let plan = |seed| {
+            let mut config = test_config("http://127.0.0.1:9");
+            config.seed = seed;
+            MarkdownProcessor::new(AsyncTranslator::new(config).unwrap()).plan_content(content)
+        };
+
+        let first = plan(Some(42));
+        assert_eq!(first, plan(Some(42)));
+        assert_eq!(first.segments, vec!["Title", "Run ⟦0⟧ now. See", "docs", "."]);
+        assert_ne!(first.delimiter, plan(Some(43)).delimiter);
+    }
+
+    #[tokio::test]
+    async fn test_largest_first_keeps_output_order() {
+        use crate::testing::{test_translator, MockServer};
+
+        let content = "Short.\n\n[A much longer piece of link text](x) and [mid size](y)\n";
+        let server = MockServer::echo().await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url))
+            .with_schedule(ScheduleOrder::LargestFirst);
+
+        let translated = processor
+            .translate_content(Path::new("test.md"), content, "zh", None)
+            .await
+            .unwrap();
+
+        let sent = server.sent_texts();
+        assert_eq!(sent[0], "A much longer piece of link text");
+        assert_eq!(sent.last().unwrap(), "and");
+        assert_eq!(
+            translated,
+            "zh:Short.\n\n[zh:A much longer piece of link text](x) zh:and [zh:mid size](y)\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fenced_code_blocks_are_copied_whole() {
+        use crate::testing::{test_translator, MockServer};
+
+        let blocks = [
+            "```rust title=\"Example\"\nfn main() {}\n```\n",
+            "````markdown\n<!-- a nested fence: -->\n```\nnot the end\n```\n````\n",
+            "  ```\n  indented\n   ```\n",
+            "~~~\n``` still code\n~~~\n",
+        ];
+        let server = MockServer::echo().await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url));
+
+        for block in blocks {
+            let content = format!("Before.\n\n{}\nAfter.\n", block);
+            let translated = processor
+                .translate_content(Path::new("test.md"), &content, "zh", None)
+                .await
+                .unwrap();
+            assert_eq!(translated, format!("zh:Before.\n\n{}\nzh:After.\n", block));
+        }
+        assert!(server
+            .sent_texts()
+            .iter()
+            .all(|t| t == "Before." || t == "After."));
+    }
+
+    #[tokio::test]
+    async fn test_progress_counts_segments_then_file() {
+        use crate::testing::{test_translator, MockServer};
+        use std::sync::{Arc, Mutex};
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("doc.md");
+        let output = temp_dir.path().join("doc_zh.md");
+        std::fs::write(&input, "# Title\n\nOne.\n\nTwo `x` three.\n").unwrap();
+
+        let server = MockServer::echo().await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        processor
+            .translate_file_with_progress(&input, &output, "zh", None, move |p| {
+                sink.lock().unwrap().push(p)
+            })
+            .await
+            .unwrap();
+
+        let events = events.lock().unwrap();
+        let (segments, file) = events.split_at(events.len() - 1);
+        let counts: Vec<(usize, usize)> = segments
+            .iter()
+            .map(|p| match *p {
+                Progress::Segment { current, total, .. } => (current, total),
+                ref other => panic!("unexpected {:?}", other),
+            })
+            .collect();
+        assert_eq!(counts, [(1, 3), (2, 3), (3, 3)]);
+        let sent: usize = server.sent_texts().iter().map(String::len).sum();
+        assert!(matches!(segments[2], Progress::Segment { bytes, .. } if bytes == sent));
+        let written = std::fs::read_to_string(&output).unwrap();
+        assert_eq!(file, [Progress::File { output, bytes: written.len() }]);
+    }
+
+    #[tokio::test]
+    async fn test_task_list_markers_survive() {
+        use crate::testing::{test_translator, MockServer};
+
+        let content = "Todo:\n\n- [ ] write docs\n- [x] fix `parser` bug\n  * [X] nested\n1. [ ] numbered\n";
+        let server = MockServer::echo().await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url));
+
+        let translated = processor
+            .translate_content(Path::new("test.md"), content, "zh", None)
+            .await
+            .unwrap();
+        assert_eq!(
+            translated,
+            "zh:Todo:\n\n- [ ] zh:write docs\n- [x] zh:fix `parser` bug\n  * [X] zh:nested\n1. [ ] zh:numbered\n"
+        );
+        assert!(server.sent_texts().iter().all(|t| !t.contains('[')));
+    }
+
+    #[tokio::test]
+    async fn test_table_structure_survives_translation() {
+        use crate::testing::{MockReply, MockServer};
+
+        let content = "| Name | Note | Size |\n|:-----|:----:|-----:|\n\
+                       | a \\| b | | 3 |\n| x | `y` z | w |\n";
+        // A careless model drops the pipe escape and breaks a line
+        let server = MockServer::start(|req| {
+            let texts: Vec<String> = req
+                .input_texts()
+                .iter()
+                .map(|text| match text.as_str() {
+                    "a \\| b" => "甲 | 乙".to_string(),
+                    "Note" => "备\n注".to_string(),
+                    _ => format!("zh:{}", text),
+                })
+                .collect();
+            MockReply::translations(&texts)
+        })
+        .await;
+        let processor = MarkdownProcessor::new(crate::testing::test_translator(&server.url));
+
+        let translated = processor
+            .translate_content(Path::new("table.md"), content, "zh", None)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            translated,
+            "| zh:Name | 备 注 | zh:Size |\n|:-----|:----:|-----:|\n\
+             | 甲 \\| 乙 | | zh:3 |\n| zh:x | zh:`y` z | zh:w |\n"
+        );
+        let lines: Vec<&str> = translated.lines().collect();
+        assert_eq!(lines[1], "|:-----|:----:|-----:|");
+        let columns = |line: &str| line.replace("\\|", "").matches('|').count() - 1;
+        assert!(lines.iter().all(|line| columns(line) == 3));
+        // Eight non-empty cells; the empty one is left alone, not sent
+        assert_eq!(server.request_count(), 8);
+    }
+
+    #[tokio::test]
+    async fn test_report_flags_failed_segment() {
+        use crate::testing::{test_translator, MockReply, MockServer};
+
+        let server = MockServer::start(|req| {
+            let text = &req.input_texts()[0];
+            if text == "Two." {
+                MockReply::error(400, "rejected")
+            } else {
+                MockReply::translations(&[format!("zh:{}", text)])
+            }
+        })
+        .await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url));
+
+        let content = "One.\n\nTwo.\n\nThree.\n";
+        let (translated, outcomes) = processor
+            .translate_content_report(Path::new("test.md"), content, "zh", None)
+            .await
+            .unwrap();
+        assert_eq!(translated, "zh:One.\n\nTwo.\n\nzh:Three.\n");
+        let statuses: Vec<SegmentStatus> = outcomes.iter().map(|o| o.status).collect();
+        assert_eq!(
+            statuses,
+            [SegmentStatus::Translated, SegmentStatus::Failed, SegmentStatus::Translated]
+        );
+        assert_eq!(outcomes[1].index, 1);
+        assert!(outcomes[1].error.is_some());
+        assert!(outcomes.iter().filter(|o| o.index != 1).all(|o| o.error.is_none()));
+
+        // Segments beyond a sample are skipped, not failed
+        let (_, outcomes) = processor
+            .with_sample(Some(1))
+            .translate_content_report(Path::new("test.md"), "A.\n\nB.\n", "zh", None)
+            .await
+            .unwrap();
+        assert_eq!(outcomes[1].status, SegmentStatus::Skipped);
+    }
+
+    #[tokio::test]
+    async fn test_failed_segment_reports_diagnostic() {
+        use crate::testing::{echo_translation, test_translator, MockReply, MockServer};
+
+        let server = MockServer::start(|req| {
+            let texts = req.input_texts();
+            if texts.iter().any(|t| t.contains("Broken")) {
+                return MockReply::error(500, "internal error");
+            }
+            let target = req.target_lang();
+            let translated: Vec<String> =
+                texts.iter().map(|t| echo_translation(&target, t)).collect();
+            MockReply::translations(&translated)
+        })
+        .await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url));
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("doc.md");
+        let output = temp_dir.path().join("doc_zh.md");
+        std::fs::write(&input, "Fine [Broken link](x) text\n").unwrap();
+        processor.translate_file(&input, &output, "zh", None).await.unwrap();
+
+        let diagnostics = processor.diagnostics().all();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[0].file.as_deref(), Some(input.as_path()));
+        assert_eq!(diagnostics[0].location.as_deref(), Some("segment 1"));
+        assert_eq!(processor.diagnostics().count_by_severity()[&Severity::Warning], 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_segment_round_trips_through_fixup_job() {
+        use crate::processors::fixup::{FixSummary, FixupJob};
+        use crate::testing::{echo_translation, test_translator, MockReply, MockServer};
+
+        let server = MockServer::start(|req| {
+            let texts = req.input_texts();
+            if texts.iter().any(|t| t.contains("Broken")) {
+                return MockReply::error(500, "internal error");
+            }
+            let target = req.target_lang();
+            let translated: Vec<String> =
+                texts.iter().map(|t| echo_translation(&target, t)).collect();
+            MockReply::translations(&translated)
+        })
+        .await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url));
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("doc.md");
+        let output = temp_dir.path().join("doc_zh.md");
+        // The code block repeats the failed text, so only its span tells them apart
+        std::fs::write(&input, "Fine text.\n\nBroken text.\n\n```\nBroken text.\n```\n").unwrap();
+        processor.translate_file(&input, &output, "zh", None).await.unwrap();
+
+        let job_path = temp_dir.path().join("fixup.json");
+        processor.fixups().job().save(&job_path).await.unwrap();
+        let mut job = FixupJob::load(&job_path).await.unwrap();
+        assert_eq!(job.entries.len(), 1);
+        let entry = &mut job.entries[0];
+        assert_eq!(entry.source, input);
+        assert_eq!(entry.file, output);
+        assert_eq!(entry.index, 1);
+        assert_eq!(entry.original, "Broken text.");
+        let (start, end) = entry.span.unwrap();
+        assert_eq!(&std::fs::read_to_string(&output).unwrap()[start..end], "Broken text.");
+
+        entry.translation = Some("损坏的文本。".to_string());
+        job.save(&job_path).await.unwrap();
+        let summary = FixupJob::load(&job_path).await.unwrap().apply().await.unwrap();
+        assert_eq!(summary, FixSummary { applied: 1, skipped: 0 });
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            format!(
+                "{}\n\n损坏的文本。\n\n```\nBroken text.\n```\n",
+                echo_translation("zh", "Fine text.")
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_combine_joins_files_in_sorted_order() {
+        use crate::testing::{test_translator, MockServer};
+
+        let server = MockServer::echo().await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url));
+        let temp_dir = tempfile::tempdir().unwrap();
+        for (name, content) in [
+            ("02-usage.md", "---\ntitle: Usage\n---\nRun it.\n"),
+            ("01-intro.md", "Welcome.\n"),
+            ("03-faq.md", "\nAsk us.\n\n"),
+        ] {
+            std::fs::write(temp_dir.path().join(name), content).unwrap();
+        }
+        let files = processor.find_files(temp_dir.path(), &[]).unwrap();
+        let output = temp_dir.path().join("out/manual.md");
+
+        let options = CombineOptions::default();
+        processor.translate_combined(&files, &output, "zh", None, &options).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "zh:Welcome.\n\n---\n\nzh:Run it.\n\n---\n\nzh:Ask us.\n"
+        );
+
+        let options = CombineOptions {
+            separator: "<!-- next -->".to_string(),
+            headings: true,
+        };
+        processor.translate_combined(&files, &output, "zh", None, &options).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "# 01-intro\n\nzh:Welcome.\n\n<!-- next -->\n\n\
+             # 02-usage\n\nzh:Run it.\n\n<!-- next -->\n\n\
+             # 03-faq\n\nzh:Ask us.\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_plan_counts_segments_without_requests() {
+        use crate::testing::{test_translator, MockServer};
+
+        let server = MockServer::echo().await;
+        let processor = MarkdownProcessor::new(test_translator(&server.url));
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("guide.md");
+        let content = "# Guide\n\nRun `cargo build` first.\n\n```sh\nmake\n```\n\n- One\n- Two\n";
+        std::fs::write(&input, content).unwrap();
+
+        let plan = processor.plan(&input).await.unwrap();
+        let segments = processor.plan_content(content).segments;
+        assert_eq!(plan.segments, segments.len());
+        assert!(plan.tokens > 0);
+        assert_eq!(server.request_count(), 0);
+    }
+
+    #[test]
+    fn test_find_files_excludes_globs() {
+        let processor = MarkdownProcessor::new(
+            AsyncTranslator::new(crate::core::config::TranslatorConfig::default()).unwrap(),
+        );
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        for file in [
+            "README.md",
+            "CHANGELOG.md",
+            "docs/guide.md",
+            "docs/CHANGELOG.md",
+            "docs/vendor/lib.md",
+            "node_modules/pkg/README.md",
+        ] {
+            std::fs::create_dir_all(root.join(file).parent().unwrap()).unwrap();
+            std::fs::write(root.join(file), "# Doc").unwrap();
+        }
+        let found = |exclude: &[&str], recursive: bool| -> Vec<String> {
+            let exclude: Vec<glob::Pattern> =
+                exclude.iter().map(|p| glob::Pattern::new(p).unwrap()).collect();
+            let files = if recursive {
+                processor.find_files_recursive(root, &exclude).unwrap()
+            } else {
+                processor.find_files(root, &exclude).unwrap()
+            };
+            let mut files: Vec<String> = files
+                .iter()
+                .map(|f| f.strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/"))
+                .collect();
+            files.sort();
+            files
+        };
+
+        // Directory exclusion, by name or by relative path
+        assert_eq!(
+            found(&["node_modules", "docs/vendor"], true),
+            vec!["CHANGELOG.md", "README.md", "docs/CHANGELOG.md", "docs/guide.md"]
+        );
+        // File name pattern, at any depth
+        assert_eq!(
+            found(&["CHANGELOG*", "node_modules/**"], true),
+            vec!["README.md", "docs/guide.md", "docs/vendor/lib.md"]
+        );
+        assert_eq!(found(&["CHANGELOG.md"], false), vec!["README.md"]);
+    }
+
     #[test]
     fn test_is_markdown_file() {
         let processor = MarkdownProcessor::new(
@@ -1,4 +1,12 @@
 //! File format processors
 
+pub mod batch;
 pub mod markdown;
-pub mod epub;
\ No newline at end of file
+pub mod epub;
+pub mod fixup;
+pub mod html;
+pub mod plan;
+pub mod progress;
+pub mod review;
+pub mod subtitle;
+pub mod text;
\ No newline at end of file
@@ -0,0 +1,27 @@
+//! Dry-run plans: how much translating a file would send, without calling the API
+
+use std::path::{Path, PathBuf};
+
+use crate::utils::tokens::estimate_tokens;
+
+/// Segments and estimated tokens a file would be translated with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilePlan {
+    /// Input file
+    pub file: PathBuf,
+    /// Texts that would be sent, one request each unless batched
+    pub segments: usize,
+    /// Estimated input tokens of those texts
+    pub tokens: usize,
+}
+
+impl FilePlan {
+    /// Plan of `file` sending `segments` to `model`
+    pub fn new<S: AsRef<str>>(file: &Path, segments: &[S], model: &str) -> Self {
+        Self {
+            file: file.to_path_buf(),
+            segments: segments.len(),
+            tokens: segments.iter().map(|s| estimate_tokens(s.as_ref(), model)).sum(),
+        }
+    }
+}
@@ -0,0 +1,66 @@
+//! Progress events, for library callers that show their own progress
+//!
+//! The CLI draws its own bar per file; callers of the `*_with_progress`
+//! methods of the Markdown and ePub processors get these events instead.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Work finished while translating a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Progress {
+    /// `current` of the `total` text segments of a Markdown file are
+    /// translated, `bytes` of source text so far
+    Segment {
+        /// Segments done
+        current: usize,
+        /// Segments to translate
+        total: usize,
+        /// Source bytes of the segments done
+        bytes: usize,
+    },
+    /// `current` of the `total` chapters of a book are translated
+    Chapter {
+        /// Chapters done
+        current: usize,
+        /// Chapters to translate (non-linear ones left out when skipped)
+        total: usize,
+    },
+    /// The translated file was written
+    File {
+        /// Where it was written
+        output: PathBuf,
+        /// Its size in bytes
+        bytes: usize,
+    },
+}
+
+/// Callback receiving [`Progress`] events; clones share it, the default
+/// ignores them
+#[derive(Clone)]
+pub(crate) struct ProgressFn(Arc<dyn Fn(Progress) + Send + Sync>);
+
+impl ProgressFn {
+    /// Send events to `callback`
+    pub(crate) fn new(callback: impl Fn(Progress) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(callback))
+    }
+
+    /// Report `progress`
+    pub(crate) fn emit(&self, progress: Progress) {
+        (self.0)(progress)
+    }
+}
+
+impl Default for ProgressFn {
+    fn default() -> Self {
+        Self::new(|_| {})
+    }
+}
+
+impl fmt::Debug for ProgressFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressFn")
+    }
+}
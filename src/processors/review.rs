@@ -0,0 +1,109 @@
+//! Editable review files for post-editing machine translation
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::core::errors::{Result, TranslationError};
+use crate::processors::markdown::DEFAULT_SKIP_DIRECTIVES;
+
+/// Segment-level review file exported instead of the final document.
+///
+/// Translators fill in `edited` for the segments they want to change, then
+/// `apply-review` reassembles the final document from the source file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReviewFile {
+    /// Source document the segments were extracted from
+    pub source: PathBuf,
+    /// Target language of the machine translation
+    pub target_lang: String,
+    /// Translated frontmatter block, if the document has one (editable)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frontmatter: Option<String>,
+    /// Skip directives the segments were extracted with
+    #[serde(default = "default_skip_directives")]
+    pub skip_directives: Vec<String>,
+    /// Whether inline code was masked into the segments around it
+    #[serde(default = "default_protect_inline_code")]
+    pub protect_inline_code: bool,
+    /// Translatable segments in document order
+    pub segments: Vec<ReviewSegment>,
+}
+
+/// Skip directives of review files exported before they were recorded
+fn default_skip_directives() -> Vec<String> {
+    DEFAULT_SKIP_DIRECTIVES.iter().map(|d| d.to_string()).collect()
+}
+
+/// Inline code masking of review files exported before it was recorded
+fn default_protect_inline_code() -> bool {
+    true
+}
+
+/// A single reviewable segment
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReviewSegment {
+    /// Position of the segment in the document
+    pub index: usize,
+    /// Source text
+    pub source: String,
+    /// Machine translation
+    pub mt: String,
+    /// Post-edited translation, used instead of `mt` when set
+    #[serde(default)]
+    pub edited: Option<String>,
+}
+
+impl ReviewSegment {
+    /// Text to use in the final document
+    pub fn final_text(&self) -> &str {
+        self.edited.as_deref().unwrap_or(&self.mt)
+    }
+}
+
+impl ReviewFile {
+    /// Load a review file from JSON
+    pub async fn load(path: &Path) -> Result<Self> {
+        let content = tokio::fs::read_to_string(path)
+            .await
+            .map_err(|e| TranslationError::FileError {
+                path: path.display().to_string(),
+                message: e.to_string(),
+            })?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Save the review file as pretty-printed JSON, creating its directory
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        let file_error = |e: std::io::Error| TranslationError::FileError {
+            path: path.display().to_string(),
+            message: e.to_string(),
+        };
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            tokio::fs::create_dir_all(parent).await.map_err(file_error)?;
+        }
+        tokio::fs::write(path, json).await.map_err(file_error)
+    }
+
+    /// Final texts in segment order, checking they still match `sources`.
+    ///
+    /// Fails if the source document changed since the review was exported.
+    pub fn final_texts(&self, sources: &[String]) -> Result<Vec<String>> {
+        let matches = self.segments.len() == sources.len()
+            && self
+                .segments
+                .iter()
+                .zip(sources)
+                .all(|(segment, source)| &segment.source == source);
+        if !matches {
+            return Err(TranslationError::InvalidFormat {
+                format: format!(
+                    "review file no longer matches {}; re-export it",
+                    self.source.display()
+                ),
+            });
+        }
+
+        Ok(self.segments.iter().map(|s| s.final_text().to_string()).collect())
+    }
+}
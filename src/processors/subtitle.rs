@@ -0,0 +1,397 @@
+//! SubRip (`.srt`) subtitle processor
+
+use regex::Regex;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tracing::{debug, info};
+
+use crate::core::client::AsyncTranslator;
+use crate::core::config::TranslatorConfig;
+use crate::core::diagnostics::{Diagnostic, Diagnostics, Severity};
+use crate::core::errors::{Result, TranslationError};
+use crate::processors::batch::Batcher;
+use crate::processors::fixup::Fixups;
+
+/// A cue whose text is translated
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Cue {
+    /// Byte offset of the first text line
+    start: usize,
+    /// Byte offset just past the last text line (before its line ending)
+    end: usize,
+    /// Formatting tags opening the text, e.g. `<i>` or `{\an8}`
+    prefix: String,
+    /// Formatting tags closing the text, e.g. `</i>`
+    suffix: String,
+    /// Text to translate, lines joined by `\n`
+    text: String,
+}
+
+/// Subtitle processor: translates cue text, keeping indices and timings
+#[derive(Debug, Clone)]
+pub struct SubtitleProcessor {
+    /// Client used for all requests
+    translator: AsyncTranslator,
+    /// Translate only this many leading cues per file
+    sample: Option<usize>,
+    /// Problems found while translating (failed cues, ...)
+    diagnostics: Diagnostics,
+    /// Cues left untranslated, for a fix-up job
+    fixups: Fixups,
+}
+
+impl SubtitleProcessor {
+    /// Create a new subtitle processor
+    pub fn new(translator: AsyncTranslator) -> Self {
+        Self {
+            translator,
+            sample: None,
+            diagnostics: Diagnostics::new(),
+            fixups: Fixups::new(),
+        }
+    }
+
+    /// Create from environment configuration
+    pub fn from_env() -> Result<Self> {
+        let translator = AsyncTranslator::from_env()?;
+        Ok(Self::new(translator))
+    }
+
+    /// Create from an already loaded configuration (e.g. from `--config`)
+    pub fn from_config(config: TranslatorConfig) -> Result<Self> {
+        let translator = AsyncTranslator::new(config)?;
+        Ok(Self::new(translator))
+    }
+
+    /// Translate only the first `limit` cues of each file, copying the rest
+    pub fn with_sample(mut self, limit: Option<usize>) -> Self {
+        self.sample = limit;
+        self
+    }
+
+    /// Report problems into `diagnostics` (e.g. one shared by a whole job)
+    pub fn with_diagnostics(mut self, diagnostics: Diagnostics) -> Self {
+        self.diagnostics = diagnostics;
+        self
+    }
+
+    /// Problems found so far
+    pub fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    /// Collect untranslated cues into `fixups` (e.g. one shared by a whole job)
+    pub fn with_fixups(mut self, fixups: Fixups) -> Self {
+        self.fixups = fixups;
+        self
+    }
+
+    /// Cues left untranslated so far
+    pub fn fixups(&self) -> &Fixups {
+        &self.fixups
+    }
+
+    /// Find subtitle files in a directory
+    pub fn find_files(&self, dir: &Path, recursive: bool) -> Result<Vec<PathBuf>> {
+        if !dir.is_dir() {
+            return Err(TranslationError::FileError {
+                path: dir.display().to_string(),
+                message: "Not a directory".to_string(),
+            });
+        }
+
+        let max_depth = if recursive { usize::MAX } else { 1 };
+        let files = walkdir::WalkDir::new(dir)
+            .max_depth(max_depth)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .map(|e| e.into_path())
+            .filter(|path| path.is_file() && self.is_subtitle_file(path))
+            .collect();
+
+        Ok(files)
+    }
+
+    /// Check if file is a SubRip subtitle
+    pub fn is_subtitle_file(&self, path: &Path) -> bool {
+        path.extension()
+            .map(|ext| ext.eq_ignore_ascii_case("srt"))
+            .unwrap_or(false)
+    }
+
+    /// Translate a single subtitle file
+    pub async fn translate_file(
+        &self,
+        input: &Path,
+        output: &Path,
+        target_lang: &str,
+        source_lang: Option<String>,
+    ) -> Result<()> {
+        debug!("Translating: {}", input.display());
+
+        let content = tokio::fs::read_to_string(input)
+            .await
+            .map_err(|e| TranslationError::FileError {
+                path: input.display().to_string(),
+                message: e.to_string(),
+            })?;
+
+        let translated = self
+            .translate_content(input, &content, target_lang, source_lang)
+            .await?;
+
+        if let Some(parent) = output.parent() {
+            if !parent.as_os_str().is_empty() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+        }
+        tokio::fs::write(output, &translated)
+            .await
+            .map_err(|e| TranslationError::FileError {
+                path: output.display().to_string(),
+                message: e.to_string(),
+            })?;
+        self.fixups.resolve(input, output, &translated);
+
+        info!("Translated: {} -> {}", input.display(), output.display());
+        Ok(())
+    }
+
+    /// Translate the cue text of an SRT document.
+    ///
+    /// Everything but the cue text is copied byte for byte: indices, timing
+    /// lines, blank lines and the document's line endings (`\n` or `\r\n`).
+    /// Consecutive cues are sent together up to `max_input_tokens`; a cue
+    /// that cannot be translated keeps its original text and is recorded in
+    /// [`fixups`](Self::fixups).
+    pub async fn translate_content(
+        &self,
+        input: &Path,
+        content: &str,
+        target_lang: &str,
+        source_lang: Option<String>,
+    ) -> Result<String> {
+        let line_ending = if content.contains("\r\n") { "\r\n" } else { "\n" };
+        let cues = parse_cues(content);
+        let sampled = self.sample.map_or(cues.len(), |n| n.min(cues.len()));
+        if sampled < cues.len() {
+            info!(
+                "Partial sample: translating the first {} of {} cues of {}",
+                sampled,
+                cues.len(),
+                input.display()
+            );
+            self.diagnostics.push(
+                Diagnostic::new(
+                    Severity::Info,
+                    format!("partial sample: first {} of {} cues translated", sampled, cues.len()),
+                )
+                .with_file(input),
+            );
+        }
+
+        let texts: Vec<&str> = cues[..sampled].iter().map(|cue| cue.text.as_str()).collect();
+        let batcher = Batcher {
+            translator: &self.translator,
+            diagnostics: &self.diagnostics,
+            input,
+            unit: "cue",
+        };
+        let translations = batcher
+            .translate_all(&texts, target_lang, source_lang.as_deref())
+            .await?;
+
+        let mut result = String::with_capacity(content.len());
+        let mut last = 0;
+        for (i, (cue, translation)) in cues.iter().zip(translations).enumerate() {
+            let Some(translation) = translation else {
+                let original = &content[cue.start + cue.prefix.len()..cue.end - cue.suffix.len()];
+                self.fixups.push_failed(input, i, original, "cue left untranslated");
+                continue;
+            };
+            result.push_str(&content[last..cue.start]);
+            result.push_str(&cue.prefix);
+            let lines: Vec<&str> = translation.lines().map(str::trim_end).collect();
+            result.push_str(&lines.join(line_ending));
+            result.push_str(&cue.suffix);
+            last = cue.end;
+        }
+        result.push_str(&content[last..]);
+        Ok(result)
+    }
+}
+
+/// Cues of an SRT document that have text, in order.
+///
+/// A cue is a block of non-blank lines: an optional index, a timing line
+/// (`00:00:01,000 --> 00:00:04,000`) and one or more text lines. Blocks
+/// without a timing line are not cues and are left alone.
+fn parse_cues(content: &str) -> Vec<Cue> {
+    // (start, end) of each line's content, without its line ending
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    for line in content.split_inclusive('\n') {
+        let text = line.trim_end_matches(['\r', '\n']);
+        lines.push((offset, offset + text.len()));
+        offset += line.len();
+    }
+
+    let mut cues = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let is_blank = |(start, end): (usize, usize)| content[start..end].trim().is_empty();
+        if is_blank(lines[i]) {
+            i += 1;
+            continue;
+        }
+        let block_start = i;
+        while i < lines.len() && !is_blank(lines[i]) {
+            i += 1;
+        }
+        let block = &lines[block_start..i];
+
+        let is_timing = |&(start, end): &(usize, usize)| content[start..end].contains("-->");
+        let Some(timing) = block.iter().take(2).position(is_timing) else {
+            continue;
+        };
+        let text_lines = &block[timing + 1..];
+        let (Some(first), Some(last)) = (text_lines.first(), text_lines.last()) else {
+            continue;
+        };
+        let (start, end) = (first.0, last.1);
+        let raw: Vec<&str> = text_lines.iter().map(|&(s, e)| &content[s..e]).collect();
+        let raw = raw.join("\n");
+
+        let prefix = leading_tags()
+            .find(&raw)
+            .map_or("", |m| m.as_str())
+            .to_string();
+        let body = &raw[prefix.len()..];
+        let suffix = trailing_tags()
+            .find(body)
+            .map_or("", |m| m.as_str())
+            .to_string();
+        let text = body[..body.len() - suffix.len()].to_string();
+        if text.trim().is_empty() {
+            continue;
+        }
+        cues.push(Cue {
+            start,
+            end,
+            prefix,
+            suffix,
+            text,
+        });
+    }
+    cues
+}
+
+/// Formatting tags at the start of cue text: `<i>`, `<font ...>`, `{\an8}`
+fn leading_tags() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"^(?:<[A-Za-z][^>]*>|\{\\[^}]*\})+").unwrap())
+}
+
+/// Closing formatting tags at the end of cue text: `</i>`, `</font>`
+fn trailing_tags() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"(?:</[A-Za-z][^>]*>)+$").unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{test_config, translate_lines, MockServer};
+
+    const SRT: &str = "1\n00:00:01,000 --> 00:00:04,000\nHello there.\n\n\
+                       2\n00:00:05,000 --> 00:00:08,500\n<i>First line\nsecond line</i>\n\n\n\
+                       3\n00:00:09,000 --> 00:00:10,000\n{\\an8}<b>Top</b>\n";
+
+    /// Processor against `server` with the given token budget per request
+    fn processor(server: &MockServer, max_input_tokens: usize) -> SubtitleProcessor {
+        let mut config = test_config(&server.url);
+        config.max_input_tokens = max_input_tokens;
+        SubtitleProcessor::new(AsyncTranslator::new(config).unwrap())
+    }
+
+    #[test]
+    fn test_parse_cues_strips_formatting_tags() {
+        let cues = parse_cues(SRT);
+        let texts: Vec<&str> = cues.iter().map(|cue| cue.text.as_str()).collect();
+        assert_eq!(texts, vec!["Hello there.", "First line\nsecond line", "Top"]);
+        assert_eq!((cues[1].prefix.as_str(), cues[1].suffix.as_str()), ("<i>", "</i>"));
+        assert_eq!((cues[2].prefix.as_str(), cues[2].suffix.as_str()), ("{\\an8}<b>", "</b>"));
+    }
+
+    #[tokio::test]
+    async fn test_translates_text_and_keeps_timing() {
+        let server = MockServer::start(translate_lines).await;
+        let translated = processor(&server, 4000)
+            .translate_content(Path::new("a.srt"), SRT, "zh", None)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            translated,
+            "1\n00:00:01,000 --> 00:00:04,000\nzh:Hello there.\n\n\
+             2\n00:00:05,000 --> 00:00:08,500\n<i>zh:First line\nzh:second line</i>\n\n\n\
+             3\n00:00:09,000 --> 00:00:10,000\n{\\an8}<b>zh:Top</b>\n"
+        );
+        // All cues fit in one request, sent without their formatting tags
+        assert_eq!(server.request_count(), 1);
+        assert!(!server.sent_texts()[0].contains("<i>"));
+    }
+
+    #[tokio::test]
+    async fn test_crlf_line_endings_are_kept() {
+        let server = MockServer::start(translate_lines).await;
+        let crlf = SRT.replace('\n', "\r\n");
+        let translated = processor(&server, 4000)
+            .translate_content(Path::new("a.srt"), &crlf, "zh", None)
+            .await
+            .unwrap();
+
+        let first_cue = "1\r\n00:00:01,000 --> 00:00:04,000\r\nzh:Hello there.\r\n";
+        assert!(translated.starts_with(first_cue));
+        assert!(translated.contains("<i>zh:First line\r\nzh:second line</i>\r\n\r\n\r\n3\r\n"));
+        assert!(!translated.replace("\r\n", "").contains('\n'));
+    }
+
+    #[tokio::test]
+    async fn test_failed_cue_is_kept_for_a_fixup_job() {
+        use crate::testing::MockReply;
+
+        let server = MockServer::start(|req| {
+            if req.input_texts().iter().any(|text| text.contains("Top")) {
+                return MockReply::error(400, "rejected");
+            }
+            translate_lines(req)
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let (input, output) = (dir.path().join("a.srt"), dir.path().join("a_zh.srt"));
+        std::fs::write(&input, SRT).unwrap();
+        let processor = processor(&server, 4000);
+        processor.translate_file(&input, &output, "zh", None).await.unwrap();
+
+        let mut job = processor.fixups().job();
+        assert_eq!(job.entries.len(), 1);
+        assert_eq!(job.entries[0].original, "Top");
+        job.entries[0].translation = Some("顶部".to_string());
+        job.apply().await.unwrap();
+        let fixed = std::fs::read_to_string(&output).unwrap();
+        assert!(fixed.ends_with("{\\an8}<b>顶部</b>\n"), "{}", fixed);
+    }
+
+    #[tokio::test]
+    async fn test_small_budget_splits_into_several_requests() {
+        let server = MockServer::echo().await;
+        let translated = processor(&server, 8)
+            .translate_content(Path::new("a.srt"), SRT, "zh", None)
+            .await
+            .unwrap();
+
+        assert_eq!(server.request_count(), 3);
+        assert!(translated.contains("\n<i>zh:First line\nsecond line</i>\n"));
+    }
+}
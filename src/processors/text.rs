@@ -0,0 +1,312 @@
+//! Plain-text processor that translates paragraph by paragraph
+
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+use crate::core::client::AsyncTranslator;
+use crate::core::config::TranslatorConfig;
+use crate::core::diagnostics::{Diagnostic, Diagnostics, Severity};
+use crate::core::errors::{Result, TranslationError};
+use crate::processors::batch::Batcher;
+use crate::processors::fixup::Fixups;
+
+/// A run of non-blank lines
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Paragraph {
+    /// Byte offset of the first non-whitespace character
+    start: usize,
+    /// Byte offset just past the last non-whitespace character
+    end: usize,
+    /// Text to translate, lines joined by `\n`
+    text: String,
+}
+
+/// Plain-text processor: paragraphs are separated by blank lines
+#[derive(Debug, Clone)]
+pub struct TextProcessor {
+    /// Client used for all requests
+    translator: AsyncTranslator,
+    /// Translate only this many leading paragraphs per file
+    sample: Option<usize>,
+    /// Problems found while translating (failed paragraphs, ...)
+    diagnostics: Diagnostics,
+    /// Paragraphs left untranslated, for a fix-up job
+    fixups: Fixups,
+}
+
+impl TextProcessor {
+    /// Create a new text processor
+    pub fn new(translator: AsyncTranslator) -> Self {
+        Self {
+            translator,
+            sample: None,
+            diagnostics: Diagnostics::new(),
+            fixups: Fixups::new(),
+        }
+    }
+
+    /// Create from environment configuration
+    pub fn from_env() -> Result<Self> {
+        let translator = AsyncTranslator::from_env()?;
+        Ok(Self::new(translator))
+    }
+
+    /// Create from an already loaded configuration (e.g. from `--config`)
+    pub fn from_config(config: TranslatorConfig) -> Result<Self> {
+        let translator = AsyncTranslator::new(config)?;
+        Ok(Self::new(translator))
+    }
+
+    /// Translate only the first `limit` paragraphs of each file, copying the rest
+    pub fn with_sample(mut self, limit: Option<usize>) -> Self {
+        self.sample = limit;
+        self
+    }
+
+    /// Report problems into `diagnostics` (e.g. one shared by a whole job)
+    pub fn with_diagnostics(mut self, diagnostics: Diagnostics) -> Self {
+        self.diagnostics = diagnostics;
+        self
+    }
+
+    /// Problems found so far
+    pub fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    /// Collect untranslated paragraphs into `fixups` (e.g. one shared by a whole job)
+    pub fn with_fixups(mut self, fixups: Fixups) -> Self {
+        self.fixups = fixups;
+        self
+    }
+
+    /// Paragraphs left untranslated so far
+    pub fn fixups(&self) -> &Fixups {
+        &self.fixups
+    }
+
+    /// Find text files in a directory
+    pub fn find_files(&self, dir: &Path, recursive: bool) -> Result<Vec<PathBuf>> {
+        if !dir.is_dir() {
+            return Err(TranslationError::FileError {
+                path: dir.display().to_string(),
+                message: "Not a directory".to_string(),
+            });
+        }
+
+        let max_depth = if recursive { usize::MAX } else { 1 };
+        let files = walkdir::WalkDir::new(dir)
+            .max_depth(max_depth)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .map(|e| e.into_path())
+            .filter(|path| path.is_file() && self.is_text_file(path))
+            .collect();
+
+        Ok(files)
+    }
+
+    /// Check if file is plain text
+    pub fn is_text_file(&self, path: &Path) -> bool {
+        path.extension()
+            .map(|ext| ext.eq_ignore_ascii_case("txt"))
+            .unwrap_or(false)
+    }
+
+    /// Translate a single text file
+    pub async fn translate_file(
+        &self,
+        input: &Path,
+        output: &Path,
+        target_lang: &str,
+        source_lang: Option<String>,
+    ) -> Result<()> {
+        debug!("Translating: {}", input.display());
+
+        let content = tokio::fs::read_to_string(input)
+            .await
+            .map_err(|e| TranslationError::FileError {
+                path: input.display().to_string(),
+                message: e.to_string(),
+            })?;
+
+        let translated = self
+            .translate_content(input, &content, target_lang, source_lang)
+            .await?;
+
+        if let Some(parent) = output.parent() {
+            if !parent.as_os_str().is_empty() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+        }
+        tokio::fs::write(output, &translated)
+            .await
+            .map_err(|e| TranslationError::FileError {
+                path: output.display().to_string(),
+                message: e.to_string(),
+            })?;
+        self.fixups.resolve(input, output, &translated);
+
+        info!("Translated: {} -> {}", input.display(), output.display());
+        Ok(())
+    }
+
+    /// Translate the paragraphs of a text document.
+    ///
+    /// Blank lines, indentation before a paragraph, the trailing newline and
+    /// the document's line endings (`\n` or `\r\n`) are kept; line breaks
+    /// inside a paragraph are sent to the translator and kept as it returns
+    /// them. Consecutive paragraphs are sent together up to `max_input_tokens`.
+    pub async fn translate_content(
+        &self,
+        input: &Path,
+        content: &str,
+        target_lang: &str,
+        source_lang: Option<String>,
+    ) -> Result<String> {
+        let line_ending = if content.contains("\r\n") { "\r\n" } else { "\n" };
+        let paragraphs = parse_paragraphs(content);
+        let sampled = self.sample.map_or(paragraphs.len(), |n| n.min(paragraphs.len()));
+        if sampled < paragraphs.len() {
+            info!(
+                "Partial sample: translating the first {} of {} paragraphs of {}",
+                sampled,
+                paragraphs.len(),
+                input.display()
+            );
+            self.diagnostics.push(
+                Diagnostic::new(
+                    Severity::Info,
+                    format!(
+                        "partial sample: first {} of {} paragraphs translated",
+                        sampled,
+                        paragraphs.len()
+                    ),
+                )
+                .with_file(input),
+            );
+        }
+
+        let texts: Vec<&str> = paragraphs[..sampled].iter().map(|p| p.text.as_str()).collect();
+        let batcher = Batcher {
+            translator: &self.translator,
+            diagnostics: &self.diagnostics,
+            input,
+            unit: "paragraph",
+        };
+        let translations = batcher
+            .translate_all(&texts, target_lang, source_lang.as_deref())
+            .await?;
+
+        let mut result = String::with_capacity(content.len());
+        let mut last = 0;
+        for (i, (paragraph, translation)) in paragraphs.iter().zip(translations).enumerate() {
+            let Some(translation) = translation else {
+                let original = &content[paragraph.start..paragraph.end];
+                self.fixups.push_failed(input, i, original, "paragraph left untranslated");
+                continue;
+            };
+            result.push_str(&content[last..paragraph.start]);
+            let lines: Vec<&str> = translation.lines().map(str::trim_end).collect();
+            result.push_str(&lines.join(line_ending));
+            last = paragraph.end;
+        }
+        result.push_str(&content[last..]);
+        Ok(result)
+    }
+}
+
+/// Paragraphs of `content`: runs of lines that are not blank, in order
+fn parse_paragraphs(content: &str) -> Vec<Paragraph> {
+    let mut paragraphs = Vec::new();
+    let mut current: Option<(usize, usize)> = None;
+    let mut offset = 0;
+
+    for line in content.split_inclusive('\n') {
+        let text = line.trim_end_matches(['\r', '\n']);
+        if text.trim().is_empty() {
+            if let Some((start, end)) = current.take() {
+                paragraphs.push(paragraph(content, start, end));
+            }
+        } else {
+            let start = current.map_or(offset, |(start, _)| start);
+            current = Some((start, offset + text.len()));
+        }
+        offset += line.len();
+    }
+    if let Some((start, end)) = current {
+        paragraphs.push(paragraph(content, start, end));
+    }
+    paragraphs
+}
+
+/// Paragraph spanning the lines in `start..end`, without surrounding whitespace
+fn paragraph(content: &str, start: usize, end: usize) -> Paragraph {
+    let raw = &content[start..end];
+    let start = start + (raw.len() - raw.trim_start().len());
+    let end = start + raw.trim().len();
+    let text = content[start..end].replace("\r\n", "\n");
+    Paragraph { start, end, text }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{test_translator, translate_lines, MockServer};
+
+    const TEXT: &str = "Title line\n\n\n  First paragraph\ncontinues here.\n\nLast one.\n";
+
+    #[test]
+    fn test_parse_paragraphs() {
+        let texts: Vec<String> = parse_paragraphs(TEXT).into_iter().map(|p| p.text).collect();
+        assert_eq!(texts, vec!["Title line", "First paragraph\ncontinues here.", "Last one."]);
+        assert!(parse_paragraphs("\n \n\t\n").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_keeps_blank_lines_and_trailing_newline() {
+        let server = MockServer::start(translate_lines).await;
+        let processor = TextProcessor::new(test_translator(&server.url));
+
+        let translated = processor
+            .translate_content(Path::new("a.txt"), TEXT, "zh", None)
+            .await
+            .unwrap();
+        assert_eq!(
+            translated,
+            "zh:Title line\n\n\n  zh:First paragraph\nzh:continues here.\n\nzh:Last one.\n"
+        );
+        assert_eq!(server.request_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_windows_line_endings() {
+        let server = MockServer::start(translate_lines).await;
+        let processor = TextProcessor::new(test_translator(&server.url));
+
+        let crlf = TEXT.replace('\n', "\r\n");
+        let translated = processor
+            .translate_content(Path::new("a.txt"), &crlf, "zh", None)
+            .await
+            .unwrap();
+        assert_eq!(
+            translated,
+            "zh:Title line\r\n\r\n\r\n  zh:First paragraph\r\nzh:continues here.\r\n\r\n\
+             zh:Last one.\r\n"
+        );
+        // The translator sees plain newlines
+        assert!(!server.sent_texts()[0].contains('\r'));
+    }
+
+    #[tokio::test]
+    async fn test_no_trailing_newline_is_kept() {
+        let server = MockServer::echo().await;
+        let processor = TextProcessor::new(test_translator(&server.url));
+
+        let translated = processor
+            .translate_content(Path::new("a.txt"), "Only line", "zh", None)
+            .await
+            .unwrap();
+        assert_eq!(translated, "zh:Only line");
+    }
+}
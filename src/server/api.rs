@@ -1,66 +1,244 @@
 //! HTTP API server implementation
 
 use axum::{
-    extract::{State, Json},
+    extract::{DefaultBodyLimit, Multipart, Request, State, Json},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::{self, Next},
+    response::{
+        sse::{Event, Sse},
+        IntoResponse, Response,
+    },
     routing::{get, post},
     Router,
 };
+use metrics_exporter_prometheus::PrometheusHandle;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use tracing::{info, warn};
+use std::time::Duration;
+use tokio::sync::watch;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tracing::{debug, info, warn};
+use utoipa::ToSchema;
 
 use crate::core::client::AsyncTranslator;
+use crate::core::config::TranslatorConfig;
+use crate::core::metrics::install_prometheus;
 use crate::core::models::TranslationRequest;
+use crate::processors::epub::EpubProcessor;
+use crate::processors::html::HtmlProcessor;
+use crate::processors::markdown::MarkdownProcessor;
+use crate::processors::subtitle::SubtitleProcessor;
+use crate::processors::text::TextProcessor;
+use crate::server::docs;
+use crate::server::request_id::{self, X_REQUEST_ID};
+use crate::server::usage::{interval_ticks, UsageFlusher, UsageSnapshot, DEFAULT_FLUSH_INTERVAL};
+use crate::utils::lang::detect_language;
+use crate::utils::tokens::estimate_tokens;
+
+/// Largest request body accepted by `POST /translate/file` by default (20 MiB)
+pub const DEFAULT_MAX_UPLOAD_BYTES: usize = 20 * 1024 * 1024;
+
+/// Browser origins allowed to call the API (CORS)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CorsOrigins {
+    /// Any origin
+    Any,
+    /// Only these origins; none if empty
+    List(Vec<HeaderValue>),
+}
+
+impl CorsOrigins {
+    /// Parse a comma-separated origin list; `*` allows any origin
+    pub fn parse(origins: &str) -> Self {
+        let origins: Vec<&str> = origins
+            .split(',')
+            .map(str::trim)
+            .filter(|origin| !origin.is_empty())
+            .collect();
+        if origins.contains(&"*") {
+            return Self::Any;
+        }
+        let valid = origins.iter().filter_map(|origin| match HeaderValue::from_str(origin) {
+            Ok(value) => Some(value),
+            Err(_) => {
+                warn!("Ignoring invalid CORS origin: {}", origin);
+                None
+            }
+        });
+        Self::List(valid.collect())
+    }
+
+    /// Origins from `CORS_ALLOWED_ORIGINS`; when unset any origin in debug
+    /// mode and none otherwise
+    pub fn from_env(debug: bool) -> Self {
+        match std::env::var("CORS_ALLOWED_ORIGINS") {
+            Ok(origins) => Self::parse(&origins),
+            Err(_) if debug => Self::Any,
+            Err(_) => Self::List(Vec::new()),
+        }
+    }
+
+    /// Layer answering preflight requests and adding CORS headers for these
+    /// origins, with the methods and headers the endpoints use
+    fn layer(&self) -> CorsLayer {
+        let origins = match self {
+            Self::Any => AllowOrigin::any(),
+            Self::List(origins) => AllowOrigin::list(origins.clone()),
+        };
+        CorsLayer::new()
+            .allow_origin(origins)
+            .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
+            .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE])
+            .expose_headers([X_REQUEST_ID])
+    }
+}
 
 /// Application state
 #[derive(Clone)]
 pub struct AppState {
     translator: Arc<AsyncTranslator>,
+    /// Bearer token required on every route except the health check
+    api_key: Option<String>,
+    /// Body size limit of file uploads
+    max_upload_bytes: usize,
+    /// Origins browsers may call the API from
+    cors: CorsOrigins,
+    /// Requests being handled, reported when shutting down
+    in_flight: Arc<AtomicUsize>,
+    /// Exporter rendering `GET /metrics`; without one the route is not found
+    metrics: Option<PrometheusHandle>,
+}
+
+impl AppState {
+    /// State without authentication, with the default upload limit and no
+    /// cross-origin access
+    pub fn new(translator: Arc<AsyncTranslator>) -> Self {
+        Self {
+            translator,
+            api_key: None,
+            max_upload_bytes: DEFAULT_MAX_UPLOAD_BYTES,
+            cors: CorsOrigins::List(Vec::new()),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            metrics: None,
+        }
+    }
+
+    /// Create from environment variables (`SERVER_API_KEY`, `MAX_UPLOAD_BYTES`,
+    /// `CORS_ALLOWED_ORIGINS`, see [`CorsOrigins::from_env`])
+    pub fn from_env(translator: Arc<AsyncTranslator>, debug: bool) -> Self {
+        let api_key = std::env::var("SERVER_API_KEY")
+            .ok()
+            .filter(|key| !key.is_empty());
+        let max_upload_bytes = std::env::var("MAX_UPLOAD_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MAX_UPLOAD_BYTES);
+
+        Self::new(translator)
+            .with_api_key(api_key)
+            .with_max_upload_bytes(max_upload_bytes)
+            .with_cors_origins(CorsOrigins::from_env(debug))
+    }
+
+    /// Require `Authorization: Bearer <key>`; `None` leaves the API open
+    pub fn with_api_key(mut self, api_key: Option<String>) -> Self {
+        self.api_key = api_key;
+        self
+    }
+
+    /// Reject file uploads larger than `bytes`
+    pub fn with_max_upload_bytes(mut self, bytes: usize) -> Self {
+        self.max_upload_bytes = bytes;
+        self
+    }
+
+    /// Let browsers call the API from `origins`
+    pub fn with_cors_origins(mut self, origins: CorsOrigins) -> Self {
+        self.cors = origins;
+        self
+    }
+
+    /// Serve the metrics of `handle` at `GET /metrics`
+    pub fn with_metrics(mut self, handle: PrometheusHandle) -> Self {
+        self.metrics = Some(handle);
+        self
+    }
 }
 
 /// Health check response
-#[derive(Serialize)]
-struct HealthResponse {
+#[derive(Serialize, ToSchema)]
+pub(crate) struct HealthResponse {
     status: String,
     service: String,
     version: String,
 }
 
 /// Models list response
-#[derive(Serialize)]
-struct ModelsResponse {
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ModelsResponse {
+    /// Always `list`
     object: String,
+    /// Available models
     data: Vec<ModelInfo>,
 }
 
-#[derive(Serialize)]
-struct ModelInfo {
+/// One entry of the models list
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ModelInfo {
+    /// Model id, as sent to the API
     id: String,
+    /// Always `model`
     object: String,
+    /// Unix timestamp the model is listed as created at
     created: i64,
+    /// Provider of the model
     owned_by: String,
+    /// Lane the model serves, `slow` or `fast`
     lane: String,
+    /// Requests per minute the model allows
     rpm: u32,
+    /// Requests the model serves at the same time
     max_concurrent: usize,
 }
 
+/// Today's token usage, overall and broken down
+#[derive(Serialize, ToSchema)]
+pub(crate) struct UsageResponse {
+    /// Daily token quota
+    daily_limit: usize,
+    /// Tokens used today
+    used_today: usize,
+    /// Tokens left today
+    remaining: usize,
+    /// Tokens used today by model id
+    by_model: BTreeMap<String, usize>,
+    /// Tokens used today by lane of the model
+    by_lane: BTreeMap<String, usize>,
+}
+
 /// OpenAI compatible request
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct OpenAIRequest {
     pub model: String,
     pub messages: Vec<OpenAIMessage>,
     pub target_language: Option<String>,
+    /// Answer with `text/event-stream` chunks instead of one JSON body
+    #[serde(default)]
+    pub stream: bool,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, ToSchema)]
 pub struct OpenAIMessage {
     pub role: String,
     pub content: String,
 }
 
 /// OpenAI compatible response
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct OpenAIResponse {
     pub id: String,
     pub object: String,
@@ -70,14 +248,51 @@ pub struct OpenAIResponse {
     pub usage: Usage,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct OpenAIChoice {
     pub index: i32,
     pub message: OpenAIMessage,
     pub finish_reason: String,
 }
 
+/// One `data:` chunk of a streamed chat completion
+#[derive(Serialize)]
+struct OpenAIChunk {
+    /// Same for every chunk of a completion
+    id: String,
+    /// Always `chat.completion.chunk`
+    object: &'static str,
+    /// Unix timestamp of the completion
+    created: i64,
+    /// Model that translated
+    model: String,
+    /// The single choice this chunk adds to
+    choices: [OpenAIChunkChoice; 1],
+}
+
+/// Choice of an [`OpenAIChunk`]
 #[derive(Serialize)]
+struct OpenAIChunkChoice {
+    /// Index of the choice being streamed
+    index: i32,
+    /// What this chunk adds to the choice's message
+    delta: OpenAIDelta,
+    /// `stop` on the last chunk of a choice, `null` before
+    finish_reason: Option<&'static str>,
+}
+
+/// Part of a streamed message; the role comes first, then the content
+#[derive(Serialize, Default)]
+struct OpenAIDelta {
+    /// Role of the message, in its first chunk
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role: Option<&'static str>,
+    /// Text added to the message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
 pub struct Usage {
     pub prompt_tokens: usize,
     pub completion_tokens: usize,
@@ -85,32 +300,72 @@ pub struct Usage {
 }
 
 /// Custom translation request
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct TranslateRequest {
     pub source_lang: Option<String>,
     pub target_lang: String,
     pub text_list: Vec<String>,
 }
 
+/// Language detection request
+#[derive(Deserialize, ToSchema)]
+pub struct DetectRequest {
+    /// Texts to detect the language of
+    pub text_list: Vec<String>,
+}
+
+/// Language detection response, one detection per input text
+#[derive(Serialize, ToSchema)]
+pub struct DetectResponse {
+    /// Detections in input order
+    pub detections: Vec<Detection>,
+}
+
+/// Detected language of one text
+#[derive(Serialize, ToSchema)]
+pub struct Detection {
+    /// Language code, `None` if neither the API nor the local detector knew
+    pub language: Option<String>,
+    /// Who detected it: `api`, `local` or `none`
+    pub source: &'static str,
+}
+
 /// Custom translation response
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct TranslateResponse {
     pub translations: Vec<TranslationItem>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct TranslationItem {
     pub detected_source_lang: Option<String>,
     pub text: String,
 }
 
 /// Error response
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct ErrorResponse {
     pub error: ErrorDetail,
+    /// ID of the failed request (its `X-Request-Id`), to quote when reporting it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
 }
 
-#[derive(Serialize)]
+impl ErrorResponse {
+    /// Error body for the request being handled
+    fn new(message: impl Into<String>, code: impl Into<String>, r#type: &str) -> Self {
+        Self {
+            error: ErrorDetail {
+                message: message.into(),
+                code: Some(code.into()),
+                r#type: Some(r#type.to_string()),
+            },
+            request_id: request_id::current(),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
 pub struct ErrorDetail {
     pub message: String,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -120,6 +375,7 @@ pub struct ErrorDetail {
 }
 
 /// Health check handler
+#[utoipa::path(get, path = "/", responses((status = 200, body = HealthResponse)))]
 async fn health_check() -> axum::Json<HealthResponse> {
     axum::Json(HealthResponse {
         status: "ok".to_string(),
@@ -128,7 +384,21 @@ async fn health_check() -> axum::Json<HealthResponse> {
     })
 }
 
+/// `GET /metrics`: request, retry and token counters and latency
+/// histograms in the Prometheus text format. Public, like the health check.
+async fn get_metrics(State(state): State<Arc<AppState>>) -> Response {
+    match &state.metrics {
+        Some(handle) => (
+            [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+            handle.render(),
+        )
+            .into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
 /// Get models handler
+#[utoipa::path(get, path = "/v1/models", responses((status = 200, body = ModelsResponse)))]
 async fn get_models(State(state): State<Arc<AppState>>) -> axum::Json<ModelsResponse> {
     let models = state.translator.get_available_models();
     let model_infos: Vec<ModelInfo> = models
@@ -154,158 +424,1274 @@ async fn get_models(State(state): State<Arc<AppState>>) -> axum::Json<ModelsResp
     })
 }
 
+/// `GET /usage`: today's token usage by model and by lane
+#[utoipa::path(get, path = "/usage", responses((status = 200, body = UsageResponse)))]
+async fn get_usage(State(state): State<Arc<AppState>>) -> axum::Json<UsageResponse> {
+    let usage = state.translator.get_token_usage().await;
+    let mut by_lane = BTreeMap::new();
+    for (model, tokens) in &usage.by_model {
+        let lane = match state.translator.config().find_model(model) {
+            Some(m) => m.lane.to_string(),
+            None => "unknown".to_string(),
+        };
+        *by_lane.entry(lane).or_default() += tokens;
+    }
+    axum::Json(UsageResponse {
+        daily_limit: usage.daily_limit,
+        used_today: usage.used_today,
+        remaining: usage.remaining(),
+        by_model: usage.by_model,
+        by_lane,
+    })
+}
+
 /// OpenAI compatible translation handler
+///
+/// Every user message is translated on its own and answered by its own
+/// choice (in message order), so the turns of a conversation are not merged.
+/// Empty user messages get an empty choice; the request is rejected only when
+/// no user message has any text.
 async fn openai_compatible(
     State(state): State<Arc<AppState>>,
     Json(payload): Json<OpenAIRequest>,
 ) -> Result<axum::Json<OpenAIResponse>, axum::Json<ErrorResponse>> {
     // Extract text from messages
-    let text = payload
+    let texts: Vec<&str> = payload
         .messages
         .iter()
         .filter(|m| m.role == "user")
-        .map(|m| m.content.clone())
-        .collect::<Vec<String>>()
-        .join(" ");
+        .map(|m| m.content.as_str())
+        .collect();
 
-    if text.is_empty() {
-        return Err(axum::Json(ErrorResponse {
-            error: ErrorDetail {
-                message: "No text to translate".to_string(),
-                code: Some("invalid_request".to_string()),
-                r#type: Some("invalid_request_error".to_string()),
-            },
-        }));
+    if texts.is_empty() {
+        return Err(invalid_request("Request contains no user message to translate"));
+    }
+    if texts.iter().all(|text| text.trim().is_empty()) {
+        return Err(invalid_request("All user messages are empty, nothing to translate"));
     }
 
-    // Create translation request
+    // Create translation requests
     let target_lang = payload.target_language.unwrap_or_else(|| "zh".to_string());
-    let request = TranslationRequest::new(text, target_lang);
+    let requests: Vec<TranslationRequest> = texts
+        .iter()
+        .filter(|text| !text.trim().is_empty())
+        .map(|text| TranslationRequest::new(text.to_string(), target_lang.clone()))
+        .collect();
 
     // Translate
-    match state.translator.translate(&request).await {
-        Ok(result) => {
-            let response = OpenAIResponse {
-                id: format!("chatcmpl-{}", chrono::Utc::now().timestamp()),
-                object: "chat.completion".to_string(),
-                created: chrono::Utc::now().timestamp(),
-                model: result.model_used,
-                choices: vec![OpenAIChoice {
-                    index: 0,
-                    message: OpenAIMessage {
-                        role: "assistant".to_string(),
-                        content: result.translation,
-                    },
-                    finish_reason: "stop".to_string(),
-                }],
-                usage: Usage {
-                    prompt_tokens: result.tokens_used / 2, // Rough estimate
-                    completion_tokens: result.tokens_used / 2,
-                    total_tokens: result.tokens_used,
-                },
-            };
-
-            Ok(axum::Json(response))
+    let mut results = Vec::with_capacity(requests.len());
+    for result in state.translator.translate_batch(requests).await {
+        match result {
+            Ok(result) => {
+                debug!(upstream_request_id = ?result.request_id, "Translated");
+                results.push(result)
+            }
+            Err(e) => {
+                warn!("Translation failed: {}", e);
+                return Err(axum::Json(ErrorResponse::new(
+                    e.to_string(),
+                    "translation_error",
+                    "api_error",
+                )));
+            }
         }
-        Err(e) => {
-            warn!("Translation failed: {}", e);
-            Err(axum::Json(ErrorResponse {
-                error: ErrorDetail {
-                    message: e.to_string(),
-                    code: Some("translation_error".to_string()),
-                    r#type: Some("api_error".to_string()),
+    }
+
+    let model = results
+        .first()
+        .map(|result| result.model_used.clone())
+        .unwrap_or(payload.model);
+    let total_tokens: usize = results.iter().map(|result| result.tokens_used).sum();
+    // The API bills input and output together; estimate the input share
+    let prompt_tokens = texts
+        .iter()
+        .map(|text| estimate_tokens(text, &model))
+        .sum::<usize>()
+        .min(total_tokens);
+    let mut results = results.into_iter();
+    let choices = texts
+        .iter()
+        .enumerate()
+        .map(|(index, text)| {
+            let content = if text.trim().is_empty() {
+                String::new()
+            } else {
+                results.next().map(|result| result.translation).unwrap_or_default()
+            };
+            OpenAIChoice {
+                index: index as i32,
+                message: OpenAIMessage {
+                    role: "assistant".to_string(),
+                    content,
                 },
-            }))
+                finish_reason: "stop".to_string(),
+            }
+        })
+        .collect();
+
+    Ok(axum::Json(OpenAIResponse {
+        id: format!("chatcmpl-{}", chrono::Utc::now().timestamp()),
+        object: "chat.completion".to_string(),
+        created: chrono::Utc::now().timestamp(),
+        model,
+        choices,
+        usage: Usage {
+            prompt_tokens,
+            completion_tokens: total_tokens - prompt_tokens,
+            total_tokens,
+        },
+    }))
+}
+
+/// `POST /v1/chat/completions`: a JSON completion, or with `stream: true`
+/// the same completion as server-sent events.
+///
+/// Translation is not streamed upstream, so each choice is sent whole: a
+/// chunk with the role, one with the content and one with the finish reason,
+/// then `data: [DONE]`.
+#[utoipa::path(
+    post,
+    path = "/v1/chat/completions",
+    request_body = OpenAIRequest,
+    responses((
+        status = 200,
+        description = "Completion (or an error body); server-sent chunks with `stream: true`",
+        content(
+            ("application/json" = OpenAIResponse),
+            ("text/event-stream" = String),
+        )
+    ))
+)]
+async fn chat_completions(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<OpenAIRequest>,
+) -> Response {
+    let stream = payload.stream;
+    let response = match openai_compatible(State(state), Json(payload)).await {
+        Ok(axum::Json(response)) => response,
+        Err(error) => return error.into_response(),
+    };
+    if !stream {
+        return axum::Json(response).into_response();
+    }
+
+    let chunk = |index, delta, finish_reason| OpenAIChunk {
+        id: response.id.clone(),
+        object: "chat.completion.chunk",
+        created: response.created,
+        model: response.model.clone(),
+        choices: [OpenAIChunkChoice {
+            index,
+            delta,
+            finish_reason,
+        }],
+    };
+    let mut events = Vec::with_capacity(response.choices.len() * 3 + 1);
+    for choice in &response.choices {
+        let chunks = [
+            chunk(choice.index, OpenAIDelta { role: Some("assistant"), content: None }, None),
+            chunk(
+                choice.index,
+                OpenAIDelta { role: None, content: Some(choice.message.content.clone()) },
+                None,
+            ),
+            chunk(choice.index, OpenAIDelta::default(), Some("stop")),
+        ];
+        for chunk in chunks {
+            match Event::default().json_data(chunk) {
+                Ok(event) => events.push(event),
+                Err(e) => return invalid_request(&e.to_string()).into_response(),
+            }
         }
     }
+    events.push(Event::default().data("[DONE]"));
+    let events = futures::stream::iter(events.into_iter().map(Ok::<_, std::convert::Infallible>));
+    Sse::new(events).into_response()
+}
+
+/// `invalid_request` error body with `message`
+fn invalid_request(message: &str) -> axum::Json<ErrorResponse> {
+    axum::Json(ErrorResponse::new(message, "invalid_request", "invalid_request_error"))
 }
 
 /// Custom translation handler
+#[utoipa::path(
+    post,
+    path = "/translate",
+    request_body = TranslateRequest,
+    responses(
+        (status = 200, description = "Translations, or an error body", body = TranslateResponse)
+    )
+)]
 async fn translate(
     State(state): State<Arc<AppState>>,
     Json(payload): Json<TranslateRequest>,
 ) -> Result<axum::Json<TranslateResponse>, axum::Json<ErrorResponse>> {
     if payload.text_list.is_empty() {
-        return Err(axum::Json(ErrorResponse {
-            error: ErrorDetail {
-                message: "text_list cannot be empty".to_string(),
-                code: Some("invalid_request".to_string()),
-                r#type: Some("invalid_request_error".to_string()),
-            },
-        }));
+        return Err(invalid_request("text_list cannot be empty"));
     }
 
-    // Convert language codes
-    let target_lang = match payload.target_lang.as_str() {
+    // Convert language codes once for the whole list
+    let target_lang = api_lang_code(&payload.target_lang);
+    let source_lang = payload.source_lang.as_deref().map(api_lang_code).filter(|l| !l.is_empty());
+
+    // Translate concurrently; the client's semaphore bounds the requests in flight
+    let translations = payload.text_list.into_iter().map(|text| {
+        let mut request = TranslationRequest::new(text.clone(), target_lang.to_string());
+        if let Some(lang) = source_lang {
+            request = request.with_source_lang(lang);
+        }
+        let translator = &state.translator;
+        async move {
+            match translator.translate(&request).await {
+                Ok(result) => {
+                    debug!(upstream_request_id = ?result.request_id, "Translated");
+                    TranslationItem {
+                        detected_source_lang: result.detected_source_lang,
+                        text: result.translation,
+                    }
+                }
+                Err(e) => {
+                    warn!("Translation failed for '{}': {}", text, e);
+                    // Return original text on error
+                    TranslationItem {
+                        detected_source_lang: None,
+                        text,
+                    }
+                }
+            }
+        }
+    });
+    let translations = futures::future::join_all(translations).await;
+
+    Ok(axum::Json(TranslateResponse { translations }))
+}
+
+/// Language code as the API names it: `zh-CN` is `zh`, `zh-TW` is
+/// `zh-Hant`, `no` is `nb`, and `auto` is empty (let the API detect it)
+fn api_lang_code(code: &str) -> &str {
+    match code {
         "zh-CN" => "zh",
         "zh-TW" => "zh-Hant",
         "auto" => "",
         "no" => "nb",
-        _ => &payload.target_lang,
-    };
+        _ => code,
+    }
+}
 
-    let source_lang = match payload.source_lang.as_deref() {
-        Some("zh-CN") => Some("zh".to_string()),
-        Some("zh-TW") => Some("zh-Hant".to_string()),
-        Some("auto") => None,
-        Some("no") => Some("nb".to_string()),
-        Some(lang) => Some(lang.to_string()),
-        None => None,
-    };
+/// `POST /detect`: the language of each text, without translating it.
+///
+/// The API's detection is used when it reports one; otherwise (or when the
+/// request fails) the language is guessed locally.
+#[utoipa::path(
+    post,
+    path = "/detect",
+    request_body = DetectRequest,
+    responses((status = 200, description = "Detections, or an error body", body = DetectResponse))
+)]
+async fn detect(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<DetectRequest>,
+) -> Result<axum::Json<DetectResponse>, axum::Json<ErrorResponse>> {
+    if payload.text_list.is_empty() {
+        return Err(invalid_request("text_list cannot be empty"));
+    }
 
-    // Translate each text
-    let mut translations = Vec::new();
-    for text in payload.text_list {
-        let mut request = TranslationRequest::new(text.clone(), target_lang.to_string());
-        if let Some(ref lang) = source_lang {
-            request = request.with_source_lang(lang);
+    let detections = payload.text_list.iter().map(|text| async {
+        if text.trim().is_empty() {
+            return Detection { language: None, source: "none" };
+        }
+        match state.translator.detect_language(text).await {
+            Ok(Some(language)) => return Detection { language: Some(language), source: "api" },
+            Ok(None) => {}
+            Err(e) => warn!("Language detection failed, guessing locally: {}", e),
+        }
+        match detect_language(text) {
+            Some(language) => Detection { language: Some(language), source: "local" },
+            None => Detection { language: None, source: "none" },
         }
+    });
+    let detections = futures::future::join_all(detections).await;
+    Ok(axum::Json(DetectResponse { detections }))
+}
 
-        match state.translator.translate(&request).await {
-            Ok(result) => {
-                translations.push(TranslationItem {
-                    detected_source_lang: result.detected_source_lang,
-                    text: result.translation,
-                });
+/// Reject requests without the configured bearer token
+async fn require_api_key(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(key) = &state.api_key {
+        let token = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if token != Some(key.as_str()) {
+            return file_error(StatusCode::UNAUTHORIZED, "Missing or invalid API key")
+                .into_response();
+        }
+    }
+    next.run(request).await
+}
+
+/// Error body with an HTTP status, for handlers that return files
+fn file_error(status: StatusCode, message: &str) -> (StatusCode, axum::Json<ErrorResponse>) {
+    let r#type = if status.is_client_error() {
+        "invalid_request_error"
+    } else {
+        "api_error"
+    };
+    (
+        status,
+        axum::Json(ErrorResponse::new(message, status.as_u16().to_string(), r#type)),
+    )
+}
+
+/// Translate an uploaded file and send the translated file back.
+///
+/// Multipart fields: `file` (Markdown, ePub, HTML, SRT or plain text, recognised by the file
+/// name's extension), `target_lang` and optionally `source_lang`. The
+/// response is named `<stem>_<target_lang>.<ext>`.
+async fn translate_file(
+    State(state): State<Arc<AppState>>,
+    mut multipart: Multipart,
+) -> Result<Response, (StatusCode, axum::Json<ErrorResponse>)> {
+    let mut upload: Option<(String, axum::body::Bytes)> = None;
+    let mut target_lang = None;
+    let mut source_lang = None;
+
+    let multipart_error = |e: axum::extract::multipart::MultipartError| {
+        file_error(e.status(), &e.body_text())
+    };
+    while let Some(field) = multipart.next_field().await.map_err(multipart_error)? {
+        match field.name() {
+            Some("file") => {
+                let name = field.file_name().unwrap_or_default().to_string();
+                let bytes = field.bytes().await.map_err(multipart_error)?;
+                upload = Some((name, bytes));
             }
-            Err(e) => {
-                warn!("Translation failed for '{}': {}", text, e);
-                // Return original text on error
-                translations.push(TranslationItem {
-                    detected_source_lang: None,
-                    text,
-                });
+            Some("target_lang") => {
+                target_lang = Some(field.text().await.map_err(multipart_error)?);
+            }
+            Some("source_lang") => {
+                source_lang = Some(field.text().await.map_err(multipart_error)?);
             }
+            _ => {}
         }
     }
 
-    Ok(axum::Json(TranslateResponse { translations }))
+    let (file_name, bytes) =
+        upload.ok_or_else(|| file_error(StatusCode::BAD_REQUEST, "Missing `file` field"))?;
+    let target_lang = target_lang
+        .map(|lang| lang.trim().to_string())
+        .filter(|lang| !lang.is_empty())
+        .ok_or_else(|| file_error(StatusCode::BAD_REQUEST, "Missing `target_lang` field"))?;
+    if !is_language_tag(&target_lang) {
+        return Err(file_error(StatusCode::BAD_REQUEST, "Invalid `target_lang` field"));
+    }
+    let source_lang = source_lang.filter(|lang| !lang.trim().is_empty() && lang != "auto");
+
+    // Only the final component of the client's name is used
+    let file_name = Path::new(&file_name)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| file_error(StatusCode::BAD_REQUEST, "Uploaded file has no name"))?;
+    let input_path = Path::new(&file_name);
+    let extension = input_path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    let stem = input_path
+        .file_stem()
+        .map_or("translated".into(), |stem| stem.to_string_lossy());
+    let output_name = format!("{}_{}.{}", stem, target_lang, extension);
+
+    let internal = |e: crate::core::errors::TranslationError| {
+        warn!("File translation failed: {}", e);
+        file_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string())
+    };
+    let dir = tempfile::tempdir().map_err(|e| internal(e.into()))?;
+    // The client's names are only used for the download name
+    let input = dir.path().join(format!("input.{}", extension));
+    let output = dir.path().join(format!("output.{}", extension));
+    tokio::fs::write(&input, &bytes)
+        .await
+        .map_err(|e| internal(e.into()))?;
+
+    let translator = (*state.translator).clone();
+    let content_type = match extension.as_str() {
+        "md" | "markdown" => {
+            MarkdownProcessor::new(translator)
+                .translate_file(&input, &output, &target_lang, source_lang)
+                .await
+                .map_err(internal)?;
+            "text/markdown; charset=utf-8"
+        }
+        "epub" => {
+            EpubProcessor::new(translator)
+                .translate_epub(&input, &output, &target_lang, source_lang, true)
+                .await
+                .map_err(internal)?;
+            "application/epub+zip"
+        }
+        "srt" => {
+            SubtitleProcessor::new(translator)
+                .translate_file(&input, &output, &target_lang, source_lang)
+                .await
+                .map_err(internal)?;
+            "application/x-subrip; charset=utf-8"
+        }
+        "txt" => {
+            TextProcessor::new(translator)
+                .translate_file(&input, &output, &target_lang, source_lang)
+                .await
+                .map_err(internal)?;
+            "text/plain; charset=utf-8"
+        }
+        "html" | "htm" | "xhtml" => {
+            HtmlProcessor::new(translator)
+                .translate_file(&input, &output, &target_lang, source_lang)
+                .await
+                .map_err(internal)?;
+            "text/html; charset=utf-8"
+        }
+        _ => {
+            return Err(file_error(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                &format!("Unsupported file type: {}", file_name),
+            ))
+        }
+    };
+
+    let translated = tokio::fs::read(&output)
+        .await
+        .map_err(|e| internal(e.into()))?;
+    info!("Translated uploaded file {} -> {}", file_name, output_name);
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, content_disposition(&output_name)),
+        ],
+        translated,
+    )
+        .into_response())
+}
+
+/// Whether `lang` is a plain language tag such as `zh-CN`
+fn is_language_tag(lang: &str) -> bool {
+    lang.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// `Content-Disposition` value offering `file_name` as a download.
+///
+/// Non-ASCII names are sent percent-encoded in `filename*`, with an ASCII
+/// fallback in `filename`.
+fn content_disposition(file_name: &str) -> String {
+    let fallback: String = file_name
+        .chars()
+        .map(|c| {
+            let plain = (c.is_ascii_graphic() || c == ' ') && c != '"' && c != '\\';
+            if plain {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if fallback == file_name {
+        return format!("attachment; filename=\"{}\"", file_name);
+    }
+    let encoded: String = file_name
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'.' | b'-' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect();
+    format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", fallback, encoded)
+}
+
+/// State of a model after an admin change
+#[derive(Serialize)]
+struct ModelStatus {
+    /// Model ID
+    id: String,
+    /// Whether the model is routed to now
+    enabled: bool,
+}
+
+/// `POST /admin/models/:id/disable`: stop routing requests to a model
+async fn disable_model(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+) -> std::result::Result<axum::Json<ModelStatus>, (StatusCode, axum::Json<ErrorResponse>)> {
+    set_model_enabled(&state, id, false)
+}
+
+/// `POST /admin/models/:id/enable`: route requests to a model again
+async fn enable_model(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+) -> std::result::Result<axum::Json<ModelStatus>, (StatusCode, axum::Json<ErrorResponse>)> {
+    set_model_enabled(&state, id, true)
+}
+
+/// Flip a model for both admin endpoints.
+///
+/// Admin changes need an API key: without `SERVER_API_KEY` they are refused.
+fn set_model_enabled(
+    state: &AppState,
+    id: String,
+    enabled: bool,
+) -> std::result::Result<axum::Json<ModelStatus>, (StatusCode, axum::Json<ErrorResponse>)> {
+    if state.api_key.is_none() {
+        return Err(file_error(
+            StatusCode::FORBIDDEN,
+            "Admin endpoints require SERVER_API_KEY to be set",
+        ));
+    }
+    if state.translator.set_model_enabled(&id, enabled).is_err() {
+        return Err(file_error(
+            StatusCode::NOT_FOUND,
+            &format!("Unknown model: {}", id),
+        ));
+    }
+    let enabled = state.translator.is_model_enabled(&id);
+    Ok(axum::Json(ModelStatus { id, enabled }))
 }
 
 /// Run the HTTP server
-pub async fn run_server(host: String, port: u16) -> anyhow::Result<()> {
+///
+/// In `debug` mode any origin may call the API unless `CORS_ALLOWED_ORIGINS`
+/// says otherwise.
+pub async fn run_server(
+    config: TranslatorConfig,
+    host: String,
+    port: u16,
+    debug: bool,
+) -> anyhow::Result<()> {
     // Create translator
-    let translator = Arc::new(AsyncTranslator::from_env()?);
+    let translator = Arc::new(AsyncTranslator::new(config)?);
 
-    // Create app state
-    let state = Arc::new(AppState { translator });
+    // Persist token usage across restarts when TOKEN_USAGE_FILE is set
+    let flusher = match std::env::var("TOKEN_USAGE_FILE").ok().filter(|p| !p.is_empty()) {
+        Some(path) => {
+            let path = PathBuf::from(path);
+            if let Some(saved) = UsageSnapshot::load(&path)? {
+                saved.restore(&translator).await;
+                info!("Restored token usage and spend from {}", path.display());
+            }
+            let period = std::env::var("USAGE_FLUSH_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .map_or(DEFAULT_FLUSH_INTERVAL, Duration::from_secs);
+            let flusher = Arc::new(UsageFlusher::new(translator.clone(), path));
+            let task = flusher.clone().spawn(interval_ticks(period));
+            Some((flusher, task))
+        }
+        None => None,
+    };
 
-    // Create router
-    let app = Router::new()
-        .route("/", get(health_check))
-        .route("/v1/models", get(get_models))
-        .route("/v1/chat/completions", post(openai_compatible))
-        .route("/translate", post(translate))
-        .with_state(state);
+    // Create app state
+    let state = Arc::new(AppState::from_env(translator, debug).with_metrics(install_prometheus()?));
+    if state.api_key.is_none() {
+        warn!("SERVER_API_KEY is not set, the API accepts unauthenticated requests");
+    }
 
     // Bind address
     let addr: SocketAddr = format!("{}:{}", host, port).parse()?;
 
     info!("Starting server on {}", addr);
 
-    // Start server
+    // Start server; SIGINT or SIGTERM stops it once in-flight requests are done
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    let (stop, shutdown) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        let _ = stop.send(true);
+    });
+    serve_until(listener, state, shutdown).await?;
+
+    // Stop the periodic flush first so the final one is the last write
+    if let Some((flusher, task)) = flusher {
+        task.abort();
+        let _ = task.await;
+        flusher.flush().await?;
+    }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Serve the API on `listener` until `shutdown` turns `true` (or its
+/// sender is dropped), then stop accepting connections and wait for the
+/// requests in flight to finish
+pub async fn serve_until(
+    listener: tokio::net::TcpListener,
+    state: Arc<AppState>,
+    mut shutdown: watch::Receiver<bool>,
+) -> std::io::Result<()> {
+    let in_flight = state.in_flight.clone();
+    axum::serve(listener, router(state))
+        .with_graceful_shutdown(async move {
+            let _ = shutdown.wait_for(|stop| *stop).await;
+            info!(
+                "Shutting down, waiting for {} in-flight requests",
+                in_flight.load(Ordering::SeqCst)
+            );
+        })
+        .await
+}
+
+/// Resolves on Ctrl-C (SIGINT) or, on Unix, SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                warn!("Cannot listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+/// Count the request as in flight while it is handled
+async fn count_in_flight(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    /// Decrements the count when the request ends, even if it is cancelled
+    struct InFlight(Arc<AtomicUsize>);
+    impl Drop for InFlight {
+        fn drop(&mut self) {
+            self.0.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    state.in_flight.fetch_add(1, Ordering::SeqCst);
+    let _guard = InFlight(state.in_flight.clone());
+    next.run(request).await
+}
+
+/// All routes of the API; everything but the health check requires the API key
+fn router(state: Arc<AppState>) -> Router {
+    let upload_limit = DefaultBodyLimit::max(state.max_upload_bytes);
+    let routes = Router::new()
+        .route("/v1/models", get(get_models))
+        .route("/v1/chat/completions", post(chat_completions))
+        .route("/usage", get(get_usage))
+        .route("/translate", post(translate))
+        .route("/detect", post(detect))
+        .route("/translate/file", post(translate_file).layer(upload_limit))
+        .route("/admin/models/:id/disable", post(disable_model))
+        .route("/admin/models/:id/enable", post(enable_model))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_api_key))
+        .route("/", get(health_check))
+        .route("/metrics", get(get_metrics))
+        .merge(docs::routes())
+        .layer(middleware::from_fn_with_state(state.clone(), count_in_flight));
+    request_id::layer(routes)
+        .layer(state.cors.layer())
+        .with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{test_translator, MockServer};
+
+    /// Request with the given `(role, content)` messages
+    fn chat(messages: &[(&str, &str)]) -> OpenAIRequest {
+        OpenAIRequest {
+            model: "doubao".to_string(),
+            messages: messages
+                .iter()
+                .map(|(role, content)| OpenAIMessage {
+                    role: role.to_string(),
+                    content: content.to_string(),
+                })
+                .collect(),
+            target_language: Some("zh".to_string()),
+            stream: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_openai_translates_each_user_message() {
+        let server = MockServer::echo().await;
+        let state = Arc::new(AppState::new(Arc::new(test_translator(&server.url))));
+
+        let payload = chat(&[
+            ("system", "You are a translator"),
+            ("user", "Good morning."),
+            ("assistant", "早上好。"),
+            ("user", "See you later."),
+        ]);
+        let response = openai_compatible(State(state), Json(payload)).await.ok().unwrap();
+
+        let contents: Vec<&str> = response
+            .choices
+            .iter()
+            .map(|choice| choice.message.content.as_str())
+            .collect();
+        assert_eq!(contents, vec!["zh:Good morning.", "zh:See you later."]);
+        assert_eq!(response.choices[1].index, 1);
+        assert_eq!(server.sent_texts(), vec!["Good morning.", "See you later."]);
+    }
+
+    #[tokio::test]
+    async fn test_openai_streams_server_sent_events() {
+        let server = MockServer::echo().await;
+        let url = serve(AppState::new(Arc::new(test_translator(&server.url)))).await;
+
+        let response = reqwest::Client::new()
+            .post(format!("{}/v1/chat/completions", url))
+            .json(&serde_json::json!({
+                "model": "doubao",
+                "messages": [{"role": "user", "content": "Hello"}],
+                "stream": true,
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+        let body = response.text().await.unwrap();
+
+        // Every event is a single `data:` line followed by a blank line
+        let events: Vec<&str> = body.split_terminator("\n\n").collect();
+        assert!(events.iter().all(|event| event.starts_with("data: ") && !event.contains('\n')));
+        assert_eq!(events.last(), Some(&"data: [DONE]"));
+
+        let chunks: Vec<serde_json::Value> = events[..events.len() - 1]
+            .iter()
+            .map(|event| serde_json::from_str(&event["data: ".len()..]).unwrap())
+            .collect();
+        assert!(chunks.iter().all(|chunk| chunk["object"] == "chat.completion.chunk"));
+        assert_eq!(chunks[0]["choices"][0]["delta"], serde_json::json!({"role": "assistant"}));
+        let content: String = chunks
+            .iter()
+            .filter_map(|chunk| chunk["choices"][0]["delta"]["content"].as_str())
+            .collect();
+        assert_eq!(content, "zh:Hello");
+        assert_eq!(chunks.last().unwrap()["choices"][0]["finish_reason"], "stop");
+    }
+
+    #[tokio::test]
+    async fn test_openai_rejects_requests_without_user_text() {
+        let server = MockServer::echo().await;
+        let state = Arc::new(AppState::new(Arc::new(test_translator(&server.url))));
+
+        let payload = chat(&[("system", "You are a translator")]);
+        let error = openai_compatible(State(state.clone()), Json(payload)).await.err().unwrap();
+        assert!(error.error.message.contains("no user message"));
+
+        let payload = chat(&[("user", "  "), ("user", "")]);
+        let error = openai_compatible(State(state), Json(payload)).await.err().unwrap();
+        assert!(error.error.message.contains("empty"));
+        assert_eq!(server.request_count(), 0);
+    }
+
+    /// Serve `state` on a free local port and return its base URL
+    async fn serve(state: AppState) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, router(Arc::new(state))).await.unwrap();
+        });
+        format!("http://{}", addr)
+    }
+
+    /// POST a multipart upload of `file_name` with `content` to `/translate/file`
+    async fn upload(
+        url: &str,
+        api_key: Option<&str>,
+        target_lang: &str,
+        file_name: &str,
+        content: &str,
+    ) -> reqwest::Response {
+        const BOUNDARY: &str = "test-boundary";
+        let body = format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"target_lang\"\r\n\r\n{lang}\r\n\
+             --{b}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{name}\"\r\n\
+             Content-Type: text/markdown\r\n\r\n{content}\r\n--{b}--\r\n",
+            b = BOUNDARY,
+            lang = target_lang,
+            name = file_name,
+            content = content,
+        );
+        let mut request = reqwest::Client::new()
+            .post(format!("{}/translate/file", url))
+            .header(
+                "Content-Type",
+                format!("multipart/form-data; boundary={}", BOUNDARY),
+            )
+            .body(body);
+        if let Some(key) = api_key {
+            request = request.bearer_auth(key);
+        }
+        request.send().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_responses_carry_request_ids() {
+        let server = MockServer::echo().await;
+        let state = AppState::new(Arc::new(test_translator(&server.url)))
+            .with_api_key(Some("secret".to_string()));
+        let url = serve(state).await;
+        let client = reqwest::Client::new();
+        let request_id = |response: &reqwest::Response| {
+            response.headers()["x-request-id"].to_str().unwrap().to_string()
+        };
+
+        let health = client.get(&url).send().await.unwrap();
+        let unauthorized = client.get(format!("{}/usage", url)).send().await.unwrap();
+        assert_eq!(unauthorized.status(), 401);
+        let (first, second) = (request_id(&health), request_id(&unauthorized));
+        assert_eq!(first.len(), 26, "{}", first);
+        assert_ne!(first, second);
+
+        // Error bodies quote the same ID
+        let invalid = client
+            .post(format!("{}/translate", url))
+            .bearer_auth("secret")
+            .json(&serde_json::json!({"text_list": [], "target_lang": "zh"}))
+            .send()
+            .await
+            .unwrap();
+        let id = request_id(&invalid);
+        let body: serde_json::Value = invalid.json().await.unwrap();
+        assert_eq!(body["request_id"], id.as_str());
+
+        // A caller's own ID is kept
+        let tagged = client.get(&url).header("x-request-id", "abc-123").send().await.unwrap();
+        assert_eq!(request_id(&tagged), "abc-123");
+    }
+
+    #[tokio::test]
+    async fn test_metrics_count_translation_requests() {
+        let server = MockServer::echo().await;
+        let state = AppState::new(Arc::new(test_translator(&server.url)))
+            .with_metrics(install_prometheus().unwrap());
+        let url = serve(state).await;
+
+        let scrape = || async {
+            let response = reqwest::get(format!("{}/metrics", url)).await.unwrap();
+            response.text().await.unwrap()
+        };
+        // Other tests share the global recorder, so only look at the increase
+        let successes = || async {
+            scrape()
+                .await
+                .lines()
+                .filter(|line| {
+                    line.starts_with("translator_requests_total{")
+                        && line.contains("lane=\"slow\"")
+                        && line.contains("outcome=\"success\"")
+                })
+                .map(|line| line.rsplit(' ').next().unwrap().parse::<f64>().unwrap())
+                .sum::<f64>()
+        };
+        let before = successes().await;
+
+        let response = reqwest::Client::new()
+            .post(format!("{}/translate", url))
+            .json(&serde_json::json!({"text_list": ["Hello"], "target_lang": "zh"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        assert!(successes().await >= before + 1.0);
+        let text = scrape().await;
+        assert!(text.contains("translator_tokens_total{"), "{}", text);
+        assert!(text.contains("translator_request_duration_seconds_bucket{"), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_waits_for_requests_in_flight() {
+        use crate::testing::{echo_translation, MockReply};
+
+        let server = MockServer::start(|req| {
+            let translated = echo_translation(&req.target_lang(), &req.input_texts()[0]);
+            MockReply::translations(&[translated]).with_delay(Duration::from_millis(300))
+        })
+        .await;
+        let state = Arc::new(AppState::new(Arc::new(test_translator(&server.url))));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (stop, shutdown) = watch::channel(false);
+        let serving = tokio::spawn(serve_until(listener, state.clone(), shutdown));
+
+        let request = tokio::spawn(
+            reqwest::Client::new()
+                .post(format!("{}/translate", url))
+                .json(&serde_json::json!({"text_list": ["Hello"], "target_lang": "zh"}))
+                .send(),
+        );
+        while state.in_flight.load(Ordering::SeqCst) == 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        stop.send(true).unwrap();
+
+        // The request in flight still gets its answer, then the server stops
+        let response = request.await.unwrap().unwrap();
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["translations"][0]["text"], "zh:Hello");
+        tokio::time::timeout(Duration::from_secs(5), serving)
+            .await
+            .expect("server did not stop")
+            .unwrap()
+            .unwrap();
+        assert_eq!(state.in_flight.load(Ordering::SeqCst), 0);
+        assert!(reqwest::get(&url).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_translate_runs_concurrently_and_keeps_order() {
+        use crate::testing::{echo_translation, test_config, MockReply};
+
+        // Earlier items answer later, so finishing order is the reverse of input order
+        let server = MockServer::start(|req| {
+            let text = req.input_texts().remove(0);
+            let n: u64 = text.trim_start_matches("item ").parse().unwrap();
+            let delay = Duration::from_millis((20 - n) * 10);
+            if n % 7 == 3 {
+                return MockReply::error(500, "internal error").with_delay(delay);
+            }
+            MockReply::translations(&[echo_translation(&req.target_lang(), &text)])
+                .with_delay(delay)
+        })
+        .await;
+        let mut config = test_config(&server.url);
+        config.max_rps = 1000.0;
+        let translator = AsyncTranslator::new(config).unwrap();
+        let url = serve(AppState::new(Arc::new(translator))).await;
+
+        let texts: Vec<String> = (0..20).map(|n| format!("item {}", n)).collect();
+        let started = std::time::Instant::now();
+        let response: serde_json::Value = reqwest::Client::new()
+            .post(format!("{}/translate", url))
+            .json(&serde_json::json!({"text_list": texts, "target_lang": "zh-CN"}))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        // One at a time this takes over two seconds
+        assert!(started.elapsed() < Duration::from_millis(1500), "{:?}", started.elapsed());
+
+        let translated: Vec<&str> = response["translations"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["text"].as_str().unwrap())
+            .collect();
+        let expected: Vec<String> = texts
+            .iter()
+            .enumerate()
+            .map(|(n, text)| match n % 7 {
+                3 => text.clone(),
+                _ => echo_translation("zh", text),
+            })
+            .collect();
+        assert_eq!(translated, expected);
+        assert_eq!(server.request_count(), 20);
+    }
+
+    #[tokio::test]
+    async fn test_translate_file_returns_translated_markdown() {
+        let server = MockServer::echo().await;
+        let url = serve(AppState::new(Arc::new(test_translator(&server.url)))).await;
+
+        let response = upload(&url, None, "zh", "notes.md", "# Title\n\nHello world.\n").await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers()["content-disposition"],
+            "attachment; filename=\"notes_zh.md\""
+        );
+        assert!(response.headers()["content-type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/markdown"));
+        assert_eq!(response.text().await.unwrap(), "# zh:Title\n\nzh:Hello world.\n");
+
+        let response = upload(&url, None, "zh", "notes.docx", "binary").await;
+        assert_eq!(response.status(), 415);
+    }
+
+    #[tokio::test]
+    async fn test_translate_file_rejects_path_in_target_lang() {
+        let server = MockServer::echo().await;
+        let url = serve(AppState::new(Arc::new(test_translator(&server.url)))).await;
+        let dir = tempfile::tempdir().unwrap();
+        let escape = dir.path().join("escaped");
+        let target_lang = format!("x/../../../../../../..{}", escape.display());
+
+        let response = upload(&url, None, &target_lang, "doc.md", "Hello.\n").await;
+        assert_eq!(response.status(), 400);
+        assert!(!escape.with_extension("md").exists());
+        assert_eq!(upload(&url, None, "..", "doc.md", "Hello.\n").await.status(), 400);
+        assert_eq!(server.request_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_translate_file_enforces_auth_and_size_limit() {
+        let server = MockServer::echo().await;
+        let state = AppState::new(Arc::new(test_translator(&server.url)))
+            .with_api_key(Some("secret".to_string()))
+            .with_max_upload_bytes(1024);
+        let url = serve(state).await;
+
+        assert_eq!(upload(&url, None, "zh", "a.md", "Hi.\n").await.status(), 401);
+        assert_eq!(upload(&url, Some("wrong"), "zh", "a.md", "Hi.\n").await.status(), 401);
+        assert_eq!(upload(&url, Some("secret"), "zh", "a.md", "Hi.\n").await.status(), 200);
+
+        let large = "Too long. ".repeat(200);
+        assert_eq!(upload(&url, Some("secret"), "zh", "a.md", &large).await.status(), 413);
+        // The health check stays open
+        let health = reqwest::get(&url).await.unwrap();
+        assert_eq!(health.status(), 200);
+        assert_eq!(server.request_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_disabled_model_is_not_listed_or_routed() {
+        use crate::core::models::LaneType;
+        use crate::testing::{test_config, test_model};
+
+        let server = MockServer::echo().await;
+        let mut config = test_config(&server.url);
+        config.models.push(test_model("mock-backup", LaneType::Slow));
+        let translator = Arc::new(AsyncTranslator::new(config).unwrap());
+        let state = AppState::new(translator.clone()).with_api_key(Some("secret".to_string()));
+        let url = serve(state).await;
+        let client = reqwest::Client::new();
+        let admin = |action: &str, id: &str| {
+            client
+                .post(format!("{}/admin/models/{}/{}", url, id, action))
+                .bearer_auth("secret")
+                .send()
+        };
+
+        let response = admin("disable", "mock-slow").await.unwrap();
+        assert_eq!(response.status(), 200);
+        let status: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(status, serde_json::json!({"id": "mock-slow", "enabled": false}));
+        assert_eq!(admin("disable", "missing").await.unwrap().status(), 404);
+
+        let models: serde_json::Value = client
+            .get(format!("{}/v1/models", url))
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let ids: Vec<&str> = models["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| m["id"].as_str().unwrap())
+            .collect();
+        assert_eq!(ids, vec!["mock-backup"]);
+
+        let request = TranslationRequest::new("Hello".to_string(), "zh".to_string());
+        let result = translator.translate(&request).await.unwrap();
+        assert_eq!(result.model_used, "mock-backup");
+        assert_eq!(server.requests()[0].model(), "mock-backup");
+
+        assert_eq!(admin("enable", "mock-slow").await.unwrap().status(), 200);
+        assert_eq!(translator.get_available_models().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_usage_is_tallied_per_model_and_lane() {
+        use crate::core::models::{LaneType, Priority};
+        use crate::testing::{test_config, test_model};
+
+        let server = MockServer::echo().await;
+        let mut config = test_config(&server.url);
+        config.models.push(test_model("mock-fast", LaneType::Fast));
+        let translator = Arc::new(AsyncTranslator::new(config).unwrap());
+        let slow = TranslationRequest::new("paragraph. ".repeat(200), "zh".to_string());
+        translator.translate(&slow).await.unwrap();
+        for text in ["Hello", "World"] {
+            let fast = TranslationRequest::new(text.to_string(), "zh".to_string())
+                .with_priority(Priority::High);
+            translator.translate(&fast).await.unwrap();
+        }
+
+        let url = serve(AppState::new(translator.clone())).await;
+        let usage: serde_json::Value = reqwest::get(format!("{}/usage", url))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(usage["used_today"], 30);
+        assert_eq!(usage["by_model"], serde_json::json!({"mock-slow": 10, "mock-fast": 20}));
+        assert_eq!(usage["by_lane"], serde_json::json!({"slow": 10, "fast": 20}));
+        assert_eq!(translator.get_usage_by_model().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_detect_prefers_api_language_and_falls_back_locally() {
+        use crate::testing::MockReply;
+
+        // The API only reports a language for French
+        let server = MockServer::start(|req| {
+            let text = &req.input_texts()[0];
+            let mut output = serde_json::json!({
+                "content": [{ "type": "output_text", "text": format!("en:{}", text) }],
+            });
+            if text.starts_with("Bonjour") {
+                output["detected_source_language"] = "fr".into();
+            }
+            MockReply::json(serde_json::json!({
+                "output": [output],
+                "usage": { "total_tokens": 10 },
+            }))
+        })
+        .await;
+        let url = serve(AppState::new(Arc::new(test_translator(&server.url)))).await;
+
+        let texts = [
+            "Bonjour tout le monde, comment allez-vous aujourd'hui ?",
+            "这是一个用于测试语言检测的中文句子。",
+            "The quick brown fox jumps over the lazy dog.",
+            "  ",
+        ];
+        let response: serde_json::Value = reqwest::Client::new()
+            .post(format!("{}/detect", url))
+            .json(&serde_json::json!({ "text_list": texts }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(
+            response["detections"],
+            serde_json::json!([
+                { "language": "fr", "source": "api" },
+                { "language": "zh", "source": "local" },
+                { "language": "en", "source": "local" },
+                { "language": null, "source": "none" },
+            ])
+        );
+
+        // Nothing is sent for blank text, and English text goes to Chinese
+        let targets: Vec<String> = server.requests().iter().map(|r| r.target_lang()).collect();
+        assert_eq!(server.request_count(), 3);
+        assert!(targets.contains(&"zh".to_string()) && targets.contains(&"en".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_openapi_documents_translate_and_docs_are_public() {
+        let server = MockServer::echo().await;
+        let state = AppState::new(Arc::new(test_translator(&server.url)))
+            .with_api_key(Some("secret".to_string()));
+        let url = serve(state).await;
+        let get = |path: &str| reqwest::get(format!("{}{}", url, path));
+
+        let spec: serde_json::Value = get("/openapi.json").await.unwrap().json().await.unwrap();
+        assert!(spec["openapi"].as_str().unwrap().starts_with("3."));
+        let translate = &spec["paths"]["/translate"]["post"];
+        assert_eq!(
+            translate["requestBody"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/TranslateRequest"
+        );
+        let schemas = &spec["components"]["schemas"];
+        assert!(schemas["TranslateRequest"]["properties"]["text_list"].is_object());
+        assert!(schemas["TranslateResponse"].is_object());
+        let chat = &spec["paths"]["/v1/chat/completions"]["post"]["responses"]["200"]["content"];
+        assert!(chat["application/json"].is_object() && chat["text/event-stream"].is_object());
+
+        for page in ["/swagger/", "/redoc"] {
+            let response = get(page).await.unwrap();
+            assert_eq!(response.status(), 200, "{}", page);
+            assert!(response.text().await.unwrap().contains("<html"), "{}", page);
+        }
+        let response = get("/swagger/swagger-initializer.js").await.unwrap();
+        assert!(response.text().await.unwrap().contains("/openapi.json"));
+        assert_eq!(get("/swagger/missing.js").await.unwrap().status(), 404);
+        assert_eq!(get("/translate").await.unwrap().status(), 401);
+    }
+
+    #[tokio::test]
+    async fn test_cors_preflight_allows_configured_origins() {
+        let server = MockServer::echo().await;
+        let origins = CorsOrigins::parse("https://app.example, https://other.example");
+        let state = AppState::new(Arc::new(test_translator(&server.url)))
+            .with_api_key(Some("secret".to_string()))
+            .with_cors_origins(origins);
+        let url = serve(state).await;
+        let preflight = |origin: &'static str| {
+            reqwest::Client::new()
+                .request(reqwest::Method::OPTIONS, format!("{}/translate", url))
+                .header("Origin", origin)
+                .header("Access-Control-Request-Method", "POST")
+                .header("Access-Control-Request-Headers", "authorization,content-type")
+                .send()
+        };
+
+        // Preflight carries no credentials, so it is answered before auth
+        let response = preflight("https://app.example").await.unwrap();
+        assert_eq!(response.status(), 200);
+        let headers = response.headers();
+        assert_eq!(headers["access-control-allow-origin"], "https://app.example");
+        let methods = headers["access-control-allow-methods"].to_str().unwrap();
+        assert!(methods.contains("POST"));
+        let allowed = headers["access-control-allow-headers"].to_str().unwrap();
+        assert!(allowed.contains("authorization") && allowed.contains("content-type"));
+
+        let response = preflight("https://evil.example").await.unwrap();
+        assert!(response.headers().get("access-control-allow-origin").is_none());
+
+        assert_eq!(CorsOrigins::parse("https://a.example,*"), CorsOrigins::Any);
+        assert_eq!(CorsOrigins::parse(""), CorsOrigins::List(Vec::new()));
+    }
+
+    #[tokio::test]
+    async fn test_admin_requires_api_key() {
+        let server = MockServer::echo().await;
+        let url = serve(AppState::new(Arc::new(test_translator(&server.url)))).await;
+
+        let response = reqwest::Client::new()
+            .post(format!("{}/admin/models/mock-slow/disable", url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 403);
+    }
+
+    #[test]
+    fn test_content_disposition_encodes_non_ascii_names() {
+        assert_eq!(
+            content_disposition("书_zh.epub"),
+            "attachment; filename=\"__zh.epub\"; filename*=UTF-8''%E4%B9%A6_zh.epub"
+        );
+    }
+}
@@ -0,0 +1,105 @@
+//! OpenAPI document of the HTTP API, browsable with Swagger UI and ReDoc
+
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::get,
+    Router,
+};
+use std::sync::Arc;
+use utoipa::OpenApi;
+
+use crate::server::api;
+
+/// Where the OpenAPI document is served
+pub const OPENAPI_PATH: &str = "/openapi.json";
+
+/// ReDoc page; the viewer itself is loaded from a CDN
+const REDOC_HTML: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+  <title>Doubao Translator API</title>
+  <meta charset="utf-8"/>
+  <meta name="viewport" content="width=device-width, initial-scale=1">
+</head>
+<body>
+  <redoc spec-url="/openapi.json"></redoc>
+  <script src="https://cdn.redoc.ly/redoc/latest/bundles/redoc.standalone.js"></script>
+</body>
+</html>
+"#;
+
+/// The documented endpoints and their schemas
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Doubao Translator API"),
+    paths(
+        api::health_check,
+        api::get_models,
+        api::get_usage,
+        api::chat_completions,
+        api::translate,
+        api::detect,
+    ),
+    components(schemas(
+        api::HealthResponse,
+        api::ModelsResponse,
+        api::ModelInfo,
+        api::UsageResponse,
+        api::OpenAIRequest,
+        api::OpenAIMessage,
+        api::OpenAIResponse,
+        api::OpenAIChoice,
+        api::Usage,
+        api::TranslateRequest,
+        api::TranslateResponse,
+        api::TranslationItem,
+        api::DetectRequest,
+        api::DetectResponse,
+        api::Detection,
+        api::ErrorResponse,
+        api::ErrorDetail,
+    ))
+)]
+pub struct ApiDoc;
+
+/// Routes serving the documentation: `/openapi.json`, `/swagger` and `/redoc`.
+///
+/// The documentation is public, like the health check.
+pub fn routes<S: Clone + Send + Sync + 'static>() -> Router<S> {
+    let swagger = Arc::new(utoipa_swagger_ui::Config::from(OPENAPI_PATH));
+    let swagger_ui = Router::new()
+        .route("/swagger", get(|| async { Redirect::permanent("/swagger/") }))
+        .route("/swagger/", get(swagger_index))
+        .route("/swagger/*file", get(swagger_file))
+        .with_state(swagger);
+    Router::new()
+        .route(OPENAPI_PATH, get(|| async { axum::Json(ApiDoc::openapi()) }))
+        .route("/redoc", get(|| async { Html(REDOC_HTML) }))
+        .merge(swagger_ui)
+}
+
+/// Swagger UI configuration shared by its handlers
+type SwaggerConfig = Arc<utoipa_swagger_ui::Config<'static>>;
+
+/// `GET /swagger/`: the Swagger UI page
+async fn swagger_index(State(config): State<SwaggerConfig>) -> Response {
+    serve_swagger("", config)
+}
+
+/// `GET /swagger/*file`: Swagger UI's scripts and styles
+async fn swagger_file(State(config): State<SwaggerConfig>, Path(file): Path<String>) -> Response {
+    serve_swagger(&file, config)
+}
+
+/// A file of the Swagger UI bundled into the binary
+fn serve_swagger(file: &str, config: SwaggerConfig) -> Response {
+    match utoipa_swagger_ui::serve(file, config) {
+        Ok(Some(file)) => {
+            ([(header::CONTENT_TYPE, file.content_type)], file.bytes.into_owned()).into_response()
+        }
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
@@ -1,3 +1,6 @@
 //! HTTP API server module
 
-pub mod api;
\ No newline at end of file
+pub mod api;
+pub mod docs;
+pub mod request_id;
+pub mod usage;
@@ -0,0 +1,72 @@
+//! Request IDs tying a request's log lines, response and error body together
+//!
+//! Every request gets an `X-Request-Id` (a ULID, unless the caller sent
+//! one), which is echoed in the response, recorded on the request's tracing
+//! span and included in error bodies.
+
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+    Router,
+};
+use tower_http::request_id::{
+    MakeRequestId, PropagateRequestIdLayer, RequestId, SetRequestIdLayer,
+};
+use tower_http::trace::TraceLayer;
+use tracing::info_span;
+
+/// Header carrying the request ID
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+tokio::task_local! {
+    /// ID of the request being handled by the current task
+    static REQUEST_ID: String;
+}
+
+/// Generates a new ULID per request
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MakeRequestUlid;
+
+impl MakeRequestId for MakeRequestUlid {
+    fn make_request_id<B>(&mut self, _request: &axum::http::Request<B>) -> Option<RequestId> {
+        let id = ulid::Ulid::new().to_string();
+        HeaderValue::from_str(&id).ok().map(RequestId::new)
+    }
+}
+
+/// ID of the request being handled, if called while handling one
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+/// Wrap `router` so each request gets an ID, a tracing span named after it
+/// and the ID echoed in its response
+pub fn layer<S: Clone + Send + Sync + 'static>(router: Router<S>) -> Router<S> {
+    let trace = TraceLayer::new_for_http().make_span_with(|request: &Request| {
+        let id = request
+            .headers()
+            .get(X_REQUEST_ID)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        info_span!("request", id, method = %request.method(), uri = %request.uri())
+    });
+    // The last layer added runs first: set the ID, then echo and trace it
+    router
+        .layer(axum::middleware::from_fn(scope_request_id))
+        .layer(trace)
+        .layer(PropagateRequestIdLayer::new(X_REQUEST_ID))
+        .layer(SetRequestIdLayer::new(X_REQUEST_ID, MakeRequestUlid))
+}
+
+/// Make the request's ID available to [`current`] while it is handled
+async fn scope_request_id(request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(X_REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    REQUEST_ID.scope(id, next.run(request)).await
+}
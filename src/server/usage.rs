@@ -0,0 +1,194 @@
+//! Periodic persistence of the server's token usage and spend
+
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use crate::core::client::AsyncTranslator;
+use crate::core::errors::{Result, TranslationError};
+use crate::core::models::TokenUsage;
+
+/// Flush interval when `USAGE_FLUSH_SECS` is not set
+pub const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Usage and spend as written to the usage file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UsageSnapshot {
+    /// Daily token quota and today's usage
+    pub usage: TokenUsage,
+    /// Estimated spend, carried over across restarts
+    pub cost_spent: f64,
+}
+
+impl UsageSnapshot {
+    /// Current usage and spend of `translator`
+    pub async fn of(translator: &AsyncTranslator) -> Self {
+        Self {
+            usage: translator.get_token_usage().await,
+            cost_spent: translator.get_cost_spent(),
+        }
+    }
+
+    /// Continue `translator`'s token usage and spend from this snapshot
+    pub async fn restore(&self, translator: &AsyncTranslator) {
+        translator.restore_token_usage(&self.usage).await;
+        translator.restore_cost_spent(self.cost_spent);
+    }
+
+    /// Read a snapshot written by [`UsageFlusher::flush`]; `None` if there is no file yet
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => Ok(Some(serde_json::from_str(&content)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(TranslationError::FileError {
+                path: path.display().to_string(),
+                message: e.to_string(),
+            }),
+        }
+    }
+}
+
+/// Writes the translator's usage to a file, periodically and at shutdown.
+///
+/// A flush only writes when usage changed since the last write, and flushes
+/// never overlap, so the periodic task and the shutdown flush cannot write
+/// the same state twice.
+#[derive(Debug)]
+pub struct UsageFlusher {
+    /// Translator whose usage is saved
+    translator: Arc<AsyncTranslator>,
+    /// Usage file
+    path: PathBuf,
+    /// Snapshot written last; held during a flush to serialize flushes
+    last_written: Mutex<Option<UsageSnapshot>>,
+    /// Number of writes so far
+    writes: AtomicUsize,
+}
+
+impl UsageFlusher {
+    /// Flusher saving the usage of `translator` to `path`
+    pub fn new(translator: Arc<AsyncTranslator>, path: PathBuf) -> Self {
+        Self {
+            translator,
+            path,
+            last_written: Mutex::new(None),
+            writes: AtomicUsize::new(0),
+        }
+    }
+
+    /// Save current usage unless it is unchanged; returns whether it wrote.
+    ///
+    /// The file is replaced atomically, so a crash mid-write keeps the previous one.
+    pub async fn flush(&self) -> Result<bool> {
+        let mut last_written = self.last_written.lock().await;
+        let snapshot = UsageSnapshot::of(&self.translator).await;
+        if last_written.as_ref() == Some(&snapshot) {
+            return Ok(false);
+        }
+
+        let file_error = |e: std::io::Error| TranslationError::FileError {
+            path: self.path.display().to_string(),
+            message: e.to_string(),
+        };
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        tokio::fs::write(&tmp, serde_json::to_vec_pretty(&snapshot)?)
+            .await
+            .map_err(file_error)?;
+        tokio::fs::rename(&tmp, &self.path).await.map_err(file_error)?;
+
+        debug!("Saved token usage to {}", self.path.display());
+        *last_written = Some(snapshot);
+        self.writes.fetch_add(1, Ordering::SeqCst);
+        Ok(true)
+    }
+
+    /// Number of times the usage file was written
+    pub fn writes(&self) -> usize {
+        self.writes.load(Ordering::SeqCst)
+    }
+
+    /// Flush on every item of `ticks` until it ends (see [`interval_ticks`])
+    pub fn spawn<S>(self: Arc<Self>, ticks: S) -> JoinHandle<()>
+    where
+        S: Stream<Item = ()> + Send + 'static,
+    {
+        tokio::spawn(async move {
+            let mut ticks = std::pin::pin!(ticks);
+            while ticks.next().await.is_some() {
+                if let Err(e) = self.flush().await {
+                    warn!("Failed to save token usage: {}", e);
+                }
+            }
+        })
+    }
+}
+
+/// A tick every `period`, the first one `period` from now
+pub fn interval_ticks(period: Duration) -> impl Stream<Item = ()> + Send {
+    let interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    futures::stream::unfold(interval, |mut interval| async move {
+        interval.tick().await;
+        Some(((), interval))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::models::TranslationRequest;
+    use crate::testing::{test_config, MockServer};
+
+    #[tokio::test]
+    async fn test_periodic_flush_saves_current_usage() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("usage.json");
+        let server = MockServer::echo().await;
+        let mut config = test_config(&server.url);
+        config.prices.insert("mock-slow".to_string(), 1.0);
+        let translator = Arc::new(AsyncTranslator::new(config.clone()).unwrap());
+        let flusher = Arc::new(UsageFlusher::new(translator.clone(), path.clone()));
+
+        // Ticks come from the test instead of a timer
+        let (tick, ticks) = tokio::sync::mpsc::unbounded_channel::<()>();
+        let ticks = futures::stream::unfold(ticks, |mut rx| async move {
+            rx.recv().await.map(|()| ((), rx))
+        });
+        let task = flusher.clone().spawn(ticks);
+
+        let request = TranslationRequest::new("Hello".to_string(), "zh".to_string());
+        translator.translate(&request).await.unwrap();
+        tick.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while flusher.writes() == 0 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("a tick should flush");
+
+        let saved = UsageSnapshot::load(&path).unwrap().unwrap();
+        assert_eq!(saved.usage, translator.get_token_usage().await);
+        assert!(saved.usage.used_today > 0);
+
+        // Nothing changed: neither another tick nor the shutdown flush writes
+        tick.send(()).unwrap();
+        drop(tick);
+        task.await.unwrap();
+        assert!(!flusher.flush().await.unwrap());
+        assert_eq!(flusher.writes(), 1);
+
+        // A restarted server continues from the saved usage
+        let restarted = AsyncTranslator::new(config).unwrap();
+        saved.restore(&restarted).await;
+        assert_eq!(restarted.get_token_usage().await.used_today, saved.usage.used_today);
+        assert!(saved.cost_spent > 0.0);
+        assert_eq!(restarted.get_cost_spent(), saved.cost_spent);
+    }
+}
@@ -0,0 +1,427 @@
+//! Shared test helpers: a local mock of the translation API and fixture builders
+
+#![allow(dead_code)]
+
+use axum::{
+    body::Bytes,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Router,
+};
+use std::future::Future;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::core::client::AsyncTranslator;
+use crate::core::config::TranslatorConfig;
+use crate::core::models::{LaneType, Model};
+
+/// A request received by the mock server
+#[derive(Debug, Clone)]
+pub(crate) struct RecordedRequest {
+    /// Request headers
+    pub headers: HeaderMap,
+    /// Parsed JSON body (`Null` if the body was not JSON)
+    pub body: serde_json::Value,
+}
+
+impl RecordedRequest {
+    /// Texts of every input item in a Doubao-style request body
+    pub fn input_texts(&self) -> Vec<String> {
+        self.body["input"]
+            .as_array()
+            .map(|items| {
+                items
+                    .iter()
+                    .filter_map(|item| item["content"][0]["text"].as_str())
+                    .map(|s| s.to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Target language requested for the first input item
+    pub fn target_lang(&self) -> String {
+        self.body["input"][0]["content"][0]["translation_options"]["target_language"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    /// Model id requested
+    pub fn model(&self) -> String {
+        self.body["model"].as_str().unwrap_or_default().to_string()
+    }
+}
+
+/// Response the mock server sends back
+#[derive(Debug, Clone)]
+pub(crate) struct MockReply {
+    /// HTTP status code
+    pub status: u16,
+    /// Extra response headers
+    pub headers: Vec<(String, String)>,
+    /// JSON body
+    pub body: serde_json::Value,
+    /// Delay before responding
+    pub delay: Duration,
+}
+
+impl MockReply {
+    /// Successful Doubao-style response with one output per translation
+    pub fn translations(texts: &[String]) -> Self {
+        let output: Vec<serde_json::Value> = texts
+            .iter()
+            .map(|t| serde_json::json!({ "content": [{ "type": "output_text", "text": t }] }))
+            .collect();
+        Self {
+            status: 200,
+            headers: Vec::new(),
+            body: serde_json::json!({
+                "id": "mock-response",
+                "output": output,
+                "usage": { "total_tokens": 10 * texts.len() },
+            }),
+            delay: Duration::ZERO,
+        }
+    }
+
+    /// Successful response with an arbitrary JSON body
+    pub fn json(body: serde_json::Value) -> Self {
+        Self {
+            status: 200,
+            headers: Vec::new(),
+            body,
+            delay: Duration::ZERO,
+        }
+    }
+
+    /// Error response with the given status and message
+    pub fn error(status: u16, message: &str) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: serde_json::json!({ "error": { "message": message } }),
+            delay: Duration::ZERO,
+        }
+    }
+
+    /// Delay the response
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Add a response header
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+/// Mock "translation": prefixes each text with the target language
+pub(crate) fn echo_translation(target_lang: &str, text: &str) -> String {
+    format!("{}:{}", target_lang, text)
+}
+
+/// Responder used by [`MockServer`]
+type Responder = dyn Fn(&RecordedRequest) -> MockReply + Send + Sync;
+
+/// Local HTTP server standing in for the translation API
+pub(crate) struct MockServer {
+    /// Endpoint URL to point the translator at
+    pub url: String,
+    /// Every request received so far
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl MockServer {
+    /// Start a server that answers each request with `responder`
+    pub async fn start<F>(responder: F) -> Self
+    where
+        F: Fn(&RecordedRequest) -> MockReply + Send + Sync + 'static,
+    {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let responder: Arc<Responder> = Arc::new(responder);
+
+        let recorded = requests.clone();
+        let app = Router::new().fallback(move |headers: HeaderMap, body: Bytes| {
+            let recorded = recorded.clone();
+            let responder = responder.clone();
+            async move {
+                let request = RecordedRequest {
+                    headers,
+                    body: serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null),
+                };
+                recorded.lock().unwrap().push(request.clone());
+                let reply = responder(&request);
+                if !reply.delay.is_zero() {
+                    tokio::time::sleep(reply.delay).await;
+                }
+                reply.into_response()
+            }
+        });
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/api/v3/responses", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        Self { url, requests }
+    }
+
+    /// Start a server that "translates" every input item with [`echo_translation`]
+    pub async fn echo() -> Self {
+        Self::start(|req| {
+            let target = req.target_lang();
+            let texts: Vec<String> = req
+                .input_texts()
+                .iter()
+                .map(|t| echo_translation(&target, t))
+                .collect();
+            MockReply::translations(&texts)
+        })
+        .await
+    }
+
+    /// All requests received so far
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Number of requests received so far
+    pub fn request_count(&self) -> usize {
+        self.requests.lock().unwrap().len()
+    }
+
+    /// Every input text sent so far, in arrival order
+    pub fn sent_texts(&self) -> Vec<String> {
+        self.requests().iter().flat_map(|r| r.input_texts()).collect()
+    }
+}
+
+impl IntoResponse for MockReply {
+    fn into_response(self) -> Response {
+        let mut response = (
+            StatusCode::from_u16(self.status).unwrap(),
+            axum::Json(self.body),
+        )
+            .into_response();
+        for (name, value) in self.headers {
+            response.headers_mut().insert(
+                axum::http::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                value.parse().unwrap(),
+            );
+        }
+        response
+    }
+}
+
+/// Responder translating every line of each text on its own (`zh:line`),
+/// so batch delimiter lines (`⟪...⟫`) come back unchanged
+pub(crate) fn translate_lines(req: &RecordedRequest) -> MockReply {
+    let target = req.target_lang();
+    let texts: Vec<String> = req
+        .input_texts()
+        .iter()
+        .map(|text| {
+            text.lines()
+                .map(|line| {
+                    if line.starts_with('⟪') {
+                        line.to_string()
+                    } else {
+                        echo_translation(&target, line)
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .collect();
+    MockReply::translations(&texts)
+}
+
+/// Resident memory of this process in bytes, from `/proc/self/status`
+/// (Linux only)
+pub(crate) fn resident_bytes() -> Option<usize> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kib: usize = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// Run `future` while sampling resident memory every millisecond; returns
+/// its output and how far resident memory rose above where it started
+pub(crate) async fn peak_resident_growth<F: Future>(future: F) -> (F::Output, usize) {
+    let start = resident_bytes().unwrap_or(0);
+    let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let sampler = {
+        let done = done.clone();
+        std::thread::spawn(move || {
+            let mut peak = start;
+            while !done.load(std::sync::atomic::Ordering::SeqCst) {
+                peak = peak.max(resident_bytes().unwrap_or(0));
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            peak
+        })
+    };
+    let output = future.await;
+    done.store(true, std::sync::atomic::Ordering::SeqCst);
+    let peak = sampler.join().unwrap().max(resident_bytes().unwrap_or(0));
+    (output, peak.saturating_sub(start))
+}
+
+/// Enabled model `id` on `lane`, with that lane's default rate limits
+pub(crate) fn test_model(id: &str, lane: LaneType) -> Model {
+    let (rpm, max_concurrent) = match lane {
+        LaneType::Fast => (30000, 500),
+        _ => (5000, 80),
+    };
+    Model {
+        id: id.to_string(),
+        lane,
+        rpm,
+        max_concurrent,
+        enabled: true,
+    }
+}
+
+/// Config pointing at `endpoint` with a single slow-lane model and no retries
+pub(crate) fn test_config(endpoint: &str) -> TranslatorConfig {
+    TranslatorConfig {
+        api_key: "test_key".to_string(),
+        api_endpoint: endpoint.to_string(),
+        models: vec![test_model("mock-slow", LaneType::Slow)],
+        max_retries: 0,
+        retry_delay_ms: 1,
+        timeout_ms: 5000,
+        ..Default::default()
+    }
+}
+
+/// Translator pointing at `endpoint`, see [`test_config`]
+pub(crate) fn test_translator(endpoint: &str) -> AsyncTranslator {
+    AsyncTranslator::new(test_config(endpoint)).unwrap()
+}
+
+/// A chapter in a fixture ePub
+pub(crate) struct TestChapter<'a> {
+    /// Manifest id
+    pub id: &'a str,
+    /// Body XHTML (inserted inside `<body>`)
+    pub body: &'a str,
+    /// Value of the spine `linear` attribute
+    pub linear: bool,
+}
+
+impl<'a> TestChapter<'a> {
+    /// Linear chapter
+    pub fn new(id: &'a str, body: &'a str) -> Self {
+        Self { id, body, linear: true }
+    }
+}
+
+/// Full XHTML document for a fixture chapter
+pub(crate) fn chapter_xhtml(body: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\">\n\
+         <head><title>Chapter</title></head>\n<body>{}</body>\n</html>\n",
+        body
+    )
+}
+
+/// Write a minimal EPUB 3 book with the given chapters to `path`
+pub(crate) fn write_epub(path: &Path, title: &str, language: &str, chapters: &[TestChapter]) {
+    let metadata = format!("<dc:title>{}</dc:title>", title);
+    write_epub_with_metadata(path, &metadata, language, chapters);
+}
+
+/// [`write_epub`] with `metadata` (raw OPF elements) in place of the title
+pub(crate) fn write_epub_with_metadata(
+    path: &Path,
+    metadata: &str,
+    language: &str,
+    chapters: &[TestChapter],
+) {
+    let file = std::fs::File::create(path).unwrap();
+    let mut zip = zip::ZipWriter::new(file);
+    let stored =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    let deflated =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+
+    zip.start_file("mimetype", stored).unwrap();
+    zip.write_all(b"application/epub+zip").unwrap();
+
+    zip.start_file("META-INF/container.xml", deflated).unwrap();
+    zip.write_all(
+        br#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#,
+    )
+    .unwrap();
+
+    let manifest: String = chapters
+        .iter()
+        .map(|c| {
+            format!(
+                "    <item id=\"{0}\" href=\"{0}.xhtml\" media-type=\"application/xhtml+xml\"/>\n",
+                c.id
+            )
+        })
+        .collect();
+    let spine: String = chapters
+        .iter()
+        .map(|c| {
+            if c.linear {
+                format!("    <itemref idref=\"{}\"/>\n", c.id)
+            } else {
+                format!("    <itemref idref=\"{}\" linear=\"no\"/>\n", c.id)
+            }
+        })
+        .collect();
+    let opf = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="bookid">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
+    <dc:identifier id="bookid">urn:uuid:00000000-0000-0000-0000-000000000000</dc:identifier>
+    {}
+    <dc:language>{}</dc:language>
+  </metadata>
+  <manifest>
+{}  </manifest>
+  <spine>
+{}  </spine>
+</package>
+"#,
+        metadata, language, manifest, spine
+    );
+    zip.start_file("OEBPS/content.opf", deflated).unwrap();
+    zip.write_all(opf.as_bytes()).unwrap();
+
+    for chapter in chapters {
+        zip.start_file(format!("OEBPS/{}.xhtml", chapter.id), deflated)
+            .unwrap();
+        zip.write_all(chapter_xhtml(chapter.body).as_bytes()).unwrap();
+    }
+
+    zip.finish().unwrap();
+}
+
+/// Read a single entry of a zip archive as a string
+pub(crate) fn read_zip_entry(path: &Path, name: &str) -> String {
+    let file = std::fs::File::open(path).unwrap();
+    let mut zip = zip::ZipArchive::new(file).unwrap();
+    let mut entry = zip.by_name(name).unwrap();
+    let mut content = String::new();
+    std::io::Read::read_to_string(&mut entry, &mut content).unwrap();
+    content
+}
@@ -0,0 +1,97 @@
+//! Local language detection, used when the API doesn't report a language
+
+use whatlang::Lang;
+
+/// A language detected locally, with how sure the detector is
+#[derive(Debug, Clone, PartialEq)]
+pub struct LanguageGuess {
+    /// Language code as the API names it (ISO 639-1, e.g. `en`, `zh`)
+    pub language: String,
+    /// Confidence between 0 and 1
+    pub confidence: f64,
+}
+
+/// Language of `text` as the API names it (ISO 639-1, e.g. `en`, `zh`);
+/// `None` if the text has no letters to go by.
+///
+/// A statistical guess: short texts and closely related languages may be
+/// misdetected, so prefer the language the API reports.
+pub fn detect_language(text: &str) -> Option<String> {
+    guess_language(text).map(|guess| guess.language)
+}
+
+/// [`detect_language`] with the detector's confidence
+pub fn guess_language(text: &str) -> Option<LanguageGuess> {
+    let info = whatlang::detect(text)?;
+    let lang = info.lang();
+    Some(LanguageGuess {
+        language: iso_639_1(lang).unwrap_or(lang.code()).to_string(),
+        confidence: info.confidence(),
+    })
+}
+
+/// Two-letter code of `lang`; `None` for languages without one
+fn iso_639_1(lang: Lang) -> Option<&'static str> {
+    Some(match lang {
+        Lang::Eng => "en",
+        Lang::Cmn => "zh",
+        Lang::Jpn => "ja",
+        Lang::Kor => "ko",
+        Lang::Fra => "fr",
+        Lang::Deu => "de",
+        Lang::Spa => "es",
+        Lang::Por => "pt",
+        Lang::Ita => "it",
+        Lang::Rus => "ru",
+        Lang::Ukr => "uk",
+        Lang::Ara => "ar",
+        Lang::Hin => "hi",
+        Lang::Ben => "bn",
+        Lang::Heb => "he",
+        Lang::Pol => "pl",
+        Lang::Nob => "nb",
+        Lang::Dan => "da",
+        Lang::Swe => "sv",
+        Lang::Fin => "fi",
+        Lang::Tur => "tr",
+        Lang::Nld => "nl",
+        Lang::Hun => "hu",
+        Lang::Ces => "cs",
+        Lang::Ell => "el",
+        Lang::Bul => "bg",
+        Lang::Ron => "ro",
+        Lang::Slv => "sl",
+        Lang::Slk => "sk",
+        Lang::Hrv => "hr",
+        Lang::Srp => "sr",
+        Lang::Lit => "lt",
+        Lang::Lav => "lv",
+        Lang::Est => "et",
+        Lang::Vie => "vi",
+        Lang::Tha => "th",
+        Lang::Ind => "id",
+        Lang::Pes => "fa",
+        Lang::Urd => "ur",
+        Lang::Cat => "ca",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_language_uses_two_letter_codes() {
+        let cases = [
+            ("The quick brown fox jumps over the lazy dog.", "en"),
+            ("这是一个用于测试语言检测的中文句子。", "zh"),
+            ("これは言語検出をテストするための日本語の文です。", "ja"),
+            ("Der schnelle braune Fuchs springt über den faulen Hund.", "de"),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(detect_language(text).as_deref(), Some(expected), "{}", text);
+        }
+        assert_eq!(detect_language("42 — 1.2.3"), None);
+    }
+}
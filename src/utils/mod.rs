@@ -1,3 +1,8 @@
 //! Utility functions and helpers
 
-pub mod progress;
\ No newline at end of file
+pub mod lang;
+pub mod progress;
+pub mod rng;
+pub mod splitter;
+pub mod tokens;
+pub mod wrap;
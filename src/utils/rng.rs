@@ -0,0 +1,48 @@
+//! Seedable randomness for reproducible runs
+//!
+//! Every random choice the translator makes (retry jitter, ordering, batch
+//! delimiters) draws from a named stream derived from the run seed, so a fixed
+//! `--seed` reproduces segmentation and scheduling decisions exactly. Network
+//! nondeterminism (response timing, model output, rate limiting) remains.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Random generator for one purpose (`stream`) of a run.
+///
+/// With a seed the generator is deterministic and independent of other
+/// streams; without one it is seeded from OS entropy.
+pub fn seeded_rng(seed: Option<u64>, stream: &str) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed ^ stream_hash(stream)),
+        None => StdRng::from_entropy(),
+    }
+}
+
+/// Delimiter token that is vanishingly unlikely to occur in source text
+pub fn delimiter_token<R: Rng>(rng: &mut R) -> String {
+    format!("⟪{:08x}⟫", rng.gen::<u32>())
+}
+
+/// FNV-1a hash of a stream name, stable across builds and platforms
+fn stream_hash(stream: &str) -> u64 {
+    stream.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded_streams_are_reproducible_and_independent() {
+        let a: Vec<u32> = (0..4).map(|_| seeded_rng(Some(7), "jitter").gen()).collect();
+        let mut rng = seeded_rng(Some(7), "jitter");
+        let first: u32 = rng.gen();
+        assert!(a.iter().all(|&x| x == first));
+
+        let mut other = seeded_rng(Some(7), "delimiter");
+        assert_ne!(first, other.gen::<u32>());
+    }
+}
@@ -0,0 +1,133 @@
+//! Splitting over-long text into chunks that can be joined back exactly
+
+/// Text cut into chunks, with the exact separators that stood between them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitText {
+    /// Chunks in order; never empty
+    pub chunks: Vec<String>,
+    /// `separators[i]` stood between `chunks[i]` and `chunks[i + 1]`
+    pub separators: Vec<String>,
+}
+
+impl SplitText {
+    /// Join `parts` (one per chunk, e.g. their translations) with the
+    /// original separators, so newlines stay newlines and spaces stay spaces
+    pub fn join<S: AsRef<str>>(&self, parts: &[S]) -> String {
+        let mut joined = String::new();
+        for (i, part) in parts.iter().enumerate() {
+            if i > 0 {
+                joined.push_str(self.separators.get(i - 1).map_or(" ", String::as_str));
+            }
+            joined.push_str(part.as_ref());
+        }
+        joined
+    }
+}
+
+/// Split `text` into chunks of at most `max_len` bytes where possible.
+///
+/// Cuts are made only in the whitespace after a sentence end (`.`, `!`, `?`,
+/// and their CJK forms) or at line breaks; the whitespace itself becomes the
+/// separator. Sentences longer than `max_len` are kept whole.
+pub fn split_text(text: &str, max_len: usize) -> SplitText {
+    split_text_by(text, max_len, str::len)
+}
+
+/// [`split_text`] with chunk size measured by `measure` (e.g. a token count)
+/// instead of bytes
+pub fn split_text_by<F>(text: &str, max_len: usize, measure: F) -> SplitText
+where
+    F: Fn(&str) -> usize,
+{
+    let mut chunks = Vec::new();
+    let mut separators = Vec::new();
+    let mut chunk_start = 0;
+    // Last possible cut inside the current chunk: (separator start, separator end)
+    let mut last_cut: Option<(usize, usize)> = None;
+
+    for (start, end) in cut_points(text) {
+        if measure(&text[chunk_start..start]) > max_len {
+            if let Some((cut_start, cut_end)) = last_cut.take() {
+                chunks.push(text[chunk_start..cut_start].to_string());
+                separators.push(text[cut_start..cut_end].to_string());
+                chunk_start = cut_end;
+            }
+        }
+        last_cut = Some((start, end));
+    }
+    if measure(&text[chunk_start..]) > max_len {
+        if let Some((cut_start, cut_end)) = last_cut {
+            chunks.push(text[chunk_start..cut_start].to_string());
+            separators.push(text[cut_start..cut_end].to_string());
+            chunk_start = cut_end;
+        }
+    }
+    chunks.push(text[chunk_start..].to_string());
+
+    SplitText { chunks, separators }
+}
+
+/// Whitespace runs of `text` where it may be cut, as byte ranges
+fn cut_points(text: &str) -> Vec<(usize, usize)> {
+    let mut points = Vec::new();
+    let mut prev: Option<char> = None;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        if !c.is_whitespace() {
+            prev = Some(c);
+            continue;
+        }
+        let mut end = i + c.len_utf8();
+        let mut has_newline = c == '\n';
+        while let Some(&(j, next)) = chars.peek() {
+            if !next.is_whitespace() {
+                break;
+            }
+            has_newline |= next == '\n';
+            end = j + next.len_utf8();
+            chars.next();
+        }
+
+        let after_sentence = prev.is_some_and(|p| ".!?。！？".contains(p));
+        // Leading and trailing whitespace is not a cut between two chunks
+        if (has_newline || after_sentence) && prev.is_some() && end < text.len() {
+            points.push((i, end));
+        }
+    }
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_join_restores_original_separators() {
+        let text = "First line.\nSecond line\n\n  Third. Fourth sentence here.";
+        let split = split_text(text, 12);
+        assert_eq!(
+            split.chunks,
+            vec!["First line.", "Second line", "Third.", "Fourth sentence here."]
+        );
+        assert_eq!(split.separators, vec!["\n", "\n\n  ", " "]);
+        assert_eq!(split.join(&split.chunks), text);
+
+        let upper: Vec<String> = split.chunks.iter().map(|c| c.to_uppercase()).collect();
+        assert_eq!(
+            split.join(&upper),
+            "FIRST LINE.\nSECOND LINE\n\n  THIRD. FOURTH SENTENCE HERE."
+        );
+    }
+
+    #[test]
+    fn test_short_text_is_one_chunk() {
+        let split = split_text("One. Two.\nThree.", 100);
+        assert_eq!(split.chunks, vec!["One. Two.\nThree."]);
+        assert!(split.separators.is_empty());
+
+        // Nothing to cut at: kept whole even if too long
+        let split = split_text("averyveryverylongword", 4);
+        assert_eq!(split.chunks.len(), 1);
+    }
+}
@@ -0,0 +1,104 @@
+//! Token counting for quota checks and request sizing
+//!
+//! With the `tokenizer` feature, counts come from the cl100k BPE, loaded once
+//! and shared. Without it, or if the BPE cannot be loaded, tokens are
+//! estimated from the text: about four bytes per token for Latin script, a
+//! little over one token per CJK character.
+
+#[cfg(feature = "tokenizer")]
+use std::sync::OnceLock;
+
+use crate::utils::wrap::is_wide;
+
+/// Number of tokens in `text`: the BPE count when available, else an estimate
+pub fn count_tokens(text: &str) -> usize {
+    #[cfg(feature = "tokenizer")]
+    if let Some(bpe) = bpe() {
+        return bpe.encode_ordinary(text).len();
+    }
+    heuristic_tokens(text)
+}
+
+/// Tokens `model` will likely bill for `text`.
+///
+/// Every model is currently counted with the same vocabulary (see
+/// [`count_tokens`]); `model` is where a model-specific tokenizer would plug in.
+pub fn estimate_tokens(text: &str, model: &str) -> usize {
+    let _ = model;
+    count_tokens(text)
+}
+
+/// The estimate used when no tokenizer is available: bytes / 4 for most
+/// text, 5/4 per wide (CJK) character, which BPE vocabularies rarely merge
+pub fn heuristic_tokens(text: &str) -> usize {
+    let (wide, wide_bytes) = text
+        .chars()
+        .filter(|&c| is_wide(c))
+        .fold((0usize, 0), |(n, bytes), c| (n + 1, bytes + c.len_utf8()));
+    (text.len() - wide_bytes) / 4 + (wide * 5).div_ceil(4)
+}
+
+/// Load the tokenizer now instead of on the first count.
+///
+/// Returns whether real token counts are available.
+pub fn warm_tokenizer() -> bool {
+    #[cfg(feature = "tokenizer")]
+    {
+        bpe().is_some()
+    }
+    #[cfg(not(feature = "tokenizer"))]
+    {
+        false
+    }
+}
+
+/// The cl100k BPE, loaded on first use; `None` if it failed to load
+#[cfg(feature = "tokenizer")]
+fn bpe() -> Option<&'static tiktoken_rs::CoreBPE> {
+    static BPE: OnceLock<Option<tiktoken_rs::CoreBPE>> = OnceLock::new();
+    BPE.get_or_init(|| match tiktoken_rs::cl100k_base() {
+        Ok(bpe) => Some(bpe),
+        Err(e) => {
+            tracing::warn!("Tokenizer unavailable, estimating tokens from length: {}", e);
+            None
+        }
+    })
+    .as_ref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 26 tokens in cl100k
+    const CJK: &str = "机器翻译的质量在过去十年里有了很大的提高。";
+
+    /// The same sentence as [`CJK`], 11 tokens in cl100k
+    const ENGLISH: &str = "Machine translation has improved a lot in the past decade.";
+
+    #[cfg(feature = "tokenizer")]
+    #[test]
+    fn test_tokenizer_counts_cjk_accurately() {
+        assert!(warm_tokenizer());
+        assert_eq!(count_tokens(CJK), 26);
+        assert_eq!(estimate_tokens(ENGLISH, "doubao-seed-translation-250915"), 11);
+    }
+
+    #[test]
+    fn test_heuristic_tells_cjk_from_latin() {
+        // Within a third of the real counts; bytes / 4 gives 15 and 14
+        let (cjk, english) = (heuristic_tokens(CJK), heuristic_tokens(ENGLISH));
+        assert!(cjk.abs_diff(26) <= 26 / 3, "CJK estimate {}", cjk);
+        assert!(english.abs_diff(11) <= 11 / 3, "English estimate {}", english);
+        assert!(cjk > english * 3 / 2);
+        assert_eq!(heuristic_tokens(""), 0);
+    }
+
+    #[cfg(not(feature = "tokenizer"))]
+    #[test]
+    fn test_falls_back_to_length_heuristic() {
+        assert!(!warm_tokenizer());
+        assert_eq!(count_tokens(CJK), heuristic_tokens(CJK));
+        assert_eq!(count_tokens("twelve bytes"), 3);
+    }
+}
@@ -0,0 +1,121 @@
+//! Reflowing text to a display width, counting CJK characters as two columns
+
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Punctuation that must not start a line
+const NO_BREAK_BEFORE: &str = ",.;:!?)]}%，。、；：！？）」』》〉】〕…’”·ー";
+
+/// Punctuation that must not end a line
+const NO_BREAK_AFTER: &str = "([{（「『《〈【〔‘“";
+
+/// Break `text` into lines of at most `width` display columns, each line
+/// assumed to follow `indent` columns of prefix.
+///
+/// Latin text breaks at whitespace, CJK text between any two characters
+/// (never before closing or after opening punctuation). Whitespace runs
+/// become a single space; text between backticks and words wider than the
+/// line are kept whole.
+pub fn wrap(text: &str, width: usize, indent: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut line_width = indent;
+
+    for (space, word) in words(text) {
+        let gap = usize::from(space);
+        let word_width = word.width();
+        if line.is_empty() {
+            line_width = indent + word_width;
+            line = word;
+        } else if line_width + gap + word_width > width && !starts_block(&word) {
+            lines.push(std::mem::take(&mut line));
+            line_width = indent + word_width;
+            line = word;
+        } else {
+            if space {
+                line.push(' ');
+            }
+            line.push_str(&word);
+            line_width += gap + word_width;
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Unbreakable pieces of `text`, each with whether whitespace preceded it
+fn words(text: &str) -> Vec<(bool, String)> {
+    let mut words: Vec<(bool, String)> = Vec::new();
+    let mut space = false;
+    let mut in_code = false;
+
+    for c in text.chars() {
+        if c.is_whitespace() && !in_code {
+            space = !words.is_empty();
+            continue;
+        }
+        let wide = is_wide(c);
+        let glued = match words.last() {
+            Some((_, last)) if !space => {
+                let prev = last.chars().last().unwrap_or(' ');
+                in_code
+                    || NO_BREAK_BEFORE.contains(c)
+                    || NO_BREAK_AFTER.contains(prev)
+                    || !(wide || is_wide(prev))
+            }
+            _ => false,
+        };
+        match words.last_mut() {
+            Some((_, last)) if glued => last.push(c),
+            _ => words.push((space, c.to_string())),
+        }
+        if c == '`' {
+            in_code = !in_code;
+        }
+        space = false;
+    }
+    words
+}
+
+/// Whether `c` takes two columns (CJK ideographs, kana, fullwidth forms)
+pub fn is_wide(c: char) -> bool {
+    c.width() == Some(2)
+}
+
+/// Whether a line starting with `word` could be read as Markdown block syntax
+/// (heading, quote, list item, setext underline)
+fn starts_block(word: &str) -> bool {
+    let ordered = word
+        .strip_suffix(['.', ')'])
+        .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()));
+    word.starts_with(['#', '>'])
+        || matches!(word, "-" | "+" | "*")
+        || word.chars().all(|c| c == '=' || c == '-')
+        || ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_latin_and_cjk() {
+        assert_eq!(wrap("one two  three four", 9, 0), vec!["one two", "three", "four"]);
+        // CJK characters take two columns; the full stop never starts a line
+        assert_eq!(wrap("这是一个很长的句子", 8, 0), vec!["这是一个", "很长的句", "子"]);
+        assert_eq!(wrap("很长的句子。", 10, 0), vec!["很长的句", "子。"]);
+        assert_eq!(wrap("使用 API 接口", 6, 2), vec!["使用", "API", "接口"]);
+
+        for line in wrap("翻译后的文本（包括标点）会按照显示宽度重新排列。", 10, 0) {
+            assert!(line.width() <= 10, "{:?} is too wide", line);
+        }
+    }
+
+    #[test]
+    fn test_wrap_keeps_code_and_block_markers_inline() {
+        assert_eq!(wrap("调用 `a b` 函数", 4, 0), vec!["调用", "`a b`", "函数"]);
+        // A line starting with "-" or "1." would become a list item
+        assert_eq!(wrap("aaa - bbb 1. c", 4, 0), vec!["aaa -", "bbb 1.", "c"]);
+    }
+}
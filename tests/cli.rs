@@ -0,0 +1,213 @@
+//! End-to-end tests of the command-line binary against a local mock of the
+//! translation API
+
+use axum::{body::Bytes, Json, Router};
+use std::io::Write;
+use std::path::Path;
+use std::process::{Output, Stdio};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// Start a mock API that "translates" every input text to `zh:<text>`;
+/// returns its endpoint URL
+async fn start_mock_api() -> String {
+    let app = Router::new().fallback(|body: Bytes| async move {
+        let request: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+        let output: Vec<serde_json::Value> = request["input"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|item| item["content"][0]["text"].as_str())
+            .map(|text| {
+                let text = format!("zh:{}", text);
+                serde_json::json!({ "content": [{ "type": "output_text", "text": text }] })
+            })
+            .collect();
+        Json(serde_json::json!({
+            "id": "mock-response",
+            "usage": { "total_tokens": 10 * output.len() },
+            "output": output,
+        }))
+    });
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/api/v3/responses", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    url
+}
+
+/// Run the binary in `dir` against `endpoint` with `args`, feeding it `stdin`
+async fn run_cli(dir: &Path, endpoint: &str, args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_doubao-translator"))
+        .args(args)
+        .current_dir(dir)
+        .env("ARK_API_KEY", "test_key")
+        .env("API_ENDPOINT", endpoint)
+        .env("MAX_RETRIES", "0")
+        .env_remove("RUST_LOG")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut input = child.stdin.take().unwrap();
+    input.write_all(stdin.as_bytes()).await.unwrap();
+    drop(input);
+    child.wait_with_output().await.unwrap()
+}
+
+#[tokio::test]
+async fn test_stdin_is_translated_to_stdout() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let endpoint = start_mock_api().await;
+
+    let output = run_cli(
+        temp_dir.path(),
+        &endpoint,
+        &["md", "--stdin", "--source-lang", "en"],
+        "# Title\n\nHello world.\n",
+    )
+    .await;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "# zh:Title\n\nzh:Hello world.\n"
+    );
+
+    let output = run_cli(
+        temp_dir.path(),
+        &endpoint,
+        &["txt", "--stdin", "--source-lang", "en"],
+        "Hello world.\n",
+    )
+    .await;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "zh:Hello world.\n"
+    );
+}
+
+/// Write a one-chapter EPUB 3 book with `body` as its chapter to `path`
+fn write_epub(path: &Path, body: &str) {
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+    let stored =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    let entries = [
+        (
+            "META-INF/container.xml",
+            r#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#
+            .to_string(),
+        ),
+        (
+            "OEBPS/content.opf",
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="bookid">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="bookid">urn:uuid:00000000-0000-0000-0000-000000000000</dc:identifier>
+    <dc:title>Book</dc:title>
+    <dc:language>en</dc:language>
+  </metadata>
+  <manifest>
+    <item id="chapter" href="chapter.xhtml" media-type="application/xhtml+xml"/>
+  </manifest>
+  <spine>
+    <itemref idref="chapter"/>
+  </spine>
+</package>
+"#
+            .to_string(),
+        ),
+        (
+            "OEBPS/chapter.xhtml",
+            format!(
+                "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+                 <html xmlns=\"http://www.w3.org/1999/xhtml\">\n\
+                 <head><title>Chapter</title></head>\n<body>{}</body>\n</html>\n",
+                body
+            ),
+        ),
+    ];
+
+    zip.start_file("mimetype", stored).unwrap();
+    zip.write_all(b"application/epub+zip").unwrap();
+    for (name, content) in entries {
+        zip.start_file(name, stored).unwrap();
+        zip.write_all(content.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap();
+}
+
+/// Parse `output`'s stdout as exactly one JSON object
+fn json_stdout(output: &Output) -> serde_json::Value {
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8(output.stdout.clone()).unwrap();
+    serde_json::from_str(&stdout).unwrap_or_else(|e| panic!("{}: {:?}", e, stdout))
+}
+
+#[tokio::test]
+async fn test_json_prints_only_the_summary() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let endpoint = start_mock_api().await;
+    let docs = temp_dir.path().join("docs");
+    std::fs::create_dir_all(&docs).unwrap();
+    std::fs::write(docs.join("a.md"), "First file.\n").unwrap();
+    std::fs::write(docs.join("b.md"), "Second file.\n").unwrap();
+    write_epub(
+        &temp_dir.path().join("book.epub"),
+        "<p>A chapter of the book.</p>",
+    );
+
+    let md = [
+        "md",
+        "-f",
+        "docs",
+        "-o",
+        "out",
+        "--source-lang",
+        "en",
+        "--resume",
+        "--json",
+    ];
+    let summary = json_stdout(&run_cli(temp_dir.path(), &endpoint, &md, "").await);
+    assert_eq!(summary["processed"], 2);
+    assert_eq!(summary["failed"], 0);
+
+    // A resumed run has files to skip, which must not be reported on stdout
+    let summary = json_stdout(&run_cli(temp_dir.path(), &endpoint, &md, "").await);
+    assert_eq!(summary["processed"], 0);
+
+    let epub = [
+        "epub",
+        "-f",
+        "book.epub",
+        "-o",
+        "book_zh.epub",
+        "--source-lang",
+        "en",
+        "--json",
+    ];
+    let summary = json_stdout(&run_cli(temp_dir.path(), &endpoint, &epub, "").await);
+    assert_eq!(summary["processed"], 1);
+    assert_eq!(summary["failed"], 0);
+}